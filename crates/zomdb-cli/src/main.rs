@@ -0,0 +1,245 @@
+//! Command-line tool to inspect and modify the files of Heaps.
+//!
+//! Keys and values are given and printed as UTF-8, or as hex with --hex.
+//! The exit code is 0 on success, EXIT_NOT_FOUND if the key wasn't found
+//! and EXIT_ERROR if the command failed or its arguments were invalid.
+
+use clap::{Arg, ArgMatches, Command};
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::{error, fmt};
+use zomdb::{Heap, HeapOptions, Index};
+
+/// Exit code of get and del if the key has no value.
+const EXIT_NOT_FOUND: u8 = 1;
+
+/// Exit code of commands that failed. clap uses it for invalid arguments
+/// as well.
+const EXIT_ERROR: u8 = 2;
+
+type Result<T> = std::result::Result<T, Box<dyn error::Error>>;
+
+/// How a command that didn't fail ended.
+enum Outcome {
+    Done,
+    NotFound,
+}
+
+fn main() -> ExitCode {
+    let matches = command().get_matches();
+    match run(&matches) {
+        Ok(Outcome::Done) => ExitCode::SUCCESS,
+        Ok(Outcome::NotFound) => ExitCode::from(EXIT_NOT_FOUND),
+        Err(e) => {
+            eprintln!("zomdb: {}", e);
+            ExitCode::from(EXIT_ERROR)
+        }
+    }
+}
+
+fn command() -> Command<'static> {
+    let file = Arg::new("file")
+        .help("Path of the Heap file")
+        .required(true)
+        .allow_invalid_utf8(true);
+    let key = Arg::new("key").help("The key").required(true);
+    let value = Arg::new("value").help("The value").required(true);
+
+    Command::new("zomdb")
+        .about("Inspects and modifies the files of zomdb Heaps")
+        .subcommand_required(true)
+        .arg_required_else_help(true)
+        .arg(
+            Arg::new("hex")
+                .long("hex")
+                .global(true)
+                .help("Read and print keys and values as hex"),
+        )
+        .subcommand(
+            Command::new("get")
+                .about("Prints the value of a key")
+                .arg(file.clone())
+                .arg(key.clone()),
+        )
+        .subcommand(
+            Command::new("set")
+                .about("Sets the value of a key, creating the file if needed")
+                .arg(file.clone())
+                .arg(key.clone())
+                .arg(value),
+        )
+        .subcommand(
+            Command::new("del")
+                .about("Deletes a key")
+                .arg(file.clone())
+                .arg(key),
+        )
+        .subcommand(
+            Command::new("list")
+                .about("Prints every key that has a value")
+                .arg(file.clone()),
+        )
+        .subcommand(
+            Command::new("dump")
+                .about("Prints every tuple stored in the file with its offset")
+                .arg(file.clone()),
+        )
+        .subcommand(
+            Command::new("stats")
+                .about("Prints statistics about the tuples in the file")
+                .arg(file.clone()),
+        )
+        .subcommand(
+            Command::new("verify")
+                .about("Checks that every tuple in the file can be read")
+                .arg(file.clone()),
+        )
+        .subcommand(
+            Command::new("compact")
+                .about("Drops stale tuples from the file")
+                .arg(file),
+        )
+}
+
+fn run(matches: &ArgMatches) -> Result<Outcome> {
+    let hex = matches.is_present("hex");
+    let (name, args) = matches.subcommand().ok_or("no command given")?;
+    let path = PathBuf::from(args.value_of_os("file").ok_or("no file given")?);
+    let mut out = io::stdout().lock();
+
+    match name {
+        "get" => {
+            let key = parse_bytes(args.value_of("key"), hex)?;
+            let mut heap = Heap::open_read_only(path)?;
+            match heap.get(&key)? {
+                Some(value) => writeln!(out, "{}", Bytes(&value, hex))?,
+                None => return Ok(Outcome::NotFound),
+            }
+        }
+        "set" => {
+            let key = parse_bytes(args.value_of("key"), hex)?;
+            let value = parse_bytes(args.value_of("value"), hex)?;
+            let mut heap = Heap::from(path)?;
+            heap.put(&key, &value)?;
+            heap.sync()?;
+        }
+        "del" => {
+            let key = parse_bytes(args.value_of("key"), hex)?;
+            let mut heap = HeapOptions::new().create(false).open(path)?;
+            if !heap.delete(&key)? {
+                return Ok(Outcome::NotFound);
+            }
+            heap.sync()?;
+        }
+        "list" => {
+            let heap = Heap::open_read_only(path)?;
+            for tuple in heap.iter() {
+                writeln!(out, "{}", Bytes(&tuple?.key, hex))?;
+            }
+        }
+        "dump" => {
+            let heap = Heap::open_read_only(path)?;
+            for record in heap.records() {
+                let record = record?;
+                if record.tombstone {
+                    writeln!(out, "{}\tdel\t{}", record.offset, Bytes(&record.key, hex))?;
+                } else {
+                    writeln!(
+                        out,
+                        "{}\tput\t{}\t{}",
+                        record.offset,
+                        Bytes(&record.key, hex),
+                        Bytes(&record.value, hex)
+                    )?;
+                }
+            }
+        }
+        "stats" => {
+            let stats = Heap::open_read_only(path)?.stats()?;
+            writeln!(out, "file_size: {}", stats.file_size)?;
+            writeln!(out, "total_records: {}", stats.total_records)?;
+            writeln!(out, "live_keys: {}", stats.live_keys)?;
+            writeln!(out, "stale_records: {}", stats.stale_records)?;
+            writeln!(out, "dead_bytes: {}", stats.dead_bytes)?;
+        }
+        "verify" => {
+            let report = Heap::open_read_only(path)?.verify()?;
+            writeln!(out, "valid_records: {}", report.valid_records)?;
+            match report.first_corrupt_offset {
+                Some(offset) => writeln!(out, "first_corrupt_offset: {}", offset)?,
+                None => writeln!(out, "first_corrupt_offset: none")?,
+            }
+            writeln!(out, "truncated_tail: {}", report.truncated_tail)?;
+            if report.first_corrupt_offset.is_some() || report.truncated_tail {
+                return Err("the file is corrupted".into());
+            }
+        }
+        "compact" => {
+            let mut heap = HeapOptions::new().create(false).open(path)?;
+            let report = heap.compact()?;
+            writeln!(out, "bytes_before: {}", report.bytes_before)?;
+            writeln!(out, "bytes_after: {}", report.bytes_after)?;
+            writeln!(out, "records_before: {}", report.records_before)?;
+            writeln!(out, "records_dropped: {}", report.records_dropped)?;
+        }
+        _ => return Err(format!("unknown command: {}", name).into()),
+    }
+
+    Ok(Outcome::Done)
+}
+
+/// Returns the bytes of a key or value given on the command line.
+fn parse_bytes(arg: Option<&str>, hex: bool) -> Result<Vec<u8>> {
+    let arg = arg.ok_or("missing argument")?;
+    if !hex {
+        return Ok(arg.as_bytes().to_vec());
+    }
+
+    if arg.len() % 2 != 0 {
+        return Err(format!("odd number of hex digits: {}", arg).into());
+    }
+    (0..arg.len())
+        .step_by(2)
+        .map(|i| {
+            arg.get(i..i + 2)
+                .and_then(|digits| u8::from_str_radix(digits, 16).ok())
+                .ok_or_else(|| format!("invalid hex: {}", arg).into())
+        })
+        .collect()
+}
+
+/// Displays a key or value as UTF-8, replacing invalid sequences, or as
+/// hex.
+struct Bytes<'a>(&'a [u8], bool);
+
+impl fmt::Display for Bytes<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.1 {
+            self.0.iter().try_for_each(|b| write!(f, "{:02x}", b))
+        } else {
+            write!(f, "{}", String::from_utf8_lossy(self.0))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_bytes() {
+        assert_eq!(parse_bytes(Some("key"), false).unwrap(), b"key");
+        assert_eq!(parse_bytes(Some("00ff1A"), true).unwrap(), [0, 0xff, 0x1a]);
+        assert!(parse_bytes(Some("0"), true).is_err());
+        assert!(parse_bytes(Some("zz"), true).is_err());
+        assert!(parse_bytes(Some("é0"), true).is_err());
+    }
+
+    #[test]
+    fn test_bytes_display() {
+        assert_eq!(Bytes(b"key", false).to_string(), "key");
+        assert_eq!(Bytes(&[0, 0xff], true).to_string(), "00ff");
+        assert_eq!(Bytes(&[b'k', 0xff], false).to_string(), "k\u{fffd}");
+    }
+}
@@ -0,0 +1,110 @@
+use std::path::Path;
+use std::process::{Command, Output};
+
+fn zomdb(args: &[&str], file: &Path) -> Output {
+    let (command, rest) = args.split_first().unwrap();
+    Command::new(env!("CARGO_BIN_EXE_zomdb"))
+        .arg(command)
+        .arg(file)
+        .args(rest)
+        .output()
+        .unwrap()
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8(output.stdout.clone()).unwrap()
+}
+
+#[test]
+fn test_set_get_del() {
+    let dir = tempfile::tempdir().unwrap();
+    let file = dir.path().join("heap");
+
+    assert!(zomdb(&["set", "key", "value"], &file).status.success());
+    let output = zomdb(&["get", "key"], &file);
+    assert!(output.status.success());
+    assert_eq!(stdout(&output), "value\n");
+
+    assert!(zomdb(&["del", "key"], &file).status.success());
+    assert_eq!(zomdb(&["get", "key"], &file).status.code(), Some(1));
+    assert_eq!(zomdb(&["del", "key"], &file).status.code(), Some(1));
+}
+
+#[test]
+fn test_hex() {
+    let dir = tempfile::tempdir().unwrap();
+    let file = dir.path().join("heap");
+
+    assert!(zomdb(&["set", "--hex", "00ff", "6869"], &file)
+        .status
+        .success());
+    assert_eq!(stdout(&zomdb(&["get", "--hex", "00ff"], &file)), "6869\n");
+    assert_eq!(stdout(&zomdb(&["list", "--hex"], &file)), "00ff\n");
+
+    let output = zomdb(&["get", "--hex", "0"], &file);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(!output.stderr.is_empty());
+}
+
+#[test]
+fn test_list_and_dump() {
+    let dir = tempfile::tempdir().unwrap();
+    let file = dir.path().join("heap");
+    for (key, value) in [("key1", "value1"), ("key2", "value2"), ("key1", "value3")] {
+        assert!(zomdb(&["set", key, value], &file).status.success());
+    }
+    assert!(zomdb(&["del", "key2"], &file).status.success());
+
+    assert_eq!(stdout(&zomdb(&["list"], &file)), "key1\n");
+    assert_eq!(
+        stdout(&zomdb(&["dump"], &file)),
+        "39\tdel\tkey2\n26\tput\tkey1\tvalue3\n13\tput\tkey2\tvalue2\n0\tput\tkey1\tvalue1\n"
+    );
+}
+
+#[test]
+fn test_stats_and_compact() {
+    let dir = tempfile::tempdir().unwrap();
+    let file = dir.path().join("heap");
+    assert!(zomdb(&["set", "key", "value1"], &file).status.success());
+    assert!(zomdb(&["set", "key", "value2"], &file).status.success());
+
+    let output = stdout(&zomdb(&["stats"], &file));
+    assert!(output.contains("total_records: 2\n"), "{}", output);
+    assert!(output.contains("live_keys: 1\n"), "{}", output);
+
+    let output = zomdb(&["compact"], &file);
+    assert!(output.status.success());
+    assert!(stdout(&output).contains("records_dropped: 1\n"));
+    assert_eq!(stdout(&zomdb(&["get", "key"], &file)), "value2\n");
+}
+
+#[test]
+fn test_verify_reports_corruption() {
+    let dir = tempfile::tempdir().unwrap();
+    let file = dir.path().join("heap");
+    assert!(zomdb(&["set", "key", "value"], &file).status.success());
+    assert!(zomdb(&["verify"], &file).status.success());
+
+    let mut data = std::fs::read(&file).unwrap();
+    data.extend_from_slice(b"garbage");
+    std::fs::write(&file, data).unwrap();
+
+    let output = zomdb(&["verify"], &file);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(stdout(&output).contains("first_corrupt_offset: 18\n"));
+
+    let output = zomdb(&["dump"], &file);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("offset 18"));
+}
+
+#[test]
+fn test_missing_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let file = dir.path().join("heap");
+
+    assert_eq!(zomdb(&["get", "key"], &file).status.code(), Some(2));
+    assert_eq!(zomdb(&["del", "key"], &file).status.code(), Some(2));
+    assert!(!file.exists());
+}
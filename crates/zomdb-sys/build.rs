@@ -2,6 +2,29 @@ use std::{env, path::PathBuf};
 
 extern crate cbindgen;
 
+// The error convention applies to the whole header, so it is stated at its
+// top rather than repeated on every function.
+const HEADER: &str = "\
+/*
+ * Errors are reported as a ZomdbErrorCode and never through errno. Functions
+ * returning a ZomdbErrorCode return ZOMDB_ERROR_CODE_OK on success or the
+ * code of the error. Functions returning a pointer return null on failure,
+ * and functions returning nothing report failure only through
+ * zomdb_last_error. The code and message of the last error on the calling
+ * thread are available from zomdb_last_error and zomdb_last_error_message.
+ * Functions with an _e suffix additionally hand out the error as a
+ * ZomdbError object.
+ *
+ * Heap handles may be shared across threads; calls using them may block
+ * while another thread uses the same heap. All other handles, like
+ * iterators and tuples, must only be used by one thread at a time.
+ */";
+
+const PLATFORM_DEFINES: &str = "
+#if defined(__unix__) || defined(__APPLE__)
+#define ZOMDB_UNIX
+#endif";
+
 fn main() {
     let crate_dir = env::var("CARGO_MANIFEST_DIR").unwrap();
 
@@ -11,9 +34,27 @@ fn main() {
         .display()
         .to_string();
 
+    // Platform-specific functions are only declared on their platform, and
+    // the deprecated aliases only if the caller asks for them.
+    // macOS compilers don't predefine __unix__, so the header derives its
+    // own macro for Unix.
+    let mut config = cbindgen::Config::default();
+    config.after_includes = Some(PLATFORM_DEFINES.into());
+    config.defines.insert("unix".into(), "ZOMDB_UNIX".into());
+    config.defines.insert("windows".into(), "_WIN32".into());
+    config.defines.insert(
+        "feature = compat-symbols".into(),
+        "ZOMDB_COMPAT_SYMBOLS".into(),
+    );
+    // C enumerators share one namespace, so they are prefixed with the name
+    // of their enum, like ZOMDB_ERROR_CODE_NOT_FOUND.
+    config.enumeration.rename_variants = cbindgen::RenameRule::QualifiedScreamingSnakeCase;
+
     cbindgen::Builder::new()
+        .with_config(config)
         .with_crate(crate_dir)
         .with_language(cbindgen::Language::C)
+        .with_header(HEADER)
         .generate()
         .unwrap()
         .write_to_file(output_file);
@@ -0,0 +1,204 @@
+//! Allocation of the buffers handed out to the caller, like values and the
+//! keys and values of tuples.
+//!
+//! By default, buffers come from Rust's allocator. A host that accounts for
+//! its memory can register its own allocator instead, which then produces
+//! and releases all of them. Handles and strings owned by the library are
+//! not affected.
+use crate::*;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::RwLock;
+use std::{ffi, ptr};
+
+/// Called to allocate a buffer of at least size bytes, which is never zero.
+///
+/// Returns null if the memory could not be allocated.
+pub type ZomdbAllocFn =
+    Option<extern "C" fn(size: usize, user_data: *mut ffi::c_void) -> *mut ffi::c_void>;
+
+/// Called to release a buffer returned by the ZomdbAllocFn, with the same
+/// size it was allocated with.
+pub type ZomdbDeallocFn =
+    Option<extern "C" fn(ptr: *mut ffi::c_void, size: usize, user_data: *mut ffi::c_void)>;
+
+#[derive(Clone, Copy)]
+struct Allocator {
+    alloc: extern "C" fn(usize, *mut ffi::c_void) -> *mut ffi::c_void,
+    dealloc: extern "C" fn(*mut ffi::c_void, usize, *mut ffi::c_void),
+    user_data: *mut ffi::c_void,
+}
+
+// The caller of zomdb_set_allocator promises that the functions can be
+// called with the user data from any thread.
+unsafe impl Send for Allocator {}
+unsafe impl Sync for Allocator {}
+
+static ALLOCATOR: RwLock<Option<Allocator>> = RwLock::new(None);
+
+// The number of buffers handed out and not yet released. The allocator can
+// only be replaced while there are none, so that every buffer is released
+// by the allocator that produced it.
+static OUTSTANDING: AtomicUsize = AtomicUsize::new(0);
+
+/// Register the functions that allocate and release the buffers handed out
+/// to the caller.
+///
+/// Once registered, values returned by zomdb_heap_get and zomdb_heap_get2,
+/// and the keys and values of tuples, are allocated with alloc, and
+/// zomdb_free_value, zomdb_free_bytes and the tuple release functions
+/// release them with dealloc. Passing null for both restores Rust's
+/// allocator, which is the default.
+///
+/// Returns 0 on success, ERR_NULL_ARGUMENT if only one of the functions is
+/// null, or ERR_BUSY while buffers handed out before are still alive. To be
+/// safe, register the allocator before any other call into the library.
+/// Allocations that fail with null make the call fail with
+/// ERR_OUT_OF_MEMORY.
+///
+/// # Safety
+///
+/// The functions may be called from any thread that calls into the library,
+/// also concurrently, so they and the user data must be safe to use from
+/// all of them. They must not call into the library themselves. The user
+/// data must stay valid until another allocator is registered.
+#[no_mangle]
+pub unsafe extern "C" fn zomdb_set_allocator(
+    alloc: ZomdbAllocFn,
+    dealloc: ZomdbDeallocFn,
+    user_data: *mut ffi::c_void,
+) -> ZomdbErrorCode {
+    catch_panic(|| {
+        let allocator = match (alloc, dealloc) {
+            (Some(alloc), Some(dealloc)) => Some(Allocator {
+                alloc,
+                dealloc,
+                user_data,
+            }),
+            (None, None) => None,
+            _ => {
+                let message = "alloc and dealloc must both be null or both be set";
+                return fail(ZomdbErrorCode::NullArgument, message.to_string());
+            }
+        };
+
+        // Buffers are counted while holding the read lock, so none can be
+        // handed out between the check and the replacement.
+        let mut current = ALLOCATOR.write().unwrap_or_else(|e| e.into_inner());
+        let outstanding = OUTSTANDING.load(Ordering::SeqCst);
+        if outstanding > 0 {
+            let message = format!("{} buffers handed out before are still alive", outstanding);
+            return fail(ZomdbErrorCode::Busy, message);
+        }
+        *current = allocator;
+        ZomdbErrorCode::Ok
+    })
+    .unwrap_or(ZomdbErrorCode::Panic)
+}
+
+/// Hands the bytes over to the caller, who releases them along with their
+/// length through free.
+pub(crate) fn into_raw(bytes: Vec<u8>) -> Result<(*mut u8, usize), ZomdbErrorCode> {
+    let allocator = ALLOCATOR.read().unwrap_or_else(|e| e.into_inner());
+    let len = bytes.len();
+    let ptr = match *allocator {
+        Some(allocator) => {
+            let ptr = (allocator.alloc)(len.max(1), allocator.user_data).cast::<u8>();
+            if ptr.is_null() {
+                let message = format!("allocation of {} bytes failed", len);
+                return Err(fail(ZomdbErrorCode::OutOfMemory, message));
+            }
+            unsafe { ptr::copy_nonoverlapping(bytes.as_ptr(), ptr, len) };
+            ptr
+        }
+        None => Box::into_raw(bytes.into_boxed_slice()).cast(),
+    };
+    OUTSTANDING.fetch_add(1, Ordering::SeqCst);
+    Ok((ptr, len))
+}
+
+/// Releases a buffer of the given length returned by into_raw.
+pub(crate) unsafe fn free(ptr: *mut u8, len: usize) {
+    let allocator = ALLOCATOR.read().unwrap_or_else(|e| e.into_inner());
+    match *allocator {
+        Some(allocator) => (allocator.dealloc)(ptr.cast(), len.max(1), allocator.user_data),
+        None => drop(unsafe { Box::from_raw(ptr::slice_from_raw_parts_mut(ptr, len)) }),
+    }
+    OUTSTANDING.fetch_sub(1, Ordering::SeqCst);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::alloc::{self, Layout};
+
+    static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+    static RELEASED: AtomicUsize = AtomicUsize::new(0);
+
+    extern "C" fn counting_alloc(size: usize, user_data: *mut ffi::c_void) -> *mut ffi::c_void {
+        assert_eq!(user_data as usize, 42);
+        ALLOCATED.fetch_add(1, Ordering::SeqCst);
+        unsafe { alloc::alloc(Layout::from_size_align(size, 1).unwrap()).cast() }
+    }
+
+    extern "C" fn counting_dealloc(ptr: *mut ffi::c_void, size: usize, _: *mut ffi::c_void) {
+        RELEASED.fetch_add(1, Ordering::SeqCst);
+        unsafe { alloc::dealloc(ptr.cast(), Layout::from_size_align(size, 1).unwrap()) };
+    }
+
+    /// Registers the allocator once the buffers of other tests are released.
+    fn set_allocator(alloc: ZomdbAllocFn, dealloc: ZomdbDeallocFn) {
+        let user_data = 42 as *mut ffi::c_void;
+        while unsafe { zomdb_set_allocator(alloc, dealloc, user_data) } == ZomdbErrorCode::Busy {
+            std::thread::yield_now();
+        }
+    }
+
+    #[test]
+    fn test_set_allocator() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("heap");
+        let path = path.to_str().unwrap();
+
+        set_allocator(Some(counting_alloc), Some(counting_dealloc));
+        unsafe {
+            let heap = zomdb_heap_create_with_options(path.as_ptr(), path.len(), ptr::null());
+            let key = ffi::CString::new("key").unwrap();
+            let value = ffi::CString::new("value").unwrap();
+            assert_eq!(
+                zomdb_heap_set(heap, key.as_ptr(), value.as_ptr()),
+                ZomdbErrorCode::Ok
+            );
+
+            let got = zomdb_heap_get(heap, key.as_ptr());
+            assert_eq!(ffi::CStr::from_ptr(got), value.as_c_str());
+            zomdb_free_value(got as *mut ffi::c_char);
+
+            let (mut value_ptr, mut value_len) = (ptr::null_mut(), 0);
+            let code = zomdb_heap_get2(heap, b"key".as_ptr(), 3, &mut value_ptr, &mut value_len);
+            assert_eq!(code, ZomdbErrorCode::Ok);
+            zomdb_free_bytes(value_ptr, value_len);
+
+            let iter = zomdb_heap_iter(heap);
+            let tuple = zomdb_heap_iter_next(iter);
+            zomdb_heap_tuple_destroy(tuple as *mut HeapTuple);
+            assert_eq!(zomdb_heap_iter_reset(iter), ZomdbErrorCode::Ok);
+            let mut tuple = ptr::null_mut();
+            assert_eq!(zomdb_heap_iter_next2(iter, &mut tuple), ZomdbErrorCode::Ok);
+            zomdb_heap_tuple2_destroy(tuple);
+            zomdb_heap_iter_destroy(iter);
+            zomdb_heap_destroy(heap);
+
+            assert_eq!(
+                zomdb_set_allocator(Some(counting_alloc), None, ptr::null_mut()),
+                ZomdbErrorCode::NullArgument
+            );
+        }
+        set_allocator(None, None);
+
+        // Other tests may have used the allocator as well, but all of their
+        // buffers were released before it could be removed.
+        let allocated = ALLOCATED.load(Ordering::SeqCst);
+        assert!(allocated >= 6);
+        assert_eq!(allocated, RELEASED.load(Ordering::SeqCst));
+    }
+}
@@ -0,0 +1,263 @@
+//! Batches of puts and deletes that are written to a heap at once.
+//!
+//! Operations are only collected while they are added to a batch. They are
+//! validated when the batch is applied, which either writes all of them or
+//! none.
+use crate::*;
+
+/// WriteBatch collects puts and deletes to apply to a heap at once.
+///
+/// Use zomdb_batch_create to create an instance of this struct. It is
+/// consumed by zomdb_heap_apply_batch, or released with zomdb_batch_destroy
+/// if it is never applied.
+pub struct WriteBatch {
+    inner: zomdb::WriteBatch,
+}
+
+/// Create an empty batch.
+///
+/// Never returns null. The batch must be passed to zomdb_heap_apply_batch
+/// or released with zomdb_batch_destroy.
+#[no_mangle]
+pub extern "C" fn zomdb_batch_create() -> *mut WriteBatch {
+    catch_panic(|| {
+        let ptr = Box::into_raw(Box::new(WriteBatch {
+            inner: zomdb::WriteBatch::new(),
+        }));
+        handles::register(ptr, Kind::Batch);
+        ptr
+    })
+    .unwrap_or(std::ptr::null_mut())
+}
+
+/// Add setting a key to a value, both of arbitrary bytes, to the batch.
+///
+/// Returns 0 on success or the code of the error that occurred. Key and
+/// value are copied, and only validated when the batch is applied.
+///
+/// # Safety
+///
+/// The batch pointer must have been returned by zomdb_batch_create and not
+/// yet been applied or destroyed. The key and value pointers must point to
+/// key_len and value_len readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn zomdb_batch_put(
+    ptr: *mut WriteBatch,
+    key_ptr: *const u8,
+    key_len: usize,
+    value_ptr: *const u8,
+    value_len: usize,
+) -> ZomdbErrorCode {
+    catch_panic(|| {
+        check_null!(ZomdbErrorCode::NullArgument; ptr, key_ptr if key_len > 0, value_ptr if value_len > 0);
+        check_handle!(ZomdbErrorCode::InvalidHandle; ptr as Batch);
+        let batch = unsafe { &mut *ptr };
+        let key = unsafe { from_raw_parts(key_ptr, key_len) };
+        let value = unsafe { from_raw_parts(value_ptr, value_len) };
+        batch.inner.put(key, value);
+        ZomdbErrorCode::Ok
+    })
+    .unwrap_or(ZomdbErrorCode::Panic)
+}
+
+/// Add deleting a key of arbitrary bytes to the batch.
+///
+/// Returns 0 on success or the code of the error that occurred. The key is
+/// copied, and only validated when the batch is applied.
+///
+/// # Safety
+///
+/// The batch pointer must have been returned by zomdb_batch_create and not
+/// yet been applied or destroyed. The key pointer must point to key_len
+/// readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn zomdb_batch_delete(
+    ptr: *mut WriteBatch,
+    key_ptr: *const u8,
+    key_len: usize,
+) -> ZomdbErrorCode {
+    catch_panic(|| {
+        check_null!(ZomdbErrorCode::NullArgument; ptr, key_ptr if key_len > 0);
+        check_handle!(ZomdbErrorCode::InvalidHandle; ptr as Batch);
+        let batch = unsafe { &mut *ptr };
+        batch
+            .inner
+            .delete(unsafe { from_raw_parts(key_ptr, key_len) });
+        ZomdbErrorCode::Ok
+    })
+    .unwrap_or(ZomdbErrorCode::Panic)
+}
+
+/// Write all operations of the batch to the heap at once, and release the
+/// batch.
+///
+/// All operations are validated before anything is written, so an invalid
+/// one leaves the heap untouched. Later operations on a key override earlier
+/// ones.
+///
+/// Returns 0 on success or the code of the error that occurred. The last
+/// error message names the index of an invalid operation. The batch is
+/// released either way and must not be used afterwards.
+///
+/// # Safety
+///
+/// The heap pointer must have been returned by zomdb_heap_create and not yet
+/// been destroyed. The batch pointer must have been returned by
+/// zomdb_batch_create and not yet been applied or destroyed.
+#[no_mangle]
+pub unsafe extern "C" fn zomdb_heap_apply_batch(
+    ptr: *mut Heap,
+    batch: *mut WriteBatch,
+) -> ZomdbErrorCode {
+    catch_panic(|| {
+        // Take ownership of the batch first, so that it is released even if
+        // the heap is rejected.
+        check_null!(ZomdbErrorCode::NullArgument; batch);
+        if !handles::unregister(batch, Kind::Batch) {
+            return invalid_handle("batch", Kind::Batch);
+        }
+        let batch = unsafe { Box::from_raw(batch) };
+        check_null!(ZomdbErrorCode::NullArgument; ptr);
+        check_handle!(ZomdbErrorCode::InvalidHandle; ptr as Heap);
+        let heap = unsafe { &*ptr };
+        if let Err(code) = heap.check_writable() {
+            return code;
+        }
+
+        for (i, (key, value)) in batch.inner.iter().enumerate() {
+            if let Err(e) = zomdb::Heap::validate(key, value.unwrap_or_default()) {
+                log!(
+                    ZOMDB_LOG_ERROR,
+                    "zomdb_heap_apply_batch: operation {}: {:?}",
+                    i,
+                    e
+                );
+                return fail(error_code(&e), format!("operation {}: {}", i, e));
+            }
+        }
+
+        match heap.lock().write_batch(&batch.inner) {
+            Ok(_) => ZomdbErrorCode::Ok,
+            Err(e) => {
+                log!(ZOMDB_LOG_ERROR, "heap.write_batch: {:?}", e);
+                set_error(e)
+            }
+        }
+    })
+    .unwrap_or(ZomdbErrorCode::Panic)
+}
+
+/// Release a batch that was never applied.
+///
+/// # Safety
+///
+/// The batch pointer must have been returned by zomdb_batch_create and not
+/// yet been applied or destroyed. It must not be used after this call.
+#[no_mangle]
+pub unsafe extern "C" fn zomdb_batch_destroy(ptr: *mut WriteBatch) {
+    let _ = catch_panic(|| {
+        if ptr.is_null() {
+            return;
+        }
+        if !handles::unregister(ptr, Kind::Batch) {
+            invalid_handle("ptr", Kind::Batch);
+            return;
+        }
+        let batch = unsafe { Box::from_raw(ptr) };
+        drop(batch);
+    });
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::{ffi, ptr};
+
+    fn create_temp_heap(dir: &tempfile::TempDir) -> *mut Heap {
+        let path = dir.path().join("heap");
+        let path = path.to_str().unwrap();
+        let heap =
+            unsafe { zomdb_heap_create_with_options(path.as_ptr(), path.len(), ptr::null()) };
+        assert!(!heap.is_null());
+        heap
+    }
+
+    unsafe fn put(batch: *mut WriteBatch, key: &[u8], value: &[u8]) {
+        let code =
+            unsafe { zomdb_batch_put(batch, key.as_ptr(), key.len(), value.as_ptr(), value.len()) };
+        assert_eq!(code, ZomdbErrorCode::Ok);
+    }
+
+    unsafe fn contains(heap: *mut Heap, key: &[u8]) -> bool {
+        unsafe { zomdb_heap_contains(heap, key.as_ptr(), key.len()) == 1 }
+    }
+
+    #[test]
+    fn test_heap_apply_batch() {
+        let dir = tempfile::tempdir().unwrap();
+        let heap = create_temp_heap(&dir);
+
+        unsafe {
+            let batch = zomdb_batch_create();
+            put(batch, b"key1", b"value1");
+            put(batch, b"key2", b"value2");
+            assert_eq!(zomdb_heap_apply_batch(heap, batch), ZomdbErrorCode::Ok);
+
+            let batch = zomdb_batch_create();
+            put(batch, b"key3", b"value3");
+            assert_eq!(
+                zomdb_batch_delete(batch, b"key1".as_ptr(), 4),
+                ZomdbErrorCode::Ok
+            );
+            assert_eq!(zomdb_heap_apply_batch(heap, batch), ZomdbErrorCode::Ok);
+
+            assert!(!contains(heap, b"key1"));
+            assert!(contains(heap, b"key2"));
+            assert!(contains(heap, b"key3"));
+            zomdb_heap_destroy(heap);
+        }
+    }
+
+    #[test]
+    fn test_heap_apply_batch_all_or_nothing() {
+        let dir = tempfile::tempdir().unwrap();
+        let heap = create_temp_heap(&dir);
+
+        unsafe {
+            let batch = zomdb_batch_create();
+            put(batch, b"key1", b"value1");
+            assert_eq!(zomdb_heap_apply_batch(heap, batch), ZomdbErrorCode::Ok);
+
+            let batch = zomdb_batch_create();
+            put(batch, b"key2", b"value2");
+            assert_eq!(
+                zomdb_batch_delete(batch, b"key1".as_ptr(), 4),
+                ZomdbErrorCode::Ok
+            );
+            put(batch, b"key3", &[0; 2048]);
+            assert_eq!(
+                zomdb_heap_apply_batch(heap, batch),
+                ZomdbErrorCode::ValueSize
+            );
+            let message = ffi::CStr::from_ptr(zomdb_last_error_message());
+            assert!(message.to_str().unwrap().starts_with("operation 2: "));
+
+            assert!(contains(heap, b"key1"));
+            assert!(!contains(heap, b"key2"));
+            assert!(!contains(heap, b"key3"));
+
+            let batch = zomdb_batch_create();
+            assert_eq!(
+                zomdb_batch_put(batch, ptr::null(), 1, ptr::null(), 0),
+                ZomdbErrorCode::NullArgument
+            );
+            assert_eq!(
+                zomdb_heap_apply_batch(ptr::null_mut(), batch),
+                ZomdbErrorCode::NullArgument
+            );
+            zomdb_batch_destroy(zomdb_batch_create());
+            zomdb_batch_destroy(ptr::null_mut());
+            zomdb_heap_destroy(heap);
+        }
+    }
+}
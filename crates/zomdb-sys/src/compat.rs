@@ -0,0 +1,356 @@
+//! Deprecated aliases of the exported functions under the names they had
+//! before they were prefixed with zomdb_.
+//!
+//! They are only compiled in with the compat-symbols feature, to give
+//! existing bindings time to move to the new names.
+use crate::*;
+use std::ffi;
+
+/// Deprecated alias of zomdb_heap_create.
+///
+/// # Safety
+///
+/// See zomdb_heap_create.
+#[no_mangle]
+#[deprecated = "use zomdb_heap_create"]
+pub unsafe extern "C" fn create_heap(file_name_cstr: *const ffi::c_char) -> *mut Heap {
+    unsafe { zomdb_heap_create(file_name_cstr) }
+}
+
+/// Deprecated alias of zomdb_heap_open_options_default.
+#[no_mangle]
+#[deprecated = "use zomdb_heap_open_options_default"]
+pub extern "C" fn heap_open_options_default() -> HeapOpenOptions {
+    zomdb_heap_open_options_default()
+}
+
+/// Deprecated alias of zomdb_heap_create_with_options.
+///
+/// # Safety
+///
+/// See zomdb_heap_create_with_options.
+#[no_mangle]
+#[deprecated = "use zomdb_heap_create_with_options"]
+pub unsafe extern "C" fn create_heap_with_options(
+    path_ptr: *const u8,
+    path_len: usize,
+    opts: *const HeapOpenOptions,
+) -> *mut Heap {
+    unsafe { zomdb_heap_create_with_options(path_ptr, path_len, opts) }
+}
+
+/// Deprecated alias of zomdb_heap_get.
+///
+/// # Safety
+///
+/// See zomdb_heap_get.
+#[no_mangle]
+#[deprecated = "use zomdb_heap_get"]
+pub unsafe extern "C" fn heap_get(
+    ptr: *mut Heap,
+    key_cstr: *const ffi::c_char,
+) -> *const ffi::c_char {
+    unsafe { zomdb_heap_get(ptr, key_cstr) }
+}
+
+/// Deprecated alias of zomdb_heap_set.
+///
+/// # Safety
+///
+/// See zomdb_heap_set.
+#[no_mangle]
+#[deprecated = "use zomdb_heap_set"]
+pub unsafe extern "C" fn heap_set(
+    ptr: *mut Heap,
+    key_cstr: *const ffi::c_char,
+    value_cstr: *const ffi::c_char,
+) -> ZomdbErrorCode {
+    unsafe { zomdb_heap_set(ptr, key_cstr, value_cstr) }
+}
+
+/// Deprecated alias of zomdb_heap_get2.
+///
+/// # Safety
+///
+/// See zomdb_heap_get2.
+#[no_mangle]
+#[deprecated = "use zomdb_heap_get2"]
+pub unsafe extern "C" fn heap_get2(
+    ptr: *mut Heap,
+    key_ptr: *const u8,
+    key_len: usize,
+    out_value_ptr: *mut *mut u8,
+    out_value_len: *mut usize,
+) -> ZomdbErrorCode {
+    unsafe { zomdb_heap_get2(ptr, key_ptr, key_len, out_value_ptr, out_value_len) }
+}
+
+/// Deprecated alias of zomdb_heap_set2.
+///
+/// # Safety
+///
+/// See zomdb_heap_set2.
+#[no_mangle]
+#[deprecated = "use zomdb_heap_set2"]
+pub unsafe extern "C" fn heap_set2(
+    ptr: *mut Heap,
+    key_ptr: *const u8,
+    key_len: usize,
+    value_ptr: *const u8,
+    value_len: usize,
+) -> ZomdbErrorCode {
+    unsafe { zomdb_heap_set2(ptr, key_ptr, key_len, value_ptr, value_len) }
+}
+
+/// Deprecated alias of zomdb_heap_put_many.
+///
+/// # Safety
+///
+/// See zomdb_heap_put_many.
+#[no_mangle]
+#[deprecated = "use zomdb_heap_put_many"]
+pub unsafe extern "C" fn heap_put_many(
+    ptr: *mut Heap,
+    keys: *const *const u8,
+    key_lens: *const usize,
+    values: *const *const u8,
+    value_lens: *const usize,
+    count: usize,
+) -> isize {
+    unsafe { zomdb_heap_put_many(ptr, keys, key_lens, values, value_lens, count) }
+}
+
+/// Deprecated alias of zomdb_heap_delete.
+///
+/// # Safety
+///
+/// See zomdb_heap_delete.
+#[no_mangle]
+#[deprecated = "use zomdb_heap_delete"]
+pub unsafe extern "C" fn heap_delete(
+    ptr: *mut Heap,
+    key_ptr: *const u8,
+    key_len: usize,
+) -> ZomdbErrorCode {
+    unsafe { zomdb_heap_delete(ptr, key_ptr, key_len) }
+}
+
+/// Deprecated alias of zomdb_heap_contains.
+///
+/// # Safety
+///
+/// See zomdb_heap_contains.
+#[no_mangle]
+#[deprecated = "use zomdb_heap_contains"]
+pub unsafe extern "C" fn heap_contains(
+    ptr: *mut Heap,
+    key_ptr: *const u8,
+    key_len: usize,
+) -> ffi::c_int {
+    unsafe { zomdb_heap_contains(ptr, key_ptr, key_len) }
+}
+
+/// Deprecated alias of zomdb_heap_count.
+///
+/// # Safety
+///
+/// See zomdb_heap_count.
+#[no_mangle]
+#[deprecated = "use zomdb_heap_count"]
+pub unsafe extern "C" fn heap_count(ptr: *mut Heap, out_count: *mut u64) -> ZomdbErrorCode {
+    unsafe { zomdb_heap_count(ptr, out_count) }
+}
+
+/// Deprecated alias of zomdb_heap_sync.
+///
+/// # Safety
+///
+/// See zomdb_heap_sync.
+#[no_mangle]
+#[deprecated = "use zomdb_heap_sync"]
+pub unsafe extern "C" fn heap_sync(ptr: *mut Heap) -> ZomdbErrorCode {
+    unsafe { zomdb_heap_sync(ptr) }
+}
+
+/// Deprecated alias of zomdb_heap_compact.
+///
+/// # Safety
+///
+/// See zomdb_heap_compact.
+#[no_mangle]
+#[deprecated = "use zomdb_heap_compact"]
+pub unsafe extern "C" fn heap_compact(
+    ptr: *mut Heap,
+    out_report: *mut HeapCompactionReport,
+) -> ZomdbErrorCode {
+    unsafe { zomdb_heap_compact(ptr, out_report) }
+}
+
+/// Deprecated alias of zomdb_heap_stats.
+///
+/// # Safety
+///
+/// See zomdb_heap_stats.
+#[no_mangle]
+#[deprecated = "use zomdb_heap_stats"]
+pub unsafe extern "C" fn heap_stats(ptr: *mut Heap, out: *mut HeapStats) -> ZomdbErrorCode {
+    unsafe { zomdb_heap_stats(ptr, out) }
+}
+
+/// Deprecated alias of zomdb_heap_destroy.
+///
+/// # Safety
+///
+/// See zomdb_heap_destroy.
+#[no_mangle]
+#[deprecated = "use zomdb_heap_destroy"]
+pub unsafe extern "C" fn destroy_heap(ptr: *mut Heap) {
+    unsafe { zomdb_heap_destroy(ptr) }
+}
+
+/// Deprecated alias of zomdb_heap_iter.
+///
+/// # Safety
+///
+/// See zomdb_heap_iter.
+#[no_mangle]
+#[deprecated = "use zomdb_heap_iter"]
+pub unsafe extern "C" fn heap_iter(ptr: *mut Heap) -> *mut HeapIter {
+    unsafe { zomdb_heap_iter(ptr) }
+}
+
+/// Deprecated alias of zomdb_heap_iter_prefix.
+///
+/// # Safety
+///
+/// See zomdb_heap_iter_prefix.
+#[no_mangle]
+#[deprecated = "use zomdb_heap_iter_prefix"]
+pub unsafe extern "C" fn heap_iter_prefix(
+    ptr: *mut Heap,
+    prefix_ptr: *const u8,
+    prefix_len: usize,
+) -> *mut HeapIter {
+    unsafe { zomdb_heap_iter_prefix(ptr, prefix_ptr, prefix_len) }
+}
+
+/// Deprecated alias of zomdb_heap_for_each.
+///
+/// # Safety
+///
+/// See zomdb_heap_for_each.
+#[no_mangle]
+#[deprecated = "use zomdb_heap_for_each"]
+pub unsafe extern "C" fn heap_for_each(
+    ptr: *mut Heap,
+    callback: Option<HeapForEachCallback>,
+    user_data: *mut ffi::c_void,
+) -> ZomdbErrorCode {
+    unsafe { zomdb_heap_for_each(ptr, callback, user_data) }
+}
+
+/// Deprecated alias of zomdb_heap_iter_next.
+///
+/// # Safety
+///
+/// See zomdb_heap_iter_next.
+#[no_mangle]
+#[deprecated = "use zomdb_heap_iter_next"]
+pub unsafe extern "C" fn heap_iter_next(ptr: *mut HeapIter) -> *const HeapTuple {
+    unsafe { zomdb_heap_iter_next(ptr) }
+}
+
+/// Deprecated alias of zomdb_heap_tuple_key.
+///
+/// # Safety
+///
+/// See zomdb_heap_tuple_key.
+#[no_mangle]
+#[deprecated = "use zomdb_heap_tuple_key"]
+pub unsafe extern "C" fn heap_tuple_key(ptr: *const HeapTuple) -> *const ffi::c_char {
+    unsafe { zomdb_heap_tuple_key(ptr) }
+}
+
+/// Deprecated alias of zomdb_heap_tuple_value.
+///
+/// # Safety
+///
+/// See zomdb_heap_tuple_value.
+#[no_mangle]
+#[deprecated = "use zomdb_heap_tuple_value"]
+pub unsafe extern "C" fn heap_tuple_value(ptr: *const HeapTuple) -> *const ffi::c_char {
+    unsafe { zomdb_heap_tuple_value(ptr) }
+}
+
+/// Deprecated alias of zomdb_heap_tuple_destroy.
+///
+/// # Safety
+///
+/// See zomdb_heap_tuple_destroy.
+#[no_mangle]
+#[deprecated = "use zomdb_heap_tuple_destroy"]
+pub unsafe extern "C" fn heap_tuple_destroy(ptr: *mut HeapTuple) {
+    unsafe { zomdb_heap_tuple_destroy(ptr) }
+}
+
+/// Deprecated alias of zomdb_heap_iter_destroy.
+///
+/// # Safety
+///
+/// See zomdb_heap_iter_destroy.
+#[no_mangle]
+#[deprecated = "use zomdb_heap_iter_destroy"]
+pub unsafe extern "C" fn heap_iter_destroy(ptr: *mut HeapIter) {
+    unsafe { zomdb_heap_iter_destroy(ptr) }
+}
+
+#[cfg(test)]
+#[allow(deprecated)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_aliases() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = ffi::CString::new(dir.path().join("heap").to_str().unwrap()).unwrap();
+        let (key, value) = (
+            ffi::CString::new("key").unwrap(),
+            ffi::CString::new("value").unwrap(),
+        );
+
+        unsafe {
+            let heap = create_heap(path.as_ptr());
+            assert!(!heap.is_null());
+            heap_set(heap, key.as_ptr(), value.as_ptr());
+            assert_eq!(zomdb_last_error(), ZomdbErrorCode::Ok);
+
+            let got = heap_get(heap, key.as_ptr());
+            assert_eq!(ffi::CStr::from_ptr(got), value.as_c_str());
+            zomdb_free_value(got.cast_mut());
+
+            let mut count = 0;
+            assert_eq!(heap_count(heap, &mut count), ZomdbErrorCode::Ok);
+            assert_eq!(count, 1);
+
+            let iter = heap_iter(heap);
+            let tuple = heap_iter_next(iter);
+            assert_eq!(ffi::CStr::from_ptr(heap_tuple_key(tuple)), key.as_c_str());
+            assert_eq!(
+                ffi::CStr::from_ptr(heap_tuple_value(tuple)),
+                value.as_c_str()
+            );
+            heap_tuple_destroy(tuple.cast_mut());
+            assert!(heap_iter_next(iter).is_null());
+            heap_iter_destroy(iter);
+
+            // The aliases hand out the same handles as the new names.
+            assert_eq!(
+                zomdb_heap_delete(heap, key.as_ptr().cast(), 3),
+                ZomdbErrorCode::Ok
+            );
+            assert!(heap_get(heap, key.as_ptr()).is_null());
+            assert_eq!(zomdb_last_error(), ZomdbErrorCode::NotFound);
+            destroy_heap(heap);
+        }
+    }
+}
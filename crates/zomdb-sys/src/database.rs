@@ -0,0 +1,314 @@
+//! Databases of named heaps, stored as files in one directory.
+//!
+//! A Database keeps every heap it opened until it is closed, and hands out
+//! a new Heap handle for it on each call to zomdb_db_heap. The handles and
+//! the Database share the heap: its file stays open until the Database is
+//! closed and all of its handles are destroyed, in any order.
+use crate::*;
+use std::collections::HashMap;
+use std::ffi;
+
+/// Database is a directory of named heaps.
+///
+/// Use zomdb_db_open to create an instance of this struct, and release it
+/// with zomdb_db_close. It may be shared across threads like a Heap.
+pub struct Database {
+    inner: zomdb::Database,
+
+    // The heaps opened so far by name. The file of a heap can only be
+    // opened once, so every handle for it shares the same Heap.
+    heaps: Mutex<HashMap<String, Heap>>,
+}
+
+/// Callback invoked by zomdb_db_list for the name of every heap.
+///
+/// The name pointer is only valid for the duration of the call. Returning a
+/// non-zero value stops the iteration.
+pub type DatabaseListCallback = Option<
+    extern "C" fn(name: *const u8, name_len: usize, user_data: *mut ffi::c_void) -> ffi::c_int,
+>;
+
+/// Open the database in the directory at the given path, creating the
+/// directory if it doesn't exist yet.
+///
+/// The path consists of path_len bytes and doesn't need to be
+/// null-terminated. On Windows, it must be valid UTF-8.
+///
+/// Returns null if the database could not be opened, in which case
+/// zomdb_last_error returns the code of the error. The database must be
+/// released with zomdb_db_close.
+///
+/// # Safety
+///
+/// The path pointer must point to path_len readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn zomdb_db_open(path_ptr: *const u8, path_len: usize) -> *mut Database {
+    catch_panic(|| {
+        check_null!(std::ptr::null_mut(); path_ptr if path_len > 0);
+        let path = match path_from_bytes(unsafe { from_raw_parts(path_ptr, path_len) }) {
+            Ok(path) => path,
+            Err(e) => {
+                utf8_error("path", e);
+                return std::ptr::null_mut();
+            }
+        };
+
+        match zomdb::Database::open(path) {
+            Ok(db) => {
+                let ptr = Box::into_raw(Box::new(Database {
+                    inner: db,
+                    heaps: Mutex::new(HashMap::new()),
+                }));
+                handles::register(ptr, Kind::Database);
+                ptr
+            }
+            Err(e) => {
+                log!(ZOMDB_LOG_ERROR, "Database::open: {:?}", e);
+                set_error(e);
+                std::ptr::null_mut()
+            }
+        }
+    })
+    .unwrap_or(std::ptr::null_mut())
+}
+
+/// Open the heap with the given name in the database, creating it if it
+/// doesn't exist yet.
+///
+/// The name consists of name_len bytes of UTF-8 and doesn't need to be
+/// null-terminated. It must not be empty, start with a dot, contain path
+/// separators or end in ".compact", ".savepoints" or ".savepoints.tmp";
+/// such names fail with ERR_IO.
+///
+/// Returns a new handle for the heap, or null if it could not be opened, in
+/// which case zomdb_last_error returns the code of the error. The handle
+/// works with all heap functions and is owned by the caller, who must
+/// release it with zomdb_heap_destroy. Every call returns a separate handle,
+/// but all handles for the same name share one heap. They stay valid after
+/// the database was closed.
+///
+/// # Safety
+///
+/// The database pointer must have been returned by zomdb_db_open and not
+/// yet been closed. The name pointer must point to name_len readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn zomdb_db_heap(
+    ptr: *mut Database,
+    name_ptr: *const u8,
+    name_len: usize,
+) -> *mut Heap {
+    catch_panic(|| {
+        check_null!(std::ptr::null_mut(); ptr, name_ptr if name_len > 0);
+        check_handle!(std::ptr::null_mut(); ptr as Database);
+        let db = unsafe { &*ptr };
+        let name = match std::str::from_utf8(unsafe { from_raw_parts(name_ptr, name_len) }) {
+            Ok(name) => name,
+            Err(e) => {
+                utf8_error("name", e);
+                return std::ptr::null_mut();
+            }
+        };
+
+        let mut heaps = db.heaps.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(heap) = heaps.get(name) {
+            return Heap::into_handle(heap.share());
+        }
+        match db.inner.heap(name) {
+            Ok(heap) => {
+                let heap = Heap::new(heap);
+                let ptr = Heap::into_handle(heap.share());
+                heaps.insert(name.to_string(), heap);
+                ptr
+            }
+            Err(e) => {
+                log!(ZOMDB_LOG_ERROR, "Database::heap: {:?}", e);
+                set_error(e);
+                std::ptr::null_mut()
+            }
+        }
+    })
+    .unwrap_or(std::ptr::null_mut())
+}
+
+/// Call the callback with the name of every heap in the database, in sorted
+/// order. user_data is passed through to the callback unchanged.
+///
+/// Returns 0 once all names were visited or the callback stopped the
+/// iteration, or the code of the error that occurred.
+///
+/// # Safety
+///
+/// The database pointer must have been returned by zomdb_db_open and not
+/// yet been closed.
+#[no_mangle]
+pub unsafe extern "C" fn zomdb_db_list(
+    ptr: *mut Database,
+    callback: DatabaseListCallback,
+    user_data: *mut ffi::c_void,
+) -> ZomdbErrorCode {
+    check_null!(ZomdbErrorCode::NullArgument; ptr);
+    check_handle!(ZomdbErrorCode::InvalidHandle; ptr as Database);
+    let Some(callback) = callback else {
+        return null_argument("callback");
+    };
+    let db = unsafe { &*ptr };
+
+    let result = catch_panic(|| db.inner.heap_names());
+    match result {
+        Some(Ok(names)) => {
+            for name in names {
+                if callback(name.as_ptr(), name.len(), user_data) != 0 {
+                    break;
+                }
+            }
+            ZomdbErrorCode::Ok
+        }
+        Some(Err(e)) => {
+            log!(ZOMDB_LOG_ERROR, "Database::heap_names: {:?}", e);
+            set_error(e)
+        }
+        None => ZomdbErrorCode::Panic,
+    }
+}
+
+/// Close the database and release its resources.
+///
+/// Heap handles returned by zomdb_db_heap stay valid and keep their heap
+/// open until they are destroyed.
+///
+/// # Safety
+///
+/// The database pointer must have been returned by zomdb_db_open and not
+/// yet been closed. It must not be used after this call.
+#[no_mangle]
+pub unsafe extern "C" fn zomdb_db_close(ptr: *mut Database) {
+    let _ = catch_panic(|| {
+        if ptr.is_null() {
+            return;
+        }
+        if !handles::unregister(ptr, Kind::Database) {
+            invalid_handle("ptr", Kind::Database);
+            return;
+        }
+        let db = unsafe { Box::from_raw(ptr) };
+        drop(db);
+    });
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::ptr;
+
+    unsafe fn open(path: &path::Path) -> *mut Database {
+        let path = path.to_str().unwrap();
+        let db = unsafe { zomdb_db_open(path.as_ptr(), path.len()) };
+        assert!(!db.is_null());
+        db
+    }
+
+    unsafe fn heap(db: *mut Database, name: &str) -> *mut Heap {
+        unsafe { zomdb_db_heap(db, name.as_ptr(), name.len()) }
+    }
+
+    unsafe fn get(heap: *mut Heap, key: &[u8]) -> Option<Vec<u8>> {
+        let (mut value_ptr, mut value_len) = (ptr::null_mut(), 0);
+        let code = unsafe {
+            zomdb_heap_get2(
+                heap,
+                key.as_ptr(),
+                key.len(),
+                &mut value_ptr,
+                &mut value_len,
+            )
+        };
+        if code != ZomdbErrorCode::Ok {
+            return None;
+        }
+        let value = unsafe { from_raw_parts(value_ptr, value_len) }.to_vec();
+        unsafe { zomdb_free_bytes(value_ptr, value_len) };
+        Some(value)
+    }
+
+    unsafe fn set(heap: *mut Heap, key: &[u8], value: &[u8]) -> ZomdbErrorCode {
+        unsafe { zomdb_heap_set2(heap, key.as_ptr(), key.len(), value.as_ptr(), value.len()) }
+    }
+
+    extern "C" fn collect_name(
+        name: *const u8,
+        name_len: usize,
+        user_data: *mut ffi::c_void,
+    ) -> ffi::c_int {
+        let names = unsafe { &mut *(user_data as *mut Vec<String>) };
+        let name = unsafe { from_raw_parts(name, name_len) };
+        names.push(String::from_utf8(name.to_vec()).unwrap());
+        0
+    }
+
+    #[test]
+    fn test_database() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("db");
+
+        unsafe {
+            let db = open(&path);
+            let users = heap(db, "users");
+            let orders = heap(db, "orders");
+            assert_eq!(set(users, b"key", b"alice"), ZomdbErrorCode::Ok);
+            assert_eq!(set(orders, b"key", b"order"), ZomdbErrorCode::Ok);
+
+            // Another handle for the same name shares the open heap.
+            let users2 = heap(db, "users");
+            assert!(!users2.is_null());
+            assert_ne!(users, users2);
+            assert_eq!(get(users2, b"key"), Some(b"alice".to_vec()));
+            zomdb_heap_destroy(users2);
+
+            // Handles outlive the database.
+            zomdb_db_close(db);
+            assert_eq!(set(users, b"other", b"bob"), ZomdbErrorCode::Ok);
+            zomdb_heap_destroy(users);
+            zomdb_heap_destroy(orders);
+
+            let db = open(&path);
+            let mut names: Vec<String> = Vec::new();
+            let code = zomdb_db_list(
+                db,
+                Some(collect_name),
+                (&mut names as *mut Vec<String>).cast(),
+            );
+            assert_eq!(code, ZomdbErrorCode::Ok);
+            assert_eq!(names, ["orders", "users"]);
+
+            let users = heap(db, "users");
+            let orders = heap(db, "orders");
+            assert_eq!(get(users, b"key"), Some(b"alice".to_vec()));
+            assert_eq!(get(users, b"other"), Some(b"bob".to_vec()));
+            assert_eq!(get(orders, b"key"), Some(b"order".to_vec()));
+            zomdb_heap_destroy(users);
+            zomdb_heap_destroy(orders);
+            zomdb_db_close(db);
+        }
+    }
+
+    #[test]
+    fn test_database_errors() {
+        let dir = tempfile::tempdir().unwrap();
+
+        unsafe {
+            let db = open(dir.path());
+            assert!(heap(db, "../escape").is_null());
+            assert_eq!(zomdb_last_error(), ZomdbErrorCode::Io);
+            assert!(zomdb_db_heap(db, [0xff].as_ptr(), 1).is_null());
+            assert_eq!(zomdb_last_error(), ZomdbErrorCode::Utf8);
+            assert!(zomdb_db_heap(ptr::null_mut(), ptr::null(), 0).is_null());
+            assert_eq!(zomdb_last_error(), ZomdbErrorCode::NullArgument);
+            assert_eq!(
+                zomdb_db_list(db, None, ptr::null_mut()),
+                ZomdbErrorCode::NullArgument
+            );
+            zomdb_db_close(db);
+            zomdb_db_close(ptr::null_mut());
+        }
+    }
+}
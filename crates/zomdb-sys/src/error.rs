@@ -0,0 +1,249 @@
+//! Errors handed to the caller as objects, for hosts that prefer them over
+//! the last error of the thread.
+//!
+//! The functions with an _e suffix behave like the ones without it, but
+//! additionally create a ZomdbError on failure and write it to err_out. On
+//! success, err_out is left untouched. It may be null, in which case no
+//! error object is created.
+use crate::*;
+use std::ffi;
+
+/// ZomdbError describes an error that occurred in a call to the library.
+///
+/// It must be released with zomdb_error_free.
+pub struct ZomdbError {
+    code: ZomdbErrorCode,
+    message: ffi::CString,
+}
+
+/// Hands the last error of this thread over to the caller, if the call
+/// failed and the caller asked for it.
+unsafe fn error_out(code: ZomdbErrorCode, err_out: *mut *mut ZomdbError) -> ZomdbErrorCode {
+    if code == ZomdbErrorCode::Ok || err_out.is_null() {
+        return code;
+    }
+
+    let (code, message) = last_error().unwrap_or_else(|| (code, ffi::CString::default()));
+    let ptr = Box::into_raw(Box::new(ZomdbError { code, message }));
+    handles::register(ptr, Kind::Error);
+    unsafe { *err_out = ptr };
+    code
+}
+
+/// Return the code of the error.
+///
+/// # Safety
+///
+/// The error pointer must have been returned by this library and not yet
+/// been released.
+#[no_mangle]
+pub unsafe extern "C" fn zomdb_error_code(ptr: *const ZomdbError) -> ZomdbErrorCode {
+    catch_panic(|| {
+        check_null!(ZomdbErrorCode::NullArgument; ptr);
+        check_handle!(ZomdbErrorCode::InvalidHandle; ptr as Error);
+        unsafe { (*ptr).code }
+    })
+    .unwrap_or(ZomdbErrorCode::Panic)
+}
+
+/// Return a message describing the error, including the errors that caused
+/// it.
+///
+/// The message is owned by the error and released along with it.
+///
+/// # Safety
+///
+/// The error pointer must have been returned by this library and not yet
+/// been released.
+#[no_mangle]
+pub unsafe extern "C" fn zomdb_error_message(ptr: *const ZomdbError) -> *const ffi::c_char {
+    catch_panic(|| {
+        check_null!(std::ptr::null(); ptr);
+        check_handle!(std::ptr::null(); ptr as Error);
+        unsafe { (*ptr).message.as_ptr() }
+    })
+    .unwrap_or(std::ptr::null())
+}
+
+/// Release the error.
+///
+/// Passing null is allowed and does nothing.
+///
+/// # Safety
+///
+/// The error pointer must have been returned by this library and not yet
+/// been released. It must not be used after this call.
+#[no_mangle]
+pub unsafe extern "C" fn zomdb_error_free(ptr: *mut ZomdbError) {
+    let _ = catch_panic(|| {
+        if ptr.is_null() {
+            return;
+        }
+        if !handles::unregister(ptr, Kind::Error) {
+            invalid_handle("ptr", Kind::Error);
+            return;
+        }
+        drop(unsafe { Box::from_raw(ptr) });
+    });
+}
+
+/// Like zomdb_heap_open, but also reports failure through err_out.
+///
+/// # Safety
+///
+/// See zomdb_heap_open. The error pointer must be null or valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn zomdb_heap_open_e(
+    path_ptr: *const u8,
+    path_len: usize,
+    opts: *const HeapOpenOptions,
+    out_heap: *mut *mut Heap,
+    err_out: *mut *mut ZomdbError,
+) -> ZomdbErrorCode {
+    unsafe {
+        let code = zomdb_heap_open(path_ptr, path_len, opts, out_heap);
+        error_out(code, err_out)
+    }
+}
+
+/// Like zomdb_heap_get2, but also reports failure through err_out.
+///
+/// # Safety
+///
+/// See zomdb_heap_get2. The error pointer must be null or valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn zomdb_heap_get2_e(
+    ptr: *mut Heap,
+    key_ptr: *const u8,
+    key_len: usize,
+    out_value_ptr: *mut *mut u8,
+    out_value_len: *mut usize,
+    err_out: *mut *mut ZomdbError,
+) -> ZomdbErrorCode {
+    unsafe {
+        let code = zomdb_heap_get2(ptr, key_ptr, key_len, out_value_ptr, out_value_len);
+        error_out(code, err_out)
+    }
+}
+
+/// Like zomdb_heap_set2, but also reports failure through err_out.
+///
+/// # Safety
+///
+/// See zomdb_heap_set2. The error pointer must be null or valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn zomdb_heap_set2_e(
+    ptr: *mut Heap,
+    key_ptr: *const u8,
+    key_len: usize,
+    value_ptr: *const u8,
+    value_len: usize,
+    err_out: *mut *mut ZomdbError,
+) -> ZomdbErrorCode {
+    unsafe {
+        let code = zomdb_heap_set2(ptr, key_ptr, key_len, value_ptr, value_len);
+        error_out(code, err_out)
+    }
+}
+
+/// Like zomdb_heap_delete, but also reports failure through err_out.
+///
+/// # Safety
+///
+/// See zomdb_heap_delete. The error pointer must be null or valid for
+/// writes.
+#[no_mangle]
+pub unsafe extern "C" fn zomdb_heap_delete_e(
+    ptr: *mut Heap,
+    key_ptr: *const u8,
+    key_len: usize,
+    err_out: *mut *mut ZomdbError,
+) -> ZomdbErrorCode {
+    unsafe {
+        let code = zomdb_heap_delete(ptr, key_ptr, key_len);
+        error_out(code, err_out)
+    }
+}
+
+/// Like zomdb_heap_sync, but also reports failure through err_out.
+///
+/// # Safety
+///
+/// See zomdb_heap_sync. The error pointer must be null or valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn zomdb_heap_sync_e(
+    ptr: *mut Heap,
+    err_out: *mut *mut ZomdbError,
+) -> ZomdbErrorCode {
+    unsafe {
+        let code = zomdb_heap_sync(ptr);
+        error_out(code, err_out)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::ptr;
+
+    unsafe fn take(err: *mut ZomdbError) -> (ZomdbErrorCode, String) {
+        let code = unsafe { zomdb_error_code(err) };
+        let message = unsafe { ffi::CStr::from_ptr(zomdb_error_message(err)) };
+        let message = message.to_string_lossy().into_owned();
+        unsafe { zomdb_error_free(err) };
+        (code, message)
+    }
+
+    #[test]
+    fn test_error_objects() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("file");
+        std::fs::write(&file, b"").unwrap();
+
+        let mut err = ptr::null_mut();
+        let mut heap = ptr::null_mut();
+        let path = file.join("heap");
+        let path = path.to_str().unwrap();
+        let code = unsafe {
+            zomdb_heap_open_e(path.as_ptr(), path.len(), ptr::null(), &mut heap, &mut err)
+        };
+        assert_eq!(code, ZomdbErrorCode::Io);
+        let (code, message) = unsafe { take(err) };
+        assert_eq!(code, ZomdbErrorCode::Io);
+        assert!(message.starts_with("IO error: "), "{}", message);
+        assert!(message.contains("Not a directory"), "{}", message);
+
+        // Successful calls leave err_out alone.
+        let path = dir.path().join("heap");
+        let path = path.to_str().unwrap();
+        let mut err = ptr::dangling_mut();
+        let code = unsafe {
+            zomdb_heap_open_e(path.as_ptr(), path.len(), ptr::null(), &mut heap, &mut err)
+        };
+        assert_eq!(code, ZomdbErrorCode::Ok);
+        assert_eq!(err, ptr::dangling_mut());
+
+        let mut err = ptr::null_mut();
+        let value = [0; 2048];
+        let code = unsafe {
+            zomdb_heap_set2_e(
+                heap,
+                b"key".as_ptr(),
+                3,
+                value.as_ptr(),
+                value.len(),
+                &mut err,
+            )
+        };
+        assert_eq!(code, ZomdbErrorCode::ValueSize);
+        let (code, message) = unsafe { take(err) };
+        assert_eq!(code, ZomdbErrorCode::ValueSize);
+        assert!(message.contains("2048"), "{}", message);
+
+        // Without err_out, only the code is returned.
+        let code = unsafe { zomdb_heap_delete_e(heap, b"key".as_ptr(), 3, ptr::null_mut()) };
+        assert_eq!(code, ZomdbErrorCode::NotFound);
+
+        unsafe { zomdb_heap_destroy(heap) };
+    }
+}
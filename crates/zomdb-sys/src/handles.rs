@@ -0,0 +1,112 @@
+//! Registry of the handles handed out to callers.
+//!
+//! Bindings easily mix up heap and iterator pointers, or keep using a
+//! handle after destroying it. Every handle is recorded here along with its
+//! kind when it is created and removed when it is destroyed, so that entry
+//! points can reject unknown handles instead of dereferencing them.
+//!
+//! The registry takes a global lock on every call, so it is only compiled
+//! into debug builds, or when the handle-checks feature is enabled. Without
+//! it, all handles are assumed to be valid.
+//!
+//! A destroyed handle whose address is reused by the allocator for a new
+//! handle of the same kind can't be told apart from the new one.
+
+/// The type of object a handle points to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Kind {
+    Heap,
+    Iter,
+    Tuple,
+    Tuple2,
+    Error,
+    Database,
+    Batch,
+}
+
+#[cfg(any(debug_assertions, feature = "handle-checks"))]
+mod registry {
+    use super::Kind;
+    use std::collections::HashMap;
+    use std::sync::{LazyLock, Mutex, MutexGuard};
+
+    static HANDLES: LazyLock<Mutex<HashMap<usize, Kind>>> = LazyLock::new(Default::default);
+
+    fn handles() -> MutexGuard<'static, HashMap<usize, Kind>> {
+        // The map is never left half-updated, so a poisoned lock is fine.
+        HANDLES.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Records a handle that was handed out to the caller.
+    pub(crate) fn register<T>(ptr: *const T, kind: Kind) {
+        handles().insert(ptr as usize, kind);
+    }
+
+    /// Returns whether the handle of the kind was handed out and not yet
+    /// destroyed.
+    pub(crate) fn is_valid<T>(ptr: *const T, kind: Kind) -> bool {
+        handles().get(&(ptr as usize)) == Some(&kind)
+    }
+
+    /// Removes a handle that is about to be destroyed, returning whether it
+    /// was valid.
+    pub(crate) fn unregister<T>(ptr: *const T, kind: Kind) -> bool {
+        let mut handles = handles();
+        if handles.get(&(ptr as usize)) != Some(&kind) {
+            return false;
+        }
+        handles.remove(&(ptr as usize));
+        true
+    }
+}
+
+#[cfg(not(any(debug_assertions, feature = "handle-checks")))]
+mod registry {
+    use super::Kind;
+
+    #[inline(always)]
+    pub(crate) fn register<T>(_: *const T, _: Kind) {}
+
+    #[inline(always)]
+    pub(crate) fn is_valid<T>(_: *const T, _: Kind) -> bool {
+        true
+    }
+
+    #[inline(always)]
+    pub(crate) fn unregister<T>(_: *const T, _: Kind) -> bool {
+        true
+    }
+}
+
+pub(crate) use registry::{is_valid, register, unregister};
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    #[cfg(any(debug_assertions, feature = "handle-checks"))]
+    fn test_registry() {
+        let handle = Box::into_raw(Box::new(0u8));
+
+        assert!(!is_valid(handle, Kind::Heap));
+        register(handle, Kind::Heap);
+        assert!(is_valid(handle, Kind::Heap));
+        assert!(!is_valid(handle, Kind::Iter));
+
+        assert!(!unregister(handle, Kind::Iter));
+        assert!(unregister(handle, Kind::Heap));
+        assert!(!unregister(handle, Kind::Heap));
+
+        drop(unsafe { Box::from_raw(handle) });
+    }
+
+    #[test]
+    #[cfg(not(any(debug_assertions, feature = "handle-checks")))]
+    fn test_registry_disabled() {
+        // Without the registry, nothing is recorded and every handle passes.
+        let handle = std::ptr::dangling::<u8>();
+        assert!(is_valid(handle, Kind::Heap));
+        assert!(unregister(handle, Kind::Iter));
+    }
+}
@@ -1,202 +1,3260 @@
 //! FFI wrapper for functions exposed from the zomdb crate.
-use std::{ffi, mem::transmute};
+//!
+//! Memory returned by this library is allocated by Rust's allocator and must
+//! be released through the matching function of this library, never with
+//! the caller's own free. Every non-null value must be released exactly once.
+//!
+//! Functions fail with ERR_NULL_ARGUMENT when a required pointer is null,
+//! instead of dereferencing it. Buffers of length zero may be null, and so
+//! may the pointers passed to the release functions, which then do nothing.
+//!
+//! Errors are reported as a ZomdbErrorCode, never through errno, which the
+//! library leaves untouched. Functions that return a ZomdbErrorCode return
+//! ZomdbErrorCode::Ok on success or the code of the error, and hand out their
+//! results through out parameters. The older functions that return a
+//! pointer or nothing report failure through zomdb_last_error instead.
+//! Either way, the code and a message describing the error can be retrieved
+//! with zomdb_last_error and zomdb_last_error_message until the next failing
+//! call on the same thread.
+//! The functions with an _e suffix hand out the error as an object as well.
+use std::cell::RefCell;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::{ffi, panic, path, slice, str};
 use zomdb::Index;
 
+/// Returns the failure value from the enclosing function if any of the
+/// pointer arguments is null. A condition can be attached to pointers that
+/// may be null in some cases, like buffers of length zero.
+macro_rules! check_null {
+    (; $($arg:ident $(if $cond:expr)?),+) => {
+        $(
+            if $arg.is_null() $(&& $cond)? {
+                null_argument(stringify!($arg));
+                return;
+            }
+        )+
+    };
+    ($failure:expr; $($arg:ident $(if $cond:expr)?),+) => {
+        $(
+            if $arg.is_null() $(&& $cond)? {
+                null_argument(stringify!($arg));
+                return $failure;
+            }
+        )+
+    };
+}
+
+/// Returns the failure value from the enclosing function if the handle was
+/// not handed out by this library as the given kind, or was destroyed
+/// already. Only checked in debug builds or with the handle-checks feature.
+macro_rules! check_handle {
+    ($failure:expr; $arg:ident as $kind:ident) => {
+        if !handles::is_valid($arg, Kind::$kind) {
+            invalid_handle(stringify!($arg), Kind::$kind);
+            return $failure;
+        }
+    };
+    (; $arg:ident as $kind:ident) => {
+        if !handles::is_valid($arg, Kind::$kind) {
+            invalid_handle(stringify!($arg), Kind::$kind);
+            return;
+        }
+    };
+}
+
+mod alloc;
+mod batch;
+#[cfg(feature = "compat-symbols")]
+mod compat;
+mod database;
+mod error;
+mod handles;
+mod log;
+
+use handles::Kind;
+use log::{log, ZOMDB_LOG_ERROR, ZOMDB_LOG_INFO, ZOMDB_LOG_WARN};
+
 /// Heap is a primitive on-disk key-value structure.
 ///
 /// A Heap can be used to set and get key-value pairs, and to iterate over them.
+///
+/// A Heap may be shared across threads. Calls using it from several threads
+/// at once are serialized, so they may block until the others return. It
+/// must not be destroyed while other threads still use it.
 pub struct Heap {
     // Heap only delegates to the inner Heap.
     // This is because it isn't straightforward to generate FFI bindings
     // for external packages, so we redefine a Heap struct here instead.
-    inner: zomdb::Heap,
+    // The lock lets callers share the handle across threads. Handles for
+    // the heaps of a Database share the inner Heap with it.
+    inner: Arc<Mutex<zomdb::Heap>>,
+
+    // The number of iterators created from this heap that are still alive.
+    // Operations that replace the heap's file must wait for them.
+    iterators: Arc<AtomicUsize>,
+}
+
+impl Heap {
+    fn into_handle(self) -> *mut Heap {
+        let ptr = Box::into_raw(Box::new(self));
+        handles::register(ptr, Kind::Heap);
+        ptr
+    }
+
+    fn new(heap: zomdb::Heap) -> Self {
+        Heap {
+            inner: Arc::new(Mutex::new(heap)),
+            iterators: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// Returns another Heap for the same inner Heap.
+    fn share(&self) -> Self {
+        Heap {
+            inner: Arc::clone(&self.inner),
+            iterators: Arc::clone(&self.iterators),
+        }
+    }
+
+    /// Locks the inner Heap for the duration of a call.
+    fn lock(&self) -> MutexGuard<'_, zomdb::Heap> {
+        // A panic while holding the lock is already turned into an error by
+        // catch_panic, and the Heap poisons itself when it is left in an
+        // unknown state, so the lock's poison can be ignored.
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Fails with ERR_READ_ONLY if the heap was opened for reading only.
+    fn check_writable(&self) -> Result<(), ZomdbErrorCode> {
+        if self.lock().is_read_only() {
+            return Err(fail(
+                ZomdbErrorCode::ReadOnly,
+                "heap was opened read-only".to_string(),
+            ));
+        }
+        Ok(())
+    }
 }
 
+/// Open or create the heap backed by the given file.
+///
+/// Returns null if the heap could not be opened, in which case
+/// zomdb_last_error returns the code of the error. File names that aren't
+/// valid UTF-8 fail with ERR_UTF8; open them with
+/// zomdb_heap_create_from_bytes or zomdb_heap_create_w instead.
+///
+/// # Safety
+///
+/// The file name must be a valid null-terminated string.
 #[no_mangle]
-pub unsafe extern "C" fn create_heap(file_name_cstr: *const ffi::c_char) -> *mut Heap {
-    let file_name = match string_from_cstr(file_name_cstr) {
-        Ok(s) => s,
-        Err(e) => {
-            println!("zomdb: file_name: {:?}", e);
-            errno::set_errno(to_errno(zomdb::Error::Input(e)));
-            return std::ptr::null_mut();
+pub unsafe extern "C" fn zomdb_heap_create(file_name_cstr: *const ffi::c_char) -> *mut Heap {
+    catch_panic(|| {
+        check_null!(std::ptr::null_mut(); file_name_cstr);
+        let file_name = match string_from_cstr(file_name_cstr) {
+            Ok(s) => s,
+            Err(e) => {
+                utf8_error("file_name", e);
+                return std::ptr::null_mut();
+            }
+        };
+
+        log!(ZOMDB_LOG_INFO, "opening heap file: {}", file_name);
+
+        let heap = match zomdb::Heap::from(file_name.into()) {
+            Ok(heap) => Heap::new(heap),
+            Err(e) => {
+                log!(ZOMDB_LOG_ERROR, "Heap::from: {:?}", e);
+                set_error(e);
+                return std::ptr::null_mut();
+            }
+        };
+
+        Heap::into_handle(heap)
+    })
+    .unwrap_or(std::ptr::null_mut())
+}
+
+/// Value of a HeapOpenOptions flag that leaves the option at its default.
+pub const HEAP_OPTION_DEFAULT: u8 = 0;
+
+/// Value of a HeapOpenOptions flag that enables the option.
+pub const HEAP_OPTION_ENABLED: u8 = 1;
+
+/// Value of a HeapOpenOptions flag that disables the option.
+pub const HEAP_OPTION_DISABLED: u8 = 2;
+
+/// HeapOpenOptions configures how zomdb_heap_create_with_options opens a heap.
+///
+/// Every field that is zero keeps its default, so a zero-initialized struct
+/// opens a heap just like zomdb_heap_create does. New fields are only ever
+/// added with that in mind. Flags are set to HEAP_OPTION_ENABLED or
+/// HEAP_OPTION_DISABLED; any other non-zero value enables them.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct HeapOpenOptions {
+    /// Open the heap for reading only. Writes fail with ERR_READ_ONLY.
+    /// Disabled by default.
+    pub read_only: u8,
+    /// Create the file if it doesn't exist. Enabled by default.
+    pub create: u8,
+    /// Create the file and fail if it exists already. Disabled by default.
+    pub create_new: u8,
+    /// Flush every write to disk before it returns. Disabled by default.
+    pub sync_on_put: u8,
+    /// Reject values larger than this many bytes. Zero means the largest
+    /// size the heap supports.
+    pub max_value_size: u32,
+}
+
+impl HeapOpenOptions {
+    fn to_options(self) -> zomdb::HeapOptions {
+        let mut opts = zomdb::HeapOptions::new();
+        opts.read_only(flag(self.read_only, false))
+            .create(flag(self.create, true))
+            .create_new(flag(self.create_new, false))
+            .sync_on_put(flag(self.sync_on_put, false));
+        if self.max_value_size != 0 {
+            opts.max_value_size(self.max_value_size as usize);
         }
-    };
+        opts
+    }
+}
+
+fn flag(value: u8, default: bool) -> bool {
+    match value {
+        HEAP_OPTION_DEFAULT => default,
+        HEAP_OPTION_DISABLED => false,
+        _ => true,
+    }
+}
+
+/// Return the options zomdb_heap_create opens a heap with.
+#[no_mangle]
+pub extern "C" fn zomdb_heap_open_options_default() -> HeapOpenOptions {
+    HeapOpenOptions::default()
+}
+
+/// Open or create the heap backed by the file at the given path.
+///
+/// The path consists of path_len bytes and doesn't need to be
+/// null-terminated. On Windows, it must be valid UTF-8. The options may be
+/// null, in which case the defaults are used.
+///
+/// Returns 0 on success, in which case the heap is written to out_heap, or
+/// the code of the error that occurred. The heap must be released with
+/// zomdb_heap_destroy.
+///
+/// # Safety
+///
+/// The path pointer must point to path_len readable bytes. The options
+/// pointer must be null or point to an initialized HeapOpenOptions, and
+/// out_heap must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn zomdb_heap_open(
+    path_ptr: *const u8,
+    path_len: usize,
+    opts: *const HeapOpenOptions,
+    out_heap: *mut *mut Heap,
+) -> ZomdbErrorCode {
+    catch_panic(|| {
+        check_null!(ZomdbErrorCode::NullArgument; path_ptr if path_len > 0, out_heap);
+        let path = match path_from_bytes(unsafe { from_raw_parts(path_ptr, path_len) }) {
+            Ok(path) => path,
+            Err(e) => return utf8_error("path", e),
+        };
+        let opts = unsafe { opts.as_ref() }.copied().unwrap_or_default();
 
-    println!("zomdb: opening heap file: {}", file_name);
+        match open_heap(path, opts) {
+            Ok(heap) => {
+                unsafe { *out_heap = heap };
+                ZomdbErrorCode::Ok
+            }
+            Err(code) => code,
+        }
+    })
+    .unwrap_or(ZomdbErrorCode::Panic)
+}
 
-    let heap = match zomdb::Heap::from(file_name.into()) {
-        Ok(heap) => Heap { inner: heap },
+fn open_heap(path: path::PathBuf, opts: HeapOpenOptions) -> Result<*mut Heap, ZomdbErrorCode> {
+    match opts.to_options().open(path) {
+        Ok(heap) => Ok(Heap::into_handle(Heap::new(heap))),
         Err(e) => {
-            println!("zomdb: Heap::from: {:?}", e);
-            errno::set_errno(to_errno(e));
-            return std::ptr::null_mut();
+            log!(ZOMDB_LOG_ERROR, "HeapOptions::open: {:?}", e);
+            Err(set_error(e))
         }
+    }
+}
+
+/// Open or create the heap backed by the file at the given path.
+///
+/// Like zomdb_heap_open, but returns null if the heap could not be opened, in
+/// which case zomdb_last_error returns the code of the error.
+///
+/// # Safety
+///
+/// The path pointer must point to path_len readable bytes. The options
+/// pointer must be null or point to an initialized HeapOpenOptions.
+#[no_mangle]
+pub unsafe extern "C" fn zomdb_heap_create_with_options(
+    path_ptr: *const u8,
+    path_len: usize,
+    opts: *const HeapOpenOptions,
+) -> *mut Heap {
+    let mut heap = std::ptr::null_mut();
+    match unsafe { zomdb_heap_open(path_ptr, path_len, opts, &mut heap) } {
+        ZomdbErrorCode::Ok => heap,
+        _ => std::ptr::null_mut(),
+    }
+}
+
+/// Open or create the heap backed by the file at the given path of
+/// arbitrary bytes, like zomdb_heap_create does.
+///
+/// The path consists of path_len bytes and doesn't need to be
+/// null-terminated or valid UTF-8. Only available on Unix.
+///
+/// Returns null if the heap could not be opened, in which case
+/// zomdb_last_error returns the code of the error.
+///
+/// # Safety
+///
+/// The path pointer must point to path_len readable bytes.
+#[cfg(unix)]
+#[no_mangle]
+pub unsafe extern "C" fn zomdb_heap_create_from_bytes(
+    path: *const u8,
+    path_len: usize,
+) -> *mut Heap {
+    unsafe { zomdb_heap_create_with_options(path, path_len, std::ptr::null()) }
+}
+
+/// Open the existing heap backed by the file at the given path for reading
+/// only.
+///
+/// The path consists of path_len bytes like for zomdb_heap_open. The file is
+/// never created, so missing files fail with ERR_IO. Reading and iterating
+/// works as usual, while every write fails with ERR_READ_ONLY. Any number
+/// of read-only heaps can be opened alongside the single writer, and they
+/// see the tuples that were complete when they were opened.
+///
+/// Returns null if the heap could not be opened, in which case
+/// zomdb_last_error returns the code of the error.
+///
+/// # Safety
+///
+/// The path pointer must point to path_len readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn zomdb_heap_open_read_only(
+    path_ptr: *const u8,
+    path_len: usize,
+) -> *mut Heap {
+    let opts = HeapOpenOptions {
+        read_only: HEAP_OPTION_ENABLED,
+        ..Default::default()
     };
+    unsafe { zomdb_heap_create_with_options(path_ptr, path_len, &opts) }
+}
+
+/// Open or create the heap backed by the file at the given wide path, like
+/// zomdb_heap_create does.
+///
+/// The path consists of path_len UTF-16 code units and doesn't need to be
+/// null-terminated or well-formed. Only available on Windows.
+///
+/// Returns null if the heap could not be opened, in which case
+/// zomdb_last_error returns the code of the error.
+///
+/// # Safety
+///
+/// The path pointer must point to path_len readable code units.
+#[cfg(windows)]
+#[no_mangle]
+pub unsafe extern "C" fn zomdb_heap_create_w(path: *const u16, path_len: usize) -> *mut Heap {
+    use std::os::windows::ffi::OsStringExt;
 
-    unsafe { transmute(Box::new(heap)) }
+    catch_panic(|| {
+        check_null!(std::ptr::null_mut(); path if path_len > 0);
+        let wide = match path_len {
+            0 => &[][..],
+            _ => unsafe { slice::from_raw_parts(path, path_len) },
+        };
+        let path = ffi::OsString::from_wide(wide).into();
+        open_heap(path, HeapOpenOptions::default()).unwrap_or(std::ptr::null_mut())
+    })
+    .unwrap_or(std::ptr::null_mut())
 }
 
 /// Get a value from the heap.
 ///
-/// Returns a pointer to the value if found, or null if not found. If not
-/// found, the global errno will be set to ERR_NOT_FOUND.
-///
-/// If an error occurs, the global errno will be set to the appropriate error.
+/// Returns a pointer to the value if found, or null otherwise, in which case
+/// zomdb_last_error returns ERR_NOT_FOUND or the code of the error that
+/// occurred. The returned value must be released with zomdb_free_value.
 ///
 /// The accepted key is a null-terminated string. Any calling code must
 /// therefore guarantee that no null bytes are present in the key.
+/// Values containing a null byte can't be returned and fail with
+/// ERR_NUL_BYTE. Use zomdb_heap_get2 to read them.
+///
+/// # Safety
+///
+/// The heap pointer must have been returned by zomdb_heap_create and not yet
+/// been destroyed.
 #[no_mangle]
-pub unsafe extern "C" fn heap_get(
+pub unsafe extern "C" fn zomdb_heap_get(
     ptr: *mut Heap,
     key_cstr: *const ffi::c_char,
 ) -> *const ffi::c_char {
-    let heap = unsafe { &mut *ptr };
+    catch_panic(|| {
+        check_null!(std::ptr::null(); ptr, key_cstr);
+        check_handle!(std::ptr::null(); ptr as Heap);
+        let heap = unsafe { &*ptr };
 
-    let key = bytes_from_cstr(key_cstr);
+        let key = bytes_from_cstr(key_cstr);
 
-    match heap.inner.get(&key) {
-        Ok(Some(value)) => to_cstr(&value),
-        Ok(None) => {
-            errno::set_errno(errno::Errno(ERR_NOT_FOUND));
-            std::ptr::null()
-        }
-        Err(e) => {
-            println!("zomdb: heap.get: {:?}", e);
-            errno::set_errno(to_errno(e));
-            std::ptr::null()
+        match heap.lock().get(&key) {
+            Ok(Some(value)) => to_cstr(&value).unwrap_or(std::ptr::null()),
+            Ok(None) => {
+                fail(ZomdbErrorCode::NotFound, "key not found".to_string());
+                std::ptr::null()
+            }
+            Err(e) => {
+                log!(ZOMDB_LOG_ERROR, "heap.get: {:?}", e);
+                set_error(e);
+                std::ptr::null()
+            }
         }
-    }
+    })
+    .unwrap_or(std::ptr::null())
 }
 
 /// Set a key and value in the heap.
 ///
-/// If an error occurs, the global errno will be set to the appropriate error.
+/// Returns 0 if the tuple was written, or the code of the error that
+/// occurred. Afterwards, zomdb_last_error returns the same code.
 ///
 /// The accepted key and value are null-terminated strings. Any calling code
 /// must therefore guarantee that no null bytes are present in the key or
 /// value.
+///
+/// Once this function returns, the tuple is visible to other readers of the
+/// file, but it may not have reached the disk yet. Call zomdb_heap_sync to
+/// make it durable.
+///
+/// # Safety
+///
+/// The heap pointer must have been returned by zomdb_heap_create and not yet
+/// been destroyed.
 #[no_mangle]
-pub unsafe extern "C" fn heap_set(
+pub unsafe extern "C" fn zomdb_heap_set(
     ptr: *mut Heap,
     key_cstr: *const ffi::c_char,
     value_cstr: *const ffi::c_char,
-) {
-    let heap = unsafe { &mut *ptr };
+) -> ZomdbErrorCode {
+    catch_panic(|| {
+        // Callers written before this returned a code still consult
+        // zomdb_last_error, so success resets it.
+        clear_last_error();
+        check_null!(ZomdbErrorCode::NullArgument; ptr, key_cstr, value_cstr);
+        check_handle!(ZomdbErrorCode::InvalidHandle; ptr as Heap);
+        let heap = unsafe { &*ptr };
+        if let Err(code) = heap.check_writable() {
+            return code;
+        }
 
-    let key = bytes_from_cstr(key_cstr);
-    let value = bytes_from_cstr(value_cstr);
+        let key = bytes_from_cstr(key_cstr);
+        let value = bytes_from_cstr(value_cstr);
 
-    match heap.inner.put(&key, &value) {
-        Ok(_) => {}
-        Err(e) => {
-            println!("zomdb: heap.put: {:?}", e);
-            errno::set_errno(to_errno(e));
+        match heap.lock().put(&key, &value) {
+            Ok(_) => ZomdbErrorCode::Ok,
+            Err(e) => {
+                log!(ZOMDB_LOG_ERROR, "heap.put: {:?}", e);
+                set_error(e)
+            }
         }
-    };
+    })
+    .unwrap_or(ZomdbErrorCode::Panic)
 }
 
+/// Get a value from the heap by a key of arbitrary bytes.
+///
+/// Returns 0 if the key was found, in which case the value and its length
+/// are written to the out parameters. The value must be released with
+/// zomdb_free_bytes. Returns ERR_NOT_FOUND if the key was not found, or
+/// the code of any other error.
+///
+/// # Safety
+///
+/// The heap pointer must have been returned by zomdb_heap_create and not yet
+/// been destroyed. The key pointer must point to key_len readable bytes, and
+/// the out parameters must be valid for writes.
 #[no_mangle]
-pub unsafe extern "C" fn destroy_heap(ptr: *mut Heap) {
-    let heap = unsafe { Box::from_raw(ptr) };
-    drop(heap);
+pub unsafe extern "C" fn zomdb_heap_get2(
+    ptr: *mut Heap,
+    key_ptr: *const u8,
+    key_len: usize,
+    out_value_ptr: *mut *mut u8,
+    out_value_len: *mut usize,
+) -> ZomdbErrorCode {
+    catch_panic(|| {
+        check_null!(ZomdbErrorCode::NullArgument; ptr, key_ptr if key_len > 0, out_value_ptr, out_value_len);
+        check_handle!(ZomdbErrorCode::InvalidHandle; ptr as Heap);
+        let heap = unsafe { &*ptr };
+        let key = unsafe { from_raw_parts(key_ptr, key_len) };
+
+        match heap.lock().get(key) {
+            Ok(Some(value)) => {
+                let (value_ptr, value_len) = match to_raw_parts(value) {
+                    Ok(parts) => parts,
+                    Err(code) => return code,
+                };
+                unsafe {
+                    *out_value_ptr = value_ptr;
+                    *out_value_len = value_len;
+                }
+                ZomdbErrorCode::Ok
+            }
+            Ok(None) => fail(ZomdbErrorCode::NotFound, "key not found".to_string()),
+            Err(e) => {
+                log!(ZOMDB_LOG_ERROR, "heap.get: {:?}", e);
+                set_error(e)
+            }
+        }
+    })
+    .unwrap_or(ZomdbErrorCode::Panic)
 }
 
+/// Get a value from the heap by a key of arbitrary bytes into a buffer
+/// provided by the caller.
+///
+/// Returns 0 if the key was found and its value fit into the buffer. The
+/// length of the value is written to out_len whenever the key was found,
+/// also if the buffer was too small, in which case ERR_BUFFER_TOO_SMALL is
+/// returned and the buffer is left untouched. Passing a buffer of length
+/// zero queries the length of the value. Returns ERR_NOT_FOUND if the key
+/// was not found, or the code of any other error.
+///
+/// # Safety
+///
+/// The heap pointer must have been returned by zomdb_heap_create and not yet
+/// been destroyed. The key pointer must point to key_len readable bytes, the
+/// buffer must point to buf_len writable bytes, and out_len must be valid
+/// for writes.
 #[no_mangle]
-pub extern "C" fn heap_iter(ptr: *mut Heap) -> *mut HeapIter<'static> {
-    let heap = unsafe { &mut *ptr };
-    let iter = heap.inner.iter();
+pub unsafe extern "C" fn zomdb_heap_get_into(
+    ptr: *mut Heap,
+    key_ptr: *const u8,
+    key_len: usize,
+    buf: *mut u8,
+    buf_len: usize,
+    out_len: *mut usize,
+) -> ZomdbErrorCode {
+    catch_panic(|| {
+        check_null!(ZomdbErrorCode::NullArgument; ptr, key_ptr if key_len > 0, buf if buf_len > 0, out_len);
+        check_handle!(ZomdbErrorCode::InvalidHandle; ptr as Heap);
+        let heap = unsafe { &*ptr };
+        let key = unsafe { from_raw_parts(key_ptr, key_len) };
 
-    unsafe { transmute(Box::new(HeapIter { inner: iter })) }
+        let copied = heap.lock().get_with(key, |value| {
+            unsafe { *out_len = value.len() };
+            if value.len() > buf_len {
+                return false;
+            }
+            if !value.is_empty() {
+                unsafe { slice::from_raw_parts_mut(buf, value.len()) }.copy_from_slice(value);
+            }
+            true
+        });
+        match copied {
+            Ok(Some(true)) => ZomdbErrorCode::Ok,
+            Ok(Some(false)) => fail(
+                ZomdbErrorCode::BufferTooSmall,
+                format!(
+                    "value of {} bytes doesn't fit into {} bytes",
+                    unsafe { *out_len },
+                    buf_len
+                ),
+            ),
+            Ok(None) => fail(ZomdbErrorCode::NotFound, "key not found".to_string()),
+            Err(e) => {
+                log!(ZOMDB_LOG_ERROR, "heap.get_with: {:?}", e);
+                set_error(e)
+            }
+        }
+    })
+    .unwrap_or(ZomdbErrorCode::Panic)
 }
 
-/// Can be used to iterate a Heap structure.
+/// Set a key and value of arbitrary bytes in the heap.
+///
+/// Returns 0 on success or the code of the error that occurred.
+///
+/// # Safety
 ///
-/// Use heap_iter to create an instance of this struct from a Heap.
-pub struct HeapIter<'a> {
-    inner: zomdb::Iter<'a>,
+/// The heap pointer must have been returned by zomdb_heap_create and not yet
+/// been destroyed. The key and value pointers must point to key_len and
+/// value_len readable bytes respectively.
+#[no_mangle]
+pub unsafe extern "C" fn zomdb_heap_set2(
+    ptr: *mut Heap,
+    key_ptr: *const u8,
+    key_len: usize,
+    value_ptr: *const u8,
+    value_len: usize,
+) -> ZomdbErrorCode {
+    catch_panic(|| {
+        check_null!(ZomdbErrorCode::NullArgument; ptr, key_ptr if key_len > 0, value_ptr if value_len > 0);
+        check_handle!(ZomdbErrorCode::InvalidHandle; ptr as Heap);
+        let heap = unsafe { &*ptr };
+        if let Err(code) = heap.check_writable() {
+            return code;
+        }
+        let key = unsafe { from_raw_parts(key_ptr, key_len) };
+        let value = unsafe { from_raw_parts(value_ptr, value_len) };
+
+        match heap.lock().put(key, value) {
+            Ok(_) => ZomdbErrorCode::Ok,
+            Err(e) => {
+                log!(ZOMDB_LOG_ERROR, "heap.put: {:?}", e);
+                set_error(e)
+            }
+        }
+    })
+    .unwrap_or(ZomdbErrorCode::Panic)
 }
 
+/// Set multiple keys and values of arbitrary bytes in the heap at once.
+///
+/// The i-th entry consists of the key keys[i] of length key_lens[i] and the
+/// value values[i] of length value_lens[i]. All entries are validated before
+/// anything is written, so an invalid entry leaves the heap untouched.
+///
+/// Returns the number of entries written, or the negated code of the error
+/// that occurred. The last error message names the index of an invalid
+/// entry.
+///
+/// # Safety
+///
+/// The heap pointer must have been returned by zomdb_heap_create and not yet
+/// been destroyed. All four arrays must hold count elements, and every key
+/// and value pointer must point to as many readable bytes as its length says.
 #[no_mangle]
-pub unsafe extern "C" fn heap_iter_next(ptr: *mut HeapIter) -> *const HeapTuple {
-    let iter = unsafe { &mut *ptr };
+pub unsafe extern "C" fn zomdb_heap_put_many(
+    ptr: *mut Heap,
+    keys: *const *const u8,
+    key_lens: *const usize,
+    values: *const *const u8,
+    value_lens: *const usize,
+    count: usize,
+) -> isize {
+    catch_panic(|| {
+        check_null!(-(ZomdbErrorCode::NullArgument as isize); ptr, keys if count > 0, key_lens if count > 0, values if count > 0, value_lens if count > 0);
+        check_handle!(-(ZomdbErrorCode::InvalidHandle as isize); ptr as Heap);
+        let heap = unsafe { &*ptr };
+        if let Err(code) = heap.check_writable() {
+            return -(code as isize);
+        }
 
-    match iter.inner.next() {
-        Some(Ok(tuple)) => {
-            let tuple = HeapTuple {
-                key: to_cstr(&tuple.key),
-                value: to_cstr(&tuple.value),
-            };
-            unsafe { transmute(Box::new(tuple)) }
+        let mut tuples = Vec::with_capacity(count);
+        for i in 0..count {
+            let (key_ptr, key_len) = unsafe { (*keys.add(i), *key_lens.add(i)) };
+            let (value_ptr, value_len) = unsafe { (*values.add(i), *value_lens.add(i)) };
+            if (key_ptr.is_null() && key_len > 0) || (value_ptr.is_null() && value_len > 0) {
+                let message = format!("entry {}: key and value must not be null", i);
+                return -(fail(ZomdbErrorCode::NullArgument, message) as isize);
+            }
+            let key = unsafe { from_raw_parts(key_ptr, key_len) };
+            let value = unsafe { from_raw_parts(value_ptr, value_len) };
+            if let Err(e) = zomdb::Heap::validate(key, value) {
+                log!(ZOMDB_LOG_ERROR, "zomdb_heap_put_many: entry {}: {:?}", i, e);
+                let message = format!("entry {}: {}", i, e);
+                return -(fail(error_code(&e), message) as isize);
+            }
+            tuples.push((key, value));
         }
-        Some(Err(e)) => {
-            println!("zomdb: heap_iter.next: {:?}", e);
-            errno::set_errno(to_errno(e));
-            std::ptr::null()
+
+        match heap.lock().put_many(tuples) {
+            Ok(_) => count as isize,
+            Err(e) => {
+                log!(ZOMDB_LOG_ERROR, "heap.put_many: {:?}", e);
+                -(set_error(e) as isize)
+            }
         }
-        None => std::ptr::null(),
-    }
+    })
+    .unwrap_or(-(ZomdbErrorCode::Panic as isize))
 }
 
-/// HeapTuple is a key-value pair from a Heap.
+/// Delete a key of arbitrary bytes from the heap.
+///
+/// Returns 0 if the key was deleted, ERR_NOT_FOUND if it had no value, or
+/// the code of any other error.
+///
+/// # Safety
+///
+/// The heap pointer must have been returned by zomdb_heap_create and not yet
+/// been destroyed. The key pointer must point to key_len readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn zomdb_heap_delete(
+    ptr: *mut Heap,
+    key_ptr: *const u8,
+    key_len: usize,
+) -> ZomdbErrorCode {
+    catch_panic(|| {
+        check_null!(ZomdbErrorCode::NullArgument; ptr, key_ptr if key_len > 0);
+        check_handle!(ZomdbErrorCode::InvalidHandle; ptr as Heap);
+        let heap = unsafe { &*ptr };
+        if let Err(code) = heap.check_writable() {
+            return code;
+        }
+        let key = unsafe { from_raw_parts(key_ptr, key_len) };
+
+        match heap.lock().delete(key) {
+            Ok(true) => ZomdbErrorCode::Ok,
+            Ok(false) => fail(ZomdbErrorCode::NotFound, "key not found".to_string()),
+            Err(e) => {
+                log!(ZOMDB_LOG_ERROR, "heap.delete: {:?}", e);
+                set_error(e)
+            }
+        }
+    })
+    .unwrap_or(ZomdbErrorCode::Panic)
+}
+
+/// Check whether a key of arbitrary bytes has a value in the heap.
+///
+/// Returns 1 if it has, 0 if it hasn't, or the negated code of the error
+/// that occurred. The value is not copied.
+///
+/// # Safety
+///
+/// The heap pointer must have been returned by zomdb_heap_create and not yet
+/// been destroyed. The key pointer must point to key_len readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn zomdb_heap_contains(
+    ptr: *mut Heap,
+    key_ptr: *const u8,
+    key_len: usize,
+) -> ffi::c_int {
+    catch_panic(|| {
+        check_null!(-(ZomdbErrorCode::NullArgument as ffi::c_int); ptr, key_ptr if key_len > 0);
+        check_handle!(-(ZomdbErrorCode::InvalidHandle as ffi::c_int); ptr as Heap);
+        let heap = unsafe { &*ptr };
+        let key = unsafe { from_raw_parts(key_ptr, key_len) };
+
+        match heap.lock().contains(key) {
+            Ok(found) => found as ffi::c_int,
+            Err(e) => {
+                log!(ZOMDB_LOG_ERROR, "heap.contains: {:?}", e);
+                -(set_error(e) as ffi::c_int)
+            }
+        }
+    })
+    .unwrap_or(-(ZomdbErrorCode::Panic as ffi::c_int))
+}
+
+/// Count the keys that have a value in the heap.
+///
+/// Returns 0 and writes the count to out_count on success, or returns the
+/// code of the error that occurred. This scans the whole heap without copying
+/// values.
+///
+/// # Safety
+///
+/// The heap pointer must have been returned by zomdb_heap_create and not yet
+/// been destroyed. The out parameter must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn zomdb_heap_count(ptr: *mut Heap, out_count: *mut u64) -> ZomdbErrorCode {
+    catch_panic(|| {
+        check_null!(ZomdbErrorCode::NullArgument; ptr, out_count);
+        check_handle!(ZomdbErrorCode::InvalidHandle; ptr as Heap);
+        let heap = unsafe { &*ptr };
+
+        match heap.lock().len() {
+            Ok(count) => {
+                unsafe { *out_count = count as u64 };
+                ZomdbErrorCode::Ok
+            }
+            Err(e) => {
+                log!(ZOMDB_LOG_ERROR, "heap.len: {:?}", e);
+                set_error(e)
+            }
+        }
+    })
+    .unwrap_or(ZomdbErrorCode::Panic)
+}
+
+/// Flush all tuples written to the heap to disk.
+///
+/// Returns 0 on success or the code of the error that occurred. Calling it
+/// without any writes since the last call is allowed.
+///
+/// # Safety
+///
+/// The heap pointer must have been returned by zomdb_heap_create and not yet
+/// been destroyed.
+#[no_mangle]
+pub unsafe extern "C" fn zomdb_heap_sync(ptr: *mut Heap) -> ZomdbErrorCode {
+    catch_panic(|| {
+        check_null!(ZomdbErrorCode::NullArgument; ptr);
+        check_handle!(ZomdbErrorCode::InvalidHandle; ptr as Heap);
+        let heap = unsafe { &*ptr };
+
+        match heap.lock().sync() {
+            Ok(_) => ZomdbErrorCode::Ok,
+            Err(e) => {
+                log!(ZOMDB_LOG_ERROR, "heap.sync: {:?}", e);
+                set_error(e)
+            }
+        }
+    })
+    .unwrap_or(ZomdbErrorCode::Panic)
+}
+
+/// Compact the heap such that only the latest value of each key remains.
+///
+/// Returns 0 and writes the report to out_report on success, or returns the
+/// code of the error that occurred. Returns ERR_BUSY without compacting while
+/// an iterator created from this heap hasn't been destroyed yet.
+///
+/// # Safety
+///
+/// The heap pointer must have been returned by zomdb_heap_create and not yet
+/// been destroyed. The out parameter must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn zomdb_heap_compact(
+    ptr: *mut Heap,
+    out_report: *mut HeapCompactionReport,
+) -> ZomdbErrorCode {
+    catch_panic(|| {
+        check_null!(ZomdbErrorCode::NullArgument; ptr, out_report);
+        check_handle!(ZomdbErrorCode::InvalidHandle; ptr as Heap);
+        let heap = unsafe { &*ptr };
+        if let Err(code) = heap.check_writable() {
+            return code;
+        }
+
+        // Iterators are only created while holding the lock, so none can
+        // appear between the check and the compaction.
+        let mut inner = heap.lock();
+        if heap.iterators.load(Ordering::Relaxed) > 0 {
+            log!(ZOMDB_LOG_WARN, "heap.compact: iterators still alive");
+            return fail(
+                ZomdbErrorCode::Busy,
+                "iterators of the heap are still alive".to_string(),
+            );
+        }
+
+        match inner.compact() {
+            Ok(report) => {
+                unsafe {
+                    *out_report = HeapCompactionReport {
+                        bytes_before: report.bytes_before,
+                        bytes_after: report.bytes_after,
+                        records_dropped: report.records_dropped,
+                    }
+                };
+                ZomdbErrorCode::Ok
+            }
+            Err(e) => {
+                log!(ZOMDB_LOG_ERROR, "heap.compact: {:?}", e);
+                set_error(e)
+            }
+        }
+    })
+    .unwrap_or(ZomdbErrorCode::Panic)
+}
+
+/// HeapCompactionReport describes the outcome of zomdb_heap_compact.
 #[repr(C)]
-pub struct HeapTuple {
-    key: *const ffi::c_char,
-    value: *const ffi::c_char,
+pub struct HeapCompactionReport {
+    /// Size of the heap file before compaction.
+    pub bytes_before: u64,
+    /// Size of the heap file after compaction.
+    pub bytes_after: u64,
+    /// Number of stale or deleted tuples that were dropped.
+    pub records_dropped: u64,
 }
 
+/// Collect statistics about the tuples stored in the heap.
+///
+/// Returns 0 and writes the statistics to out on success, or returns the
+/// code of the error that occurred. This scans the whole heap.
+///
+/// # Safety
+///
+/// The heap pointer must have been returned by zomdb_heap_create and not yet
+/// been destroyed. The out parameter must be valid for writes.
 #[no_mangle]
-pub extern "C" fn heap_iter_destroy(ptr: *mut HeapIter) {
-    let iter = unsafe { Box::from_raw(ptr) };
-    drop(iter);
+pub unsafe extern "C" fn zomdb_heap_stats(ptr: *mut Heap, out: *mut HeapStats) -> ZomdbErrorCode {
+    catch_panic(|| {
+        check_null!(ZomdbErrorCode::NullArgument; ptr, out);
+        check_handle!(ZomdbErrorCode::InvalidHandle; ptr as Heap);
+        let heap = unsafe { &*ptr };
+
+        match heap.lock().stats() {
+            Ok(stats) => {
+                unsafe { *out = HeapStats::from(stats) };
+                ZomdbErrorCode::Ok
+            }
+            Err(e) => {
+                log!(ZOMDB_LOG_ERROR, "heap.stats: {:?}", e);
+                set_error(e)
+            }
+        }
+    })
+    .unwrap_or(ZomdbErrorCode::Panic)
 }
 
-unsafe fn string_from_cstr(s: *const ffi::c_char) -> Result<String, zomdb::InputError> {
-    let cstr = unsafe { ffi::CStr::from_ptr(s) };
-    let s = cstr.to_str().map_err(zomdb::InputError::Utf8)?;
-    Ok(s.to_string())
+/// HeapStats holds statistics about the tuples stored in a heap.
+#[repr(C)]
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct HeapStats {
+    /// Size of the heap file in bytes.
+    pub file_size: u64,
+    /// Number of tuples stored, including stale ones and tombstones.
+    pub total_records: u64,
+    /// Number of keys that have a value.
+    pub live_keys: u64,
+    /// Number of tuples that compaction would drop.
+    pub stale_records: u64,
+    /// Number of bytes that compaction would free.
+    pub dead_bytes: u64,
 }
 
-unsafe fn bytes_from_cstr(s: *const ffi::c_char) -> Vec<u8> {
-    let cstr = unsafe { ffi::CStr::from_ptr(s) };
-    cstr.to_bytes().to_vec()
+impl From<zomdb::HeapStats> for HeapStats {
+    fn from(stats: zomdb::HeapStats) -> Self {
+        Self {
+            file_size: stats.file_size,
+            total_records: stats.total_records,
+            live_keys: stats.live_keys,
+            stale_records: stats.stale_records,
+            dead_bytes: stats.dead_bytes,
+        }
+    }
 }
 
-unsafe fn to_cstr(s: &[u8]) -> *const ffi::c_char {
-    let cstr = ffi::CString::new(s).unwrap();
-    cstr.into_raw()
+impl HeapStats {
+    /// Formats the statistics as a JSON object with a key for every field.
+    fn to_json(&self) -> String {
+        format!(
+            "{{\"file_size\":{},\"total_records\":{},\"live_keys\":{},\"stale_records\":{},\"dead_bytes\":{}}}",
+            self.file_size, self.total_records, self.live_keys, self.stale_records, self.dead_bytes
+        )
+    }
 }
 
-/// Error code for keys that could not be found.
-pub const ERR_NOT_FOUND: i32 = 1;
+/// Collect statistics about the tuples stored in the heap as a JSON object.
+///
+/// The object holds the fields of HeapStats under their names: file_size,
+/// total_records, live_keys, stale_records and dead_bytes, all of them
+/// integers. These keys stay stable, and statistics added later only appear
+/// here under new keys, so consumers should ignore keys they don't know.
+///
+/// Returns a null-terminated string that must be released with
+/// zomdb_free_value, or null if the statistics could not be collected, in
+/// which case zomdb_last_error returns the code of the error. This scans the
+/// whole heap.
+///
+/// # Safety
+///
+/// The heap pointer must have been returned by zomdb_heap_create and not yet
+/// been destroyed.
+#[no_mangle]
+pub unsafe extern "C" fn zomdb_heap_stats_json(ptr: *mut Heap) -> *mut ffi::c_char {
+    catch_panic(|| {
+        check_null!(std::ptr::null_mut(); ptr);
+        check_handle!(std::ptr::null_mut(); ptr as Heap);
+        let heap = unsafe { &*ptr };
 
-/// Error code for I/O errors.
-pub const ERR_IO: i32 = 10;
+        match heap.lock().stats() {
+            Ok(stats) => match to_cstr(HeapStats::from(stats).to_json().as_bytes()) {
+                Ok(json) => json as *mut ffi::c_char,
+                Err(_) => std::ptr::null_mut(),
+            },
+            Err(e) => {
+                log!(ZOMDB_LOG_ERROR, "heap.stats: {:?}", e);
+                set_error(e);
+                std::ptr::null_mut()
+            }
+        }
+    })
+    .unwrap_or(std::ptr::null_mut())
+}
 
-/// Error code for invalid UTF-8.
-/// Type of an input error.
-pub const ERR_UTF8: i32 = 30;
+/// Check that all tuples stored in the heap can be read.
+///
+/// Returns 0 and writes the findings to out if the check ran, even if it
+/// found corrupted bytes. Returns the code of the error that occurred if the
+/// heap could not be read. This scans the whole heap.
+///
+/// # Safety
+///
+/// The heap pointer must have been returned by zomdb_heap_create and not yet
+/// been destroyed. The out parameter must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn zomdb_heap_verify(
+    ptr: *mut Heap,
+    out: *mut HeapVerifyReport,
+) -> ZomdbErrorCode {
+    catch_panic(|| {
+        check_null!(ZomdbErrorCode::NullArgument; ptr, out);
+        check_handle!(ZomdbErrorCode::InvalidHandle; ptr as Heap);
+        let heap = unsafe { &*ptr };
 
-/// Error code for invalid key size.
-/// Type of an input error.
-pub const ERR_KEY_SIZE: i32 = 31;
+        match heap.lock().verify() {
+            Ok(report) => {
+                unsafe { *out = HeapVerifyReport::from(report) };
+                ZomdbErrorCode::Ok
+            }
+            Err(e) => {
+                log!(ZOMDB_LOG_ERROR, "heap.verify: {:?}", e);
+                set_error(e)
+            }
+        }
+    })
+    .unwrap_or(ZomdbErrorCode::Panic)
+}
 
-/// Error code for invalid value size.
-/// Type of an input error.
-pub const ERR_VALUE_SIZE: i32 = 32;
+/// HeapVerifyReport holds the findings of zomdb_heap_verify.
+#[repr(C)]
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct HeapVerifyReport {
+    /// Number of tuples that could be read, counting from the end of the
+    /// file.
+    pub valid_records: u64,
+    /// Offset at which the scan from the end of the file ran into corrupted
+    /// bytes, or UINT64_MAX if the heap is intact. Tuples in front of it
+    /// can't be read.
+    pub first_corrupt_offset: u64,
+    /// 1 if the file doesn't end with a complete tuple, like after a write
+    /// that was cut short, 0 otherwise.
+    pub truncated_tail: u8,
+}
 
-/// Error code for data errors.
-/// Indicates that data on disk is corrupted.
-pub const ERR_DATA: i32 = 50;
+impl From<zomdb::VerifyReport> for HeapVerifyReport {
+    fn from(report: zomdb::VerifyReport) -> Self {
+        Self {
+            valid_records: report.valid_records,
+            first_corrupt_offset: report.first_corrupt_offset.unwrap_or(u64::MAX),
+            truncated_tail: report.truncated_tail as u8,
+        }
+    }
+}
 
-fn to_errno(e: zomdb::Error) -> errno::Errno {
-    let no = match e {
-        zomdb::Error::IO(_) => ERR_IO,
-        zomdb::Error::Input(zomdb::InputError::Utf8(_)) => ERR_UTF8,
-        zomdb::Error::Input(zomdb::InputError::KeySize(_)) => ERR_KEY_SIZE,
-        zomdb::Error::Input(zomdb::InputError::ValueSize(_)) => ERR_VALUE_SIZE,
-        zomdb::Error::Data(_) => ERR_DATA,
-    };
+/// Copy the heap to a new file at the given path.
+///
+/// The path consists of dest_len bytes like for zomdb_heap_open and must not
+/// exist yet. The copy holds the tuples that were complete when the backup
+/// started and can be opened as a heap. The heap can be used from other
+/// threads while it is copied, but their writes aren't included.
+///
+/// Returns the number of bytes copied, or the negated code of the error that
+/// occurred. A partial copy is removed on failure.
+///
+/// # Safety
+///
+/// The heap pointer must have been returned by zomdb_heap_create and not yet
+/// been destroyed. The path pointer must point to dest_len readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn zomdb_heap_backup(
+    ptr: *mut Heap,
+    dest_ptr: *const u8,
+    dest_len: usize,
+) -> i64 {
+    catch_panic(|| {
+        check_null!(-(ZomdbErrorCode::NullArgument as i64); ptr, dest_ptr if dest_len > 0);
+        check_handle!(-(ZomdbErrorCode::InvalidHandle as i64); ptr as Heap);
+        let heap = unsafe { &*ptr };
+        let dest = match path_from_bytes(unsafe { from_raw_parts(dest_ptr, dest_len) }) {
+            Ok(dest) => dest,
+            Err(e) => return -(utf8_error("dest", e) as i64),
+        };
+
+        // Copy through a reader, so that the heap isn't locked meanwhile.
+        let reader = heap.lock().reader();
+        let result = reader.and_then(|reader| reader.backup_to(&dest));
+        match result {
+            Ok(len) => len as i64,
+            Err(e) => {
+                log!(ZOMDB_LOG_ERROR, "heap.backup_to: {:?}", e);
+                -(set_error(e) as i64)
+            }
+        }
+    })
+    .unwrap_or(-(ZomdbErrorCode::Panic as i64))
+}
+
+/// Release a value returned by zomdb_heap_get, or a string returned by
+/// zomdb_heap_stats_json.
+///
+/// Passing null is allowed and does nothing.
+///
+/// # Safety
+///
+/// The pointer must have been returned by zomdb_heap_get or
+/// zomdb_heap_stats_json and must not have been released before.
+#[no_mangle]
+pub unsafe extern "C" fn zomdb_free_value(ptr: *mut ffi::c_char) {
+    let _ = catch_panic(|| {
+        if ptr.is_null() {
+            return;
+        }
+        let len = unsafe { ffi::CStr::from_ptr(ptr) }
+            .to_bytes_with_nul()
+            .len();
+        unsafe { alloc::free(ptr.cast(), len) };
+    });
+}
+
+/// Release a buffer of bytes returned by the heap.
+///
+/// Passing null is allowed and does nothing.
+///
+/// # Safety
+///
+/// The pointer and length must have been returned together by a function
+/// of this library, like zomdb_heap_get2, and the buffer must not have been
+/// released before.
+#[no_mangle]
+pub unsafe extern "C" fn zomdb_free_bytes(ptr: *mut u8, len: usize) {
+    let _ = catch_panic(|| {
+        if ptr.is_null() {
+            return;
+        }
+        unsafe { alloc::free(ptr, len) };
+    });
+}
+
+/// Close the heap and release its resources.
+///
+/// # Safety
+///
+/// The heap pointer must have been returned by zomdb_heap_create and not yet
+/// been destroyed. It must not be used after this call.
+#[no_mangle]
+pub unsafe extern "C" fn zomdb_heap_destroy(ptr: *mut Heap) {
+    let _ = catch_panic(|| {
+        if ptr.is_null() {
+            return;
+        }
+        if !handles::unregister(ptr, Kind::Heap) {
+            invalid_handle("ptr", Kind::Heap);
+            return;
+        }
+        let heap = unsafe { Box::from_raw(ptr) };
+        drop(heap);
+    });
+}
 
-    errno::Errno(no)
+/// Sync the heap to disk, then close it and release its resources.
+///
+/// Returns 0 if the heap was synced, or the code of the error that
+/// occurred. The heap is released either way, unlike with zomdb_heap_sync,
+/// so callers learn whether their writes are durable without having to keep
+/// the heap open.
+///
+/// # Safety
+///
+/// The heap pointer must have been returned by zomdb_heap_create and not yet
+/// been destroyed. It must not be used after this call.
+#[no_mangle]
+pub unsafe extern "C" fn zomdb_heap_close(ptr: *mut Heap) -> ZomdbErrorCode {
+    catch_panic(|| {
+        check_null!(ZomdbErrorCode::NullArgument; ptr);
+        if !handles::unregister(ptr, Kind::Heap) {
+            return invalid_handle("ptr", Kind::Heap);
+        }
+        let heap = unsafe { Box::from_raw(ptr) };
+
+        let result = heap.lock().sync();
+        match result {
+            Ok(_) => ZomdbErrorCode::Ok,
+            Err(e) => {
+                log!(ZOMDB_LOG_ERROR, "heap.sync: {:?}", e);
+                set_error(e)
+            }
+        }
+    })
+    .unwrap_or(ZomdbErrorCode::Panic)
+}
+
+/// Create an iterator over the heap.
+///
+/// The iterator reads the heap through its own file handle, so it stays
+/// valid after the heap was destroyed: heap and iterators can be destroyed
+/// in any order. It sees the tuples written before the first call to
+/// zomdb_heap_iter_next.
+///
+/// Returns null if the iterator could not be created, in which case
+/// zomdb_last_error returns the code of the error.
+///
+/// # Safety
+///
+/// The heap pointer must have been returned by zomdb_heap_create and not yet
+/// been destroyed.
+#[no_mangle]
+pub unsafe extern "C" fn zomdb_heap_iter(ptr: *mut Heap) -> *mut HeapIter {
+    catch_panic(|| {
+        check_null!(std::ptr::null_mut(); ptr);
+        check_handle!(std::ptr::null_mut(); ptr as Heap);
+        let heap = unsafe { &*ptr };
+        match heap.lock().reader() {
+            Ok(reader) => HeapIter::create(heap, reader.into_iter()),
+            Err(e) => {
+                log!(ZOMDB_LOG_ERROR, "heap.reader: {:?}", e);
+                set_error(e);
+                std::ptr::null_mut()
+            }
+        }
+    })
+    .unwrap_or(std::ptr::null_mut())
+}
+
+/// Create an iterator over the tuples of the heap whose keys start with the
+/// given prefix of arbitrary bytes.
+///
+/// The iterator is used and destroyed like one created by zomdb_heap_iter. An
+/// empty prefix matches all keys.
+///
+/// # Safety
+///
+/// The heap pointer must have been returned by zomdb_heap_create and not yet
+/// been destroyed. The prefix pointer must point to prefix_len readable
+/// bytes.
+#[no_mangle]
+pub unsafe extern "C" fn zomdb_heap_iter_prefix(
+    ptr: *mut Heap,
+    prefix_ptr: *const u8,
+    prefix_len: usize,
+) -> *mut HeapIter {
+    catch_panic(|| {
+        check_null!(std::ptr::null_mut(); ptr, prefix_ptr if prefix_len > 0);
+        check_handle!(std::ptr::null_mut(); ptr as Heap);
+        let heap = unsafe { &*ptr };
+        let prefix = unsafe { from_raw_parts(prefix_ptr, prefix_len) };
+        match heap.lock().reader() {
+            Ok(reader) => HeapIter::create(heap, reader.into_scan_prefix(prefix)),
+            Err(e) => {
+                log!(ZOMDB_LOG_ERROR, "heap.reader: {:?}", e);
+                set_error(e);
+                std::ptr::null_mut()
+            }
+        }
+    })
+    .unwrap_or(std::ptr::null_mut())
+}
+
+/// Callback invoked by zomdb_heap_for_each for every tuple.
+///
+/// The key and value pointers are only valid for the duration of the call.
+/// Returning a non-zero value stops the iteration.
+pub type HeapForEachCallback = extern "C" fn(
+    key: *const u8,
+    key_len: usize,
+    value: *const u8,
+    value_len: usize,
+    user_data: *mut ffi::c_void,
+) -> ffi::c_int;
+
+/// Call the callback for every tuple of the heap, starting from the last
+/// inserted one.
+///
+/// Unlike iterators, this doesn't allocate memory that the caller has to
+/// release. user_data is passed through to the callback unchanged.
+///
+/// Returns 0 once all tuples were visited or the callback stopped the
+/// iteration, or the code of the error that occurred.
+///
+/// # Safety
+///
+/// The heap pointer must have been returned by zomdb_heap_create and not yet
+/// been destroyed. The heap stays locked while the callback runs, so the
+/// callback must not use the heap.
+#[no_mangle]
+pub unsafe extern "C" fn zomdb_heap_for_each(
+    ptr: *mut Heap,
+    callback: Option<HeapForEachCallback>,
+    user_data: *mut ffi::c_void,
+) -> ZomdbErrorCode {
+    check_null!(ZomdbErrorCode::NullArgument; ptr);
+    check_handle!(ZomdbErrorCode::InvalidHandle; ptr as Heap);
+    let Some(callback) = callback else {
+        return null_argument("callback");
+    };
+    let heap = unsafe { &*ptr };
+
+    let result = catch_panic(|| {
+        for tuple in heap.lock().iter() {
+            let tuple = tuple?;
+            let stop = callback(
+                tuple.key.as_ptr(),
+                tuple.key.len(),
+                tuple.value.as_ptr(),
+                tuple.value.len(),
+                user_data,
+            );
+            if stop != 0 {
+                break;
+            }
+        }
+        Ok(())
+    });
+
+    match result {
+        Some(Ok(())) => ZomdbErrorCode::Ok,
+        Some(Err(e)) => {
+            log!(ZOMDB_LOG_ERROR, "heap.iter: {:?}", e);
+            set_error(e)
+        }
+        None => ZomdbErrorCode::Panic,
+    }
+}
+
+/// Can be used to iterate a Heap structure.
+///
+/// Use zomdb_heap_iter to create an instance of this struct from a Heap.
+/// Unlike the Heap, an iterator and its tuples must only be used by one
+/// thread at a time.
+pub struct HeapIter {
+    // The iterator owns a reader of the heap, which shares nothing with the
+    // heap that destroying it could invalidate.
+    inner: zomdb::ReaderIter,
+    iterators: Arc<AtomicUsize>,
+}
+
+impl HeapIter {
+    fn create(heap: &Heap, inner: zomdb::ReaderIter) -> *mut Self {
+        heap.iterators.fetch_add(1, Ordering::Relaxed);
+
+        let ptr = Box::into_raw(Box::new(HeapIter {
+            inner,
+            iterators: heap.iterators.clone(),
+        }));
+        handles::register(ptr, Kind::Iter);
+        ptr
+    }
+}
+
+impl Drop for HeapIter {
+    fn drop(&mut self) {
+        self.iterators.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Advance the iterator and write the next tuple to out_tuple.
+///
+/// Returns 0 on success or the code of the error that occurred. Once the
+/// iterator is exhausted, 0 is returned and null is written to out_tuple.
+/// Keys and values are returned with their lengths, so they may contain
+/// null bytes. Every returned tuple must be released with
+/// zomdb_heap_tuple2_destroy.
+///
+/// # Safety
+///
+/// The iterator pointer must have been returned by zomdb_heap_iter and not
+/// yet been destroyed. The out parameter must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn zomdb_heap_iter_next2(
+    ptr: *mut HeapIter,
+    out_tuple: *mut *mut HeapTuple2,
+) -> ZomdbErrorCode {
+    catch_panic(|| {
+        check_null!(ZomdbErrorCode::NullArgument; ptr, out_tuple);
+        check_handle!(ZomdbErrorCode::InvalidHandle; ptr as Iter);
+
+        let tuple = match unsafe { next_tuple(ptr) } {
+            Ok(Some(tuple)) => tuple,
+            Ok(None) => {
+                unsafe { *out_tuple = std::ptr::null_mut() };
+                return ZomdbErrorCode::Ok;
+            }
+            Err(code) => return code,
+        };
+
+        let (key_ptr, key_len) = match to_raw_parts(tuple.key) {
+            Ok(parts) => parts,
+            Err(code) => return code,
+        };
+        let (value_ptr, value_len) = match to_raw_parts(tuple.value) {
+            Ok(parts) => parts,
+            Err(code) => {
+                unsafe { zomdb_free_bytes(key_ptr, key_len) };
+                return code;
+            }
+        };
+        let tuple = Box::into_raw(Box::new(HeapTuple2 {
+            key_ptr,
+            key_len,
+            value_ptr,
+            value_len,
+        }));
+        handles::register(tuple, Kind::Tuple2);
+        unsafe { *out_tuple = tuple };
+        ZomdbErrorCode::Ok
+    })
+    .unwrap_or(ZomdbErrorCode::Panic)
+}
+
+/// Advance the iterator and return the next tuple.
+///
+/// Returns null once the iterator is exhausted or if an error occurred.
+/// Afterwards, zomdb_last_error returns 0 in the first case and the code of
+/// the error in the second. Every returned tuple must be released with
+/// zomdb_heap_tuple_destroy.
+///
+/// Tuples whose key or value contain a null byte fail with ERR_NUL_BYTE.
+/// The iterator can still be advanced past them. Use zomdb_heap_iter_next2
+/// to read them.
+///
+/// # Safety
+///
+/// The iterator pointer must have been returned by zomdb_heap_iter and not
+/// yet been destroyed.
+#[no_mangle]
+pub unsafe extern "C" fn zomdb_heap_iter_next(ptr: *mut HeapIter) -> *const HeapTuple {
+    catch_panic(|| {
+        clear_last_error();
+        check_null!(std::ptr::null(); ptr);
+        check_handle!(std::ptr::null(); ptr as Iter);
+
+        let tuple = match unsafe { next_tuple(ptr) } {
+            Ok(Some(tuple)) => tuple,
+            Ok(None) | Err(_) => return std::ptr::null(),
+        };
+
+        let Ok(key) = to_cstr(&tuple.key) else {
+            return std::ptr::null();
+        };
+        let Ok(value) = to_cstr(&tuple.value) else {
+            unsafe { zomdb_free_value(key as *mut ffi::c_char) };
+            return std::ptr::null();
+        };
+        let tuple = Box::into_raw(Box::new(HeapTuple { key, value }));
+        handles::register(tuple, Kind::Tuple);
+        tuple
+    })
+    .unwrap_or(std::ptr::null())
+}
+
+/// Advances the iterator, which must have been checked already.
+unsafe fn next_tuple(ptr: *mut HeapIter) -> Result<Option<zomdb::HeapTuple>, ZomdbErrorCode> {
+    let iter = unsafe { &mut *ptr };
+    match iter.inner.next() {
+        Some(Ok(tuple)) => Ok(Some(tuple)),
+        Some(Err(e)) => {
+            log!(ZOMDB_LOG_ERROR, "heap_iter.next: {:?}", e);
+            Err(set_error(e))
+        }
+        None => Ok(None),
+    }
+}
+
+/// HeapTuple is a key-value pair from a Heap.
+///
+/// Prefer zomdb_heap_tuple_key and zomdb_heap_tuple_value over accessing the
+/// fields directly.
+#[repr(C)]
+pub struct HeapTuple {
+    key: *const ffi::c_char,
+    value: *const ffi::c_char,
+}
+
+/// Return the key of the tuple.
+///
+/// The key is owned by the tuple and released along with it.
+///
+/// # Safety
+///
+/// The tuple pointer must have been returned by zomdb_heap_iter_next and not
+/// yet been destroyed.
+#[no_mangle]
+pub unsafe extern "C" fn zomdb_heap_tuple_key(ptr: *const HeapTuple) -> *const ffi::c_char {
+    catch_panic(|| {
+        check_null!(std::ptr::null(); ptr);
+        check_handle!(std::ptr::null(); ptr as Tuple);
+        unsafe { (*ptr).key }
+    })
+    .unwrap_or(std::ptr::null())
+}
+
+/// Return the value of the tuple.
+///
+/// The value is owned by the tuple and released along with it.
+///
+/// # Safety
+///
+/// The tuple pointer must have been returned by zomdb_heap_iter_next and not
+/// yet been destroyed.
+#[no_mangle]
+pub unsafe extern "C" fn zomdb_heap_tuple_value(ptr: *const HeapTuple) -> *const ffi::c_char {
+    catch_panic(|| {
+        check_null!(std::ptr::null(); ptr);
+        check_handle!(std::ptr::null(); ptr as Tuple);
+        unsafe { (*ptr).value }
+    })
+    .unwrap_or(std::ptr::null())
+}
+
+/// Release the tuple along with its key and value.
+///
+/// Passing null is allowed and does nothing.
+///
+/// # Safety
+///
+/// The tuple pointer must have been returned by zomdb_heap_iter_next and not
+/// yet been destroyed. It must not be used after this call.
+#[no_mangle]
+pub unsafe extern "C" fn zomdb_heap_tuple_destroy(ptr: *mut HeapTuple) {
+    let _ = catch_panic(|| {
+        if ptr.is_null() {
+            return;
+        }
+        if !handles::unregister(ptr, Kind::Tuple) {
+            invalid_handle("ptr", Kind::Tuple);
+            return;
+        }
+        let tuple = unsafe { Box::from_raw(ptr) };
+        unsafe {
+            zomdb_free_value(tuple.key as *mut ffi::c_char);
+            zomdb_free_value(tuple.value as *mut ffi::c_char);
+        }
+    });
+}
+
+/// HeapTuple2 is a key-value pair of arbitrary bytes from a Heap.
+///
+/// The key and value are owned by the tuple and released along with it by
+/// zomdb_heap_tuple2_destroy. They are not null-terminated.
+#[repr(C)]
+pub struct HeapTuple2 {
+    pub key_ptr: *mut u8,
+    pub key_len: usize,
+    pub value_ptr: *mut u8,
+    pub value_len: usize,
+}
+
+/// Release the tuple along with its key and value.
+///
+/// Passing null is allowed and does nothing.
+///
+/// # Safety
+///
+/// The tuple pointer must have been returned by zomdb_heap_iter_next2 and
+/// not yet been destroyed. It must not be used after this call.
+#[no_mangle]
+pub unsafe extern "C" fn zomdb_heap_tuple2_destroy(ptr: *mut HeapTuple2) {
+    let _ = catch_panic(|| {
+        if ptr.is_null() {
+            return;
+        }
+        if !handles::unregister(ptr, Kind::Tuple2) {
+            invalid_handle("ptr", Kind::Tuple2);
+            return;
+        }
+        let tuple = unsafe { Box::from_raw(ptr) };
+        unsafe {
+            zomdb_free_bytes(tuple.key_ptr, tuple.key_len);
+            zomdb_free_bytes(tuple.value_ptr, tuple.value_len);
+        }
+    });
+}
+
+/// Rewind the iterator, so that it can be drained again.
+///
+/// Afterwards, the iterator behaves exactly like a new one created from the
+/// same heap: it sees the tuples written before the next call to
+/// zomdb_heap_iter_next, and yields every key again. This works even after
+/// the heap was destroyed. Tuples returned before stay valid.
+///
+/// Returns 0 on success or the code of the error that occurred.
+///
+/// # Safety
+///
+/// The iterator pointer must have been returned by zomdb_heap_iter and not
+/// yet been destroyed.
+#[no_mangle]
+pub unsafe extern "C" fn zomdb_heap_iter_reset(ptr: *mut HeapIter) -> ZomdbErrorCode {
+    catch_panic(|| {
+        check_null!(ZomdbErrorCode::NullArgument; ptr);
+        check_handle!(ZomdbErrorCode::InvalidHandle; ptr as Iter);
+        let iter = unsafe { &mut *ptr };
+        iter.inner.reset();
+        ZomdbErrorCode::Ok
+    })
+    .unwrap_or(ZomdbErrorCode::Panic)
+}
+
+/// Release the iterator.
+///
+/// # Safety
+///
+/// The iterator pointer must have been returned by zomdb_heap_iter and not
+/// yet been destroyed. It must not be used after this call.
+#[no_mangle]
+pub unsafe extern "C" fn zomdb_heap_iter_destroy(ptr: *mut HeapIter) {
+    let _ = catch_panic(|| {
+        if ptr.is_null() {
+            return;
+        }
+        if !handles::unregister(ptr, Kind::Iter) {
+            invalid_handle("ptr", Kind::Iter);
+            return;
+        }
+        let iter = unsafe { Box::from_raw(ptr) };
+        drop(iter);
+    });
+}
+
+unsafe fn string_from_cstr(s: *const ffi::c_char) -> Result<String, str::Utf8Error> {
+    let cstr = unsafe { ffi::CStr::from_ptr(s) };
+    let s = cstr.to_str()?;
+    Ok(s.to_string())
+}
+
+#[cfg(unix)]
+fn path_from_bytes(bytes: &[u8]) -> Result<path::PathBuf, str::Utf8Error> {
+    use std::os::unix::ffi::OsStrExt;
+    Ok(ffi::OsStr::from_bytes(bytes).into())
+}
+
+#[cfg(not(unix))]
+fn path_from_bytes(bytes: &[u8]) -> Result<path::PathBuf, str::Utf8Error> {
+    let s = str::from_utf8(bytes)?;
+    Ok(s.into())
+}
+
+unsafe fn bytes_from_cstr(s: *const ffi::c_char) -> Vec<u8> {
+    let cstr = unsafe { ffi::CStr::from_ptr(s) };
+    cstr.to_bytes().to_vec()
+}
+
+/// Borrows len bytes starting at ptr, which may be null if len is zero.
+unsafe fn from_raw_parts<'a>(ptr: *const u8, len: usize) -> &'a [u8] {
+    if len == 0 {
+        return &[];
+    }
+    unsafe { slice::from_raw_parts(ptr, len) }
+}
+
+/// Hands the bytes over to the caller, who releases them with
+/// zomdb_free_bytes.
+fn to_raw_parts(bytes: Vec<u8>) -> Result<(*mut u8, usize), ZomdbErrorCode> {
+    alloc::into_raw(bytes)
+}
+
+/// Hands the bytes over to the caller as a null-terminated string, who
+/// releases it with zomdb_free_value.
+///
+/// Fails with ERR_NUL_BYTE if the bytes contain a null byte, because the
+/// caller would only see the part before it.
+fn to_cstr(s: &[u8]) -> Result<*const ffi::c_char, ZomdbErrorCode> {
+    match ffi::CString::new(s) {
+        Ok(cstr) => {
+            let (ptr, _) = alloc::into_raw(cstr.into_bytes_with_nul())?;
+            Ok(ptr.cast())
+        }
+        Err(e) => Err(fail(
+            ZomdbErrorCode::NulByte,
+            format!(
+                "null byte at position {} can't be returned as a string",
+                e.nul_position()
+            ),
+        )),
+    }
+}
+
+/// Runs the body of an exported function, turning a panic into ERR_PANIC.
+///
+/// Unwinding into the caller is undefined behavior, so every exported
+/// function that does more than return a constant runs its body through
+/// this. The panic message becomes the last error message. Returns None if
+/// the body panicked.
+fn catch_panic<T>(f: impl FnOnce() -> T) -> Option<T> {
+    // The heap stays usable after a panic: it is poisoned by anything that
+    // could have left the file in an unknown state.
+    panic::catch_unwind(panic::AssertUnwindSafe(f))
+        .map_err(|payload| {
+            let message = payload
+                .downcast_ref::<&str>()
+                .copied()
+                .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+                .unwrap_or("unknown panic");
+            log!(ZOMDB_LOG_ERROR, "panicked: {}", message);
+            fail(ZomdbErrorCode::Panic, format!("panicked: {}", message));
+        })
+        .ok()
+}
+
+/// The version of the C ABI exposed by this library.
+///
+/// It is incremented whenever a change could break callers built against an
+/// earlier version, like changed struct layouts, error codes or function
+/// semantics. Compare it with zomdb_abi_version to check that the loaded
+/// library matches the header.
+pub const ZOMDB_ABI_VERSION: u32 = 4;
+
+/// The version of this library as a null-terminated "MAJOR.MINOR.PATCH"
+/// string.
+const VERSION: &[u8] = concat!(env!("CARGO_PKG_VERSION"), "\0").as_bytes();
+
+/// Return the version of this library as a "MAJOR.MINOR.PATCH" string.
+///
+/// The string is static and must not be released.
+#[no_mangle]
+pub extern "C" fn zomdb_version() -> *const ffi::c_char {
+    VERSION.as_ptr().cast()
+}
+
+/// Return the version of the C ABI implemented by this library.
+///
+/// See ZOMDB_ABI_VERSION.
+#[no_mangle]
+pub extern "C" fn zomdb_abi_version() -> u32 {
+    ZOMDB_ABI_VERSION
+}
+
+/// The code of an error reported by this library.
+///
+/// Functions that return a status return ZomdbErrorCode::Ok on success, and
+/// the code of the error otherwise. The values never change, and each has an
+/// ERR_* constant of the same value for callers that store codes as ints.
+/// Errors of the core library keep the value of their zomdb::Error::code.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ZomdbErrorCode {
+    /// No error occurred.
+    Ok = 0,
+    /// Key could not be found.
+    NotFound = 1,
+    /// I/O error.
+    Io = 10,
+    /// Heap is already opened by another writer.
+    Locked = 11,
+    /// Heap refuses writes after an earlier error.
+    Poisoned = 12,
+    /// Operation can't run while iterators are alive.
+    Busy = 13,
+    /// Write to a heap that was opened for reading only.
+    ReadOnly = 14,
+    /// Allocator registered with zomdb_set_allocator returned null.
+    OutOfMemory = 15,
+    /// Operation was cancelled before it completed.
+    Cancelled = 16,
+    /// Invalid UTF-8. Type of an input error.
+    Utf8 = 30,
+    /// Key is longer than the maximum. Type of an input error.
+    KeySize = 31,
+    /// Invalid value size. Type of an input error.
+    ValueSize = 32,
+    /// Value contains a null byte but was requested as a null-terminated
+    /// string. Use the functions taking byte buffers instead.
+    NulByte = 33,
+    /// Null pointer passed where a valid one is required. Type of an input
+    /// error.
+    NullArgument = 34,
+    /// Handle was not created by this library, was created as another type,
+    /// or was destroyed already. Only detected in debug builds or with the
+    /// handle-checks feature.
+    InvalidHandle = 35,
+    /// Buffer is too small to hold the result.
+    BufferTooSmall = 36,
+    /// Key is empty. Type of an input error.
+    EmptyKey = 37,
+    /// Pattern to match keys against is invalid. Type of an input error.
+    Pattern = 38,
+    /// Savepoint is unknown, the heap was compacted since it was created, or
+    /// the heap ends before it.
+    Savepoint = 39,
+    /// Data on disk is corrupted.
+    Data = 50,
+    /// Heap file was truncated or replaced while open.
+    ExternallyModified = 51,
+    /// Stored value doesn't have the length of the requested type.
+    ValueLength = 52,
+    /// Unexpected failure inside the library.
+    Panic = 60,
+}
+
+impl ZomdbErrorCode {
+    /// Every code, to look them up by value.
+    const ALL: [Self; 23] = [
+        Self::Ok,
+        Self::NotFound,
+        Self::Io,
+        Self::Locked,
+        Self::Poisoned,
+        Self::Busy,
+        Self::ReadOnly,
+        Self::OutOfMemory,
+        Self::Cancelled,
+        Self::Utf8,
+        Self::KeySize,
+        Self::ValueSize,
+        Self::NulByte,
+        Self::NullArgument,
+        Self::InvalidHandle,
+        Self::BufferTooSmall,
+        Self::EmptyKey,
+        Self::Pattern,
+        Self::Savepoint,
+        Self::Data,
+        Self::ExternallyModified,
+        Self::ValueLength,
+        Self::Panic,
+    ];
+
+    fn from_int(code: ffi::c_int) -> Option<Self> {
+        Self::ALL.into_iter().find(|c| *c as ffi::c_int == code)
+    }
+
+    /// The description returned by zomdb_strerror.
+    fn message(self) -> &'static [u8] {
+        match self {
+            Self::Ok => b"no error\0",
+            Self::NotFound => b"key not found\0",
+            Self::Io => b"I/O error\0",
+            Self::Locked => b"heap is locked by another writer\0",
+            Self::Poisoned => b"heap refuses writes after an earlier error\0",
+            Self::Busy => b"heap is busy with open iterators\0",
+            Self::ReadOnly => b"heap was opened read-only\0",
+            Self::OutOfMemory => b"out of memory\0",
+            Self::Cancelled => b"operation was cancelled\0",
+            Self::Utf8 => b"invalid UTF-8\0",
+            Self::KeySize => b"key is too long\0",
+            Self::ValueSize => b"invalid value size\0",
+            Self::NulByte => b"value contains a null byte\0",
+            Self::NullArgument => b"required pointer argument is null\0",
+            Self::InvalidHandle => b"handle is invalid or was destroyed\0",
+            Self::BufferTooSmall => b"buffer is too small\0",
+            Self::EmptyKey => b"key is empty\0",
+            Self::Pattern => b"invalid pattern\0",
+            Self::Savepoint => b"savepoint is unknown or was invalidated\0",
+            Self::Data => b"data on disk is corrupted\0",
+            Self::ExternallyModified => b"heap file was modified externally\0",
+            Self::ValueLength => b"value has an unexpected length\0",
+            Self::Panic => b"unexpected failure inside the library\0",
+        }
+    }
+}
+
+// The constants predate ZomdbErrorCode and are kept for callers that use
+// them. A test makes sure that they match.
+
+/// Same as ZomdbErrorCode::NotFound.
+pub const ERR_NOT_FOUND: i32 = 1;
+
+/// Same as ZomdbErrorCode::Io.
+pub const ERR_IO: i32 = 10;
+
+/// Same as ZomdbErrorCode::Locked.
+pub const ERR_LOCKED: i32 = 11;
+
+/// Same as ZomdbErrorCode::Poisoned.
+pub const ERR_POISONED: i32 = 12;
+
+/// Same as ZomdbErrorCode::Busy.
+pub const ERR_BUSY: i32 = 13;
+
+/// Same as ZomdbErrorCode::ReadOnly.
+pub const ERR_READ_ONLY: i32 = 14;
+
+/// Same as ZomdbErrorCode::OutOfMemory.
+pub const ERR_OUT_OF_MEMORY: i32 = 15;
+
+/// Same as ZomdbErrorCode::Cancelled.
+pub const ERR_CANCELLED: i32 = 16;
+
+/// Same as ZomdbErrorCode::Utf8.
+pub const ERR_UTF8: i32 = 30;
+
+/// Same as ZomdbErrorCode::KeySize.
+pub const ERR_KEY_SIZE: i32 = 31;
+
+/// Same as ZomdbErrorCode::ValueSize.
+pub const ERR_VALUE_SIZE: i32 = 32;
+
+/// Same as ZomdbErrorCode::NulByte.
+pub const ERR_NUL_BYTE: i32 = 33;
+
+/// Same as ZomdbErrorCode::NullArgument.
+pub const ERR_NULL_ARGUMENT: i32 = 34;
+
+/// Same as ZomdbErrorCode::InvalidHandle.
+pub const ERR_INVALID_HANDLE: i32 = 35;
+
+/// Same as ZomdbErrorCode::BufferTooSmall.
+pub const ERR_BUFFER_TOO_SMALL: i32 = 36;
+
+/// Same as ZomdbErrorCode::EmptyKey.
+pub const ERR_EMPTY_KEY: i32 = 37;
+
+/// Same as ZomdbErrorCode::Pattern.
+pub const ERR_PATTERN: i32 = 38;
+
+/// Same as ZomdbErrorCode::Savepoint.
+pub const ERR_SAVEPOINT: i32 = 39;
+
+/// Same as ZomdbErrorCode::Data.
+pub const ERR_DATA: i32 = 50;
+
+/// Same as ZomdbErrorCode::ExternallyModified.
+pub const ERR_EXTERNALLY_MODIFIED: i32 = 51;
+
+/// Same as ZomdbErrorCode::ValueLength.
+pub const ERR_VALUE_LENGTH: i32 = 52;
+
+/// Same as ZomdbErrorCode::Panic.
+pub const ERR_PANIC: i32 = 60;
+
+/// Return a description of the error code.
+///
+/// Returns "unknown error" for codes that are not defined by this library,
+/// and never null. The string is static and must not be released.
+#[no_mangle]
+pub extern "C" fn zomdb_strerror(code: ffi::c_int) -> *const ffi::c_char {
+    catch_panic(|| {
+        let message =
+            ZomdbErrorCode::from_int(code).map_or(&b"unknown error\0"[..], ZomdbErrorCode::message);
+        message.as_ptr().cast()
+    })
+    .unwrap_or(std::ptr::null())
+}
+
+thread_local! {
+    // The code and message of the last error that occurred on this thread.
+    static LAST_ERROR: RefCell<Option<(ZomdbErrorCode, ffi::CString)>> = const { RefCell::new(None) };
+}
+
+/// Return the code of the last error that occurred on the calling thread,
+/// or 0 if none occurred yet.
+///
+/// Functions that report failure only by returning null or nothing reset it
+/// to 0 when they succeed.
+#[no_mangle]
+pub extern "C" fn zomdb_last_error() -> ZomdbErrorCode {
+    catch_panic(|| {
+        LAST_ERROR.with(|last| {
+            last.borrow()
+                .as_ref()
+                .map_or(ZomdbErrorCode::Ok, |(code, _)| *code)
+        })
+    })
+    .unwrap_or(ZomdbErrorCode::Panic)
+}
+
+/// Return a message describing the last error that occurred on the calling
+/// thread, or null if none occurred yet.
+///
+/// The message is owned by the library and stays valid until the next call
+/// into the library from the same thread. It must not be released.
+#[no_mangle]
+pub extern "C" fn zomdb_last_error_message() -> *const ffi::c_char {
+    catch_panic(|| {
+        LAST_ERROR.with(|last| match &*last.borrow() {
+            Some((_, message)) => message.as_ptr(),
+            None => std::ptr::null(),
+        })
+    })
+    .unwrap_or(std::ptr::null())
+}
+
+/// Records an error as the last error of this thread and returns its code.
+fn fail(code: ZomdbErrorCode, message: String) -> ZomdbErrorCode {
+    let message = ffi::CString::new(message.replace('\0', "\\0")).unwrap_or_default();
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some((code, message)));
+    code
+}
+
+/// Returns the code and message of the last error of this thread.
+fn last_error() -> Option<(ZomdbErrorCode, ffi::CString)> {
+    LAST_ERROR.with(|last| last.borrow().clone())
+}
+
+/// Forgets the last error of this thread, for functions that can't tell
+/// success from failure through their return value.
+fn clear_last_error() {
+    LAST_ERROR.with(|last| *last.borrow_mut() = None);
+}
+
+/// Reports that the named argument isn't valid UTF-8 and returns ERR_UTF8.
+///
+/// The core library works with bytes only, so UTF-8 is only required of
+/// strings at this boundary.
+fn utf8_error(name: &str, e: str::Utf8Error) -> ZomdbErrorCode {
+    log!(ZOMDB_LOG_ERROR, "{}: {:?}", name, e);
+    fail(
+        ZomdbErrorCode::Utf8,
+        format!("{} is not valid UTF-8: {}", name, e),
+    )
+}
+
+/// Reports a null pointer passed for the named argument and returns
+/// ERR_NULL_ARGUMENT.
+fn null_argument(name: &str) -> ZomdbErrorCode {
+    log!(ZOMDB_LOG_ERROR, "{} must not be null", name);
+    fail(
+        ZomdbErrorCode::NullArgument,
+        format!("{} must not be null", name),
+    )
+}
+
+/// Reports a handle that isn't valid as the given kind and returns
+/// ERR_INVALID_HANDLE.
+fn invalid_handle(name: &str, kind: Kind) -> ZomdbErrorCode {
+    log!(ZOMDB_LOG_ERROR, "{} is not a valid {:?} handle", name, kind);
+    fail(
+        ZomdbErrorCode::InvalidHandle,
+        format!("{} is not a valid {:?} handle", name, kind),
+    )
+}
+
+/// Reports the error like fail and returns its code.
+fn set_error(e: zomdb::Error) -> ZomdbErrorCode {
+    // Append the causes that the message doesn't mention yet.
+    let mut message = e.to_string();
+    let mut source = std::error::Error::source(&e);
+    while let Some(cause) = source {
+        let cause_message = cause.to_string();
+        if !message.contains(&cause_message) {
+            message = format!("{}: {}", message, cause_message);
+        }
+        source = cause.source();
+    }
+    fail(error_code(&e), message)
+}
+
+/// Returns the code of a core error, which has the same value as the
+/// error's zomdb::Error::code.
+fn error_code(e: &zomdb::Error) -> ZomdbErrorCode {
+    // A test makes sure that every core code has a variant.
+    ZomdbErrorCode::from_int(e.code().into()).unwrap_or(ZomdbErrorCode::Panic)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::ptr;
+
+    fn create_temp_heap(dir: &tempfile::TempDir) -> *mut Heap {
+        let path = ffi::CString::new(dir.path().join("heap").to_str().unwrap()).unwrap();
+        let heap = unsafe { zomdb_heap_create(path.as_ptr()) };
+        assert!(!heap.is_null());
+        heap
+    }
+
+    unsafe fn get(heap: *mut Heap, key: &[u8]) -> Result<Vec<u8>, ZomdbErrorCode> {
+        let mut value_ptr = ptr::null_mut();
+        let mut value_len = 0;
+        let code = unsafe {
+            zomdb_heap_get2(
+                heap,
+                key.as_ptr(),
+                key.len(),
+                &mut value_ptr,
+                &mut value_len,
+            )
+        };
+        if code != ZomdbErrorCode::Ok {
+            return Err(code);
+        }
+
+        let value = unsafe { slice::from_raw_parts(value_ptr, value_len) }.to_vec();
+        unsafe { zomdb_free_bytes(value_ptr, value_len) };
+        Ok(value)
+    }
+
+    unsafe fn set(heap: *mut Heap, key: &[u8], value: &[u8]) -> ZomdbErrorCode {
+        unsafe { zomdb_heap_set2(heap, key.as_ptr(), key.len(), value.as_ptr(), value.len()) }
+    }
+
+    #[test]
+    fn test_heap_get2_set2_embedded_nul_bytes() {
+        let dir = tempfile::tempdir().unwrap();
+        let heap = create_temp_heap(&dir);
+
+        unsafe {
+            assert_eq!(set(heap, b"key\0one", b"value\0\0one"), ZomdbErrorCode::Ok);
+            assert_eq!(set(heap, b"key\0two", b"\0"), ZomdbErrorCode::Ok);
+
+            assert_eq!(get(heap, b"key\0one"), Ok(b"value\0\0one".to_vec()));
+            assert_eq!(get(heap, b"key\0two"), Ok(b"\0".to_vec()));
+            assert_eq!(get(heap, b"key"), Err(ZomdbErrorCode::NotFound));
+
+            zomdb_heap_destroy(heap);
+        }
+    }
+
+    #[test]
+    fn test_heap_get2_set2_empty_value() {
+        let dir = tempfile::tempdir().unwrap();
+        let heap = create_temp_heap(&dir);
+
+        unsafe {
+            assert_eq!(
+                zomdb_heap_set2(heap, b"key".as_ptr(), 3, ptr::null(), 0),
+                ZomdbErrorCode::Ok
+            );
+            assert_eq!(get(heap, b"key"), Ok(Vec::new()));
+
+            assert_eq!(set(heap, b"", b"value"), ZomdbErrorCode::EmptyKey);
+            assert_eq!(set(heap, b"key", &[0; 2048]), ZomdbErrorCode::ValueSize);
+
+            zomdb_heap_destroy(heap);
+        }
+    }
+
+    #[test]
+    fn test_heap_set_status() {
+        let dir = tempfile::tempdir().unwrap();
+        let heap = create_temp_heap(&dir);
+        let key = ffi::CString::new("key").unwrap();
+        let large = ffi::CString::new(vec![b'v'; 2048]).unwrap();
+        let value = ffi::CString::new("value").unwrap();
+
+        unsafe {
+            assert_eq!(
+                zomdb_heap_set(heap, key.as_ptr(), large.as_ptr()),
+                ZomdbErrorCode::ValueSize
+            );
+            assert_eq!(
+                zomdb_heap_set(heap, key.as_ptr(), value.as_ptr()),
+                ZomdbErrorCode::Ok
+            );
+            assert_eq!(zomdb_last_error(), ZomdbErrorCode::Ok);
+            assert_eq!(get(heap, b"key"), Ok(b"value".to_vec()));
+
+            assert_eq!(zomdb_heap_close(heap), ZomdbErrorCode::Ok);
+            assert_eq!(
+                zomdb_heap_close(ptr::null_mut()),
+                ZomdbErrorCode::NullArgument
+            );
+        }
+    }
+
+    #[test]
+    fn test_heap_get_free_value() {
+        let dir = tempfile::tempdir().unwrap();
+        let heap = create_temp_heap(&dir);
+        let key = ffi::CString::new("key").unwrap();
+        let value = ffi::CString::new("value").unwrap();
+
+        unsafe {
+            assert_eq!(
+                zomdb_heap_set(heap, key.as_ptr(), value.as_ptr()),
+                ZomdbErrorCode::Ok
+            );
+            for _ in 0..1000 {
+                let got = zomdb_heap_get(heap, key.as_ptr());
+                assert_eq!(ffi::CStr::from_ptr(got), value.as_c_str());
+                zomdb_free_value(got as *mut ffi::c_char);
+            }
+            zomdb_free_value(ptr::null_mut());
+
+            zomdb_heap_destroy(heap);
+        }
+    }
+
+    #[test]
+    fn test_heap_iter_tuple_destroy() {
+        let dir = tempfile::tempdir().unwrap();
+        let heap = create_temp_heap(&dir);
+
+        unsafe {
+            for i in 0..3000 {
+                let key = format!("key{}", i);
+                let value = format!("value{}", i);
+                assert_eq!(
+                    set(heap, key.as_bytes(), value.as_bytes()),
+                    ZomdbErrorCode::Ok
+                );
+            }
+
+            let iter = zomdb_heap_iter(heap);
+            let mut count = 0;
+            loop {
+                let tuple = zomdb_heap_iter_next(iter);
+                if tuple.is_null() {
+                    break;
+                }
+                let key = ffi::CStr::from_ptr(zomdb_heap_tuple_key(tuple))
+                    .to_str()
+                    .unwrap();
+                let value = ffi::CStr::from_ptr(zomdb_heap_tuple_value(tuple))
+                    .to_str()
+                    .unwrap();
+                assert_eq!(&value[5..], &key[3..]);
+
+                zomdb_heap_tuple_destroy(tuple as *mut HeapTuple);
+                count += 1;
+            }
+            assert_eq!(count, 3000);
+
+            zomdb_heap_iter_destroy(iter);
+            zomdb_heap_destroy(heap);
+        }
+    }
+
+    #[test]
+    fn test_heap_delete() {
+        let dir = tempfile::tempdir().unwrap();
+        let heap = create_temp_heap(&dir);
+
+        unsafe {
+            assert_eq!(set(heap, b"k\0ey", b"value"), ZomdbErrorCode::Ok);
+            assert_eq!(set(heap, b"other", b"value"), ZomdbErrorCode::Ok);
+
+            assert_eq!(
+                zomdb_heap_delete(heap, b"k\0ey".as_ptr(), 4),
+                ZomdbErrorCode::Ok
+            );
+            assert_eq!(get(heap, b"k\0ey"), Err(ZomdbErrorCode::NotFound));
+            assert_eq!(
+                zomdb_heap_delete(heap, b"k\0ey".as_ptr(), 4),
+                ZomdbErrorCode::NotFound
+            );
+            assert_eq!(get(heap, b"other"), Ok(b"value".to_vec()));
+
+            zomdb_heap_destroy(heap);
+        }
+    }
+
+    #[test]
+    fn test_heap_sync() {
+        let dir = tempfile::tempdir().unwrap();
+        let heap = create_temp_heap(&dir);
+
+        unsafe {
+            assert_eq!(zomdb_heap_sync(heap), ZomdbErrorCode::Ok);
+            assert_eq!(set(heap, b"key", b"value"), ZomdbErrorCode::Ok);
+            assert_eq!(zomdb_heap_sync(heap), ZomdbErrorCode::Ok);
+            assert_eq!(zomdb_heap_sync(heap), ZomdbErrorCode::Ok);
+            assert_eq!(get(heap, b"key"), Ok(b"value".to_vec()));
+
+            zomdb_heap_destroy(heap);
+        }
+    }
+
+    #[test]
+    fn test_heap_contains_count() {
+        let dir = tempfile::tempdir().unwrap();
+        let heap = create_temp_heap(&dir);
+        let mut count = u64::MAX;
+
+        unsafe {
+            assert_eq!(zomdb_heap_count(heap, &mut count), ZomdbErrorCode::Ok);
+            assert_eq!(count, 0);
+
+            assert_eq!(set(heap, b"key1", b"value1"), ZomdbErrorCode::Ok);
+            assert_eq!(set(heap, b"key2", b"value2"), ZomdbErrorCode::Ok);
+            assert_eq!(set(heap, b"key1", b"value3"), ZomdbErrorCode::Ok);
+
+            assert_eq!(zomdb_heap_contains(heap, b"key1".as_ptr(), 4), 1);
+            assert_eq!(zomdb_heap_contains(heap, b"key2".as_ptr(), 4), 1);
+            assert_eq!(zomdb_heap_contains(heap, b"key3".as_ptr(), 4), 0);
+            assert_eq!(zomdb_heap_count(heap, &mut count), ZomdbErrorCode::Ok);
+            assert_eq!(count, 2);
+
+            zomdb_heap_destroy(heap);
+        }
+    }
+
+    #[test]
+    fn test_heap_compact() {
+        let dir = tempfile::tempdir().unwrap();
+        let heap = create_temp_heap(&dir);
+        let mut report = HeapCompactionReport {
+            bytes_before: 0,
+            bytes_after: 0,
+            records_dropped: 0,
+        };
+
+        unsafe {
+            for i in 0..10 {
+                assert_eq!(
+                    set(heap, b"key1", format!("value{}", i).as_bytes()),
+                    ZomdbErrorCode::Ok
+                );
+            }
+            assert_eq!(set(heap, b"key2", b"value"), ZomdbErrorCode::Ok);
+
+            let iter = zomdb_heap_iter(heap);
+            assert_eq!(zomdb_heap_compact(heap, &mut report), ZomdbErrorCode::Busy);
+            zomdb_heap_iter_destroy(iter);
+
+            assert_eq!(zomdb_heap_compact(heap, &mut report), ZomdbErrorCode::Ok);
+            assert_eq!(report.records_dropped, 9);
+            assert_eq!(report.bytes_before, 11 * (4 + 6 + 3) - 1);
+            assert_eq!(report.bytes_after, 2 * (4 + 6 + 3) - 1);
+
+            assert_eq!(get(heap, b"key1"), Ok(b"value9".to_vec()));
+            assert_eq!(get(heap, b"key2"), Ok(b"value".to_vec()));
+
+            zomdb_heap_destroy(heap);
+        }
+    }
+
+    #[test]
+    fn test_heap_stats() {
+        let dir = tempfile::tempdir().unwrap();
+        let heap = create_temp_heap(&dir);
+        let mut stats = HeapStats::default();
+
+        unsafe {
+            assert_eq!(set(heap, b"key1", b"value1"), ZomdbErrorCode::Ok);
+            assert_eq!(set(heap, b"key1", b"value2"), ZomdbErrorCode::Ok);
+            assert_eq!(set(heap, b"key2", b"value3"), ZomdbErrorCode::Ok);
+            assert_eq!(
+                zomdb_heap_delete(heap, b"key2".as_ptr(), 4),
+                ZomdbErrorCode::Ok
+            );
+
+            assert_eq!(zomdb_heap_stats(heap, &mut stats), ZomdbErrorCode::Ok);
+            assert_eq!(stats, HeapStats::from((*heap).lock().stats().unwrap()));
+            assert_eq!(stats.total_records, 4);
+            assert_eq!(stats.live_keys, 1);
+
+            zomdb_heap_destroy(heap);
+        }
+    }
+
+    #[test]
+    fn test_heap_stats_json() {
+        let dir = tempfile::tempdir().unwrap();
+        let heap = create_temp_heap(&dir);
+        let mut stats = HeapStats::default();
+
+        unsafe {
+            assert_eq!(set(heap, b"key1", b"value1"), ZomdbErrorCode::Ok);
+            assert_eq!(set(heap, b"key1", b"value2"), ZomdbErrorCode::Ok);
+            assert_eq!(set(heap, b"key2", b"value3"), ZomdbErrorCode::Ok);
+            assert_eq!(zomdb_heap_stats(heap, &mut stats), ZomdbErrorCode::Ok);
+
+            let ptr = zomdb_heap_stats_json(heap);
+            assert!(!ptr.is_null());
+            let json = ffi::CStr::from_ptr(ptr).to_str().unwrap();
+            let json: serde_json::Value = serde_json::from_str(json).unwrap();
+            zomdb_free_value(ptr);
+
+            assert_eq!(json["file_size"], stats.file_size);
+            assert_eq!(json["total_records"], stats.total_records);
+            assert_eq!(json["live_keys"], stats.live_keys);
+            assert_eq!(json["stale_records"], stats.stale_records);
+            assert_eq!(json["dead_bytes"], stats.dead_bytes);
+            assert_eq!(json.as_object().unwrap().len(), 5);
+
+            assert!(zomdb_heap_stats_json(ptr::null_mut()).is_null());
+            assert_eq!(zomdb_last_error(), ZomdbErrorCode::NullArgument);
+            zomdb_heap_destroy(heap);
+        }
+    }
+
+    #[test]
+    fn test_heap_verify() {
+        let dir = tempfile::tempdir().unwrap();
+        let heap = create_temp_heap(&dir);
+        let mut report = HeapVerifyReport::default();
+
+        unsafe {
+            assert_eq!(set(heap, b"key1", b"value1"), ZomdbErrorCode::Ok);
+            assert_eq!(set(heap, b"key2", b"value2"), ZomdbErrorCode::Ok);
+            assert_eq!(zomdb_heap_verify(heap, &mut report), ZomdbErrorCode::Ok);
+            assert_eq!(report.valid_records, 2);
+            assert_eq!(report.first_corrupt_offset, u64::MAX);
+            assert_eq!(report.truncated_tail, 0);
+
+            // Make the key size of the first tuple reach past the start of
+            // the file.
+            let path = dir.path().join("heap");
+            let mut data = std::fs::read(&path).unwrap();
+            data[12] = 0xff;
+            std::fs::write(&path, &data).unwrap();
+
+            assert_eq!(zomdb_heap_verify(heap, &mut report), ZomdbErrorCode::Ok);
+            assert_eq!(report.valid_records, 1);
+            assert_eq!(report.first_corrupt_offset, 13);
+            assert_eq!(report.truncated_tail, 0);
+
+            assert_eq!(
+                zomdb_heap_verify(heap, ptr::null_mut()),
+                ZomdbErrorCode::NullArgument
+            );
+            zomdb_heap_destroy(heap);
+        }
+    }
+
+    #[test]
+    fn test_heap_backup() {
+        let dir = tempfile::tempdir().unwrap();
+        let heap = create_temp_heap(&dir);
+        for i in 0..1000 {
+            let key = format!("key{}", i);
+            assert_eq!(
+                unsafe { set(heap, key.as_bytes(), b"value") },
+                ZomdbErrorCode::Ok
+            );
+        }
+
+        // Keep appending through the same handle while the backup runs.
+        let addr = heap as usize;
+        let writer = std::thread::spawn(move || {
+            let heap = addr as *mut Heap;
+            for i in 0..1000 {
+                let key = format!("late{}", i);
+                assert_eq!(
+                    unsafe { set(heap, key.as_bytes(), b"value") },
+                    ZomdbErrorCode::Ok
+                );
+            }
+        });
+        let dest = dir.path().join("backup");
+        let dest = dest.to_str().unwrap();
+        let copied = unsafe { zomdb_heap_backup(heap, dest.as_ptr(), dest.len()) };
+        writer.join().unwrap();
+        assert!(copied > 0);
+
+        unsafe {
+            let copy = zomdb_heap_open_read_only(dest.as_ptr(), dest.len());
+            assert!(!copy.is_null());
+            let mut count = 0;
+            assert_eq!(zomdb_heap_count(copy, &mut count), ZomdbErrorCode::Ok);
+            assert!((1000..=2000).contains(&count));
+            assert_eq!(get(copy, b"key999"), Ok(b"value".to_vec()));
+            let mut report = HeapVerifyReport::default();
+            assert_eq!(zomdb_heap_verify(copy, &mut report), ZomdbErrorCode::Ok);
+            assert_eq!(report.valid_records, count);
+            assert_eq!(report.first_corrupt_offset, u64::MAX);
+            zomdb_heap_destroy(copy);
+
+            // The destination must not exist yet.
+            assert_eq!(
+                zomdb_heap_backup(heap, dest.as_ptr(), dest.len()),
+                -(ZomdbErrorCode::Io as i64)
+            );
+            zomdb_heap_destroy(heap);
+        }
+    }
+
+    unsafe fn collect_keys(iter: *mut HeapIter) -> Vec<Vec<u8>> {
+        let mut keys = Vec::new();
+        loop {
+            let mut tuple = ptr::null_mut();
+            assert_eq!(
+                unsafe { zomdb_heap_iter_next2(iter, &mut tuple) },
+                ZomdbErrorCode::Ok
+            );
+            if tuple.is_null() {
+                break;
+            }
+            let (key_ptr, key_len) = unsafe { ((*tuple).key_ptr, (*tuple).key_len) };
+            keys.push(unsafe { slice::from_raw_parts(key_ptr, key_len) }.to_vec());
+            unsafe { zomdb_heap_tuple2_destroy(tuple) };
+        }
+        unsafe { zomdb_heap_iter_destroy(iter) };
+        keys
+    }
+
+    #[test]
+    fn test_heap_shared_across_threads() {
+        let dir = tempfile::tempdir().unwrap();
+        let heap = create_temp_heap(&dir);
+        // Raw pointers aren't Send, but the handle may be shared.
+        let addr = heap as usize;
+
+        let threads: Vec<_> = (0..4)
+            .map(|t| {
+                std::thread::spawn(move || {
+                    let heap = addr as *mut Heap;
+                    for i in 0..100 {
+                        let key = format!("key{}-{}", t, i);
+                        let value = format!("value{}", i);
+                        assert_eq!(
+                            unsafe { set(heap, key.as_bytes(), value.as_bytes()) },
+                            ZomdbErrorCode::Ok
+                        );
+                        assert_eq!(unsafe { get(heap, key.as_bytes()) }, Ok(value.into_bytes()));
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        let mut count = 0;
+        assert_eq!(
+            unsafe { zomdb_heap_count(heap, &mut count) },
+            ZomdbErrorCode::Ok
+        );
+        assert_eq!(count, 400);
+        unsafe { zomdb_heap_destroy(heap) };
+    }
+
+    #[test]
+    fn test_heap_iter_outlives_heap() {
+        let dir = tempfile::tempdir().unwrap();
+        let heap = create_temp_heap(&dir);
+        assert_eq!(unsafe { set(heap, b"key1", b"value1") }, ZomdbErrorCode::Ok);
+        assert_eq!(unsafe { set(heap, b"key2", b"value2") }, ZomdbErrorCode::Ok);
+
+        let (iter, prefixed) = unsafe {
+            let iter = zomdb_heap_iter(heap);
+            let first = zomdb_heap_iter_next(iter);
+            assert!(!first.is_null());
+            zomdb_heap_tuple_destroy(first.cast_mut());
+
+            let prefix = b"key1";
+            let prefixed = zomdb_heap_iter_prefix(heap, prefix.as_ptr(), prefix.len());
+            zomdb_heap_destroy(heap);
+            (iter, prefixed)
+        };
+
+        assert_eq!(unsafe { collect_keys(iter) }, vec![b"key1".to_vec()]);
+        assert_eq!(unsafe { collect_keys(prefixed) }, vec![b"key1".to_vec()]);
+    }
+
+    #[test]
+    fn test_heap_get_into() {
+        let dir = tempfile::tempdir().unwrap();
+        let heap = create_temp_heap(&dir);
+        assert_eq!(unsafe { set(heap, b"key", b"value") }, ZomdbErrorCode::Ok);
+
+        let get_into = |key: &[u8], buf: &mut [u8], out_len: &mut usize| unsafe {
+            zomdb_heap_get_into(
+                heap,
+                key.as_ptr(),
+                key.len(),
+                buf.as_mut_ptr(),
+                buf.len(),
+                out_len,
+            )
+        };
+
+        // An exact fit.
+        let (mut buf, mut len) = ([0; 5], 0);
+        assert_eq!(get_into(b"key", &mut buf, &mut len), ZomdbErrorCode::Ok);
+        assert_eq!((&buf, len), (b"value", 5));
+
+        // Too small, then retried with the reported length.
+        let (mut buf, mut len) = ([0; 2], 0);
+        assert_eq!(
+            get_into(b"key", &mut buf, &mut len),
+            ZomdbErrorCode::BufferTooSmall
+        );
+        assert_eq!((buf, len), ([0; 2], 5));
+        let mut buf = vec![0; len];
+        assert_eq!(get_into(b"key", &mut buf, &mut len), ZomdbErrorCode::Ok);
+        assert_eq!(buf, b"value");
+
+        // A null buffer of length zero queries the length.
+        let code =
+            unsafe { zomdb_heap_get_into(heap, b"key".as_ptr(), 3, ptr::null_mut(), 0, &mut len) };
+        assert_eq!((code, len), (ZomdbErrorCode::BufferTooSmall, 5));
+
+        let mut len = usize::MAX;
+        assert_eq!(
+            get_into(b"missing", &mut buf, &mut len),
+            ZomdbErrorCode::NotFound
+        );
+        assert_eq!(len, usize::MAX);
+
+        unsafe { zomdb_heap_destroy(heap) };
+    }
+
+    #[test]
+    fn test_heap_iter_next2_nul_bytes() {
+        let dir = tempfile::tempdir().unwrap();
+        let heap = create_temp_heap(&dir);
+        assert_eq!(
+            unsafe { set(heap, b"k\0ey", b"va\0lue\0") },
+            ZomdbErrorCode::Ok
+        );
+
+        let iter = unsafe { zomdb_heap_iter(heap) };
+        let mut tuple = ptr::null_mut();
+        unsafe {
+            assert_eq!(zomdb_heap_iter_next2(iter, &mut tuple), ZomdbErrorCode::Ok);
+            let t = &*tuple;
+            assert_eq!(slice::from_raw_parts(t.key_ptr, t.key_len), b"k\0ey");
+            assert_eq!(
+                slice::from_raw_parts(t.value_ptr, t.value_len),
+                b"va\0lue\0"
+            );
+            zomdb_heap_tuple2_destroy(tuple);
+
+            assert_eq!(zomdb_heap_iter_next2(iter, &mut tuple), ZomdbErrorCode::Ok);
+            assert!(tuple.is_null());
+            zomdb_heap_iter_destroy(iter);
+            zomdb_heap_destroy(heap);
+        }
+    }
+
+    #[test]
+    fn test_heap_iter_reset() {
+        let dir = tempfile::tempdir().unwrap();
+        let heap = create_temp_heap(&dir);
+        assert_eq!(unsafe { set(heap, b"key1", b"value1") }, ZomdbErrorCode::Ok);
+
+        let iter = unsafe { zomdb_heap_iter(heap) };
+        let mut tuple = ptr::null_mut();
+        unsafe {
+            assert_eq!(zomdb_heap_iter_next2(iter, &mut tuple), ZomdbErrorCode::Ok);
+            zomdb_heap_tuple2_destroy(tuple);
+            assert_eq!(zomdb_heap_iter_next2(iter, &mut tuple), ZomdbErrorCode::Ok);
+            assert!(tuple.is_null());
+        }
+
+        assert_eq!(unsafe { set(heap, b"key2", b"value2") }, ZomdbErrorCode::Ok);
+        assert_eq!(unsafe { zomdb_heap_iter_reset(iter) }, ZomdbErrorCode::Ok);
+        assert_eq!(
+            unsafe { collect_keys(iter) },
+            vec![b"key2".to_vec(), b"key1".to_vec()]
+        );
+        unsafe { zomdb_heap_destroy(heap) };
+    }
+
+    #[test]
+    fn test_heap_iter_prefix() {
+        let dir = tempfile::tempdir().unwrap();
+        let heap = create_temp_heap(&dir);
+
+        unsafe {
+            assert_eq!(set(heap, b"user:1", b"alice"), ZomdbErrorCode::Ok);
+            assert_eq!(set(heap, b"group:1", b"admins"), ZomdbErrorCode::Ok);
+            assert_eq!(set(heap, b"user:2", b"bob"), ZomdbErrorCode::Ok);
+
+            let iter = zomdb_heap_iter_prefix(heap, b"user:".as_ptr(), 5);
+            assert_eq!(
+                collect_keys(iter),
+                vec![b"user:2".to_vec(), b"user:1".to_vec()]
+            );
+
+            let iter = zomdb_heap_iter_prefix(heap, ptr::null(), 0);
+            assert_eq!(collect_keys(iter).len(), 3);
+
+            // Keys with NUL bytes can't be returned as C strings, so only
+            // matching nothing is checked here.
+            let iter = zomdb_heap_iter_prefix(heap, b"user\0:".as_ptr(), 6);
+            assert!(collect_keys(iter).is_empty());
+
+            zomdb_heap_destroy(heap);
+        }
+    }
+
+    extern "C" fn collect_tuple(
+        key: *const u8,
+        key_len: usize,
+        value: *const u8,
+        value_len: usize,
+        user_data: *mut ffi::c_void,
+    ) -> ffi::c_int {
+        let tuples = unsafe { &mut *(user_data as *mut Vec<(Vec<u8>, Vec<u8>)>) };
+        tuples.push((
+            unsafe { from_raw_parts(key, key_len) }.to_vec(),
+            unsafe { from_raw_parts(value, value_len) }.to_vec(),
+        ));
+        // Stop after the third tuple.
+        (tuples.len() == 3) as ffi::c_int
+    }
+
+    #[test]
+    fn test_heap_for_each() {
+        let dir = tempfile::tempdir().unwrap();
+        let heap = create_temp_heap(&dir);
+        let mut tuples: Vec<(Vec<u8>, Vec<u8>)> = Vec::new();
+        let user_data = &mut tuples as *mut Vec<(Vec<u8>, Vec<u8>)> as *mut ffi::c_void;
+
+        unsafe {
+            assert_eq!(set(heap, b"key\0a", b"value\0-1"), ZomdbErrorCode::Ok);
+            assert_eq!(set(heap, b"key\0b", b""), ZomdbErrorCode::Ok);
+            assert_eq!(
+                zomdb_heap_for_each(heap, Some(collect_tuple), user_data),
+                ZomdbErrorCode::Ok
+            );
+
+            let expected: Vec<_> = (*heap)
+                .lock()
+                .iter()
+                .map(|tuple| {
+                    let tuple = tuple.unwrap();
+                    (tuple.key, tuple.value)
+                })
+                .collect();
+            assert_eq!(tuples, expected);
+
+            assert_eq!(set(heap, b"key\0c", b"value\0-3"), ZomdbErrorCode::Ok);
+            assert_eq!(set(heap, b"key\0d", b"value\0-4"), ZomdbErrorCode::Ok);
+            tuples.clear();
+            assert_eq!(
+                zomdb_heap_for_each(heap, Some(collect_tuple), user_data),
+                ZomdbErrorCode::Ok
+            );
+            assert_eq!(tuples.len(), 3);
+
+            zomdb_heap_destroy(heap);
+        }
+    }
+
+    #[test]
+    fn test_heap_put_many() {
+        let dir = tempfile::tempdir().unwrap();
+        let heap = create_temp_heap(&dir);
+
+        let keys: Vec<Vec<u8>> = (0..2000u32).map(|i| i.to_be_bytes().to_vec()).collect();
+        let values: Vec<Vec<u8>> = (0..2000u32)
+            .map(|i| format!("value{}", i).into_bytes())
+            .collect();
+        let key_ptrs: Vec<_> = keys.iter().map(|k| k.as_ptr()).collect();
+        let key_lens: Vec<_> = keys.iter().map(Vec::len).collect();
+        let mut value_ptrs: Vec<_> = values.iter().map(|v| v.as_ptr()).collect();
+        let mut value_lens: Vec<_> = values.iter().map(Vec::len).collect();
+
+        unsafe {
+            let written = zomdb_heap_put_many(
+                heap,
+                key_ptrs.as_ptr(),
+                key_lens.as_ptr(),
+                value_ptrs.as_ptr(),
+                value_lens.as_ptr(),
+                keys.len(),
+            );
+            assert_eq!(written, 2000);
+            for (key, value) in keys.iter().zip(&values) {
+                assert_eq!(get(heap, key).as_ref(), Ok(value));
+            }
+
+            let too_big = vec![0; 2048];
+            value_ptrs[42] = too_big.as_ptr();
+            value_lens[42] = too_big.len();
+            let stats_before = (*heap).lock().stats().unwrap();
+            let written = zomdb_heap_put_many(
+                heap,
+                key_ptrs.as_ptr(),
+                key_lens.as_ptr(),
+                value_ptrs.as_ptr(),
+                value_lens.as_ptr(),
+                keys.len(),
+            );
+            assert_eq!(written, -(ZomdbErrorCode::ValueSize as isize));
+            let message = ffi::CStr::from_ptr(zomdb_last_error_message());
+            assert!(message.to_str().unwrap().starts_with("entry 42: "));
+            assert_eq!((*heap).lock().stats().unwrap(), stats_before);
+
+            zomdb_heap_destroy(heap);
+        }
+    }
+
+    #[test]
+    fn test_create_heap_with_options_read_only() {
+        let dir = tempfile::tempdir().unwrap();
+        let writer = create_temp_heap(&dir);
+        assert_eq!(unsafe { set(writer, b"key", b"value") }, ZomdbErrorCode::Ok);
+
+        let path = dir.path().join("heap");
+        let path = path.to_str().unwrap();
+        let opts = HeapOpenOptions {
+            read_only: HEAP_OPTION_ENABLED,
+            ..zomdb_heap_open_options_default()
+        };
+        let heap = unsafe { zomdb_heap_create_with_options(path.as_ptr(), path.len(), &opts) };
+        assert!(!heap.is_null());
+
+        assert_eq!(unsafe { get(heap, b"key") }, Ok(b"value".to_vec()));
+        assert_eq!(
+            unsafe { set(heap, b"key", b"other") },
+            ZomdbErrorCode::ReadOnly
+        );
+
+        let (key, value) = (
+            ffi::CString::new("key").unwrap(),
+            ffi::CString::new("other").unwrap(),
+        );
+        assert_eq!(
+            unsafe { zomdb_heap_set(heap, key.as_ptr(), value.as_ptr()) },
+            ZomdbErrorCode::ReadOnly
+        );
+        assert_eq!(unsafe { get(writer, b"key") }, Ok(b"value".to_vec()));
+
+        unsafe {
+            zomdb_heap_destroy(heap);
+            zomdb_heap_destroy(writer);
+        }
+    }
+
+    #[test]
+    fn test_heap_open_read_only() {
+        let dir = tempfile::tempdir().unwrap();
+        let writer = create_temp_heap(&dir);
+        assert_eq!(unsafe { set(writer, b"key", b"value") }, ZomdbErrorCode::Ok);
+
+        let path = dir.path().join("heap");
+        let path = path.to_str().unwrap();
+        let heap = unsafe { zomdb_heap_open_read_only(path.as_ptr(), path.len()) };
+        assert!(!heap.is_null());
+
+        assert_eq!(unsafe { get(heap, b"key") }, Ok(b"value".to_vec()));
+        assert_eq!(
+            unsafe { set(heap, b"key", b"other") },
+            ZomdbErrorCode::ReadOnly
+        );
+        assert_eq!(
+            unsafe { zomdb_heap_delete(heap, b"key".as_ptr(), 3) },
+            ZomdbErrorCode::ReadOnly
+        );
+        let mut count = 0;
+        assert_eq!(
+            unsafe { zomdb_heap_count(heap, &mut count) },
+            ZomdbErrorCode::Ok
+        );
+        assert_eq!(count, 1);
+        assert_eq!(
+            unsafe { collect_keys(zomdb_heap_iter(heap)) },
+            vec![b"key".to_vec()]
+        );
+
+        let missing = dir.path().join("missing");
+        let missing_path = missing.to_str().unwrap();
+        let opened =
+            unsafe { zomdb_heap_open_read_only(missing_path.as_ptr(), missing_path.len()) };
+        assert!(opened.is_null());
+        assert_eq!(zomdb_last_error(), ZomdbErrorCode::Io);
+        assert!(!missing.exists());
+
+        unsafe {
+            zomdb_heap_destroy(heap);
+            zomdb_heap_destroy(writer);
+        }
+    }
+
+    #[test]
+    fn test_create_heap_with_options() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("heap");
+        let path = path.to_str().unwrap();
+
+        let opts = HeapOpenOptions {
+            create: HEAP_OPTION_DISABLED,
+            ..Default::default()
+        };
+        let heap = unsafe { zomdb_heap_create_with_options(path.as_ptr(), path.len(), &opts) };
+        assert!(heap.is_null());
+        assert_eq!(zomdb_last_error(), ZomdbErrorCode::Io);
+
+        let opts = HeapOpenOptions {
+            create_new: HEAP_OPTION_ENABLED,
+            max_value_size: 4,
+            ..Default::default()
+        };
+        let heap = unsafe { zomdb_heap_create_with_options(path.as_ptr(), path.len(), &opts) };
+        assert!(!heap.is_null());
+        assert_eq!(unsafe { set(heap, b"key", b"1234") }, ZomdbErrorCode::Ok);
+        assert_eq!(
+            unsafe { set(heap, b"key", b"12345") },
+            ZomdbErrorCode::ValueSize
+        );
+        unsafe { zomdb_heap_destroy(heap) };
+
+        // Null options open the existing heap with the defaults.
+        let heap =
+            unsafe { zomdb_heap_create_with_options(path.as_ptr(), path.len(), ptr::null()) };
+        assert!(!heap.is_null());
+        assert_eq!(unsafe { set(heap, b"key", b"12345") }, ZomdbErrorCode::Ok);
+        unsafe { zomdb_heap_destroy(heap) };
+    }
+
+    #[test]
+    fn test_heap_open_errors() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("file");
+        std::fs::write(&file, b"").unwrap();
+
+        // A path below a regular file can't be opened, also when running as
+        // root, which would ignore permissions.
+        let path = file.join("heap");
+        let path = path.to_str().unwrap();
+        let mut heap = ptr::null_mut();
+        let code = unsafe { zomdb_heap_open(path.as_ptr(), path.len(), ptr::null(), &mut heap) };
+        assert_eq!(code, ZomdbErrorCode::Io);
+        assert!(heap.is_null());
+        assert_eq!(zomdb_last_error(), ZomdbErrorCode::Io);
+        let message = unsafe { ffi::CStr::from_ptr(zomdb_last_error_message()) };
+        assert!(message.to_str().unwrap().contains("Not a directory"));
+        assert!(message.to_str().unwrap().contains(path));
+
+        // The library leaves errno to the OS. EPERM shares its value with
+        // ERR_NOT_FOUND, so reporting through errno would mix them up.
+        const EPERM: i32 = 1;
+        let heap = create_temp_heap(&dir);
+        errno::set_errno(errno::Errno(EPERM));
+        assert_eq!(
+            unsafe { get(heap, b"missing") },
+            Err(ZomdbErrorCode::NotFound)
+        );
+        assert_eq!(zomdb_last_error(), ZomdbErrorCode::NotFound);
+        assert_eq!(errno::errno().0, EPERM);
+
+        // The legacy functions reset the last error when they succeed.
+        unsafe {
+            let iter = zomdb_heap_iter(heap);
+            assert!(zomdb_heap_iter_next(iter).is_null());
+            assert_eq!(zomdb_last_error(), ZomdbErrorCode::Ok);
+            zomdb_heap_iter_destroy(iter);
+            zomdb_heap_destroy(heap);
+        }
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_create_heap_from_bytes() {
+        use std::os::unix::ffi::OsStrExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let subdir = dir.path().join(ffi::OsStr::from_bytes(b"non-utf8-\xff"));
+        std::fs::create_dir(&subdir).unwrap();
+        let path = subdir.join("heap");
+        let path = path.as_os_str().as_bytes();
+
+        let heap = unsafe { zomdb_heap_create_from_bytes(path.as_ptr(), path.len()) };
+        assert!(!heap.is_null());
+        assert_eq!(unsafe { set(heap, b"key", b"value") }, ZomdbErrorCode::Ok);
+        unsafe { zomdb_heap_destroy(heap) };
+        assert!(subdir.join("heap").exists());
+
+        // Only the legacy function insists on UTF-8.
+        let cpath = ffi::CString::new(path).unwrap();
+        assert!(unsafe { zomdb_heap_create(cpath.as_ptr()) }.is_null());
+        assert_eq!(zomdb_last_error(), ZomdbErrorCode::Utf8);
+
+        let heap = unsafe { zomdb_heap_create_from_bytes(path.as_ptr(), path.len()) };
+        assert_eq!(unsafe { get(heap, b"key") }, Ok(b"value".to_vec()));
+        unsafe { zomdb_heap_destroy(heap) };
+    }
+
+    #[test]
+    fn test_zomdb_version() {
+        let version = unsafe { ffi::CStr::from_ptr(zomdb_version()) };
+        let version = version.to_str().unwrap();
+        assert_eq!(version, env!("CARGO_PKG_VERSION"));
+
+        let parts: Vec<u32> = version.split('.').map(|p| p.parse().unwrap()).collect();
+        assert_eq!(parts.len(), 3);
+
+        // The string is static, so every call returns the same pointer.
+        assert_eq!(zomdb_version(), zomdb_version());
+        assert_eq!(zomdb_abi_version(), ZOMDB_ABI_VERSION);
+    }
+
+    #[test]
+    fn test_error_codes() {
+        // The values are part of the ABI and must never change.
+        let codes = [
+            (ZomdbErrorCode::Ok, 0),
+            (ZomdbErrorCode::NotFound, ERR_NOT_FOUND),
+            (ZomdbErrorCode::Io, ERR_IO),
+            (ZomdbErrorCode::Locked, ERR_LOCKED),
+            (ZomdbErrorCode::Poisoned, ERR_POISONED),
+            (ZomdbErrorCode::Busy, ERR_BUSY),
+            (ZomdbErrorCode::ReadOnly, ERR_READ_ONLY),
+            (ZomdbErrorCode::OutOfMemory, ERR_OUT_OF_MEMORY),
+            (ZomdbErrorCode::Cancelled, ERR_CANCELLED),
+            (ZomdbErrorCode::Utf8, ERR_UTF8),
+            (ZomdbErrorCode::KeySize, ERR_KEY_SIZE),
+            (ZomdbErrorCode::ValueSize, ERR_VALUE_SIZE),
+            (ZomdbErrorCode::NulByte, ERR_NUL_BYTE),
+            (ZomdbErrorCode::NullArgument, ERR_NULL_ARGUMENT),
+            (ZomdbErrorCode::InvalidHandle, ERR_INVALID_HANDLE),
+            (ZomdbErrorCode::BufferTooSmall, ERR_BUFFER_TOO_SMALL),
+            (ZomdbErrorCode::EmptyKey, ERR_EMPTY_KEY),
+            (ZomdbErrorCode::Pattern, ERR_PATTERN),
+            (ZomdbErrorCode::Savepoint, ERR_SAVEPOINT),
+            (ZomdbErrorCode::Data, ERR_DATA),
+            (ZomdbErrorCode::ExternallyModified, ERR_EXTERNALLY_MODIFIED),
+            (ZomdbErrorCode::ValueLength, ERR_VALUE_LENGTH),
+            (ZomdbErrorCode::Panic, ERR_PANIC),
+        ];
+        let values: Vec<_> = codes.iter().map(|(_, value)| *value).collect();
+        assert_eq!(
+            values,
+            [
+                0, 1, 10, 11, 12, 13, 14, 15, 16, 30, 31, 32, 33, 34, 35, 36, 37, 38, 39, 50, 51,
+                52, 60
+            ]
+        );
+        for (code, value) in codes {
+            assert_eq!(code as i32, value);
+            assert_eq!(ZomdbErrorCode::from_int(value), Some(code));
+        }
+        assert_eq!(codes.len(), ZomdbErrorCode::ALL.len());
+        assert_eq!(ZomdbErrorCode::from_int(2), None);
+    }
+
+    #[test]
+    fn test_core_error_codes() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("corrupt");
+        // A tuple with invalid flags after one with the key "k".
+        std::fs::write(&path, [b'k', 0, 0, 0, b'k', 0x40, 0, 0]).unwrap();
+        let mut heap = zomdb::Heap::from(path).unwrap();
+        let data = heap.get(b"k").unwrap_err();
+        let pattern = heap.scan_glob(b"\\").err().unwrap();
+
+        let errors = [
+            (zomdb::Error::IO(std::io::Error::other("io")), ERR_IO),
+            (zomdb::Error::Locked, ERR_LOCKED),
+            (
+                zomdb::Error::LockTimeout {
+                    waited: std::time::Duration::from_secs(1),
+                    holder: None,
+                },
+                ERR_LOCKED,
+            ),
+            (
+                zomdb::Error::Poisoned {
+                    cause: Arc::new(zomdb::Error::Locked),
+                },
+                ERR_POISONED,
+            ),
+            (zomdb::Error::Cancelled, ERR_CANCELLED),
+            (
+                zomdb::Error::Input(zomdb::InputError::KeySize(300)),
+                ERR_KEY_SIZE,
+            ),
+            (
+                zomdb::Error::Input(zomdb::InputError::ValueSize(2000)),
+                ERR_VALUE_SIZE,
+            ),
+            (
+                zomdb::Error::Input(zomdb::InputError::EmptyKey),
+                ERR_EMPTY_KEY,
+            ),
+            (pattern, ERR_PATTERN),
+            (
+                zomdb::Error::Savepoint(zomdb::SavepointError::NotFound("s".into())),
+                ERR_SAVEPOINT,
+            ),
+            (data, ERR_DATA),
+            (
+                zomdb::Error::ExternallyModified(zomdb::ExternalModification::Removed),
+                ERR_EXTERNALLY_MODIFIED,
+            ),
+            (
+                zomdb::Error::ValueLength {
+                    expected: 8,
+                    actual: 3,
+                },
+                ERR_VALUE_LENGTH,
+            ),
+        ];
+        for (e, value) in errors {
+            assert_eq!(e.code() as i32, value, "{:?}", e);
+            assert_eq!(error_code(&e) as i32, value, "{:?}", e);
+        }
+
+        let codes = [
+            (zomdb::codes::IO, ERR_IO),
+            (zomdb::codes::LOCKED, ERR_LOCKED),
+            (zomdb::codes::POISONED, ERR_POISONED),
+            (zomdb::codes::CANCELLED, ERR_CANCELLED),
+            (zomdb::codes::KEY_SIZE, ERR_KEY_SIZE),
+            (zomdb::codes::VALUE_SIZE, ERR_VALUE_SIZE),
+            (zomdb::codes::EMPTY_KEY, ERR_EMPTY_KEY),
+            (zomdb::codes::PATTERN, ERR_PATTERN),
+            (zomdb::codes::SAVEPOINT, ERR_SAVEPOINT),
+            (zomdb::codes::DATA, ERR_DATA),
+            (zomdb::codes::EXTERNALLY_MODIFIED, ERR_EXTERNALLY_MODIFIED),
+            (zomdb::codes::VALUE_LENGTH, ERR_VALUE_LENGTH),
+        ];
+        for (code, value) in codes {
+            assert_eq!(code as i32, value);
+        }
+    }
+
+    #[test]
+    fn test_zomdb_strerror() {
+        // Find the error constants in the source, so that a new one can't be
+        // added without a variant.
+        let constants = include_str!("lib.rs")
+            .lines()
+            .filter(|line| line.starts_with("pub const ERR_"))
+            .count();
+        assert_eq!(constants + 1, ZomdbErrorCode::ALL.len());
+
+        let mut messages = Vec::new();
+        for code in ZomdbErrorCode::ALL {
+            let message = unsafe { ffi::CStr::from_ptr(zomdb_strerror(code as ffi::c_int)) };
+            let message = message.to_str().unwrap();
+            assert!(!message.is_empty());
+            assert_ne!(message, "unknown error", "no message for {:?}", code);
+            assert!(
+                !messages.contains(&message),
+                "duplicate message for {:?}",
+                code
+            );
+            messages.push(message);
+        }
+
+        let unknown = unsafe { ffi::CStr::from_ptr(zomdb_strerror(-1)) };
+        assert_eq!(unknown.to_str().unwrap(), "unknown error");
+    }
+
+    #[test]
+    fn test_heap_get_nul_byte() {
+        let dir = tempfile::tempdir().unwrap();
+        let heap = create_temp_heap(&dir);
+        assert_eq!(unsafe { set(heap, b"key", b"va\0lue") }, ZomdbErrorCode::Ok);
+
+        let key = ffi::CString::new("key").unwrap();
+        let value = unsafe { zomdb_heap_get(heap, key.as_ptr()) };
+        assert!(value.is_null());
+        assert_eq!(zomdb_last_error(), ZomdbErrorCode::NulByte);
+
+        unsafe {
+            let iter = zomdb_heap_iter(heap);
+            assert!(zomdb_heap_iter_next(iter).is_null());
+            assert_eq!(zomdb_last_error(), ZomdbErrorCode::NulByte);
+            zomdb_heap_iter_destroy(iter);
+            zomdb_heap_destroy(heap);
+        }
+    }
+
+    #[test]
+    fn test_catch_panic() {
+        let result = catch_panic(|| -> ffi::c_int { panic!("boom") });
+        assert_eq!(result, None);
+        assert_eq!(zomdb_last_error(), ZomdbErrorCode::Panic);
+
+        let message = unsafe { ffi::CStr::from_ptr(zomdb_last_error_message()) };
+        assert_eq!(message.to_str().unwrap(), "panicked: boom");
+    }
+
+    #[test]
+    fn test_null_arguments() {
+        let dir = tempfile::tempdir().unwrap();
+        let heap = create_temp_heap(&dir);
+        let null = ptr::null_mut();
+        let key = b"key";
+
+        unsafe {
+            assert!(zomdb_heap_create(ptr::null()).is_null());
+            assert_eq!(zomdb_last_error(), ZomdbErrorCode::NullArgument);
+            assert!(zomdb_heap_create_with_options(ptr::null(), 1, ptr::null()).is_null());
+            assert_eq!(zomdb_last_error(), ZomdbErrorCode::NullArgument);
+
+            assert!(zomdb_heap_get(null, ptr::null()).is_null());
+            assert_eq!(zomdb_last_error(), ZomdbErrorCode::NullArgument);
+            assert_eq!(
+                zomdb_heap_set(heap, ptr::null(), ptr::null()),
+                ZomdbErrorCode::NullArgument
+            );
+
+            let (mut value_ptr, mut value_len) = (ptr::null_mut(), 0);
+            let code = zomdb_heap_get2(
+                null,
+                key.as_ptr(),
+                key.len(),
+                &mut value_ptr,
+                &mut value_len,
+            );
+            assert_eq!(code, ZomdbErrorCode::NullArgument);
+            let code = zomdb_heap_get2(heap, key.as_ptr(), key.len(), null.cast(), &mut value_len);
+            assert_eq!(code, ZomdbErrorCode::NullArgument);
+            assert_eq!(
+                zomdb_heap_set2(heap, ptr::null(), 3, key.as_ptr(), 3),
+                ZomdbErrorCode::NullArgument
+            );
+            assert_eq!(
+                zomdb_heap_delete(heap, ptr::null(), 3),
+                ZomdbErrorCode::NullArgument
+            );
+            assert_eq!(
+                zomdb_heap_contains(null, key.as_ptr(), 3),
+                -(ZomdbErrorCode::NullArgument as ffi::c_int)
+            );
+            assert_eq!(
+                zomdb_heap_count(heap, ptr::null_mut()),
+                ZomdbErrorCode::NullArgument
+            );
+            assert_eq!(zomdb_heap_sync(null), ZomdbErrorCode::NullArgument);
+            assert_eq!(
+                zomdb_heap_compact(heap, ptr::null_mut()),
+                ZomdbErrorCode::NullArgument
+            );
+            assert_eq!(
+                zomdb_heap_stats(heap, ptr::null_mut()),
+                ZomdbErrorCode::NullArgument
+            );
+            assert_eq!(
+                zomdb_heap_for_each(heap, None, null.cast()),
+                ZomdbErrorCode::NullArgument
+            );
+
+            let (keys, lens) = ([ptr::null::<u8>()], [3usize]);
+            let written = zomdb_heap_put_many(
+                heap,
+                keys.as_ptr(),
+                lens.as_ptr(),
+                keys.as_ptr(),
+                lens.as_ptr(),
+                1,
+            );
+            assert_eq!(written, -(ZomdbErrorCode::NullArgument as isize));
+            let written =
+                zomdb_heap_put_many(heap, ptr::null(), ptr::null(), ptr::null(), ptr::null(), 0);
+            assert_eq!(written, 0);
+
+            assert!(zomdb_heap_iter(null).is_null());
+            assert!(zomdb_heap_iter_prefix(heap, ptr::null(), 1).is_null());
+            assert!(zomdb_heap_iter_next(ptr::null_mut()).is_null());
+            assert_eq!(zomdb_last_error(), ZomdbErrorCode::NullArgument);
+            assert!(zomdb_heap_tuple_key(ptr::null()).is_null());
+            assert!(zomdb_heap_tuple_value(ptr::null()).is_null());
+
+            // Empty buffers may be null.
+            assert_eq!(set(heap, b"key", b""), ZomdbErrorCode::Ok);
+            assert_eq!(
+                zomdb_heap_set2(heap, key.as_ptr(), 3, ptr::null(), 0),
+                ZomdbErrorCode::Ok
+            );
+
+            zomdb_heap_destroy(ptr::null_mut());
+            zomdb_heap_iter_destroy(ptr::null_mut());
+            zomdb_heap_tuple_destroy(ptr::null_mut());
+            zomdb_heap_destroy(heap);
+        }
+    }
+
+    #[test]
+    #[cfg(any(debug_assertions, feature = "handle-checks"))]
+    fn test_invalid_handles() {
+        let dir = tempfile::tempdir().unwrap();
+        let heap = create_temp_heap(&dir);
+        assert_eq!(unsafe { set(heap, b"key", b"value") }, ZomdbErrorCode::Ok);
+
+        unsafe {
+            let iter = zomdb_heap_iter(heap);
+            let tuple = zomdb_heap_iter_next(iter);
+            assert!(!tuple.is_null());
+
+            // Handles of the wrong type.
+            assert_eq!(zomdb_heap_sync(iter.cast()), ZomdbErrorCode::InvalidHandle);
+            assert!(zomdb_heap_iter_next(heap.cast()).is_null());
+            assert_eq!(zomdb_last_error(), ZomdbErrorCode::InvalidHandle);
+            assert!(zomdb_heap_tuple_key(iter.cast()).is_null());
+            zomdb_heap_destroy(tuple.cast_mut().cast());
+            assert_eq!(zomdb_last_error(), ZomdbErrorCode::InvalidHandle);
+
+            zomdb_heap_tuple_destroy(tuple.cast_mut());
+            zomdb_heap_iter_destroy(iter);
+            zomdb_heap_destroy(heap);
+
+            // Handles that were destroyed already.
+            assert_eq!(get(heap, b"key"), Err(ZomdbErrorCode::InvalidHandle));
+            assert!(zomdb_heap_iter_next(iter).is_null());
+            assert_eq!(zomdb_last_error(), ZomdbErrorCode::InvalidHandle);
+            clear_last_error();
+            zomdb_heap_destroy(heap);
+            assert_eq!(zomdb_last_error(), ZomdbErrorCode::InvalidHandle);
+            zomdb_heap_iter_destroy(iter);
+            zomdb_heap_tuple_destroy(tuple.cast_mut());
+        }
+    }
 }
@@ -0,0 +1,150 @@
+//! Diagnostics of the library, delivered to a callback registered by the
+//! host.
+use std::sync::RwLock;
+use std::{ffi, fmt};
+
+/// Log level of messages about failed operations.
+pub const ZOMDB_LOG_ERROR: ffi::c_int = 1;
+
+/// Log level of messages about unexpected conditions that were handled.
+pub const ZOMDB_LOG_WARN: ffi::c_int = 2;
+
+/// Log level of informational messages.
+pub const ZOMDB_LOG_INFO: ffi::c_int = 3;
+
+/// Called with every message the library logs.
+///
+/// The level is one of the ZOMDB_LOG_* constants. The message is only valid
+/// for the duration of the call.
+pub type ZomdbLogCallback =
+    Option<extern "C" fn(level: ffi::c_int, msg: *const ffi::c_char, user_data: *mut ffi::c_void)>;
+
+#[derive(Clone, Copy)]
+struct Logger {
+    callback: extern "C" fn(ffi::c_int, *const ffi::c_char, *mut ffi::c_void),
+    user_data: *mut ffi::c_void,
+}
+
+// The caller of zomdb_set_log_callback promises that the callback can be
+// called with the user data from any thread.
+unsafe impl Send for Logger {}
+unsafe impl Sync for Logger {}
+
+static LOGGER: RwLock<Option<Logger>> = RwLock::new(None);
+
+/// Register the callback that receives the library's log messages.
+///
+/// Passing null removes the current callback, after which messages are
+/// dropped. This is the default. The callback replaces any earlier one.
+///
+/// # Safety
+///
+/// The callback may be called from any thread that calls into the library,
+/// also concurrently, so it and the user data must be safe to use from all
+/// of them. The user data must stay valid until another callback is
+/// registered.
+#[no_mangle]
+pub unsafe extern "C" fn zomdb_set_log_callback(cb: ZomdbLogCallback, user_data: *mut ffi::c_void) {
+    let _ = crate::catch_panic(|| {
+        let logger = cb.map(|callback| Logger {
+            callback,
+            user_data,
+        });
+        *LOGGER.write().unwrap_or_else(|e| e.into_inner()) = logger;
+    });
+}
+
+/// Hands the message to the registered callback, if any.
+pub(crate) fn dispatch(level: ffi::c_int, args: fmt::Arguments<'_>) {
+    // The lock is released before calling back, so that the callback can
+    // log or register another callback itself.
+    let logger = *LOGGER.read().unwrap_or_else(|e| e.into_inner());
+    let Some(logger) = logger else {
+        return;
+    };
+
+    let message = args.to_string().replace('\0', "\\0");
+    if let Ok(message) = ffi::CString::new(message) {
+        (logger.callback)(level, message.as_ptr(), logger.user_data);
+    }
+}
+
+/// Formats a message and logs it at the given level.
+macro_rules! log {
+    ($level:expr, $($arg:tt)*) => {
+        $crate::log::dispatch($level, format_args!($($arg)*))
+    };
+}
+
+pub(crate) use log;
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        zomdb_heap_create_with_options, zomdb_heap_destroy, zomdb_heap_get2, HeapOpenOptions,
+    };
+    use crate::{ZomdbErrorCode, HEAP_OPTION_DISABLED};
+    use std::sync::Mutex;
+    use std::{fs, ptr};
+
+    // The callback is global, so messages of other tests end up here too.
+    static MESSAGES: Mutex<Vec<(ffi::c_int, String)>> = Mutex::new(Vec::new());
+
+    extern "C" fn collect(level: ffi::c_int, msg: *const ffi::c_char, _: *mut ffi::c_void) {
+        let msg = unsafe { ffi::CStr::from_ptr(msg) };
+        let msg = msg.to_string_lossy().into_owned();
+        MESSAGES.lock().unwrap().push((level, msg));
+    }
+
+    fn logged(level: ffi::c_int, prefix: &str) -> bool {
+        let messages = MESSAGES.lock().unwrap();
+        messages
+            .iter()
+            .any(|(l, msg)| *l == level && msg.starts_with(prefix))
+    }
+
+    #[test]
+    fn test_log_callback() {
+        unsafe { zomdb_set_log_callback(Some(collect), ptr::null_mut()) };
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("heap");
+        let path = path.to_str().unwrap();
+
+        let opts = HeapOpenOptions {
+            create: HEAP_OPTION_DISABLED,
+            ..Default::default()
+        };
+        let heap = unsafe { zomdb_heap_create_with_options(path.as_ptr(), path.len(), &opts) };
+        assert!(heap.is_null());
+        assert_eq!(crate::zomdb_last_error(), ZomdbErrorCode::Io);
+        assert!(logged(ZOMDB_LOG_ERROR, "HeapOptions::open: "));
+
+        // A trailer that announces a value larger than allowed, after a
+        // tuple with the key "k" and an empty value.
+        fs::write(path, [b'k', 0, 0, 0, 0xff, 0xff, 0xff, 0xff]).unwrap();
+        let heap =
+            unsafe { zomdb_heap_create_with_options(path.as_ptr(), path.len(), ptr::null()) };
+        assert!(!heap.is_null());
+
+        let (mut value_ptr, mut value_len) = (ptr::null_mut(), 0);
+        let key = b"key";
+        let code = unsafe {
+            zomdb_heap_get2(
+                heap,
+                key.as_ptr(),
+                key.len(),
+                &mut value_ptr,
+                &mut value_len,
+            )
+        };
+        assert_eq!(code, ZomdbErrorCode::Data);
+        assert!(logged(ZOMDB_LOG_ERROR, "heap.get: "));
+
+        unsafe {
+            zomdb_heap_destroy(heap);
+            zomdb_set_log_callback(None, ptr::null_mut());
+        }
+    }
+}
@@ -0,0 +1,150 @@
+use crate::{Error, Heap, Operation};
+use std::{fs, io, path};
+
+/// A directory of named Heaps.
+///
+/// Every Heap is stored in a file named after it, directly inside the
+/// directory. Names must not be empty, start with a dot, contain path
+/// separators or end in ".compact", ".savepoints" or ".savepoints.tmp", so
+/// that they can't point outside of the directory or clash with the files
+/// that compaction and savepoints create next to a Heap.
+pub struct Database {
+    dir: path::PathBuf,
+}
+
+impl Database {
+    /// Opens the Database in the directory, creating the directory if it
+    /// doesn't exist yet.
+    pub fn open(dir: path::PathBuf) -> Result<Self, Error> {
+        fs::create_dir_all(&dir).map_err(|e| Error::io(Operation::Open, Some(&dir), e))?;
+        Ok(Self { dir })
+    }
+
+    /// Returns the directory of the Database.
+    pub fn dir(&self) -> &path::Path {
+        &self.dir
+    }
+
+    /// Opens the Heap with the given name, creating it if it doesn't exist
+    /// yet.
+    ///
+    /// Like [`Heap::from`], this fails with [`Error::Locked`] while the Heap
+    /// is open elsewhere, including through an earlier call to this method.
+    pub fn heap(&self, name: &str) -> Result<Heap, Error> {
+        if !is_valid_name(name) {
+            return Err(Error::IO(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid heap name: {:?}", name),
+            )));
+        }
+        Heap::from(self.dir.join(name))
+    }
+
+    /// Returns the names of the Heaps in the Database, in sorted order.
+    ///
+    /// Files whose names aren't valid Heap names are skipped.
+    pub fn heap_names(&self) -> Result<Vec<String>, Error> {
+        let mut names = Vec::new();
+        let read_error = |e: io::Error| Error::io(Operation::Read, Some(&self.dir), e);
+        for entry in fs::read_dir(&self.dir).map_err(read_error)? {
+            let entry = entry.map_err(read_error)?;
+            let file_type = entry
+                .file_type()
+                .map_err(|e| Error::io(Operation::Stat, Some(&entry.path()), e))?;
+            if !file_type.is_file() {
+                continue;
+            }
+            let Ok(name) = entry.file_name().into_string() else {
+                continue;
+            };
+            if is_valid_name(&name) {
+                names.push(name);
+            }
+        }
+        names.sort();
+        Ok(names)
+    }
+}
+
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with('.')
+        && !name.ends_with(".compact")
+        && !name.ends_with(".savepoints")
+        && !name.ends_with(".savepoints.tmp")
+        && !name.contains(['/', '\\', '\0'])
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Index;
+
+    #[test]
+    fn test_database() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("db");
+
+        let db = Database::open(path.clone()).unwrap();
+        assert_eq!(db.heap_names().unwrap(), Vec::<String>::new());
+        db.heap("users").unwrap().put(b"key", b"alice").unwrap();
+        db.heap("orders").unwrap().put(b"key", b"order").unwrap();
+        fs::create_dir(path.join("nested")).unwrap();
+        fs::write(path.join(".hidden"), b"").unwrap();
+        fs::write(path.join("users.compact"), b"").unwrap();
+        fs::write(path.join("users.savepoints"), b"0\n").unwrap();
+
+        let db = Database::open(path).unwrap();
+        assert_eq!(db.heap_names().unwrap(), vec!["orders", "users"]);
+        let mut users = db.heap("users").unwrap();
+        assert_eq!(users.get(b"key").unwrap(), Some(b"alice".to_vec()));
+        assert!(matches!(db.heap("users"), Err(Error::Locked)));
+    }
+
+    #[test]
+    fn test_database_invalid_names() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = Database::open(dir.path().to_path_buf()).unwrap();
+
+        for name in [
+            "",
+            ".",
+            "..",
+            ".hidden",
+            "a/b",
+            "../escape",
+            "a\\b",
+            "a.compact",
+            "a.savepoints",
+            "a.savepoints.tmp",
+        ] {
+            match db.heap(name) {
+                Err(Error::IO(e)) => assert_eq!(e.kind(), io::ErrorKind::InvalidInput),
+                _ => panic!("{:?} was accepted", name),
+            }
+        }
+        assert_eq!(db.heap_names().unwrap(), Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_database_io_context() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("db");
+
+        // The path of the Database is taken by a file.
+        fs::write(&path, b"").unwrap();
+        let Err(e) = Database::open(path.clone()) else {
+            panic!("opened a file as a database");
+        };
+        let context = e.io_context().unwrap();
+        assert_eq!(context.operation(), Operation::Open);
+        assert_eq!(context.path(), Some(path.as_path()));
+
+        fs::remove_file(&path).unwrap();
+        let db = Database::open(path.clone()).unwrap();
+        fs::remove_dir(&path).unwrap();
+        let e = db.heap_names().unwrap_err();
+        assert_eq!(e.io_context().unwrap().operation(), Operation::Read);
+        assert_eq!(e.io_context().unwrap().path(), Some(path.as_path()));
+    }
+}
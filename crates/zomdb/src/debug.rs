@@ -0,0 +1,268 @@
+//! Tools to inspect the files of Heaps, like those that fail to read.
+
+use crate::heap::{HeapTuple, REFERENCE_FLAG, TOMBSTONE_FLAG};
+use crate::{DeserializationError, Error, Operation};
+use std::io::Write;
+use std::{fs, path};
+
+/// Options to tune [`dump`].
+#[derive(Debug, Clone)]
+pub struct DumpOptions {
+    /// Number of consecutive tuples that have to be readable in front of a
+    /// position before the dump resumes there after undecodable bytes.
+    ///
+    /// Random bytes often look like a single tuple, so requiring a few of
+    /// them avoids resuming in the middle of the damage. A position from
+    /// which tuples can be read up to the start of the file is accepted
+    /// regardless.
+    pub resync_records: usize,
+
+    /// Writes keys as hex instead of escaping their non-ASCII bytes.
+    pub hex_keys: bool,
+}
+
+impl Default for DumpOptions {
+    fn default() -> Self {
+        Self {
+            resync_records: 4,
+            hex_keys: false,
+        }
+    }
+}
+
+/// Summary of a [`dump`].
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct DumpSummary {
+    /// Number of tuples that could be decoded.
+    pub records: u64,
+    /// Number of bytes that don't belong to any decoded tuple.
+    pub garbage_bytes: u64,
+    /// Number of separate regions of such bytes.
+    pub garbage_regions: u64,
+}
+
+/// A part of the file, either a tuple or bytes that couldn't be decoded.
+enum Segment {
+    Record {
+        offset: u64,
+        key: Vec<u8>,
+        value_len: usize,
+        flags: u16,
+    },
+    Garbage {
+        start: usize,
+        end: usize,
+        cause: DeserializationError,
+    },
+}
+
+/// Writes a description of every tuple in the file at path to w, in file
+/// order.
+///
+/// Each tuple is written on a line with its offset, key, value length and
+/// flags. The format has no checksums, so a tuple counts as valid if its
+/// trailer decodes and fits the file. Bytes that don't decode are written
+/// as a hex dump along with the reason, and the dump resumes at the next
+/// position that looks like the end of a tuple again, see
+/// [`DumpOptions::resync_records`].
+///
+/// The whole file is read into memory. Fails only if the file can't be read
+/// or w can't be written to.
+pub fn dump<W: Write>(
+    path: &path::Path,
+    mut w: W,
+    opts: &DumpOptions,
+) -> Result<DumpSummary, Error> {
+    let data = fs::read(path).map_err(|e| Error::io(Operation::Read, Some(path), e))?;
+
+    let mut segments = Vec::new();
+    let mut end = data.len();
+    while end > 0 {
+        match HeapTuple::parse(&data[..end]) {
+            Ok((key, value, flags)) => {
+                let start = end - (key.len() + value.len() + HeapTuple::trailer_len(flags));
+                segments.push(Segment::Record {
+                    offset: start as u64,
+                    key: key.to_vec(),
+                    value_len: value.len(),
+                    flags,
+                });
+                end = start;
+            }
+            Err(cause) => {
+                let start = (0..end)
+                    .rev()
+                    .find(|&start| is_tuple_end(&data, start, opts.resync_records))
+                    .unwrap_or(0);
+                segments.push(Segment::Garbage { start, end, cause });
+                end = start;
+            }
+        }
+    }
+
+    let mut summary = DumpSummary::default();
+    for segment in segments.iter().rev() {
+        match segment {
+            Segment::Record {
+                offset,
+                key,
+                value_len,
+                flags,
+            } => {
+                summary.records += 1;
+                let kind = if flags & TOMBSTONE_FLAG != 0 {
+                    "del"
+                } else if flags & REFERENCE_FLAG != 0 {
+                    "ref"
+                } else {
+                    "put"
+                };
+                write!(w, "{:>10}  {}  key=", offset, kind).map_err(Error::IO)?;
+                if opts.hex_keys {
+                    write_hex(&mut w, key)?;
+                } else {
+                    write!(w, "\"{}\"", key.escape_ascii()).map_err(Error::IO)?;
+                }
+                writeln!(w, "  value_len={}  flags=0x{:04x}", value_len, flags)
+                    .map_err(Error::IO)?;
+            }
+            Segment::Garbage { start, end, cause } => {
+                summary.garbage_bytes += (end - start) as u64;
+                summary.garbage_regions += 1;
+                writeln!(
+                    w,
+                    "{:>10}  garbage  {} bytes that don't form a tuple ending at offset {}: {}",
+                    start,
+                    end - start,
+                    end,
+                    cause
+                )
+                .map_err(Error::IO)?;
+                for (i, line) in data[*start..*end].chunks(16).enumerate() {
+                    write!(w, "{:>10}    ", start + i * 16).map_err(Error::IO)?;
+                    for byte in line {
+                        write!(w, "{:02x} ", byte).map_err(Error::IO)?;
+                    }
+                    let padding = 3 * (16 - line.len());
+                    writeln!(w, "{:padding$} |{}|", "", printable(line)).map_err(Error::IO)?;
+                }
+            }
+        }
+    }
+
+    Ok(summary)
+}
+
+/// Returns whether tuples can be read backwards from end, for the given
+/// number of them or up to the start of the file.
+fn is_tuple_end(data: &[u8], mut end: usize, records: usize) -> bool {
+    for _ in 0..records {
+        if end == 0 {
+            return true;
+        }
+        match HeapTuple::parse(&data[..end]) {
+            Ok((key, value, flags)) => {
+                end -= key.len() + value.len() + HeapTuple::trailer_len(flags)
+            }
+            Err(_) => return false,
+        }
+    }
+    true
+}
+
+fn write_hex<W: Write>(w: &mut W, bytes: &[u8]) -> Result<(), Error> {
+    bytes
+        .iter()
+        .try_for_each(|b| write!(w, "{:02x}", b))
+        .map_err(Error::IO)
+}
+
+/// Replaces the bytes that aren't printable ASCII characters with dots.
+fn printable(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|&b| {
+            if b.is_ascii_graphic() || b == b' ' {
+                b as char
+            } else {
+                '.'
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Heap, Index};
+
+    fn dump_to_string(path: &path::Path) -> (DumpSummary, String) {
+        let mut out = Vec::new();
+        let summary = dump(path, &mut out, &DumpOptions::default()).unwrap();
+        (summary, String::from_utf8(out).unwrap())
+    }
+
+    #[test]
+    fn test_dump() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("heap");
+        let mut heap = Heap::from(path.clone()).unwrap();
+        heap.put(b"key1", b"value1").unwrap();
+        heap.put(b"key\n", b"value2").unwrap();
+        heap.delete(b"key1").unwrap();
+
+        let (summary, out) = dump_to_string(&path);
+        assert_eq!(
+            summary,
+            DumpSummary {
+                records: 3,
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            out,
+            concat!(
+                "         0  put  key=\"key1\"  value_len=6  flags=0x0000\n",
+                "        13  put  key=\"key\\n\"  value_len=6  flags=0x0000\n",
+                "        26  del  key=\"key1\"  value_len=0  flags=0x8000\n",
+            )
+        );
+    }
+
+    #[test]
+    fn test_dump_resynchronizes_after_garbage() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("heap");
+        let mut data = Vec::new();
+        for i in 0..5 {
+            let key = format!("key{}", i);
+            data.extend_from_slice(b"value");
+            data.extend_from_slice(key.as_bytes());
+            data.extend_from_slice(&HeapTuple::trailer(key.len(), 5, 0));
+        }
+        let damaged = data.len();
+        data.extend_from_slice(&[0xff; 20]);
+        for i in 5..7 {
+            let key = format!("key{}", i);
+            data.extend_from_slice(b"value");
+            data.extend_from_slice(key.as_bytes());
+            data.extend_from_slice(&HeapTuple::trailer(key.len(), 5, 0));
+        }
+        fs::write(&path, &data).unwrap();
+
+        let (summary, out) = dump_to_string(&path);
+        assert_eq!(
+            summary,
+            DumpSummary {
+                records: 7,
+                garbage_bytes: 20,
+                garbage_regions: 1,
+            }
+        );
+        let lines: Vec<_> = out.lines().collect();
+        assert!(lines[4].contains("key=\"key4\""));
+        assert!(lines[5].starts_with(&format!("{:>10}  garbage  20 bytes", damaged)));
+        assert!(lines[6].contains("ff ff ff"));
+        assert!(lines.last().unwrap().contains("key=\"key6\""));
+    }
+}
@@ -1,22 +1,167 @@
-use crate::{DeserializationError, Error, Index, InputError, MAX_KEY_SIZE, MAX_VALUE_SIZE};
+use crate::perf::{Counters, PerfCounters};
+use crate::{
+    DataError, DeserializationError, Error, ExternalModification, Index, InputError, Operation,
+    MAX_KEY_SIZE, MAX_VALUE_SIZE,
+};
+use std::borrow::Cow;
 use std::collections::HashSet;
-use std::io::{Read, Seek, Write};
+use std::io::Seek;
+use std::ops::Deref;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 use std::{cmp, fs, io, path};
 
+mod backup;
+mod batch;
+mod compact;
+mod csv;
+mod dedup;
+mod diff;
+mod digest;
+#[cfg(any(test, feature = "testing"))]
+mod fault;
+mod find;
+mod glob;
+mod hash;
+mod inplace;
+mod lock;
+mod observer;
+mod oplog;
+mod options;
+mod page;
+mod prealloc;
+mod reader;
+mod records;
+mod sample;
+mod savepoint;
+mod secondary;
+mod slow;
+mod stats;
+mod sync;
+mod transform;
+mod typed;
+mod verify;
+mod writer;
+
+pub use batch::WriteBatch;
+pub use compact::{CompactOptions, CompactionProgress, CompactionReport};
+pub use csv::CsvOptions;
+pub use diff::{DiffOptions, DiffReport};
+#[cfg(any(test, feature = "testing"))]
+pub use fault::FailingStorage;
+pub use glob::GlobIter;
+pub use observer::HeapObserver;
+pub use oplog::Op;
+pub use options::HeapOptions;
+pub use page::PageToken;
+pub use reader::{HeapReader, ReaderIter, Snapshot};
+pub use records::{Record, Records};
+pub use savepoint::Savepoint;
+pub use secondary::SecondaryIndex;
+pub use stats::{HeapStats, SizeHistogram, SIZE_BUCKETS};
+pub use sync::{SyncHeap, SyncIter};
+pub use transform::ValueTransform;
+pub use verify::VerifyReport;
+pub use writer::{Ack, ReaderFactory, WriterHandle};
+
+/// Maps a key to the form it is stored in.
+type KeyNormalizer = fn(&[u8]) -> Vec<u8>;
+
 /// An on-disk heap data structure.
 pub struct Heap {
     file: fs::File,
+    path: Option<path::PathBuf>,
+    counters: Counters,
+
+    // The length of the file up to which all tuples have been completely
+    // written. Reads never go past it. It is shared with readers of this
+    // Heap, so that they see appends as soon as they complete.
+    committed: Arc<AtomicU64>,
+
+    // Whether the Heap was opened with Heap::open_read_only.
+    read_only: bool,
+
+    // Whether every write is synced to disk before it returns.
+    sync_on_put: bool,
+
+    // Whether the directory is synced after files are created in it or
+    // renamed into it.
+    sync_dir: bool,
+
+    // The largest value accepted by writes, at most MAX_VALUE_SIZE.
+    max_value_size: usize,
+
+    // Maps keys to the form they are stored and looked up in. See
+    // HeapOptions::key_normalizer.
+    normalize_key: Option<KeyNormalizer>,
+
+    // The error that poisoned the Heap, if any. See Heap::verify_and_clear.
+    poison: Mutex<Option<Arc<Error>>>,
+
+    // The indexes updated by writes. See Heap::add_index.
+    indexes: Vec<SecondaryIndex>,
+
+    // The values written recently, if puts deduplicate values. See
+    // HeapOptions::deduplicate_values.
+    values: Option<Mutex<dedup::ValueCache>>,
+
+    // Encodes the values written and decodes those read. See
+    // HeapOptions::value_transform.
+    transform: Option<Arc<dyn ValueTransform>>,
+
+    // Whether the tuples written store the hash of their key. See
+    // HeapOptions::key_hashes.
+    key_hashes: bool,
+
+    // Notified of the operations of the Heap. See HeapOptions::observer.
+    observer: Option<Arc<dyn HeapObserver>>,
+
+    // Whether the op log was read from the Heap, which rules out in-place
+    // updates. See Heap::update_in_place.
+    logged: AtomicBool,
+
+    // Operations taking longer are logged. See
+    // HeapOptions::slow_op_threshold.
+    slow_op_threshold: Option<Duration>,
+
+    #[cfg(any(test, feature = "testing"))]
+    faults: fault::FailingStorage,
 }
 
 impl Heap {
-    /// The maximum byte size of a tuple on disk.
-    const MAX_TUPLE_SIZE: usize = MAX_KEY_SIZE + MAX_VALUE_SIZE + 3;
+    /// The maximum byte size of a tuple on disk, with a tag and a key hash.
+    const MAX_TUPLE_SIZE: usize = MAX_KEY_SIZE + MAX_VALUE_SIZE + 1 + hash::HASH_LEN + 3;
 
     /// The minimum byte size of a tuple on disk.
     const MIN_TUPLE_SIZE: usize = 1 + 3; // 1 byte key + 0 byte value
 
-    fn new(file: fs::File) -> Self {
-        Self { file }
+    fn new(file: fs::File) -> Result<Self, Error> {
+        let committed = file
+            .metadata()
+            .map_err(|e| Error::io(Operation::Stat, None, e))?
+            .len();
+        Ok(Self {
+            file,
+            path: None,
+            counters: Counters::default(),
+            committed: Arc::new(AtomicU64::new(committed)),
+            read_only: false,
+            sync_on_put: false,
+            sync_dir: true,
+            max_value_size: MAX_VALUE_SIZE,
+            normalize_key: None,
+            poison: Mutex::new(None),
+            indexes: Vec::new(),
+            values: None,
+            transform: None,
+            key_hashes: false,
+            observer: None,
+            logged: AtomicBool::new(false),
+            slow_op_threshold: None,
+            #[cfg(any(test, feature = "testing"))]
+            faults: fault::FailingStorage::default(),
+        })
     }
 
     /// Creates a new Heap from the provided path.
@@ -24,103 +169,902 @@ impl Heap {
     /// If the path points to an existing Heap, it will be opened and reused.
     /// If it points to a new location, a new file is going to be created to
     /// back the Heap.
+    ///
+    /// The Heap takes an exclusive lock on the file, so that only a single
+    /// writer can use it at a time. If another Heap holds the lock already,
+    /// [`Error::Locked`] is returned. Use [`Heap::open_read_only`] to read
+    /// the file from other processes while it is being written.
+    ///
+    /// Use [`HeapOptions`] to configure how the Heap is opened.
     pub fn from(path: path::PathBuf) -> Result<Self, Error> {
-        let file = fs::OpenOptions::new()
+        HeapOptions::new().open(path)
+    }
+
+    /// Opens an existing Heap for reading only.
+    ///
+    /// Any number of read-only Heaps can be opened alongside the single
+    /// writer. They don't take a lock, because the writer only appends to
+    /// the file, except for [`Heap::update_in_place`], whose writes they may
+    /// see halfway through. A read-only Heap sees the tuples
+    /// that were complete when it was opened; call [`Heap::refresh`] to pick
+    /// up the ones appended since.
+    ///
+    /// Writing to a read-only Heap fails with an I/O error.
+    pub fn open_read_only(path: path::PathBuf) -> Result<Self, Error> {
+        HeapOptions::new().read_only(true).open(path)
+    }
+
+    /// Returns whether the Heap was opened for reading only.
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    /// Opens the file at the path for appending and takes the writer lock.
+    fn open_locked(path: &path::Path) -> Result<fs::File, Error> {
+        let file = Self::open_file(path)?;
+        lock_exclusive(&file, path)?;
+        Ok(file)
+    }
+
+    /// Reopens the Heap from its path.
+    ///
+    /// Use this to follow a file that was replaced, for example by another
+    /// process compacting it, or to continue after the file was truncated.
+    /// Readers created with [`Heap::reader`] keep reading the previous file.
+    pub fn reload(&mut self) -> Result<(), Error> {
+        let path = self.path.as_ref().ok_or_else(|| {
+            Error::IO(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "reloading requires a heap backed by a path",
+            ))
+        })?;
+
+        let file = if self.read_only {
+            fs::File::open(path).map_err(|e| Error::io(Operation::Open, Some(path), e))?
+        } else {
+            // The path may still point to the file we hold the lock on, in
+            // which case locking it again would fail.
+            self.file
+                .unlock()
+                .map_err(|e| Error::io(Operation::Lock, Some(path), e))?;
+            let file = Self::open_locked(path);
+            if file.is_err() {
+                let _ = self.file.try_lock();
+            }
+            file?
+        };
+
+        let len = file
+            .metadata()
+            .map_err(|e| Error::io(Operation::Stat, Some(path), e))?
+            .len();
+        log::debug!("reloaded {} with {} bytes", self.log_name(), len);
+        self.file = file;
+        self.committed = Arc::new(AtomicU64::new(len));
+        self.forget_values();
+
+        Ok(())
+    }
+
+    /// Makes tuples appended by another writer visible to this Heap and
+    /// returns the new length of the visible part of the file.
+    ///
+    /// The file is re-stated and the appended bytes are only accepted if
+    /// they parse into tuples that line up exactly with the previous end.
+    /// A write that is still in progress, or was torn by a crash, fails that
+    /// check, in which case the visible length stays unchanged. This way a
+    /// reader never sees a partially written tuple.
+    ///
+    /// Compaction by the writer replaces the file, which is not picked up
+    /// by a refresh. Use [`Heap::reload`] to read the compacted file.
+    pub fn refresh(&mut self) -> Result<u64, Error> {
+        let committed = self.committed_len();
+        let len = self.stat()?.len();
+        if len <= committed || !self.is_complete(committed, len)? {
+            return Ok(committed);
+        }
+
+        self.committed.store(len, Ordering::Release);
+        Ok(len)
+    }
+
+    /// Returns whether the bytes between start and end consist of complete
+    /// tuples only.
+    fn is_complete(&self, start: u64, end: u64) -> Result<bool, Error> {
+        let mut scanner = Scanner::raw();
+        scanner.reset(end);
+
+        loop {
+            match scanner.next_tuple(self) {
+                Ok(Some(tuple)) if tuple.offset > start => {}
+                Ok(Some(tuple)) => return Ok(tuple.offset == start),
+                Ok(None) => return Ok(false),
+                Err(Error::Data(_)) => return Ok(false),
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    fn open_file(path: &path::Path) -> Result<fs::File, Error> {
+        fs::OpenOptions::new()
             .read(true)
-            .write(true)
             .append(true)
             .create(true)
             .open(path)
-            .map_err(Error::IO)?;
-        Ok(Self::new(file))
+            .map_err(|e| Error::io(Operation::Open, Some(path), e))
+    }
+
+    /// Writes multiple key-value pairs with as few write calls as possible.
+    ///
+    /// All pairs are validated before anything is written, so an invalid
+    /// pair leaves the Heap untouched.
+    pub fn put_many<'t, I>(&mut self, tuples: I) -> Result<(), Error>
+    where
+        I: IntoIterator<Item = (&'t [u8], &'t [u8])>,
+    {
+        self.append_many(tuples).map(|_| ())
+    }
+
+    /// Sets the value of the key like [`Index::put`], and tags its tuple
+    /// with an application-defined byte, like the kind of the record.
+    ///
+    /// The tag is returned by [`HeapTuple::tag`] and can be filtered on with
+    /// [`Iter::with_tag`]. It takes one more byte on disk, and compaction
+    /// keeps it.
+    pub fn put_tagged(&mut self, key: &[u8], value: &[u8], tag: u8) -> Result<(), Error> {
+        self.put_with_tag(key, value, Some(tag))
+    }
+
+    fn put_with_tag(&mut self, key: &[u8], value: &[u8], tag: Option<u8>) -> Result<(), Error> {
+        let timer = self.time_op();
+        let offset = self.committed_len();
+        let result = self.append(key, value, tag).map(|_| ());
+        self.log_slow_op(timer, "put", key.len());
+        self.observe(&result, |o, _| o.on_put(key, value.len(), offset));
+        result
+    }
+
+    /// Checks whether a key-value pair can be stored in a Heap.
+    ///
+    /// Writes perform the same check, so this is only useful to find an
+    /// invalid pair before writing, like the one that caused put_many to
+    /// fail.
+    pub fn validate(key: &[u8], value: &[u8]) -> Result<(), Error> {
+        validate(key, value)
+    }
+
+    /// Validates and appends key-value pairs, returning the number of bytes
+    /// written.
+    fn append_many<'t, I>(&self, tuples: I) -> Result<u64, Error>
+    where
+        I: IntoIterator<Item = (&'t [u8], &'t [u8])>,
+    {
+        let mut entries = Vec::new();
+        let mut values = Vec::new();
+        let mut pending = dedup::PendingValues::default();
+        let mut offset = self.committed_len();
+        for (key, value) in tuples {
+            let key = self.normalize(key);
+            let (encoded, flags) = self.encode(&key, value)?;
+            validate(&key, &encoded)?;
+            self.check_value_size(&encoded)?;
+            let (stored, flags) = match self.find_pending_duplicate(&encoded, &pending) {
+                Some(reference) => (Cow::Owned(reference.to_vec()), flags | REFERENCE_FLAG),
+                None => {
+                    self.add_pending_value(&mut pending, offset, &encoded);
+                    (encoded, flags)
+                }
+            };
+            let (flags, hash) = self.hash_key(&key, flags);
+            let trailer = HeapTuple::trailer(key.len(), stored.len(), flags);
+            offset += (stored.len() + key.len() + hash.map_or(0, |h| h.len()) + 3) as u64;
+            entries.push((key, stored, hash, trailer));
+            values.push(value);
+        }
+        let update = self.prepare_index_update(
+            entries
+                .iter()
+                .zip(&values)
+                .map(|((key, _, _, _), value)| (&**key, Some(*value))),
+        )?;
+
+        let mut slices = Vec::with_capacity(entries.len() * 4);
+        for (key, value, hash, trailer) in &entries {
+            slices.push(io::IoSlice::new(value));
+            slices.push(io::IoSlice::new(key));
+            slices.push(io::IoSlice::new(
+                hash.as_ref().map_or(&[], |hash| &hash[..]),
+            ));
+            slices.push(io::IoSlice::new(trailer));
+        }
+
+        let written = self.write_vectored(&mut slices)?;
+        self.remember_pending_values(pending);
+        self.apply_index_update(update)?;
+        Ok(written)
+    }
+
+    /// Validates and appends a single key-value pair, with the tag if given,
+    /// returning the number of bytes written.
+    fn append(&self, key: &[u8], value: &[u8], tag: Option<u8>) -> Result<u64, Error> {
+        let key = &*self.normalize(key);
+        let (encoded, flags) = self.encode(key, value)?;
+        validate(key, &encoded)?;
+        self.check_value_size(&encoded)?;
+        let update = self.prepare_index_update([(key, Some(value))])?;
+
+        let offset = self.committed_len();
+        let reference = self.find_duplicate(&encoded);
+        let (stored, mut flags) = match &reference {
+            Some(reference) => (&reference[..], flags | REFERENCE_FLAG),
+            None => (&*encoded, flags),
+        };
+        if tag.is_some() {
+            flags |= TAG_FLAG;
+        }
+        let tag = tag.map(|tag| [tag]);
+        let (flags, hash) = self.hash_key(key, flags);
+        let trailer = HeapTuple::trailer(key.len(), stored.len(), flags);
+        let mut slices = [
+            io::IoSlice::new(stored),
+            io::IoSlice::new(key),
+            io::IoSlice::new(tag.as_ref().map_or(&[], |tag| &tag[..])),
+            io::IoSlice::new(hash.as_ref().map_or(&[], |hash| &hash[..])),
+            io::IoSlice::new(&trailer),
+        ];
+
+        let written = self.write_vectored(&mut slices)?;
+        if reference.is_none() {
+            self.remember_value(offset, &encoded);
+        }
+        self.apply_index_update(update)?;
+        Ok(written)
+    }
+
+    /// Appends a tombstone marking the key as deleted, returning the number
+    /// of bytes written.
+    fn append_tombstone(&self, key: &[u8]) -> Result<u64, Error> {
+        let key = &*self.normalize(key);
+        validate(key, &[])?;
+        let update = self.prepare_index_update([(key, None)])?;
+
+        let (flags, hash) = self.hash_key(key, TOMBSTONE_FLAG);
+        let trailer = HeapTuple::trailer(key.len(), 0, flags);
+        let mut slices = [
+            io::IoSlice::new(key),
+            io::IoSlice::new(hash.as_ref().map_or(&[], |hash| &hash[..])),
+            io::IoSlice::new(&trailer),
+        ];
+
+        let written = self.write_vectored(&mut slices)?;
+        self.apply_index_update(update)?;
+        Ok(written)
+    }
+
+    /// Writes all slices to the end of the file, returning the number of
+    /// bytes written.
+    ///
+    /// Vectored writes may be short, in which case the remaining bytes are
+    /// written with further calls until all slices are exhausted. Only then
+    /// the written bytes are published to readers.
+    fn write_vectored(&self, mut slices: &mut [io::IoSlice<'_>]) -> Result<u64, Error> {
+        self.check_writable()?;
+        self.check_poisoned()?;
+
+        let mut written = 0;
+
+        io::IoSlice::advance_slices(&mut slices, 0);
+        while !slices.is_empty() {
+            match self.write_file(slices) {
+                Ok(0) => return Err(self.write_failed(written, io::ErrorKind::WriteZero.into())),
+                Ok(n) => {
+                    io::IoSlice::advance_slices(&mut slices, n);
+                    written += n as u64;
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(self.write_failed(written, e)),
+            }
+        }
+        self.committed.fetch_add(written, Ordering::Release);
+
+        if self.sync_on_put {
+            self.sync()?;
+        }
+
+        Ok(written)
+    }
+
+    /// Issues a single vectored write against the file.
+    #[cfg(not(any(test, feature = "testing")))]
+    fn write_file(&self, slices: &[io::IoSlice<'_>]) -> io::Result<usize> {
+        use std::io::Write;
+        (&self.file).write_vectored(slices)
+    }
+
+    /// Issues a single vectored write against the file, unless a test
+    /// injected a fault.
+    #[cfg(any(test, feature = "testing"))]
+    fn write_file(&self, slices: &[io::IoSlice<'_>]) -> io::Result<usize> {
+        self.faults.write_vectored(&self.file, slices)
+    }
+
+    /// Fills buf with the bytes of the file starting at offset.
+    #[cfg(not(any(test, feature = "testing")))]
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<()> {
+        read_exact_at(&self.file, buf, offset)
+    }
+
+    /// Fills buf with the bytes of the file starting at offset, unless a
+    /// test injected a fault.
+    #[cfg(any(test, feature = "testing"))]
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<()> {
+        self.faults.read_exact_at(&self.file, buf, offset)
+    }
+
+    /// Returns the key in the form it is stored in, see
+    /// [`HeapOptions::key_normalizer`]. Only allocates if there is a
+    /// normalizer.
+    fn normalize<'k>(&self, key: &'k [u8]) -> Cow<'k, [u8]> {
+        match self.normalize_key {
+            Some(normalize) => Cow::Owned(normalize(key)),
+            None => Cow::Borrowed(key),
+        }
+    }
+
+    /// Syncs the directory that holds the file at path, so that a crash
+    /// can't lose the file's entry in it.
+    ///
+    /// Does nothing if the Heap was opened without directory syncs.
+    fn sync_parent(&self, path: &path::Path) -> Result<(), Error> {
+        if !self.sync_dir {
+            return Ok(());
+        }
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => path::Path::new("."),
+        };
+        self.sync_dir_at(dir)
+            .map_err(|e| Error::io(Operation::Sync, Some(dir), e))
+    }
+
+    #[cfg(not(any(test, feature = "testing")))]
+    fn sync_dir_at(&self, dir: &path::Path) -> io::Result<()> {
+        sync_dir(dir)
+    }
+
+    /// Syncs the directory, unless a test injected a fault.
+    #[cfg(any(test, feature = "testing"))]
+    fn sync_dir_at(&self, dir: &path::Path) -> io::Result<()> {
+        self.faults.sync_dir(dir)
+    }
+
+    /// Poisons the Heap if a failed write left some of its bytes behind.
+    fn write_failed(&self, written: u64, e: io::Error) -> Error {
+        let e = Error::io(Operation::Write, self.path.as_deref(), e);
+        if let (true, Error::IO(e)) = (written > 0, &e) {
+            self.poison(Error::IO(io::Error::new(e.kind(), e.to_string())));
+        }
+        e
+    }
+
+    /// Checks the value against the limit the Heap was opened with.
+    fn check_value_size(&self, value: &[u8]) -> Result<(), Error> {
+        if value.len() > self.max_value_size {
+            return Err(Error::Input(InputError::ValueSize(value.len())));
+        }
+        Ok(())
+    }
+
+    fn check_writable(&self) -> Result<(), Error> {
+        if self.read_only {
+            return Err(Error::IO(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "heap was opened read-only",
+            )));
+        }
+        Ok(())
+    }
+
+    /// Checks that the Heap is intact and makes it accept writes again.
+    ///
+    /// A Heap is poisoned by errors after which appending to the file could
+    /// make things worse:
+    ///
+    /// - a write that failed after some of its bytes were written,
+    /// - data errors while reading tuples, which indicate a corrupted file,
+    /// - and [`Error::ExternallyModified`].
+    ///
+    /// Other errors, like input errors, missing keys or failed reads, never
+    /// poison the Heap. Once poisoned, all writes fail with
+    /// [`Error::Poisoned`] until this method succeeds or the Heap is opened
+    /// again. Reads keep working.
+    ///
+    /// Bytes of failed writes are truncated and the last tuple is read to
+    /// make sure the end of the file is intact. If a check fails, its error
+    /// is returned and the Heap stays poisoned.
+    pub fn verify_and_clear(&mut self) -> Result<(), Error> {
+        self.check_file()?;
+
+        let committed = self.committed_len();
+        let len = self.stat()?.len();
+        // The bytes after the committed end of a read-only Heap belong to
+        // its writer.
+        if len > committed && !self.read_only {
+            log::warn!(
+                "truncating {} bytes of failed writes from {}",
+                len - committed,
+                self.log_name()
+            );
+            self.file
+                .set_len(committed)
+                .map_err(|e| Error::io(Operation::Truncate, self.path.as_deref(), e))?;
+            // Files that weren't opened for appending would otherwise
+            // continue writing after the truncated bytes.
+            (&self.file)
+                .seek(io::SeekFrom::End(0))
+                .map_err(|e| Error::io(Operation::Seek, self.path.as_deref(), e))?;
+        }
+
+        let mut scanner = Scanner::raw();
+        scanner.reset(committed);
+        scanner.next_tuple(self)?;
+
+        *self.poison.get_mut().unwrap_or_else(|e| e.into_inner()) = None;
+        log::info!("{} accepts writes again", self.log_name());
+        Ok(())
+    }
+
+    /// Remembers the first error that poisoned the Heap.
+    fn poison(&self, cause: Error) {
+        let mut poison = self.poison.lock().unwrap_or_else(|e| e.into_inner());
+        if poison.is_none() {
+            log::warn!(
+                "{} refuses writes after an error: {}",
+                self.log_name(),
+                cause
+            );
+            *poison = Some(Arc::new(cause));
+        }
+    }
+
+    /// Poisons the Heap if the result holds an error that calls for it.
+    fn track<T>(&self, result: Result<T, Error>) -> Result<T, Error> {
+        let cause = match &result {
+            Err(Error::Data(e)) => Error::Data(e.clone()),
+            Err(Error::ExternallyModified(e)) => Error::ExternallyModified(e.clone()),
+            _ => return result,
+        };
+        self.poison(cause);
+        result
+    }
+
+    fn check_poisoned(&self) -> Result<(), Error> {
+        let poison = self.poison.lock().unwrap_or_else(|e| e.into_inner());
+        match &*poison {
+            Some(cause) => Err(Error::Poisoned {
+                cause: cause.clone(),
+            }),
+            None => Ok(()),
+        }
+    }
+
+    /// Returns the metadata of the file.
+    fn stat(&self) -> Result<fs::Metadata, Error> {
+        self.file
+            .metadata()
+            .map_err(|e| Error::io(Operation::Stat, self.path.as_deref(), e))
+    }
+
+    /// Makes sure that the file still holds all committed tuples and that
+    /// the path of the Heap still points to it.
+    ///
+    /// Scanning a file that was truncated or replaced behind our back would
+    /// otherwise fail with confusing errors or return stale data.
+    fn check_file(&self) -> Result<(), Error> {
+        let meta = self.stat()?;
+        let committed = self.committed_len();
+        if meta.len() < committed {
+            return Err(Error::ExternallyModified(ExternalModification::Truncated {
+                committed,
+                len: meta.len(),
+            }));
+        }
+
+        #[cfg(unix)]
+        if let Some(path) = &self.path {
+            use std::os::unix::fs::MetadataExt;
+
+            match fs::metadata(path) {
+                Ok(current) if (current.dev(), current.ino()) == (meta.dev(), meta.ino()) => {}
+                Ok(_) => return Err(Error::ExternallyModified(ExternalModification::Replaced)),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                    return Err(Error::ExternallyModified(ExternalModification::Removed))
+                }
+                Err(e) => return Err(Error::io(Operation::Stat, Some(path), e)),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Names the Heap in log messages.
+    fn log_name(&self) -> String {
+        match &self.path {
+            Some(path) => format!("heap {}", path.display()),
+            None => "heap".to_string(),
+        }
+    }
+
+    /// Returns the length of the file up to which all tuples are complete.
+    fn committed_len(&self) -> u64 {
+        self.committed.load(Ordering::Acquire)
+    }
+
+    /// Looks up the latest value of a key among the tuples ending before
+    /// the end offset.
+    fn find(&self, key: &[u8], end: u64) -> Result<Option<Vec<u8>>, Error> {
+        self.find_with(key, end, |value| value.to_vec())
+    }
+
+    /// Looks up the latest value of a key like find, but passes it to f
+    /// instead of copying it.
+    fn find_with<T>(
+        &self,
+        key: &[u8],
+        end: u64,
+        f: impl FnOnce(&[u8]) -> T,
+    ) -> Result<Option<T>, Error> {
+        let key = self.normalize(key);
+        let result = self.check_file().and_then(|_| self.scan_for(&key, end, f));
+        self.track(result)
+    }
+
+    fn scan_for<T>(
+        &self,
+        key: &[u8],
+        end: u64,
+        f: impl FnOnce(&[u8]) -> T,
+    ) -> Result<Option<T>, Error> {
+        let mut scanner = Scanner::lookup(key);
+        scanner.reset(end);
+
+        while let Some(tuple) = scanner.next_tuple(self)? {
+            // Only the bytes of keys of the same length are compared.
+            if tuple.key.len() == key.len() {
+                self.counters.compare_key(key.len());
+            }
+            if tuple.key == key {
+                return Ok((!tuple.tombstone).then(|| f(tuple.value)));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Returns whether the key has a value, without reading the value into
+    /// memory.
+    pub fn contains(&self, key: &[u8]) -> Result<bool, Error> {
+        self.find_with(key, self.committed_len(), |_| ())
+            .map(|found| found.is_some())
+    }
+
+    /// Looks up the latest value of the key and passes it to f, without
+    /// copying it into a new allocation.
+    ///
+    /// Returns None without calling f if the key has no value.
+    pub fn get_with<T>(&self, key: &[u8], f: impl FnOnce(&[u8]) -> T) -> Result<Option<T>, Error> {
+        self.find_with(key, self.committed_len(), f)
+    }
+
+    /// Returns the number of keys that have a value.
+    ///
+    /// This scans the whole file and keeps every key in memory, but none of
+    /// the values.
+    pub fn len(&self) -> Result<usize, Error> {
+        let result = self.check_file().and_then(|_| self.count_keys(&[]));
+        self.track(result)
+    }
+
+    /// Returns the number of keys starting with the prefix that have a value.
+    ///
+    /// Like [`Heap::len`], this keeps the matching keys in memory, but none
+    /// of the values.
+    pub fn count_prefix(&self, prefix: &[u8]) -> Result<u64, Error> {
+        let prefix = self.normalize(prefix);
+        let result = self.check_file().and_then(|_| self.count_keys(&prefix));
+        self.track(result).map(|count| count as u64)
+    }
+
+    /// Returns whether no key has a value.
+    pub fn is_empty(&self) -> Result<bool, Error> {
+        self.iter().next().transpose().map(|tuple| tuple.is_none())
+    }
+
+    /// Counts the keys starting with the prefix that have a value.
+    fn count_keys(&self, prefix: &[u8]) -> Result<usize, Error> {
+        let mut scanner = Scanner::new();
+        scanner.reset(self.committed_len());
+        let mut seen_keys = HashSet::new();
+        let mut count = 0;
+
+        while let Some(tuple) = scanner.next_tuple(self)? {
+            if !tuple.key.starts_with(prefix) || seen_keys.contains(tuple.key) {
+                continue;
+            }
+            seen_keys.insert(tuple.key.to_vec());
+
+            if !tuple.tombstone {
+                count += 1;
+            }
+        }
+
+        Ok(count)
+    }
+
+    /// Returns the smallest key that has a value, comparing keys byte by
+    /// byte.
+    ///
+    /// Like [`Heap::len`], this keeps every key in memory, but none of the
+    /// values. Returns None if the Heap is empty.
+    pub fn min_key(&self) -> Result<Option<Vec<u8>>, Error> {
+        let result = self
+            .check_file()
+            .and_then(|_| self.extreme_key(cmp::Ordering::Less));
+        self.track(result)
+    }
+
+    /// Returns the largest key that has a value, comparing keys byte by
+    /// byte.
+    ///
+    /// See [`Heap::min_key`].
+    pub fn max_key(&self) -> Result<Option<Vec<u8>>, Error> {
+        let result = self
+            .check_file()
+            .and_then(|_| self.extreme_key(cmp::Ordering::Greater));
+        self.track(result)
+    }
+
+    /// Returns the live key that orders before all others as given, if any.
+    fn extreme_key(&self, order: cmp::Ordering) -> Result<Option<Vec<u8>>, Error> {
+        let mut scanner = Scanner::new();
+        scanner.reset(self.committed_len());
+        let mut seen_keys = HashSet::new();
+        let mut extreme: Option<Vec<u8>> = None;
+
+        while let Some(tuple) = scanner.next_tuple(self)? {
+            if seen_keys.contains(tuple.key) {
+                continue;
+            }
+            seen_keys.insert(tuple.key.to_vec());
+
+            let replaces = match &extreme {
+                Some(key) => tuple.key.cmp(key) == order,
+                None => true,
+            };
+            if !tuple.tombstone && replaces {
+                extreme = Some(tuple.key.to_vec());
+            }
+        }
+
+        Ok(extreme)
+    }
+
+    /// Flushes all written tuples to disk.
+    ///
+    /// Tuples are written to the file without buffering, but the operating
+    /// system may keep them in memory until this method is called.
+    pub fn sync(&self) -> Result<(), Error> {
+        self.file
+            .sync_data()
+            .map_err(|e| Error::io(Operation::Sync, self.path.as_deref(), e))?;
+        self.counters.fsync();
+        Ok(())
+    }
+
+    /// Returns a snapshot of the I/O counters accumulated by this Heap.
+    pub fn perf_counters(&self) -> PerfCounters {
+        self.counters.snapshot()
+    }
+
+    /// Resets all I/O counters to zero.
+    pub fn reset_counters(&self) {
+        self.counters.reset()
     }
 
     /// Returns an Iter that starts iterating from the last inserted tuple.
     pub fn iter(&self) -> Iter<'_> {
         Iter {
-            file: &self.file,
-            initialized: false,
-
-            file_size: 0,
-            file_offset: 0,
-
-            chunk_buffer: Vec::new(),
-            buffer_offset: 0,
-            overflow: Vec::new(),
+            tuples: Tuples::new(self, None),
+        }
+    }
 
-            seen_keys: HashSet::new(),
+    /// Returns an Iter over the tuples whose keys start with the prefix.
+    ///
+    /// Tuples of other keys are skipped without copying them.
+    pub fn scan_prefix(&self, prefix: &[u8]) -> Iter<'_> {
+        Iter {
+            tuples: Tuples::new(self, None).with_prefix(&self.normalize(prefix)),
         }
     }
 }
 
+/// Marks a tuple as a tombstone for a deleted key.
+///
+/// Flags are stored in the upper bits of the encoded value size, which are
+/// never set by the value sizes we allow.
+pub(crate) const TOMBSTONE_FLAG: u16 = 0x8000;
+
+/// Marks a tuple whose value is stored by an earlier tuple. See
+/// [`HeapOptions::deduplicate_values`].
+///
+/// The value of the tuple is a reference to the earlier value instead: its
+/// offset in the file as eight big-endian bytes followed by its length as
+/// two.
+pub(crate) const REFERENCE_FLAG: u16 = 0x4000;
+
+/// The length of the value of a tuple with the REFERENCE_FLAG.
+pub(crate) const REFERENCE_LEN: usize = 10;
+
+/// Marks a tuple whose value is stored encoded by a [`ValueTransform`].
+///
+/// The flag may be combined with the REFERENCE_FLAG, in which case the
+/// referenced value is the encoded one.
+pub(crate) const TRANSFORM_FLAG: u16 = 0x2000;
+
+/// Marks a tuple that carries a tag, see [`Heap::put_tagged`].
+///
+/// The tag is stored in a byte between the key and the trailer. The flag
+/// may be combined with all others but the TOMBSTONE_FLAG.
+pub(crate) const TAG_FLAG: u16 = 0x0800;
+
+/// Marks a tuple that stores the hash of its key, see
+/// [`HeapOptions::key_hashes`].
+///
+/// The hash is stored in the 4 bytes in front of the trailer, behind the
+/// tag if there is one. The flag may be combined with all others.
+pub(crate) const HASH_FLAG: u16 = 0x1000;
+
+/// The bits of the encoded value size that hold the actual size.
+const VALUE_SIZE_MASK: u16 = 0x07ff;
+
 /// On-disk representation of key-value pairs.
 #[derive(Debug, PartialEq)]
 pub struct HeapTuple {
     pub key: Vec<u8>,
     pub value: Vec<u8>,
+    tag: Option<u8>,
 }
 
 impl HeapTuple {
     /// Creates a new HeapTuple from a known key-value pair.
+    ///
+    /// The pair must have been validated or parsed from disk before.
     fn from(key: &[u8], value: &[u8]) -> Self {
-        assert!(key.len() <= MAX_KEY_SIZE);
-        assert!(!key.is_empty());
-        assert!(value.len() <= MAX_VALUE_SIZE);
+        debug_assert!(key.len() <= MAX_KEY_SIZE);
+        debug_assert!(!key.is_empty());
+        debug_assert!(value.len() <= MAX_VALUE_SIZE);
         HeapTuple {
             key: key.to_vec(),
             value: value.to_vec(),
+            tag: None,
         }
     }
 
+    /// Returns the tag the tuple was written with by [`Heap::put_tagged`],
+    /// or None if it was written without one.
+    pub fn tag(&self) -> Option<u8> {
+        self.tag
+    }
+
+    #[cfg(test)]
     fn disk_len(&self) -> usize {
         self.key.len() + self.value.len() + 3
     }
 
+    #[cfg(test)]
     fn serialize(&self) -> Vec<u8> {
-        assert!(self.key.len() <= MAX_KEY_SIZE);
-        assert!(self.value.len() <= MAX_VALUE_SIZE);
-        // 8bit for key size
-        // 16bit for value size
-        let mut data = Vec::with_capacity(self.key.len() + self.value.len() + 1 + 2);
+        let mut data = Vec::with_capacity(self.disk_len());
         data.extend_from_slice(&self.value);
         data.extend_from_slice(&self.key);
-        data.push((self.value.len() >> 8) as u8);
-        data.push(self.value.len() as u8);
+        data.extend_from_slice(&Self::trailer(self.key.len(), self.value.len(), 0));
+
+        data
+    }
 
+    /// Encodes the sizes that follow the value and key bytes on disk.
+    ///
+    /// The sizes must have been validated before.
+    pub(crate) fn trailer(key_len: usize, value_len: usize, flags: u16) -> [u8; 3] {
+        debug_assert!(key_len <= MAX_KEY_SIZE && key_len > 0);
+        debug_assert!(value_len <= MAX_VALUE_SIZE);
+        // 16bit for flags and value size
+        // 8bit for key size
+        //
         // We use a single byte to encode the key size which allows to store
         // the value 255 as a maximum. We also require keys to be of at least
         // one byte in size. This means, that we don't need the 0 value and
         // can shift the encoded number by 1 to allow for key sizes of 256 bytes.
-        let key_len = self.key.len() - 1;
-        data.push(key_len as u8);
-
-        data
+        let value_len = value_len as u16 | flags;
+        [(value_len >> 8) as u8, value_len as u8, (key_len - 1) as u8]
     }
 
     // Parses the key and value from a series of bytes.
+    #[cfg(test)]
     fn deserialize(data: &[u8]) -> Result<Self, DeserializationError> {
+        let (key, value, _) = Self::parse(data)?;
+        Ok(HeapTuple::from(key, value))
+    }
+
+    /// Returns the number of bytes that follow the key of a tuple with the
+    /// flags: the trailer, and the tag and key hash if there are any.
+    pub(crate) fn trailer_len(flags: u16) -> usize {
+        let mut len = 3;
+        if flags & TAG_FLAG != 0 {
+            len += 1;
+        }
+        if flags & HASH_FLAG != 0 {
+            len += hash::HASH_LEN;
+        }
+        len
+    }
+
+    /// Decodes the key size, value size and flags from a trailer, without
+    /// checking them.
+    fn decode_trailer(trailer: &[u8]) -> (usize, usize, u16) {
+        let key_size = (trailer[2] as usize) + 1;
+        let encoded = ((trailer[0] as u16) << 8) | trailer[1] as u16;
+        let value_size = (encoded & VALUE_SIZE_MASK) as usize;
+        (key_size, value_size, encoded & !VALUE_SIZE_MASK)
+    }
+
+    /// Locates the key and value of the tuple stored at the end of data and
+    /// returns them along with the tuple's flags.
+    ///
+    /// Data may hold arbitrary bytes, like those of a corrupted file, so
+    /// every size decoded from it is checked before it is used.
+    pub(crate) fn parse(data: &[u8]) -> Result<(&[u8], &[u8], u16), DeserializationError> {
+        let too_short = |needed| DeserializationError::DataTooShort {
+            needed,
+            available: data.len(),
+        };
         if data.len() < Heap::MIN_TUPLE_SIZE {
-            return Err(DeserializationError::DataTooShort);
+            return Err(too_short(Heap::MIN_TUPLE_SIZE));
         }
 
-        let key_size = (data[data.len() - 1] as usize) + 1;
+        let trailer = &data[data.len() - 3..];
+        let (key_size, value_size, flags) = Self::decode_trailer(trailer);
         if key_size > MAX_KEY_SIZE {
-            return Err(DeserializationError::KeySizeTooBig);
+            return Err(DeserializationError::KeySizeTooBig {
+                decoded: key_size,
+                max: MAX_KEY_SIZE,
+            });
         }
-
-        let value_size = ((data[data.len() - 3] as usize) << 8) | data[data.len() - 2] as usize;
         if value_size > MAX_VALUE_SIZE {
-            return Err(DeserializationError::ValueSizeTooBig);
+            return Err(DeserializationError::ValueSizeTooBig {
+                decoded: value_size,
+                max: MAX_VALUE_SIZE,
+            });
         }
-
-        if data.len() < key_size + value_size + 3 {
-            return Err(DeserializationError::DataTooShort);
+        let valid_flags = match flags & !(TRANSFORM_FLAG | TAG_FLAG | HASH_FLAG) {
+            0 => true,
+            TOMBSTONE_FLAG if flags & (TRANSFORM_FLAG | TAG_FLAG) != 0 => false,
+            TOMBSTONE_FLAG => value_size == 0,
+            REFERENCE_FLAG => value_size == REFERENCE_LEN,
+            _ => false,
+        };
+        if !valid_flags {
+            return Err(DeserializationError::InvalidFlags);
         }
 
-        let key = &data[data.len() - 3 - key_size..data.len() - 3];
-        let value = &data[data.len() - 3 - key_size - value_size..data.len() - 3 - key_size];
+        // The tag and key hash in front of the trailer aren't part of the
+        // key. Both sizes are bounded by now, so the sum can't overflow.
+        let tuple_size = key_size + value_size + Self::trailer_len(flags);
+        let Some(start) = data.len().checked_sub(tuple_size) else {
+            return Err(too_short(tuple_size));
+        };
+        let (value, key) = data[start..start + key_size + value_size].split_at(value_size);
 
-        Ok(Self::from(key, value))
+        Ok((key, value, flags))
     }
 }
 
@@ -134,159 +1078,542 @@ impl<'a> IntoIterator for &'a Heap {
 }
 
 pub struct Iter<'a> {
-    file: &'a fs::File,
-    initialized: bool,
-
-    file_size: u64,
-    file_offset: u64, // offset measured from the beginning of the file
-
-    chunk_buffer: Vec<u8>,
-    buffer_offset: usize,
-    overflow: Vec<u8>,
+    tuples: Tuples<&'a Heap>,
+}
 
-    seen_keys: HashSet<Vec<u8>>,
+impl<'a> Iter<'a> {
+    /// Only yields the keys whose latest tuple was written with the tag by
+    /// [`Heap::put_tagged`]. Other tuples are skipped before their values
+    /// are copied.
+    pub fn with_tag(mut self, tag: u8) -> Self {
+        self.tuples.tag = Some(tag);
+        self
+    }
 }
 
 impl<'a> Iterator for Iter<'a> {
     type Item = Result<HeapTuple, Error>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        self.next_iter().transpose()
+        self.tuples.next_tuple().transpose()
     }
 }
 
-impl<'a> Iter<'a> {
-    const DEFAULT_CHUNK_SIZE: usize = Heap::MAX_TUPLE_SIZE;
-
-    fn next_iter(&mut self) -> Result<Option<HeapTuple>, Error> {
-        if !self.initialized {
-            self.file_size = self.file.metadata().map_err(Error::IO)?.len();
-            self.file_offset = self.file_size;
-            self.initialized = true;
+impl IntoIterator for Heap {
+    type Item = Result<HeapTuple, Error>;
+    type IntoIter = IntoIter;
+
+    /// Consumes the Heap into an iterator that yields the same tuples as
+    /// [`Heap::iter`]. The file is closed, and its lock released, once the
+    /// iterator is dropped.
+    fn into_iter(self) -> IntoIter {
+        IntoIter {
+            tuples: Tuples::new(Box::new(self), None),
         }
+    }
+}
 
-        while self.file_bytes_remaining() > 0 {
-            self.seek()?; // This call could be avoided if this run is reentrant
-            if self.buffer_bytes_remaining() == 0 {
-                self.fill_chunk_buffer()?;
-                self.buffer_offset = 0;
-            }
-
-            while self.buffer_bytes_remaining() > 0 {
-                // Read next tuple from the chunk buffer.
-
-                let bytes = &self.chunk_buffer[..self.buffer_bytes_remaining()];
-                let tuple = match HeapTuple::deserialize(bytes) {
-                    Ok(tuple) => tuple,
-                    Err(DeserializationError::DataTooShort) => {
-                        // We've exhausted the buffer and need to read a new chunk from the file
-                        // before completely deserializing this tuple. We move the remaining
-                        // bytes to an overflow buffer to append them on the next chunk read.
-                        self.overflow = Vec::from(bytes);
-                        self.buffer_offset += self.overflow.len(); // Skip to the next chunk
+/// An iterator that owns the Heap it iterates over.
+pub struct IntoIter {
+    tuples: Tuples<Box<Heap>>,
+}
 
-                        continue;
-                    }
-                    Err(e) => return Err(Error::Data(e)),
-                };
+impl Iterator for IntoIter {
+    type Item = Result<HeapTuple, Error>;
 
-                self.buffer_offset += tuple.disk_len();
+    fn next(&mut self) -> Option<Self::Item> {
+        self.tuples.next_tuple().transpose()
+    }
+}
 
-                if self.seen_keys.contains(&tuple.key) {
-                    // We've already seen a more recent tuple with this key.
-                    continue;
-                }
-                self.seen_keys.insert(tuple.key.clone());
+/// Yields the latest version of every live key of a Heap.
+///
+/// Tuples is generic over the way the Heap is referenced, so that it can be
+/// shared by iterators that borrow a Heap directly or through a lock guard,
+/// and by those that own one.
+struct Tuples<H> {
+    heap: H,
+    scanner: Scanner,
+    // The end offset of the scan. Determined on the first call to
+    // next_tuple, if not provided up front.
+    end: Option<u64>,
+    // Only keys starting with the prefix are yielded.
+    prefix: Vec<u8>,
+    // Only keys whose latest tuple has the tag are yielded, if set.
+    tag: Option<u8>,
 
-                return Ok(Some(tuple));
-            }
+    seen_keys: HashSet<Vec<u8>>,
+}
 
-            self.file_offset -= self.buffer_offset as u64;
+impl<H: Deref<Target = Heap>> Tuples<H> {
+    fn new(heap: H, end: Option<u64>) -> Self {
+        Self {
+            heap,
+            scanner: Scanner::new(),
+            end,
+            prefix: Vec::new(),
+            tag: None,
+            seen_keys: HashSet::new(),
         }
+    }
 
-        Ok(None)
+    fn with_prefix(self, prefix: &[u8]) -> Self {
+        Self {
+            prefix: prefix.to_vec(),
+            ..self
+        }
     }
 
-    fn seek(&mut self) -> Result<(), Error> {
-        // In between calls to iter, new tuples may be appended to the file
-        // which changes its size. Because the file is append-only, seeking
-        // to offsets starting at the beginning should be safe.
-        self.file
-            .seek(io::SeekFrom::Start(self.file_offset))
-            .map_err(Error::IO)
-            .map(|_| ())
+    /// Starts over on the next call to next_tuple, keeping the allocated
+    /// buffers.
+    fn rewind(&mut self) {
+        self.scanner.started = false;
+        self.seen_keys.clear();
     }
 
-    fn file_bytes_remaining(&self) -> usize {
-        self.file_offset as usize
+    fn next_tuple(&mut self) -> Result<Option<HeapTuple>, Error> {
+        self.next_matching(|_| true)
     }
 
-    fn buffer_bytes_remaining(&self) -> usize {
-        self.chunk_buffer.len() - self.buffer_offset
+    /// Like next_tuple, but skips keys for which matches returns false
+    /// before their values are copied.
+    fn next_matching(
+        &mut self,
+        matches: impl Fn(&[u8]) -> bool,
+    ) -> Result<Option<HeapTuple>, Error> {
+        let timer = self.heap.time_op();
+        let result = self.next_live_tuple(matches);
+        let key_len = match &result {
+            Ok(Some(tuple)) => tuple.key.len(),
+            _ => 0,
+        };
+        self.heap.log_slow_op(timer, "next", key_len);
+        self.heap.track(result)
     }
 
-    fn fill_chunk_buffer(&mut self) -> Result<usize, Error> {
-        let new_chunk_size = cmp::min(Self::DEFAULT_CHUNK_SIZE, self.file_bytes_remaining());
+    fn next_live_tuple(
+        &mut self,
+        matches: impl Fn(&[u8]) -> bool,
+    ) -> Result<Option<HeapTuple>, Error> {
+        if !self.scanner.is_started() {
+            self.heap.check_file()?;
+            let end = match self.end {
+                Some(end) => end,
+                None => self.heap.committed_len(),
+            };
+            self.scanner.reset(end);
+        }
 
-        self.file
-            .seek(io::SeekFrom::Current(-(new_chunk_size as i64)))
-            .map_err(Error::IO)?;
+        while let Some(tuple) = self.scanner.next_tuple(&self.heap)? {
+            if !tuple.key.starts_with(&self.prefix) || !matches(tuple.key) {
+                // None of the versions of this key are yielded, so there is
+                // no need to remember it.
+                continue;
+            }
+            if self.seen_keys.contains(tuple.key) {
+                // We've already seen a more recent tuple with this key.
+                continue;
+            }
+            self.seen_keys.insert(tuple.key.to_vec());
 
-        self.chunk_buffer = vec![0u8; new_chunk_size];
-        self.file
-            .read_exact(&mut self.chunk_buffer)
-            .map_err(Error::IO)?;
+            if tuple.tombstone {
+                // The key was deleted, which hides all of its older tuples.
+                continue;
+            }
+            if self.tag.is_some_and(|tag| tuple.tag != Some(tag)) {
+                continue;
+            }
 
-        if !self.overflow.is_empty() {
-            // Empties self.overflow into chunk_buffer
-            self.chunk_buffer.append(&mut self.overflow);
-            assert!(self.overflow.is_empty());
+            return Ok(Some(HeapTuple {
+                tag: tuple.tag,
+                ..HeapTuple::from(tuple.key, tuple.value)
+            }));
         }
 
-        Ok(new_chunk_size)
+        Ok(None)
     }
 }
 
-impl Index for Heap {
-    fn put(&mut self, key: &[u8], value: &[u8]) -> Result<(), Error> {
-        if key.len() > MAX_KEY_SIZE || key.is_empty() {
-            return Err(Error::Input(InputError::KeySize(key.len())));
+/// A tuple borrowed from the buffers of a Scanner.
+struct RawTuple<'b> {
+    /// Offset of the first byte of the tuple in the file.
+    offset: u64,
+    key: &'b [u8],
+    /// The value of the tuple, which is read from the earlier tuple that
+    /// stores it if the tuple holds a reference, and decoded if it was
+    /// encoded by a ValueTransform.
+    value: &'b [u8],
+    /// Length of the value as it is stored in the tuple.
+    stored_len: usize,
+    tombstone: bool,
+    reference: bool,
+    tag: Option<u8>,
+    key_hash: Option<u32>,
+}
+
+impl<'b> RawTuple<'b> {
+    fn disk_len(&self) -> usize {
+        let hash_len = self.key_hash.map_or(0, |_| hash::HASH_LEN);
+        self.key.len() + self.stored_len + usize::from(self.tag.is_some()) + hash_len + 3
+    }
+}
+
+/// Reads all tuples of a file, starting from the last one.
+///
+/// The Scanner reads the file backwards in chunks using positioned reads,
+/// so multiple Scanners may read the same file concurrently. It doesn't
+/// deduplicate keys, so it yields every version of a key and every
+/// tombstone that is still stored on disk.
+struct Scanner {
+    // The chunk buffer holds the file bytes [window_start, window_start + len).
+    // Bytes in [window_start, cursor) haven't been consumed yet.
+    chunk_buffer: Vec<u8>,
+    window_start: u64,
+    cursor: u64,
+    started: bool,
+    // Whether values encoded by a ValueTransform are decoded.
+    decode: bool,
+    // Whether stored key hashes are checked against the keys.
+    check_hashes: bool,
+    // Tuples with a stored key hash other than this one are skipped.
+    lookup_hash: Option<u32>,
+    // The value the last tuple refers to, if it holds a reference, or its
+    // decoded value.
+    resolved: Vec<u8>,
+}
+
+impl Scanner {
+    /// A chunk is large enough to hold any tuple, so at most one more chunk
+    /// has to be read to complete a tuple that spans two chunks.
+    const DEFAULT_CHUNK_SIZE: usize = Heap::MAX_TUPLE_SIZE;
+
+    fn new() -> Self {
+        Self {
+            chunk_buffer: Vec::new(),
+            window_start: 0,
+            cursor: 0,
+            started: false,
+            decode: true,
+            check_hashes: false,
+            lookup_hash: None,
+            resolved: Vec::new(),
         }
-        if value.len() > MAX_VALUE_SIZE {
-            return Err(Error::Input(InputError::ValueSize(value.len())));
+    }
+
+    /// Creates a Scanner that yields values as they are stored, without
+    /// decoding them, for passes that only look at the layout of the file.
+    /// It fails on tuples whose stored key hash doesn't match their key.
+    fn raw() -> Self {
+        Self {
+            decode: false,
+            check_hashes: true,
+            ..Self::new()
         }
+    }
 
-        let bytes = HeapTuple::from(key, value).serialize();
+    /// Creates a Scanner for a lookup of the key, which skips tuples that
+    /// store the hash of another key without resolving their values.
+    fn lookup(key: &[u8]) -> Self {
+        Self {
+            lookup_hash: Some(hash::key_hash(key)),
+            ..Self::new()
+        }
+    }
 
-        self.file.write_all(bytes.as_slice()).map_err(Error::IO)
+    /// Returns whether the Scanner has been reset to an end offset.
+    fn is_started(&self) -> bool {
+        self.started
     }
 
-    fn get(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>, Error> {
-        for tuple in self.iter() {
-            match tuple {
-                Ok(tuple) => {
-                    if tuple.key == key {
-                        return Ok(Some(tuple.value));
+    /// Restarts the scan from the given end offset.
+    fn reset(&mut self, end: u64) {
+        self.chunk_buffer.clear();
+        self.window_start = end;
+        self.cursor = end;
+        self.started = true;
+    }
+
+    fn next_tuple(&mut self, heap: &Heap) -> Result<Option<RawTuple<'_>>, Error> {
+        loop {
+            if self.cursor == 0 {
+                return Ok(None);
+            }
+
+            let remaining = (self.cursor - self.window_start) as usize;
+            let parsed = HeapTuple::parse(&self.chunk_buffer[..remaining])
+                .map(|(key, value, flags)| (key.len(), value.len(), flags));
+            match parsed {
+                Ok((key_len, value_len, flags)) => {
+                    heap.counters.record_deserialized();
+                    let start = remaining - (key_len + value_len + HeapTuple::trailer_len(flags));
+                    let offset = self.window_start + start as u64;
+                    let key_hash = (flags & HASH_FLAG != 0).then(|| {
+                        let end = remaining - 3;
+                        let mut hash = [0; hash::HASH_LEN];
+                        hash.copy_from_slice(&self.chunk_buffer[end - hash::HASH_LEN..end]);
+                        u32::from_be_bytes(hash)
+                    });
+                    if self.lookup_hash.is_some()
+                        && key_hash.is_some()
+                        && key_hash != self.lookup_hash
+                    {
+                        self.cursor = offset;
+                        continue;
                     }
+                    if let (true, Some(stored)) = (self.check_hashes, key_hash) {
+                        let key_start = start + value_len;
+                        let computed =
+                            hash::key_hash(&self.chunk_buffer[key_start..key_start + key_len]);
+                        if stored != computed {
+                            let cause = DeserializationError::KeyHashMismatch { stored, computed };
+                            return Err(Error::Data(self.data_error(heap, cause, remaining)));
+                        }
+                    }
+                    let reference = flags & REFERENCE_FLAG != 0;
+                    if reference {
+                        let stored = &self.chunk_buffer[start..start + value_len];
+                        let (target, len) = dedup::decode_reference(stored);
+                        if target + len as u64 > offset || len > MAX_VALUE_SIZE {
+                            let cause = DeserializationError::InvalidReference { target, len };
+                            return Err(Error::Data(self.data_error(heap, cause, remaining)));
+                        }
+                        self.resolved.resize(len, 0);
+                        heap.read_at(&mut self.resolved, target)
+                            .map_err(|e| Error::io(Operation::Read, heap.path.as_deref(), e))?;
+                        heap.counters.read(len);
+                    }
+                    let bytes = &self.chunk_buffer[start..remaining];
+                    let key = &bytes[value_len..value_len + key_len];
+                    let tag = (flags & TAG_FLAG != 0).then(|| bytes[value_len + key_len]);
+                    let mut decoded = false;
+                    if self.decode && flags & TRANSFORM_FLAG != 0 {
+                        let Some(transform) = &heap.transform else {
+                            let cause = DeserializationError::MissingTransform;
+                            return Err(Error::Data(self.data_error(heap, cause, remaining)));
+                        };
+                        let stored = if reference {
+                            &self.resolved
+                        } else {
+                            &bytes[..value_len]
+                        };
+                        self.resolved = transform.decode(key, stored)?;
+                        decoded = true;
+                    }
+                    self.cursor = offset;
+
+                    return Ok(Some(RawTuple {
+                        offset,
+                        value: if reference || decoded {
+                            &self.resolved
+                        } else {
+                            &bytes[..value_len]
+                        },
+                        key,
+                        stored_len: value_len,
+                        tombstone: flags & TOMBSTONE_FLAG != 0,
+                        reference,
+                        tag,
+                        key_hash,
+                    }));
                 }
-                Err(e) => return Err(e),
+                Err(DeserializationError::DataTooShort { .. }) if self.window_start > 0 => {
+                    // The tuple continues in front of the chunk buffer, so we
+                    // need to read the preceding chunk before completing it.
+                    self.fill_chunk_buffer(heap, remaining)?;
+                }
+                Err(e) => return Err(Error::Data(self.data_error(heap, e, remaining))),
             }
         }
+    }
 
-        Ok(None)
+    /// Describes the tuple ending at the cursor, whose first remaining bytes
+    /// of the chunk buffer could not be parsed.
+    fn data_error(&self, heap: &Heap, cause: DeserializationError, remaining: usize) -> DataError {
+        let sizes = (remaining >= 3).then(|| {
+            let (key_size, value_size, _) =
+                HeapTuple::decode_trailer(&self.chunk_buffer[remaining - 3..remaining]);
+            (key_size, value_size)
+        });
+
+        // The excerpt may reach in front of the chunk buffer, so it is read
+        // from the file. Failing to read it shouldn't hide the data error.
+        let start = self.cursor.saturating_sub(DataError::EXCERPT_SIZE as u64);
+        let mut excerpt = vec![0u8; (self.cursor - start) as usize];
+        if heap.read_at(&mut excerpt, start).is_err() {
+            excerpt.clear();
+        }
+
+        DataError::new(cause, self.cursor, heap.path.as_deref(), sizes, excerpt)
+    }
+
+    /// Reads the chunk preceding the current window and keeps the first
+    /// `keep` bytes of the current window behind it.
+    fn fill_chunk_buffer(&mut self, heap: &Heap, keep: usize) -> Result<(), Error> {
+        let new_chunk_size = cmp::min(Self::DEFAULT_CHUNK_SIZE as u64, self.window_start);
+        let new_window_start = self.window_start - new_chunk_size;
+
+        self.chunk_buffer.truncate(keep);
+        let mut chunk = vec![0u8; new_chunk_size as usize];
+        // In between calls to next_tuple, new tuples may be appended to the
+        // file which changes its size. Because the file is append-only,
+        // reading at offsets measured from the beginning is safe.
+        heap.read_at(&mut chunk, new_window_start)
+            .map_err(|e| Error::io(Operation::Read, heap.path.as_deref(), e))?;
+        heap.counters.read(chunk.len());
+
+        chunk.extend_from_slice(&self.chunk_buffer);
+        self.chunk_buffer = chunk;
+        self.window_start = new_window_start;
+
+        Ok(())
     }
 }
 
+/// Takes an exclusive advisory lock on the file without blocking.
+///
+/// The lock is released when the file is closed.
+fn lock_exclusive(file: &fs::File, path: &path::Path) -> Result<(), Error> {
+    file.try_lock().map_err(|e| match e {
+        fs::TryLockError::WouldBlock => Error::Locked,
+        fs::TryLockError::Error(e) => Error::io(Operation::Lock, Some(path), e),
+    })
+}
+
+/// Flushes the entries of the directory to disk.
+///
+/// Directories can't be opened as files on Windows, which persists their
+/// entries along with the metadata of the files instead.
+#[cfg(unix)]
+fn sync_dir(dir: &path::Path) -> io::Result<()> {
+    fs::File::open(dir)?.sync_all()
+}
+
+#[cfg(not(unix))]
+fn sync_dir(_dir: &path::Path) -> io::Result<()> {
+    Ok(())
+}
+
+/// Fills buf with the bytes of the file starting at offset, without moving
+/// the file's cursor.
+#[cfg(unix)]
+fn read_exact_at(file: &fs::File, buf: &mut [u8], offset: u64) -> io::Result<()> {
+    use std::os::unix::fs::FileExt;
+    file.read_exact_at(buf, offset)
+}
+
+/// Fills buf with the bytes of the file starting at offset.
+///
+/// Windows has no positioned reads that leave the file's cursor untouched,
+/// but reads of tuples never depend on the cursor and appends always go to
+/// the end of the file.
+#[cfg(windows)]
+fn read_exact_at(file: &fs::File, mut buf: &mut [u8], mut offset: u64) -> io::Result<()> {
+    use std::os::windows::fs::FileExt;
+    while !buf.is_empty() {
+        match file.seek_read(buf, offset) {
+            Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+            Ok(n) => {
+                buf = &mut buf[n..];
+                offset += n as u64;
+            }
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+impl Index for Heap {
+    fn put(&mut self, key: &[u8], value: &[u8]) -> Result<(), Error> {
+        self.put_with_tag(key, value, None)
+    }
+
+    fn get(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>, Error> {
+        let timer = self.time_op();
+        let bytes_read = self.counters.snapshot().bytes_read;
+        let result = self.find(key, self.committed_len());
+        let scanned = self
+            .counters
+            .snapshot()
+            .bytes_read
+            .saturating_sub(bytes_read);
+        self.log_slow_op(timer, "get", key.len());
+        self.observe(&result, |o, value| o.on_get(key, value.is_some(), scanned));
+        result
+    }
+
+    fn delete(&mut self, key: &[u8]) -> Result<bool, Error> {
+        let timer = self.time_op();
+        let offset = self.committed_len();
+        let result = self
+            .find_with(key, offset, |_| ())
+            .and_then(|found| match found {
+                Some(()) => self.append_tombstone(key).map(|_| true),
+                None => Ok(false),
+            });
+        self.log_slow_op(timer, "delete", key.len());
+        self.observe(&result, |o, &deleted| {
+            if deleted {
+                o.on_delete(key, offset)
+            }
+        });
+        result
+    }
+}
+
+/// Checks that a key-value pair fits the on-disk format.
+fn validate(key: &[u8], value: &[u8]) -> Result<(), Error> {
+    if key.is_empty() {
+        return Err(Error::Input(InputError::EmptyKey));
+    }
+    if key.len() > MAX_KEY_SIZE {
+        return Err(Error::Input(InputError::KeySize(key.len())));
+    }
+    if value.len() > MAX_VALUE_SIZE {
+        return Err(Error::Input(InputError::ValueSize(value.len())));
+    }
+
+    Ok(())
+}
+
 #[cfg(test)]
 mod test {
     use std::{
-        io::{Read, Seek},
+        alloc::{GlobalAlloc, Layout, System},
+        cell::Cell,
+        error,
+        io::{Read, Seek, Write},
         vec,
     };
     use tempfile::tempfile;
 
     use super::*;
 
+    /// Counts allocations made by the current thread.
+    struct CountingAllocator;
+
+    thread_local! {
+        static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+    }
+
+    unsafe impl GlobalAlloc for CountingAllocator {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            let _ = ALLOCATIONS.try_with(|n| n.set(n.get() + 1));
+            System.alloc(layout)
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            System.dealloc(ptr, layout)
+        }
+    }
+
+    #[global_allocator]
+    static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+    fn allocations() -> usize {
+        ALLOCATIONS.with(|n| n.get())
+    }
+
     #[test]
     fn test_heap_serialize() {
         let serialized = HeapTuple::from(b"key", b"value").serialize();
@@ -323,7 +1650,7 @@ mod test {
             .unwrap();
         heap_file.rewind().unwrap();
 
-        let mut heap = Heap::new(heap_file);
+        let mut heap = Heap::new(heap_file).unwrap();
         let value = heap.get(b"key").unwrap();
 
         assert_eq!(value, Some(b"value".to_vec()));
@@ -333,7 +1660,7 @@ mod test {
     fn test_heap_put() {
         let heap_file = tempfile().unwrap();
 
-        let mut heap = Heap::new(heap_file);
+        let mut heap = Heap::new(heap_file).unwrap();
         heap.put(b"key", b"value").unwrap();
 
         heap.file.rewind().unwrap();
@@ -347,10 +1674,59 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_heap_put_does_not_allocate() {
+        let heap_file = tempfile().unwrap();
+        let mut heap = Heap::new(heap_file).unwrap();
+
+        let before = allocations();
+        heap.put(b"key", b"value").unwrap();
+
+        assert_eq!(allocations(), before);
+    }
+
+    #[test]
+    fn test_heap_put_many() {
+        let heap_file = tempfile().unwrap();
+        let mut heap = Heap::new(heap_file).unwrap();
+
+        let tuples: [(&[u8], &[u8]); 3] =
+            [(b"key1", b"value1"), (b"key2", b""), (b"key1", b"value3")];
+        heap.put_many(tuples).unwrap();
+
+        heap.file.rewind().unwrap();
+        let mut buf = Vec::new();
+        heap.file.read_to_end(&mut buf).unwrap();
+
+        let expected: Vec<u8> = tuples
+            .iter()
+            .flat_map(|(k, v)| HeapTuple::from(k, v).serialize())
+            .collect();
+        assert_eq!(buf, expected);
+        assert_eq!(heap.get(b"key1").unwrap(), Some(b"value3".to_vec()));
+        assert_eq!(heap.get(b"key2").unwrap(), Some(b"".to_vec()));
+    }
+
+    #[test]
+    fn test_heap_put_many_rejects_whole_batch() {
+        let heap_file = tempfile().unwrap();
+        let mut heap = Heap::new(heap_file).unwrap();
+
+        let value = vec![0u8; MAX_VALUE_SIZE + 1];
+        let tuples: [(&[u8], &[u8]); 2] = [(b"key1", b"value1"), (b"key2", &value)];
+        let result = heap.put_many(tuples);
+
+        assert!(matches!(
+            result,
+            Err(Error::Input(InputError::ValueSize(_)))
+        ));
+        assert_eq!(heap.file.metadata().unwrap().len(), 0);
+    }
+
     #[test]
     fn test_heap_put_get() {
         let heap_file = tempfile().unwrap();
-        let mut heap = Heap::new(heap_file);
+        let mut heap = Heap::new(heap_file).unwrap();
 
         heap.put(b"key", b"value").unwrap();
         let value = heap.get(b"key").unwrap();
@@ -361,7 +1737,7 @@ mod test {
     #[test]
     fn test_heap_put_get_multiple() {
         let heap_file = tempfile().unwrap();
-        let mut heap = Heap::new(heap_file);
+        let mut heap = Heap::new(heap_file).unwrap();
 
         heap.put(b"key1", b"value1").unwrap();
         heap.put(b"key2", b"value2").unwrap();
@@ -379,7 +1755,7 @@ mod test {
     #[test]
     fn test_heap_put_get_non_utf8_bytes() {
         let heap_file = tempfile().unwrap();
-        let mut heap = Heap::new(heap_file);
+        let mut heap = Heap::new(heap_file).unwrap();
 
         heap.put(b"key", b"ke\xf2").unwrap();
         let value = heap.get(b"key").unwrap();
@@ -390,7 +1766,7 @@ mod test {
     #[test]
     fn test_heap_iter() {
         let heap_file = tempfile().unwrap();
-        let mut heap = Heap::new(heap_file);
+        let mut heap = Heap::new(heap_file).unwrap();
 
         heap.put(b"key1", b"value1").unwrap();
         heap.put(b"key2", b"value2").unwrap();
@@ -406,10 +1782,146 @@ mod test {
         assert_eq!(tuple3, HeapTuple::from(b"key1", b"value1"));
     }
 
+    #[test]
+    fn test_heap_into_iter() {
+        fn collect<I: IntoIterator<Item = Result<HeapTuple, Error>>>(tuples: I) -> Vec<HeapTuple> {
+            tuples.into_iter().map(Result::unwrap).collect()
+        }
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("heap");
+        let mut heap = Heap::from(path.clone()).unwrap();
+        heap.put(b"key1", b"red").unwrap();
+        heap.put(b"key2", b"green").unwrap();
+        heap.put(b"key1", b"blue").unwrap();
+        heap.delete(b"key2").unwrap();
+        heap.put(b"key3", b"yellow").unwrap();
+
+        let borrowed = collect(&heap);
+        let owned = collect(heap);
+        assert_eq!(owned, borrowed);
+        assert_eq!(
+            owned,
+            [
+                HeapTuple::from(b"key3", b"yellow"),
+                HeapTuple::from(b"key1", b"blue")
+            ]
+        );
+
+        // Dropping the iterator releases the lock on the file.
+        let heap = Heap::from(path.clone()).unwrap();
+        let mut iter = heap.into_iter();
+        iter.next().unwrap().unwrap();
+        assert!(matches!(Heap::from(path.clone()), Err(Error::Locked)));
+        drop(iter);
+        Heap::from(path).unwrap();
+    }
+
+    #[test]
+    fn test_heap_put_tagged() {
+        let tags = |heap: &Heap| {
+            let mut tags: Vec<_> = heap
+                .iter()
+                .map(|tuple| {
+                    let tuple = tuple.unwrap();
+                    (tuple.key.clone(), tuple.tag())
+                })
+                .collect();
+            tags.sort();
+            tags
+        };
+        let k = |key: &str| key.as_bytes().to_vec();
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("heap");
+        let mut heap = Heap::from(path.clone()).unwrap();
+        heap.put_tagged(b"key1", b"value1", 1).unwrap();
+        heap.put(b"key2", b"value2").unwrap();
+        heap.put_tagged(b"key3", b"value3", 2).unwrap();
+        heap.put_tagged(b"key4", b"value4", 1).unwrap();
+        // The latest tuple of a key decides whether it has a tag.
+        heap.put(b"key4", b"value5").unwrap();
+        heap.put_tagged(b"key2", b"value6", 1).unwrap();
+        heap.put_tagged(b"key5", b"", 0).unwrap();
+        heap.put_tagged(b"key6", b"value7", 2).unwrap();
+        heap.delete(b"key6").unwrap();
+
+        let expected = vec![
+            (k("key1"), Some(1)),
+            (k("key2"), Some(1)),
+            (k("key3"), Some(2)),
+            (k("key4"), None),
+            (k("key5"), Some(0)),
+        ];
+        assert_eq!(tags(&heap), expected);
+        assert_eq!(heap.get(b"key2").unwrap(), Some(b"value6".to_vec()));
+        assert_eq!(heap.get(b"key5").unwrap(), Some(Vec::new()));
+
+        let mut tagged: Vec<_> = heap.iter().with_tag(1).map(Result::unwrap).collect();
+        tagged.sort_by(|a, b| a.key.cmp(&b.key));
+        let values: Vec<_> = tagged.iter().map(|t| &t.value[..]).collect();
+        assert_eq!(values, [b"value1", b"value6"]);
+        assert_eq!(heap.iter().with_tag(3).count(), 0);
+
+        heap.compact().unwrap();
+        assert_eq!(tags(&heap), expected);
+        drop(heap);
+        let heap = Heap::from(path).unwrap();
+        assert_eq!(tags(&heap), expected);
+        assert_eq!(heap.verify().unwrap().first_corrupt_offset, None);
+    }
+
+    #[test]
+    fn test_heap_put_tagged_with_references() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut heap = HeapOptions::new()
+            .deduplicate_values(4)
+            .open(dir.path().join("heap"))
+            .unwrap();
+        let value = [b'v'; 100];
+        heap.put_tagged(b"key1", &value, 1).unwrap();
+        heap.put_tagged(b"key2", &value, 2).unwrap();
+        heap.put(b"key3", &value).unwrap();
+        assert_eq!(
+            heap.stats().unwrap().file_size,
+            100 + 3 * (4 + 3) + 2 + 2 * 10
+        );
+
+        // Compaction moves the value of the first tuple into the place of a
+        // reference, and keeps all tags.
+        heap.delete(b"key1").unwrap();
+        heap.compact().unwrap();
+        let mut tuples: Vec<_> = heap.iter().map(Result::unwrap).collect();
+        tuples.sort_by(|a, b| a.key.cmp(&b.key));
+        assert_eq!(tuples.len(), 2);
+        assert_eq!(tuples[0].tag(), Some(2));
+        assert_eq!(tuples[0].value, value);
+        assert_eq!(tuples[1].tag(), None);
+        assert_eq!(tuples[1].value, value);
+    }
+
+    #[test]
+    fn test_heap_deserialize_tagged_tuples() {
+        let mut data = b"valuekey".to_vec();
+        data.push(7);
+        data.extend_from_slice(&HeapTuple::trailer(3, 5, TAG_FLAG));
+        let (key, value, flags) = HeapTuple::parse(&data).unwrap();
+        assert_eq!((key, value, flags), (&b"key"[..], &b"value"[..], TAG_FLAG));
+
+        // Tombstones have no tag.
+        let mut data = b"key".to_vec();
+        data.push(7);
+        data.extend_from_slice(&HeapTuple::trailer(3, 0, TOMBSTONE_FLAG | TAG_FLAG));
+        assert!(matches!(
+            HeapTuple::parse(&data),
+            Err(DeserializationError::InvalidFlags)
+        ));
+    }
+
     #[test]
     fn test_heap_iter_skips_duplicate_keys() {
         let heap_file = tempfile().unwrap();
-        let mut heap = Heap::new(heap_file);
+        let mut heap = Heap::new(heap_file).unwrap();
 
         heap.put(b"key1", b"red").unwrap();
         heap.put(b"key2", b"green").unwrap();
@@ -428,10 +1940,10 @@ mod test {
     #[test]
     fn test_heap_iter_handles_chunk_spanning_tuples() {
         let heap_file = tempfile().unwrap();
-        let mut heap = Heap::new(heap_file);
+        let mut heap = Heap::new(heap_file).unwrap();
 
         // Compute key and value size such that the second tuple will overshoot the chunk size.
-        let test_tuple_size = (Iter::DEFAULT_CHUNK_SIZE / 2) + 5;
+        let test_tuple_size = (Scanner::DEFAULT_CHUNK_SIZE / 2) + 5;
         let key_size = MAX_KEY_SIZE;
         let value_size = test_tuple_size - key_size;
 
@@ -460,4 +1972,735 @@ mod test {
         );
         assert_eq!(tuple1, HeapTuple::from(&key1, &value1));
     }
+
+    #[test]
+    fn test_heap_delete() {
+        let heap_file = tempfile().unwrap();
+        let mut heap = Heap::new(heap_file).unwrap();
+
+        heap.put(b"key1", b"value1").unwrap();
+        heap.put(b"key2", b"value2").unwrap();
+
+        assert!(heap.delete(b"key1").unwrap());
+        assert_eq!(heap.get(b"key1").unwrap(), None);
+        assert_eq!(heap.get(b"key2").unwrap(), Some(b"value2".to_vec()));
+    }
+
+    #[test]
+    fn test_heap_delete_missing_key_writes_nothing() {
+        let heap_file = tempfile().unwrap();
+        let mut heap = Heap::new(heap_file).unwrap();
+
+        heap.put(b"key1", b"value1").unwrap();
+        let len = heap.file.metadata().unwrap().len();
+
+        assert!(!heap.delete(b"key2").unwrap());
+        assert_eq!(heap.file.metadata().unwrap().len(), len);
+    }
+
+    #[test]
+    fn test_heap_put_after_delete() {
+        let heap_file = tempfile().unwrap();
+        let mut heap = Heap::new(heap_file).unwrap();
+
+        heap.put(b"key", b"red").unwrap();
+        heap.delete(b"key").unwrap();
+        heap.put(b"key", b"blue").unwrap();
+
+        assert_eq!(heap.get(b"key").unwrap(), Some(b"blue".to_vec()));
+    }
+
+    #[test]
+    fn test_heap_count_prefix() {
+        let mut heap = Heap::new(tempfile().unwrap()).unwrap();
+        for i in 0..20 {
+            heap.put(format!("session:{}", i % 10).as_bytes(), b"value")
+                .unwrap();
+            heap.put(format!("user:{}", i).as_bytes(), b"value")
+                .unwrap();
+        }
+        heap.delete(b"session:3").unwrap();
+        heap.delete(b"user:3").unwrap();
+        heap.put(b"session:3", b"again").unwrap();
+        heap.delete(b"session:4").unwrap();
+
+        for prefix in [&b"session:"[..], b"user:", b"user:1", b"other", b""] {
+            let expected = heap.scan_prefix(prefix).count() as u64;
+            assert_eq!(heap.count_prefix(prefix).unwrap(), expected);
+        }
+        assert_eq!(heap.count_prefix(b"session:").unwrap(), 9);
+        assert_eq!(heap.count_prefix(b"").unwrap(), 28);
+    }
+
+    #[test]
+    fn test_heap_min_max_key() {
+        let mut heap = Heap::new(tempfile().unwrap()).unwrap();
+        assert_eq!(heap.min_key().unwrap(), None);
+        assert_eq!(heap.max_key().unwrap(), None);
+
+        for key in [&b"b"[..], b"\xff", b"a\xff", b"Z", b"\x00", b"ab"] {
+            heap.put(key, b"value").unwrap();
+        }
+        // Bytes compare unsigned, and a prefix orders before longer keys.
+        assert_eq!(heap.min_key().unwrap(), Some(b"\x00".to_vec()));
+        assert_eq!(heap.max_key().unwrap(), Some(b"\xff".to_vec()));
+
+        heap.delete(b"\x00").unwrap();
+        heap.delete(b"\xff").unwrap();
+        assert_eq!(heap.min_key().unwrap(), Some(b"Z".to_vec()));
+        assert_eq!(heap.max_key().unwrap(), Some(b"b".to_vec()));
+
+        heap.delete(b"b").unwrap();
+        assert_eq!(heap.max_key().unwrap(), Some(b"a\xff".to_vec()));
+        heap.put(b"\xff", b"again").unwrap();
+        assert_eq!(heap.max_key().unwrap(), Some(b"\xff".to_vec()));
+    }
+
+    #[test]
+    fn test_heap_iter_skips_deleted_keys() {
+        let heap_file = tempfile().unwrap();
+        let mut heap = Heap::new(heap_file).unwrap();
+
+        heap.put(b"key1", b"value1").unwrap();
+        heap.put(b"key2", b"value2").unwrap();
+        heap.delete(b"key1").unwrap();
+
+        let tuples: Vec<_> = heap.iter().map(Result::unwrap).collect();
+
+        assert_eq!(tuples, vec![HeapTuple::from(b"key2", b"value2")]);
+    }
+
+    #[test]
+    fn test_heap_deserialize_rejects_unknown_flags() {
+        let serialized = vec![b'k', b'e', b'y', 0x40, 0, 2];
+        let result = HeapTuple::deserialize(&serialized);
+
+        assert!(matches!(result, Err(DeserializationError::InvalidFlags)));
+    }
+
+    #[test]
+    fn test_heap_deserialize_reports_sizes() {
+        // The value size 0x07ff is above the maximum of 1024.
+        let result = HeapTuple::deserialize(&[b'k', 0x07, 0xff, 0]);
+        let e = result.unwrap_err();
+        assert!(matches!(
+            e,
+            DeserializationError::ValueSizeTooBig {
+                decoded: 2047,
+                max: 1024
+            }
+        ));
+        assert_eq!(e.to_string(), "Value size too big: 2047 > 1024");
+
+        // A key of 3 bytes and a value of 5 bytes don't fit into 6 bytes.
+        let result = HeapTuple::deserialize(&[b'k', b'e', b'y', 0, 5, 2]);
+        let e = result.unwrap_err();
+        assert!(matches!(
+            e,
+            DeserializationError::DataTooShort {
+                needed: 11,
+                available: 6
+            }
+        ));
+        assert_eq!(
+            e.to_string(),
+            "data buffer too short: 11 bytes needed, 6 available"
+        );
+
+        let result = HeapTuple::deserialize(&[0, 2]);
+        assert!(matches!(
+            result,
+            Err(DeserializationError::DataTooShort {
+                needed: 4,
+                available: 2
+            })
+        ));
+    }
+
+    /// Returns random bytes, ending with a plausible trailer half of the
+    /// time, so that parsing gets past the size checks.
+    fn random_bytes(rng: &mut fastrand::Rng, max_len: usize) -> Vec<u8> {
+        let mut data: Vec<u8> = (0..rng.usize(..=max_len)).map(|_| rng.u8(..)).collect();
+        if data.len() >= 3 && rng.bool() {
+            let flags = if rng.bool() { TOMBSTONE_FLAG } else { 0 };
+            let value_len = if flags == 0 {
+                rng.usize(..=MAX_VALUE_SIZE)
+            } else {
+                0
+            };
+            let trailer = HeapTuple::trailer(rng.usize(1..=MAX_KEY_SIZE), value_len, flags);
+            let len = data.len();
+            data[len - 3..].copy_from_slice(&trailer);
+        }
+        data
+    }
+
+    #[test]
+    fn test_heap_deserialize_arbitrary_bytes() {
+        let mut rng = fastrand::Rng::with_seed(7);
+        for _ in 0..100_000 {
+            let data = random_bytes(&mut rng, Heap::MAX_TUPLE_SIZE + 16);
+            match HeapTuple::parse(&data) {
+                Ok((key, value, flags)) => {
+                    let end = data.len() - HeapTuple::trailer_len(flags);
+                    let start = end - key.len() - value.len();
+                    assert!(!key.is_empty() && key.len() <= MAX_KEY_SIZE);
+                    assert!(value.len() <= MAX_VALUE_SIZE);
+                    assert_eq!(value, &data[start..start + value.len()]);
+                    assert_eq!(key, &data[end - key.len()..end]);
+                }
+                Err(DeserializationError::DataTooShort { needed, available }) => {
+                    assert_eq!(available, data.len());
+                    assert!(needed > available);
+                }
+                Err(_) => {}
+            }
+        }
+    }
+
+    #[test]
+    fn test_heap_reads_arbitrary_bytes() {
+        let mut rng = fastrand::Rng::with_seed(7);
+        for _ in 0..500 {
+            let data = random_bytes(&mut rng, 3 * Heap::MAX_TUPLE_SIZE);
+            let mut file = tempfile().unwrap();
+            file.write_all(&data).unwrap();
+            let mut heap = Heap::new(file).unwrap();
+            let len = data.len() as u64;
+
+            match heap.get(b"key") {
+                Ok(_) => {}
+                Err(Error::Data(e)) => assert!(e.offset() <= len),
+                Err(e) => panic!("unexpected error: {:?}", e),
+            }
+            for result in heap.iter() {
+                match result {
+                    Ok(tuple) => assert!(!tuple.key.is_empty()),
+                    Err(Error::Data(e)) => {
+                        assert!(e.offset() <= len);
+                        break;
+                    }
+                    Err(e) => panic!("unexpected error: {:?}", e),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_heap_iter_spanning_many_chunks() {
+        let heap_file = tempfile().unwrap();
+        let mut heap = Heap::new(heap_file).unwrap();
+
+        for i in 0..20u8 {
+            let value = vec![i; 400 + i as usize];
+            heap.put(&[i + 1; 100], &value).unwrap();
+        }
+
+        let tuples: Vec<_> = heap.iter().map(Result::unwrap).collect();
+
+        assert_eq!(tuples.len(), 20);
+        for (tuple, i) in tuples.iter().zip((0..20u8).rev()) {
+            assert_eq!(
+                tuple,
+                &HeapTuple::from(&[i + 1; 100], &vec![i; 400 + i as usize])
+            );
+        }
+    }
+
+    #[test]
+    fn test_heap_perf_counters_get_latest_reads_one_chunk() {
+        let heap_file = tempfile().unwrap();
+        let mut heap = Heap::new(heap_file).unwrap();
+
+        heap.put(b"key1", b"value1").unwrap();
+        heap.put(b"key2", b"value2").unwrap();
+        heap.reset_counters();
+
+        heap.get(b"key2").unwrap();
+
+        let counters = heap.perf_counters();
+        assert_eq!(counters.reads, 1);
+        assert_eq!(counters.bytes_read, 2 * (4 + 6 + 3));
+        assert_eq!(counters.records_deserialized, 1);
+    }
+
+    #[test]
+    fn test_heap_perf_counters_reset() {
+        let heap_file = tempfile().unwrap();
+        let mut heap = Heap::new(heap_file).unwrap();
+
+        heap.put(b"key", b"value").unwrap();
+        heap.get(b"key").unwrap();
+        heap.sync().unwrap();
+        assert_eq!(heap.perf_counters().fsyncs, 1);
+
+        heap.reset_counters();
+        assert_eq!(heap.perf_counters(), PerfCounters::default());
+    }
+
+    #[test]
+    fn test_heap_from_locks_out_second_writer() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("heap");
+
+        let mut heap = Heap::from(path.clone()).unwrap();
+        assert!(matches!(Heap::from(path.clone()), Err(Error::Locked)));
+
+        heap.put(b"key", b"value1").unwrap();
+        heap.put(b"key", b"value2").unwrap();
+        heap.compact().unwrap();
+        assert!(matches!(Heap::from(path.clone()), Err(Error::Locked)));
+
+        drop(heap);
+        assert!(Heap::from(path).is_ok());
+    }
+
+    #[test]
+    fn test_heap_read_only_rejects_writes() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("heap");
+        Heap::from(path.clone())
+            .unwrap()
+            .put(b"key", b"value")
+            .unwrap();
+
+        let mut heap = Heap::open_read_only(path).unwrap();
+        assert!(heap.is_read_only());
+
+        assert!(matches!(heap.put(b"key", b"other"), Err(Error::IO(_))));
+        assert!(matches!(heap.delete(b"key"), Err(Error::IO(_))));
+        assert!(matches!(heap.compact(), Err(Error::IO(_))));
+        assert_eq!(heap.get(b"key").unwrap(), Some(b"value".to_vec()));
+    }
+
+    #[test]
+    fn test_heap_refresh_picks_up_appends_of_writer() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("heap");
+        let mut writer = Heap::from(path.clone()).unwrap();
+        writer.put(b"key0", b"value0").unwrap();
+
+        let mut reader = Heap::open_read_only(path).unwrap();
+        let (written_tx, written_rx) = std::sync::mpsc::channel::<u32>();
+
+        let handle = std::thread::spawn(move || {
+            for i in 1..50 {
+                let key = format!("key{}", i);
+                let value = format!("value{}", i);
+                writer.put(key.as_bytes(), value.as_bytes()).unwrap();
+                written_tx.send(i).unwrap();
+            }
+            writer.file.metadata().unwrap().len()
+        });
+
+        assert_eq!(reader.get(b"key0").unwrap(), Some(b"value0".to_vec()));
+        for i in written_rx {
+            let key = format!("key{}", i);
+            let value = format!("value{}", i);
+            reader.refresh().unwrap();
+            assert_eq!(
+                reader.get(key.as_bytes()).unwrap(),
+                Some(value.into_bytes())
+            );
+        }
+
+        let len = handle.join().unwrap();
+        assert_eq!(reader.refresh().unwrap(), len);
+        assert_eq!(reader.iter().count(), 50);
+    }
+
+    #[test]
+    fn test_heap_refresh_ignores_partial_tuple() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("heap");
+        let mut writer = Heap::from(path.clone()).unwrap();
+        writer.put(b"key1", b"value1").unwrap();
+
+        let mut reader = Heap::open_read_only(path.clone()).unwrap();
+        let len = reader.refresh().unwrap();
+
+        let data = HeapTuple::from(b"key2", b"value2").serialize();
+        let (head, tail) = data.split_at(5);
+        let mut raw = fs::OpenOptions::new().append(true).open(&path).unwrap();
+
+        raw.write_all(head).unwrap();
+        assert_eq!(reader.refresh().unwrap(), len);
+        assert_eq!(reader.get(b"key2").unwrap(), None);
+
+        raw.write_all(tail).unwrap();
+        assert_eq!(reader.refresh().unwrap(), len + data.len() as u64);
+        assert_eq!(reader.get(b"key2").unwrap(), Some(b"value2".to_vec()));
+    }
+
+    #[test]
+    fn test_heap_detects_truncation() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("heap");
+        let mut heap = Heap::from(path.clone()).unwrap();
+        heap.put(b"key1", b"value1").unwrap();
+        heap.put(b"key2", b"value2").unwrap();
+
+        let len = HeapTuple::from(b"key1", b"value1").disk_len() as u64;
+        fs::OpenOptions::new()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_len(len)
+            .unwrap();
+
+        assert!(matches!(
+            heap.get(b"key1"),
+            Err(Error::ExternallyModified(ExternalModification::Truncated { len: l, .. })) if l == len
+        ));
+        assert!(matches!(
+            heap.iter().next(),
+            Some(Err(Error::ExternallyModified(_)))
+        ));
+
+        heap.reload().unwrap();
+        assert_eq!(heap.get(b"key1").unwrap(), Some(b"value1".to_vec()));
+        assert_eq!(heap.get(b"key2").unwrap(), None);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_heap_detects_replacement() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("heap");
+        let other_path = dir.path().join("other");
+
+        Heap::from(path.clone())
+            .unwrap()
+            .put(b"key", b"old")
+            .unwrap();
+        let mut heap = Heap::open_read_only(path.clone()).unwrap();
+
+        Heap::from(other_path.clone())
+            .unwrap()
+            .put(b"key", b"new")
+            .unwrap();
+        fs::rename(&other_path, &path).unwrap();
+
+        assert!(matches!(
+            heap.get(b"key"),
+            Err(Error::ExternallyModified(ExternalModification::Replaced))
+        ));
+
+        heap.reload().unwrap();
+        assert_eq!(heap.get(b"key").unwrap(), Some(b"new".to_vec()));
+
+        fs::remove_file(&path).unwrap();
+        assert!(matches!(
+            heap.get(b"key"),
+            Err(Error::ExternallyModified(ExternalModification::Removed))
+        ));
+    }
+
+    #[test]
+    fn test_heap_reload_keeps_writer_lock() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("heap");
+        let mut heap = Heap::from(path.clone()).unwrap();
+        heap.put(b"key", b"value").unwrap();
+
+        heap.reload().unwrap();
+
+        assert!(matches!(Heap::from(path), Err(Error::Locked)));
+        assert_eq!(heap.get(b"key").unwrap(), Some(b"value".to_vec()));
+    }
+
+    #[test]
+    fn test_heap_short_write_poisons_until_verified() {
+        let mut heap = Heap::new(tempfile().unwrap()).unwrap();
+        heap.put(b"key1", b"value1").unwrap();
+        let len = heap.committed_len();
+
+        heap.faults.fail_writes_after(5);
+        assert!(matches!(heap.put(b"key2", b"value2"), Err(Error::IO(_))));
+        assert_eq!(heap.file.metadata().unwrap().len(), len + 5);
+
+        heap.faults.clear();
+        match heap.put(b"key3", b"value3") {
+            Err(Error::Poisoned { cause }) => assert!(matches!(*cause, Error::IO(_))),
+            other => panic!("expected poisoned heap, got {:?}", other),
+        }
+        assert!(matches!(heap.delete(b"key1"), Err(Error::Poisoned { .. })));
+        assert_eq!(heap.get(b"key1").unwrap(), Some(b"value1".to_vec()));
+
+        heap.verify_and_clear().unwrap();
+        assert_eq!(heap.file.metadata().unwrap().len(), len);
+
+        heap.put(b"key3", b"value3").unwrap();
+        assert_eq!(heap.get(b"key2").unwrap(), None);
+        assert_eq!(heap.get(b"key3").unwrap(), Some(b"value3".to_vec()));
+    }
+
+    #[test]
+    fn test_heap_validate() {
+        assert!(matches!(
+            Heap::validate(b"", b"value"),
+            Err(Error::Input(InputError::EmptyKey))
+        ));
+        assert!(matches!(
+            Heap::validate(&[0; MAX_KEY_SIZE + 1], b"value"),
+            Err(Error::Input(InputError::KeySize(257)))
+        ));
+        assert!(matches!(
+            Heap::validate(b"key", &[0; MAX_VALUE_SIZE + 1]),
+            Err(Error::Input(InputError::ValueSize(1025)))
+        ));
+        Heap::validate(&[0; MAX_KEY_SIZE], &[0; MAX_VALUE_SIZE]).unwrap();
+    }
+
+    #[test]
+    fn test_heap_failed_write_leaves_torn_tail() {
+        let mut heap = Heap::new(tempfile().unwrap()).unwrap();
+        heap.put(b"key1", b"value1").unwrap();
+        let len = heap.committed_len();
+
+        heap.faults.fail_writes_after(5);
+        assert!(matches!(heap.put(b"key2", b"value2"), Err(Error::IO(_))));
+        heap.faults.clear();
+
+        let report = heap.verify().unwrap();
+        assert_eq!(report.first_corrupt_offset, Some(len + 5));
+        assert!(report.truncated_tail);
+    }
+
+    #[test]
+    fn test_heap_failed_read_surfaces_io_error() {
+        let mut heap = Heap::new(tempfile().unwrap()).unwrap();
+        heap.put(b"key", b"value").unwrap();
+
+        heap.faults.fail_read(0, io::ErrorKind::PermissionDenied);
+        match heap.get(b"key") {
+            Err(Error::IO(e)) => assert_eq!(e.kind(), io::ErrorKind::PermissionDenied),
+            other => panic!("expected I/O error, got {:?}", other),
+        }
+        assert_eq!(heap.get(b"key").unwrap(), Some(b"value".to_vec()));
+
+        heap.faults.short_reads(true);
+        match heap.get(b"key") {
+            Err(Error::IO(e)) => assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof),
+            other => panic!("expected I/O error, got {:?}", other),
+        }
+        heap.faults.short_reads(false);
+        assert_eq!(heap.get(b"key").unwrap(), Some(b"value".to_vec()));
+    }
+
+    #[test]
+    fn test_heap_failed_read_names_operation_and_path() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("heap");
+        let mut heap = Heap::from(path.clone()).unwrap();
+        heap.put(b"key", b"value").unwrap();
+
+        heap.faults.fail_read(0, io::ErrorKind::PermissionDenied);
+        let e = heap.get(b"key").unwrap_err();
+        let context = e.io_context().unwrap();
+        assert_eq!(context.operation(), Operation::Read);
+        assert_eq!(context.path(), Some(path.as_path()));
+        let message = e.to_string();
+        let expected = format!("IO error: read of {} failed: ", path.display());
+        assert!(message.starts_with(&expected), "{}", message);
+    }
+
+    #[test]
+    fn test_heap_iter_survives_failed_read() {
+        let mut heap = Heap::new(tempfile().unwrap()).unwrap();
+        for i in 0..100 {
+            heap.put(format!("key{}", i).as_bytes(), &[b'v'; 100])
+                .unwrap();
+        }
+
+        heap.faults.fail_read(2, io::ErrorKind::Other);
+        let results: Vec<_> = heap.iter().collect();
+        let errors = results.iter().filter(|r| r.is_err()).count();
+        assert_eq!(errors, 1);
+        // The failed read is retried by the next call.
+        assert_eq!(results.len(), 101);
+
+        heap.faults.fail_read(0, io::ErrorKind::Other);
+        let mut iter = heap.reader().unwrap().iter().collect::<Vec<_>>();
+        assert!(matches!(iter.remove(0), Err(Error::IO(_))));
+        assert_eq!(iter.len(), 100);
+    }
+
+    #[test]
+    fn test_heap_failed_write_without_bytes_does_not_poison() {
+        let mut heap = Heap::new(tempfile().unwrap()).unwrap();
+
+        heap.faults.fail_writes_after(0);
+        assert!(matches!(heap.put(b"key", b"value"), Err(Error::IO(_))));
+
+        heap.faults.clear();
+        heap.put(b"key", b"value").unwrap();
+        assert!(matches!(heap.put(b"", b"value"), Err(Error::Input(_))));
+        heap.put(b"key", b"other").unwrap();
+    }
+
+    #[test]
+    fn test_heap_data_error_poisons() {
+        let mut file = tempfile().unwrap();
+        file.write_all(&HeapTuple::from(b"key1", b"value1").serialize())
+            .unwrap();
+        file.write_all(&[b'k', b'e', b'y', 0x40, 0, 2]).unwrap();
+        let mut heap = Heap::new(file).unwrap();
+
+        assert!(matches!(heap.get(b"key1"), Err(Error::Data(_))));
+        assert!(matches!(
+            heap.put(b"key2", b"value2"),
+            Err(Error::Poisoned { .. })
+        ));
+
+        // The corrupted tuple is still there, so the Heap stays poisoned.
+        assert!(matches!(heap.verify_and_clear(), Err(Error::Data(_))));
+        assert!(matches!(
+            heap.put(b"key2", b"value2"),
+            Err(Error::Poisoned { .. })
+        ));
+    }
+
+    #[test]
+    fn test_heap_errors_have_context() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("heap");
+        let mut file = fs::File::create(&path).unwrap();
+        file.write_all(&HeapTuple::from(b"key1", b"value1").serialize())
+            .unwrap();
+        file.write_all(&[b'k', b'e', b'y', 0x40, 0, 2]).unwrap();
+        let mut heap = Heap::from(path.clone()).unwrap();
+
+        let e = heap.get(b"key1").unwrap_err();
+        let Error::Data(data) = &e else {
+            panic!("unexpected error: {:?}", e);
+        };
+        assert_eq!(data.offset(), 19);
+        assert_eq!(data.path(), Some(path.as_path()));
+        assert!(matches!(data.cause(), DeserializationError::InvalidFlags));
+        let message = e.to_string();
+        assert!(message.contains("offset 19"), "{}", message);
+        assert!(message.contains(&path.display().to_string()), "{}", message);
+        assert!(error::Error::source(&e).is_some());
+
+        let Err(e) = Heap::from(dir.path().to_path_buf()) else {
+            panic!("opened a directory");
+        };
+        let context = e.io_context().unwrap();
+        assert_eq!(context.operation(), Operation::Open);
+        assert_eq!(context.path(), Some(dir.path()));
+        let message = e.to_string();
+        assert!(message.contains("open of "), "{}", message);
+        assert!(
+            message.contains(&dir.path().display().to_string()),
+            "{}",
+            message
+        );
+    }
+
+    #[test]
+    fn test_heap_data_error_describes_tuple() {
+        let mut file = tempfile().unwrap();
+        for i in 0..10 {
+            let key = format!("key{:03}", i);
+            file.write_all(&HeapTuple::from(key.as_bytes(), &[b'v'; 500]).serialize())
+                .unwrap();
+        }
+        // Set a flag that doesn't exist in the trailer of the third tuple.
+        // Read from the end in chunks of 1284 bytes, the tuple spans the
+        // chunks that meet at offset 1238.
+        let tuple_size = 6 + 500 + 3;
+        let end = 3 * tuple_size as u64;
+        file.seek(io::SeekFrom::Start(end - 3)).unwrap();
+        file.write_all(&[0x41]).unwrap();
+        let mut heap = Heap::new(file).unwrap();
+
+        let check = |e: Error| {
+            let Error::Data(data) = &e else {
+                panic!("unexpected error: {:?}", e);
+            };
+            assert!(matches!(data.cause(), DeserializationError::InvalidFlags));
+            assert_eq!(data.offset(), end);
+            assert_eq!(data.key_size(), Some(6));
+            assert_eq!(data.value_size(), Some(500));
+            assert_eq!(data.excerpt().len(), 32);
+            assert!(data.excerpt().ends_with(b"key002\x41\xf4\x05"));
+            let message = e.to_string();
+            assert!(message.contains("offset 1527"), "{}", message);
+            assert!(
+                message.contains("key size 6, value size 500"),
+                "{}",
+                message
+            );
+            assert!(message.contains(" 32 41 f4 05"), "{}", message);
+        };
+        check(heap.get(b"key000").unwrap_err());
+        check(heap.iter().find_map(Result::err).unwrap());
+    }
+
+    #[test]
+    fn test_heap_contains_and_len() {
+        let mut heap = Heap::new(tempfile().unwrap()).unwrap();
+        assert_eq!(heap.len().unwrap(), 0);
+        assert!(heap.is_empty().unwrap());
+
+        heap.put(b"key1", b"value1").unwrap();
+        heap.put(b"key2", b"value2").unwrap();
+        heap.put(b"key1", b"value3").unwrap();
+        heap.put(b"key3", b"value4").unwrap();
+        heap.delete(b"key3").unwrap();
+
+        assert!(heap.contains(b"key1").unwrap());
+        assert!(heap.contains(b"key2").unwrap());
+        assert!(!heap.contains(b"key3").unwrap());
+        assert!(!heap.contains(b"key4").unwrap());
+        assert_eq!(heap.len().unwrap(), 2);
+        assert!(!heap.is_empty().unwrap());
+    }
+
+    #[test]
+    fn test_heap_get_with() {
+        let mut heap = Heap::new(tempfile().unwrap()).unwrap();
+        heap.put(b"key1", b"value1").unwrap();
+        heap.put(b"key1", b"value2").unwrap();
+        heap.put(b"key2", b"value3").unwrap();
+        heap.delete(b"key2").unwrap();
+
+        assert_eq!(heap.get_with(b"key1", |v| v.len()).unwrap(), Some(6));
+        assert_eq!(
+            heap.get_with(b"key1", <[u8]>::to_vec).unwrap(),
+            Some(b"value2".to_vec())
+        );
+        assert_eq!(
+            heap.get_with(b"key2", |_| unreachable!()).unwrap(),
+            None::<()>
+        );
+        assert_eq!(
+            heap.get_with(b"key3", |_| unreachable!()).unwrap(),
+            None::<()>
+        );
+    }
+
+    #[test]
+    fn test_heap_scan_prefix() {
+        let mut heap = Heap::new(tempfile().unwrap()).unwrap();
+        heap.put(b"user:1", b"alice").unwrap();
+        heap.put(b"group:1", b"admins").unwrap();
+        heap.put(b"user:2", b"bob").unwrap();
+        heap.put(b"user:1", b"carol").unwrap();
+        heap.put(b"user", b"none").unwrap();
+        heap.put(b"user:3", b"dave").unwrap();
+        heap.delete(b"user:3").unwrap();
+
+        let tuples: Vec<_> = heap.scan_prefix(b"user:").map(Result::unwrap).collect();
+        assert_eq!(
+            tuples,
+            vec![
+                HeapTuple::from(b"user:1", b"carol"),
+                HeapTuple::from(b"user:2", b"bob"),
+            ]
+        );
+        assert_eq!(heap.scan_prefix(b"").count(), heap.iter().count());
+        assert_eq!(heap.scan_prefix(b"none").count(), 0);
+    }
 }
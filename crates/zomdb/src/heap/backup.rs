@@ -0,0 +1,95 @@
+use super::Heap;
+use crate::{Error, Operation};
+use std::io::Write;
+use std::{cmp, fs, path};
+
+const COPY_BUFFER_SIZE: usize = 64 * 1024;
+
+impl Heap {
+    /// Copies the Heap to a new file at dest and returns the number of bytes
+    /// copied.
+    ///
+    /// The copy holds the tuples that were completely written when the
+    /// backup started, and can be opened as a Heap. Writes may continue in
+    /// the meantime, but aren't included. The copy is synced to disk before
+    /// this returns. Fails if dest already exists, and removes a partial
+    /// copy on failure.
+    pub fn backup_to(&self, dest: &path::Path) -> Result<u64, Error> {
+        let result = self.check_file().map(|_| self.committed_len());
+        let len = self.track(result)?;
+
+        let mut file = fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(dest)
+            .map_err(|e| Error::io(Operation::Open, Some(dest), e))?;
+        match self.copy_into(&mut file, dest, len) {
+            Ok(_) => Ok(len),
+            Err(e) => {
+                drop(file);
+                let _ = fs::remove_file(dest);
+                Err(e)
+            }
+        }
+    }
+
+    /// Writes the first len bytes of the file to the file at dest_path and
+    /// syncs it.
+    fn copy_into(
+        &self,
+        dest: &mut fs::File,
+        dest_path: &path::Path,
+        len: u64,
+    ) -> Result<(), Error> {
+        let mut buffer = vec![0u8; cmp::min(COPY_BUFFER_SIZE as u64, len) as usize];
+        let mut offset = 0;
+        while offset < len {
+            let chunk = &mut buffer[..cmp::min(COPY_BUFFER_SIZE as u64, len - offset) as usize];
+            self.read_at(chunk, offset)
+                .map_err(|e| Error::io(Operation::Read, self.path.as_deref(), e))?;
+            self.counters.read(chunk.len());
+            dest.write_all(chunk)
+                .map_err(|e| Error::io(Operation::Write, Some(dest_path), e))?;
+            offset += chunk.len() as u64;
+        }
+        dest.sync_all()
+            .map_err(|e| Error::io(Operation::Sync, Some(dest_path), e))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Index;
+    use std::io;
+    use tempfile::tempfile;
+
+    #[test]
+    fn test_backup_to() {
+        let dir = tempfile::tempdir().unwrap();
+        let dest = dir.path().join("backup");
+        let mut heap = Heap::new(tempfile().unwrap()).unwrap();
+        for i in 0..1000 {
+            heap.put(format!("key{}", i).as_bytes(), &[b'v'; 100])
+                .unwrap();
+        }
+
+        let reader = heap.reader().unwrap();
+        heap.put(b"late", b"value").unwrap();
+        // Bytes of a write that hasn't completed.
+        let len = heap.committed_len();
+        heap.file.set_len(len + 2).unwrap();
+
+        assert_eq!(reader.backup_to(&dest).unwrap(), len);
+        let mut copy = Heap::from(dest.clone()).unwrap();
+        assert_eq!(copy.get(b"key999").unwrap(), Some(vec![b'v'; 100]));
+        assert_eq!(copy.get(b"late").unwrap(), Some(b"value".to_vec()));
+        assert_eq!(copy.len().unwrap(), 1001);
+        drop(copy);
+
+        match heap.backup_to(&dest) {
+            Err(Error::IO(e)) => assert_eq!(e.kind(), io::ErrorKind::AlreadyExists),
+            result => panic!("unexpected result: {:?}", result),
+        }
+    }
+}
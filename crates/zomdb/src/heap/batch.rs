@@ -0,0 +1,265 @@
+use super::{validate, Heap, HeapTuple, TOMBSTONE_FLAG};
+use crate::{Error, InputError};
+use std::borrow::Cow;
+use std::io;
+use std::ops::RangeBounds;
+
+/// A list of puts and deletes that a Heap writes at once.
+///
+/// See [`Heap::write_batch`].
+#[derive(Debug, Clone, Default)]
+pub struct WriteBatch {
+    // Every operation as a key with the value to put, or None to delete it.
+    ops: Vec<(Vec<u8>, Option<Vec<u8>>)>,
+}
+
+impl WriteBatch {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds setting the key to the value.
+    pub fn put(&mut self, key: &[u8], value: &[u8]) {
+        self.ops.push((key.to_vec(), Some(value.to_vec())));
+    }
+
+    /// Adds deleting the key.
+    pub fn delete(&mut self, key: &[u8]) {
+        self.ops.push((key.to_vec(), None));
+    }
+
+    /// Returns the number of operations in the batch.
+    pub fn len(&self) -> usize {
+        self.ops.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+
+    /// Returns the operations in the order they were added, as the key and
+    /// the value to put, or None for a delete.
+    pub fn iter(&self) -> impl Iterator<Item = (&[u8], Option<&[u8]>)> {
+        self.ops
+            .iter()
+            .map(|(key, value)| (key.as_slice(), value.as_deref()))
+    }
+}
+
+impl Heap {
+    /// Writes all operations of the batch with a single write.
+    ///
+    /// Like [`Heap::put_many`], all operations are validated before anything
+    /// is written, so an invalid one leaves the Heap untouched. Later
+    /// operations on a key override earlier ones. A delete writes a
+    /// tombstone even if the key has no value.
+    pub fn write_batch(&mut self, batch: &WriteBatch) -> Result<(), Error> {
+        if self.normalize_key.is_none() {
+            return self.append_batch(batch);
+        }
+
+        let mut normalized = WriteBatch::new();
+        for (key, value) in batch.iter() {
+            let key = self.normalize(key);
+            match value {
+                Some(value) => normalized.put(&key, value),
+                None => normalized.delete(&key),
+            };
+        }
+        self.append_batch(&normalized)
+    }
+
+    /// Validates and appends the operations of a batch.
+    pub(super) fn append_batch(&self, batch: &WriteBatch) -> Result<(), Error> {
+        let mut entries = Vec::with_capacity(batch.len());
+        for (key, value) in batch.iter() {
+            let (value, flags) = match value {
+                Some(value) => {
+                    let (stored, flags) = self.encode(key, value)?;
+                    validate(key, &stored)?;
+                    self.check_value_size(&stored)?;
+                    (stored, flags)
+                }
+                None => {
+                    validate(key, &[])?;
+                    (Cow::Borrowed(&[][..]), TOMBSTONE_FLAG)
+                }
+            };
+            let (flags, hash) = self.hash_key(key, flags);
+            let trailer = HeapTuple::trailer(key.len(), value.len(), flags);
+            entries.push((key, value, hash, trailer));
+        }
+        let update = self.prepare_index_update(batch.iter())?;
+
+        let mut slices = Vec::with_capacity(entries.len() * 4);
+        for (key, value, hash, trailer) in &entries {
+            slices.push(io::IoSlice::new(value));
+            slices.push(io::IoSlice::new(key));
+            slices.push(io::IoSlice::new(
+                hash.as_ref().map_or(&[], |hash| &hash[..]),
+            ));
+            slices.push(io::IoSlice::new(trailer));
+        }
+
+        self.write_vectored(&mut slices)?;
+        self.apply_index_update(update)
+    }
+
+    /// Deletes every key that starts with the prefix with a single write,
+    /// and returns the number of keys deleted.
+    ///
+    /// An empty prefix would delete every key, and is rejected with
+    /// [`InputError::EmptyKey`] to avoid accidents.
+    pub fn delete_prefix(&mut self, prefix: &[u8]) -> Result<u64, Error> {
+        if prefix.is_empty() {
+            return Err(Error::Input(InputError::EmptyKey));
+        }
+
+        let keys = self
+            .scan_prefix(prefix)
+            .map(|tuple| tuple.map(|tuple| tuple.key))
+            .collect::<Result<Vec<_>, _>>()?;
+        self.delete_keys(keys)
+    }
+
+    /// Deletes every key within the bounds with a single write, and returns
+    /// the number of keys deleted.
+    ///
+    /// Keys are compared lexicographically, byte by byte. Nothing is
+    /// written if no key is within the bounds.
+    pub fn delete_range(&mut self, bounds: impl RangeBounds<Vec<u8>>) -> Result<u64, Error> {
+        let mut keys = Vec::new();
+        for tuple in self.iter() {
+            let tuple = tuple?;
+            if bounds.contains(&tuple.key) {
+                keys.push(tuple.key);
+            }
+        }
+        self.delete_keys(keys)
+    }
+
+    /// Writes tombstones for the keys with a single write, and returns their
+    /// number.
+    fn delete_keys(&mut self, keys: Vec<Vec<u8>>) -> Result<u64, Error> {
+        if keys.is_empty() {
+            return Ok(0);
+        }
+
+        let batch = WriteBatch {
+            ops: keys.into_iter().map(|key| (key, None)).collect(),
+        };
+        self.write_batch(&batch)?;
+        Ok(batch.len() as u64)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Index, MAX_VALUE_SIZE};
+    use tempfile::tempfile;
+
+    #[test]
+    fn test_write_batch() {
+        let mut heap = Heap::new(tempfile().unwrap()).unwrap();
+        heap.put(b"old", b"value").unwrap();
+
+        let mut batch = WriteBatch::new();
+        batch.put(b"key1", b"value1");
+        batch.put(b"key2", b"value2");
+        batch.delete(b"old");
+        batch.delete(b"key2");
+        batch.put(b"empty", b"");
+        assert_eq!(batch.len(), 5);
+        heap.write_batch(&batch).unwrap();
+
+        assert_eq!(heap.get(b"key1").unwrap(), Some(b"value1".to_vec()));
+        assert_eq!(heap.get(b"key2").unwrap(), None);
+        assert_eq!(heap.get(b"old").unwrap(), None);
+        assert_eq!(heap.get(b"empty").unwrap(), Some(Vec::new()));
+    }
+
+    #[test]
+    fn test_write_batch_rejects_whole_batch() {
+        let mut heap = Heap::new(tempfile().unwrap()).unwrap();
+        heap.put(b"key", b"value").unwrap();
+        let len = heap.file.metadata().unwrap().len();
+
+        let mut batch = WriteBatch::new();
+        batch.delete(b"key");
+        batch.put(b"large", &vec![0; MAX_VALUE_SIZE + 1]);
+        assert!(matches!(
+            heap.write_batch(&batch),
+            Err(Error::Input(InputError::ValueSize(_)))
+        ));
+
+        assert_eq!(heap.file.metadata().unwrap().len(), len);
+        assert_eq!(heap.get(b"key").unwrap(), Some(b"value".to_vec()));
+    }
+
+    #[test]
+    fn test_delete_prefix() {
+        let mut heap = Heap::new(tempfile().unwrap()).unwrap();
+        for key in ["session:1", "session:2", "session:3", "sessions", "user:1"] {
+            heap.put(key.as_bytes(), b"value").unwrap();
+        }
+        heap.delete(b"session:3").unwrap();
+        let len = heap.committed_len();
+
+        // All tombstones are written at once, so a failure of the second
+        // write doesn't matter.
+        heap.faults.fail_write(1, io::ErrorKind::Other);
+        assert_eq!(heap.delete_prefix(b"session:").unwrap(), 2);
+        heap.faults.clear();
+        assert_eq!(heap.committed_len(), len + 2 * 12);
+
+        assert_eq!(heap.scan_prefix(b"session:").count(), 0);
+        assert_eq!(heap.get(b"sessions").unwrap(), Some(b"value".to_vec()));
+        assert_eq!(heap.get(b"user:1").unwrap(), Some(b"value".to_vec()));
+
+        assert_eq!(heap.delete_prefix(b"session:").unwrap(), 0);
+        assert_eq!(heap.committed_len(), len + 2 * 12);
+        assert!(matches!(
+            heap.delete_prefix(b""),
+            Err(Error::Input(InputError::EmptyKey))
+        ));
+    }
+
+    #[test]
+    fn test_delete_range() {
+        let keys = |heap: &Heap| {
+            let mut keys: Vec<_> = heap.iter().map(|t| t.unwrap().key).collect();
+            keys.sort();
+            keys
+        };
+        let new_heap = || {
+            let mut heap = Heap::new(tempfile().unwrap()).unwrap();
+            for key in ["a", "b", "ba", "c", "d"] {
+                heap.put(key.as_bytes(), b"value").unwrap();
+            }
+            heap
+        };
+        let k = |key: &str| key.as_bytes().to_vec();
+
+        let mut heap = new_heap();
+        assert_eq!(heap.delete_range(k("b")..k("c")).unwrap(), 2);
+        assert_eq!(keys(&heap), vec![k("a"), k("c"), k("d")]);
+
+        let mut heap = new_heap();
+        assert_eq!(heap.delete_range(k("b")..=k("c")).unwrap(), 3);
+        assert_eq!(keys(&heap), vec![k("a"), k("d")]);
+
+        let mut heap = new_heap();
+        assert_eq!(heap.delete_range(..k("b")).unwrap(), 1);
+        assert_eq!(heap.delete_range(k("c")..).unwrap(), 2);
+        assert_eq!(keys(&heap), vec![k("b"), k("ba")]);
+        assert_eq!(heap.delete_range(..).unwrap(), 2);
+        assert!(keys(&heap).is_empty());
+
+        let mut heap = new_heap();
+        let len = heap.committed_len();
+        assert_eq!(heap.delete_range(k("e")..).unwrap(), 0);
+        assert_eq!(heap.delete_range(k("bb")..k("c")).unwrap(), 0);
+        assert_eq!(heap.committed_len(), len);
+    }
+}
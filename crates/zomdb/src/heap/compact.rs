@@ -0,0 +1,995 @@
+//! Compaction rewrites a Heap so that only the latest version of each key
+//! remains on disk.
+//!
+//! Compaction runs in two passes so that memory stays bounded regardless of
+//! the amount of data stored in the Heap:
+//!
+//! 1. The file is scanned from its end to build a map from each key to the
+//!    location of its newest tuple. Values are never held in memory. If the
+//!    map outgrows the memory budget, it is spilled to disk as a sorted run,
+//!    and all runs are merged once the scan is complete.
+//! 2. The live tuples are copied to the destination in file order through a
+//!    fixed-size buffer.
+//!
+//! Tuples that refer to the value of an earlier tuple, see
+//! [`HeapOptions::deduplicate_values`](crate::HeapOptions::deduplicate_values),
+//! are rewritten to the new offset of the value. If the value was dropped,
+//! the first tuple that refers to it stores it instead.
+use super::dedup::{decode_reference, encode_reference};
+use super::{
+    Heap, HeapTuple, Scanner, HASH_FLAG, REFERENCE_FLAG, REFERENCE_LEN, TAG_FLAG, TRANSFORM_FLAG,
+};
+use crate::{Error, Operation};
+use std::collections::HashMap;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::{cmp, env, fmt, fs, io, mem, path, process};
+
+/// Options to tune a compaction run.
+pub struct CompactOptions {
+    /// Upper bound in bytes for the key map built while scanning the Heap.
+    ///
+    /// Once the map grows larger, it is written to a sorted run on disk and
+    /// cleared.
+    pub memory_budget: usize,
+
+    /// Directory to store spilled runs in.
+    ///
+    /// Defaults to the system's temporary directory.
+    pub spill_dir: Option<path::PathBuf>,
+
+    /// Called with the progress of the run, about every megabyte of data
+    /// read or written, after the scan and once the copy is complete.
+    pub progress: Option<Box<dyn FnMut(CompactionProgress) + Send>>,
+
+    /// Aborts the run with [`Error::Cancelled`] once set, which is checked
+    /// along with every tuple scanned and every chunk copied.
+    ///
+    /// A cancelled run removes the partial compacted file and leaves the
+    /// Heap untouched.
+    pub cancel: Option<Arc<AtomicBool>>,
+}
+
+impl Default for CompactOptions {
+    fn default() -> Self {
+        Self {
+            memory_budget: 64 * 1024 * 1024,
+            spill_dir: None,
+            progress: None,
+            cancel: None,
+        }
+    }
+}
+
+impl fmt::Debug for CompactOptions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CompactOptions")
+            .field("memory_budget", &self.memory_budget)
+            .field("spill_dir", &self.spill_dir)
+            .field("progress", &self.progress.as_ref().map(|_| ".."))
+            .field("cancel", &self.cancel)
+            .finish()
+    }
+}
+
+/// Progress of a compaction run, see [`CompactOptions::progress`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CompactionProgress {
+    /// Size of the Heap file being compacted.
+    pub bytes_total: u64,
+    /// Number of bytes of the Heap read so far, while scanning its tuples
+    /// and then while copying the live ones.
+    pub bytes_read: u64,
+    /// Number of bytes written to the compacted file so far.
+    pub bytes_written: u64,
+    /// Number of tuples scanned so far.
+    pub records_processed: u64,
+}
+
+/// Summary of a compaction run.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompactionReport {
+    /// Size of the Heap file before compaction.
+    pub bytes_before: u64,
+    /// Size of the Heap file after compaction.
+    pub bytes_after: u64,
+    /// Number of tuples stored before compaction.
+    pub records_before: u64,
+    /// Number of stale tuples that were dropped.
+    pub records_dropped: u64,
+    /// Number of sorted runs the key map was spilled into.
+    pub spilled_runs: usize,
+    /// Peak number of bytes held by compaction's data structures.
+    ///
+    /// This is an estimate that accounts for keys and tuple locations held
+    /// in memory as well as the copy buffer.
+    pub peak_memory: usize,
+}
+
+impl Heap {
+    /// Rewrites the Heap such that only the latest version of each key
+    /// remains.
+    ///
+    /// The compacted tuples are written to a shadow file next to the Heap
+    /// which then atomically replaces the original file.
+    pub fn compact(&mut self) -> Result<CompactionReport, Error> {
+        self.compact_with(CompactOptions::default())
+    }
+
+    /// Compacts the Heap with the provided options.
+    ///
+    /// See [`Heap::compact`].
+    pub fn compact_with(&mut self, mut opts: CompactOptions) -> Result<CompactionReport, Error> {
+        let result = self.compact_file(&mut opts);
+        self.observe(&result, |o, report| o.on_compaction(report));
+        result
+    }
+
+    fn compact_file(&mut self, opts: &mut CompactOptions) -> Result<CompactionReport, Error> {
+        self.check_writable()?;
+        self.check_poisoned()?;
+        self.track(self.check_file())?;
+        let path = self.path.clone().ok_or_else(|| {
+            Error::IO(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "compaction requires a heap backed by a path",
+            ))
+        })?;
+        let shadow_path = shadow_path(&path);
+        log::info!(
+            "compacting {} with {} bytes",
+            self.log_name(),
+            self.committed_len()
+        );
+
+        let mut shadow = fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&shadow_path)
+            .map_err(|e| Error::io(Operation::Open, Some(&shadow_path), e))?;
+
+        let report = match self
+            .track(self.compact_into(&mut shadow, &shadow_path, opts))
+            .and_then(|report| {
+                shadow
+                    .sync_all()
+                    .map_err(|e| Error::io(Operation::Sync, Some(&shadow_path), e))
+                    .map(|_| report)
+            }) {
+            Ok(report) => report,
+            Err(e) => {
+                let _ = fs::remove_file(&shadow_path);
+                return Err(e);
+            }
+        };
+
+        // Lock the compacted file before it replaces the original, so that
+        // no other writer can open it in between.
+        let replace = || -> Result<fs::File, Error> {
+            let file = Self::open_locked(&shadow_path)?;
+            self.invalidate_savepoints(&path)?;
+            fs::rename(&shadow_path, &path)
+                .map_err(|e| Error::io(Operation::Rename, Some(&shadow_path), e))?;
+            Ok(file)
+        };
+        let file = match replace() {
+            Ok(file) => file,
+            Err(e) => {
+                let _ = fs::remove_file(&shadow_path);
+                return Err(e);
+            }
+        };
+        self.file = file;
+        // Readers of the old file keep their own view of its length.
+        self.committed = Arc::new(AtomicU64::new(report.bytes_after));
+        self.forget_values();
+        // Until the directory is synced, a crash may bring the old file
+        // back. The Heap is usable either way.
+        self.sync_parent(&path)?;
+        log::info!(
+            "compacted {} from {} to {} bytes, dropping {} tuples",
+            self.log_name(),
+            report.bytes_before,
+            report.bytes_after,
+            report.records_dropped
+        );
+
+        Ok(report)
+    }
+
+    /// Writes the compacted form of the Heap to a new file at dest, leaving
+    /// the Heap itself untouched.
+    ///
+    /// This compacts onto another path, for example on a larger disk, with
+    /// the tuples committed when the call starts. The file at dest must not
+    /// exist yet, and is removed again if compaction fails. Unless
+    /// [`HeapOptions::sync_directory`](crate::HeapOptions::sync_directory)
+    /// is turned off, the directory of dest is synced as well.
+    pub fn vacuum_into<P: AsRef<path::Path>>(&self, dest: P) -> Result<CompactionReport, Error> {
+        let dest = dest.as_ref();
+        self.track(self.check_file())?;
+        log::info!(
+            "vacuuming {} with {} bytes into {}",
+            self.log_name(),
+            self.committed_len(),
+            dest.display()
+        );
+
+        let mut file = fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(dest)
+            .map_err(|e| Error::io(Operation::Open, Some(dest), e))?;
+
+        let mut opts = CompactOptions::default();
+        let report = self
+            .track(self.compact_into(&mut file, dest, &mut opts))
+            .and_then(|report| {
+                file.sync_all()
+                    .map_err(|e| Error::io(Operation::Sync, Some(dest), e))?;
+                self.sync_parent(dest)?;
+                Ok(report)
+            });
+        if report.is_err() {
+            let _ = fs::remove_file(dest);
+        }
+        report
+    }
+
+    /// Writes the live tuples of the Heap to dest, the file at dest_path.
+    fn compact_into<W: Write>(
+        &self,
+        dest: &mut W,
+        dest_path: &path::Path,
+        opts: &mut CompactOptions,
+    ) -> Result<CompactionReport, Error> {
+        let bytes_before = self.committed_len();
+
+        let mut scanner = Scanner::raw();
+        scanner.reset(bytes_before);
+
+        let mut keys = KeyMap::new(opts);
+        let mut progress = Progress::new(opts, bytes_before);
+        let mut records_before = 0;
+        while let Some(tuple) = scanner.next_tuple(self)? {
+            records_before += 1;
+            let extent = Extent {
+                offset: tuple.offset,
+                len: tuple.disk_len() as u32,
+                tombstone: tuple.tombstone,
+                reference: tuple.reference,
+            };
+            keys.insert(tuple.key, extent)?;
+
+            progress.current.records_processed = records_before;
+            progress.current.bytes_read = bytes_before - tuple.offset;
+            progress.advance()?;
+        }
+        progress.report();
+
+        let spilled_runs = keys.runs.len();
+        let mut peak_memory = keys.peak_memory;
+        let live = keys.into_extents()?;
+        peak_memory = cmp::max(
+            peak_memory,
+            live.len() * mem::size_of::<Extent>() + COPY_BUFFER_SIZE,
+        );
+
+        let bytes_after = self.copy_extents(&live, dest, dest_path, &mut progress)?;
+        progress.report();
+
+        Ok(CompactionReport {
+            bytes_before,
+            bytes_after,
+            records_before,
+            records_dropped: records_before - live.len() as u64,
+            spilled_runs,
+            peak_memory,
+        })
+    }
+
+    /// Copies the given extents to dest, coalescing adjacent ones.
+    ///
+    /// The extents must be sorted by offset.
+    fn copy_extents<W: Write>(
+        &self,
+        extents: &[Extent],
+        dest: &mut W,
+        dest_path: &path::Path,
+        progress: &mut Progress,
+    ) -> Result<u64, Error> {
+        let mut buffer = vec![0u8; COPY_BUFFER_SIZE];
+        let mut written = 0;
+        let mut moves = Moves::default();
+        let track_moves = extents.iter().any(|e| e.reference);
+
+        let mut extents = extents.iter().peekable();
+        while let Some(first) = extents.next() {
+            if first.reference {
+                let n = self.copy_reference(first, &mut moves, written, dest, dest_path)?;
+                written += n;
+                progress.current.bytes_written += n;
+                progress.advance()?;
+                continue;
+            }
+
+            let start = first.offset;
+            let mut end = first.end();
+            while let Some(next) = extents.next_if(|e| e.offset == end && !e.reference) {
+                end = next.end();
+            }
+            if track_moves {
+                moves.runs.push((start, end, written));
+            }
+
+            let mut offset = start;
+            while offset < end {
+                let n = cmp::min(end - offset, buffer.len() as u64) as usize;
+                self.read_at(&mut buffer[..n], offset)
+                    .map_err(|e| Error::io(Operation::Read, self.path.as_deref(), e))?;
+                self.counters.read(n);
+                dest.write_all(&buffer[..n])
+                    .map_err(|e| Error::io(Operation::Write, Some(dest_path), e))?;
+                offset += n as u64;
+
+                progress.current.bytes_read += n as u64;
+                progress.current.bytes_written += n as u64;
+                progress.advance()?;
+            }
+            written += end - start;
+        }
+
+        Ok(written)
+    }
+
+    /// Writes the tuple of the extent, which refers to an earlier value, to
+    /// dest at the given offset, and returns the number of bytes written.
+    fn copy_reference<W: Write>(
+        &self,
+        extent: &Extent,
+        moves: &mut Moves,
+        offset: u64,
+        dest: &mut W,
+        dest_path: &path::Path,
+    ) -> Result<u64, Error> {
+        let read = |buf: &mut [u8], offset| {
+            self.read_at(buf, offset)
+                .map_err(|e| Error::io(Operation::Read, self.path.as_deref(), e))?;
+            self.counters.read(buf.len());
+            Ok::<_, Error>(())
+        };
+
+        let mut tuple = vec![0u8; extent.len as usize];
+        read(&mut tuple, extent.offset)?;
+        let (target, len) = decode_reference(&tuple[..REFERENCE_LEN]);
+        let (_, _, flags) = HeapTuple::decode_trailer(&tuple[tuple.len() - 3..]);
+        let (key, suffix) = tuple[REFERENCE_LEN..tuple.len() - 3]
+            .split_at(tuple.len() - REFERENCE_LEN - HeapTuple::trailer_len(flags));
+        // The referenced value stays encoded if it was, and the tag and key
+        // hash in the suffix stay.
+        let flags = flags & (TRANSFORM_FLAG | TAG_FLAG | HASH_FLAG);
+
+        let mut data = Vec::with_capacity(len + key.len() + suffix.len() + 3);
+        match moves.find(target, len) {
+            Some(moved) => {
+                data.extend_from_slice(&encode_reference(moved, len));
+                data.extend_from_slice(key);
+                data.extend_from_slice(suffix);
+                data.extend_from_slice(&HeapTuple::trailer(
+                    key.len(),
+                    REFERENCE_LEN,
+                    REFERENCE_FLAG | flags,
+                ));
+            }
+            None => {
+                data.resize(len, 0);
+                read(&mut data, target)?;
+                data.extend_from_slice(key);
+                data.extend_from_slice(suffix);
+                data.extend_from_slice(&HeapTuple::trailer(key.len(), len, flags));
+                moves.values.insert((target, len), offset);
+            }
+        }
+
+        dest.write_all(&data)
+            .map_err(|e| Error::io(Operation::Write, Some(dest_path), e))?;
+        Ok(data.len() as u64)
+    }
+}
+
+/// Where the bytes of the Heap ended up in the compacted file, to rewrite
+/// references to values.
+#[derive(Default)]
+struct Moves {
+    /// Ranges of bytes that were copied as they are, as their start and end
+    /// offsets before and their start offset after compaction, in order.
+    runs: Vec<(u64, u64, u64)>,
+    /// Values that were dropped and are now stored by a tuple that referred
+    /// to them, by their offset and length before compaction.
+    values: HashMap<(u64, usize), u64>,
+}
+
+impl Moves {
+    /// Returns the offset after compaction of the value with the given
+    /// offset and length before it.
+    fn find(&self, target: u64, len: usize) -> Option<u64> {
+        if let Some(&offset) = self.values.get(&(target, len)) {
+            return Some(offset);
+        }
+        let i = self.runs.partition_point(|&(start, _, _)| start <= target);
+        let &(start, end, moved) = self.runs.get(i.checked_sub(1)?)?;
+        (target + len as u64 <= end).then(|| moved + (target - start))
+    }
+}
+
+/// Size of the buffer used to copy tuples to the compacted file.
+const COPY_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Number of bytes read or written between two reports of the progress.
+const PROGRESS_INTERVAL: u64 = 1024 * 1024;
+
+/// Reports the progress of a compaction run and checks whether it was
+/// cancelled.
+struct Progress<'o> {
+    current: CompactionProgress,
+    // The bytes read and written at the last report.
+    reported: u64,
+    callback: &'o mut Option<Box<dyn FnMut(CompactionProgress) + Send>>,
+    cancel: Option<&'o AtomicBool>,
+}
+
+impl<'o> Progress<'o> {
+    fn new(opts: &'o mut CompactOptions, bytes_total: u64) -> Self {
+        Self {
+            current: CompactionProgress {
+                bytes_total,
+                ..Default::default()
+            },
+            reported: 0,
+            callback: &mut opts.progress,
+            cancel: opts.cancel.as_deref(),
+        }
+    }
+
+    /// Reports the progress if enough happened since the last report, and
+    /// fails if the run was cancelled.
+    fn advance(&mut self) -> Result<(), Error> {
+        if self.cancel.is_some_and(|c| c.load(Ordering::Relaxed)) {
+            return Err(Error::Cancelled);
+        }
+        let done = self.current.bytes_read + self.current.bytes_written;
+        if done - self.reported >= PROGRESS_INTERVAL {
+            self.report();
+        }
+        Ok(())
+    }
+
+    fn report(&mut self) {
+        self.reported = self.current.bytes_read + self.current.bytes_written;
+        if let Some(callback) = self.callback {
+            callback(self.current);
+        }
+    }
+}
+
+/// Estimated memory overhead of a key map entry, excluding the key bytes.
+const ENTRY_OVERHEAD: usize = mem::size_of::<(Vec<u8>, Extent)>();
+
+fn shadow_path(path: &path::Path) -> path::PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".compact");
+    path.with_file_name(name)
+}
+
+/// Location of a tuple in the Heap file.
+#[derive(Debug, Clone, Copy)]
+struct Extent {
+    offset: u64,
+    len: u32,
+    tombstone: bool,
+    reference: bool,
+}
+
+impl Extent {
+    fn end(&self) -> u64 {
+        self.offset + self.len as u64
+    }
+}
+
+/// Maps keys to the location of their newest tuple.
+///
+/// Keys have to be inserted in reverse file order, so that the first
+/// insertion of a key wins.
+struct KeyMap {
+    map: HashMap<Vec<u8>, Extent>,
+    map_bytes: usize,
+    peak_memory: usize,
+
+    memory_budget: usize,
+    spill_dir: path::PathBuf,
+    runs: Vec<SpillRun>,
+}
+
+impl KeyMap {
+    fn new(opts: &CompactOptions) -> Self {
+        Self {
+            map: HashMap::new(),
+            map_bytes: 0,
+            peak_memory: 0,
+            memory_budget: opts.memory_budget,
+            spill_dir: opts.spill_dir.clone().unwrap_or_else(env::temp_dir),
+            runs: Vec::new(),
+        }
+    }
+
+    fn insert(&mut self, key: &[u8], extent: Extent) -> Result<(), Error> {
+        if self.map.contains_key(key) {
+            return Ok(());
+        }
+        self.map.insert(key.to_vec(), extent);
+
+        self.map_bytes += key.len() + ENTRY_OVERHEAD;
+        self.peak_memory = cmp::max(self.peak_memory, self.map_bytes);
+        if self.map_bytes > self.memory_budget {
+            self.spill()?;
+        }
+
+        Ok(())
+    }
+
+    /// Writes the current map to a sorted run on disk and clears it.
+    fn spill(&mut self) -> Result<(), Error> {
+        let mut entries: Vec<_> = self.map.drain().collect();
+        entries.sort_unstable_by(|(a, _), (b, _)| a.cmp(b));
+        self.map_bytes = 0;
+
+        self.runs.push(SpillRun::write(&self.spill_dir, &entries)?);
+        Ok(())
+    }
+
+    /// Returns the locations of the newest tuple of every live key, sorted
+    /// by offset.
+    ///
+    /// Keys whose newest tuple is a tombstone are left out entirely, since no
+    /// older tuples will remain that the tombstone would need to hide.
+    fn into_extents(mut self) -> Result<Vec<Extent>, Error> {
+        let mut extents: Vec<Extent> = if self.runs.is_empty() {
+            self.map.into_values().collect()
+        } else {
+            if !self.map.is_empty() {
+                self.spill()?;
+            }
+            merge_runs(&self.runs)?
+        };
+
+        extents.retain(|e| !e.tombstone);
+        extents.sort_unstable_by_key(|e| e.offset);
+        Ok(extents)
+    }
+}
+
+/// Merges sorted runs, keeping the newest extent of each key.
+///
+/// Runs may contain the same key when it was encountered again after the
+/// map had been spilled. Since tuples are scanned in reverse file order,
+/// the later occurrences are older and the extent with the highest offset
+/// wins.
+fn merge_runs(runs: &[SpillRun]) -> Result<Vec<Extent>, Error> {
+    let mut readers = runs
+        .iter()
+        .map(SpillRun::reader)
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut extents = Vec::new();
+    loop {
+        let min_key = readers
+            .iter()
+            .filter_map(|r| r.head.as_ref().map(|(key, _)| key))
+            .min()
+            .cloned();
+        let Some(min_key) = min_key else {
+            break;
+        };
+
+        let mut newest: Option<Extent> = None;
+        for reader in readers.iter_mut() {
+            if let Some((key, extent)) = &reader.head {
+                if *key == min_key {
+                    if newest.is_none_or(|n| extent.offset > n.offset) {
+                        newest = Some(*extent);
+                    }
+                    reader.advance()?;
+                }
+            }
+        }
+
+        extents.extend(newest);
+    }
+
+    Ok(extents)
+}
+
+/// A sorted run of key map entries spilled to a temporary file.
+///
+/// The file is removed when the run is dropped.
+struct SpillRun {
+    path: path::PathBuf,
+}
+
+impl SpillRun {
+    fn write(dir: &path::Path, entries: &[(Vec<u8>, Extent)]) -> Result<Self, Error> {
+        static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+        let (path, file) = loop {
+            let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+            let path = dir.join(format!("zomdb-spill-{}-{}", process::id(), id));
+            match fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&path)
+            {
+                Ok(file) => break (path, file),
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(Error::io(Operation::Open, Some(&path), e)),
+            }
+        };
+        let run = Self { path };
+
+        let mut writer = BufWriter::new(file);
+        for (key, extent) in entries {
+            writer
+                .write_all(&(key.len() as u16).to_le_bytes())
+                .and_then(|_| writer.write_all(key))
+                .and_then(|_| writer.write_all(&extent.offset.to_le_bytes()))
+                .and_then(|_| writer.write_all(&extent.len.to_le_bytes()))
+                .and_then(|_| {
+                    writer.write_all(&[extent.tombstone as u8 | (extent.reference as u8) << 1])
+                })
+                .map_err(|e| Error::io(Operation::Write, Some(&run.path), e))?;
+        }
+        writer
+            .flush()
+            .map_err(|e| Error::io(Operation::Write, Some(&run.path), e))?;
+
+        Ok(run)
+    }
+
+    fn reader(&self) -> Result<RunReader, Error> {
+        let file = fs::File::open(&self.path)
+            .map_err(|e| Error::io(Operation::Open, Some(&self.path), e))?;
+        let mut reader = RunReader {
+            path: self.path.clone(),
+            reader: BufReader::new(file),
+            head: None,
+        };
+        reader.advance()?;
+
+        Ok(reader)
+    }
+}
+
+impl Drop for SpillRun {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Reads the entries of a SpillRun in order.
+struct RunReader {
+    path: path::PathBuf,
+    reader: BufReader<fs::File>,
+    head: Option<(Vec<u8>, Extent)>,
+}
+
+impl RunReader {
+    fn advance(&mut self) -> Result<(), Error> {
+        let mut len = [0u8; 2];
+        match self.reader.read_exact(&mut len) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                self.head = None;
+                return Ok(());
+            }
+            Err(e) => return Err(Error::io(Operation::Read, Some(&self.path), e)),
+        }
+
+        let mut key = vec![0u8; u16::from_le_bytes(len) as usize];
+        let mut offset = [0u8; 8];
+        let mut extent_len = [0u8; 4];
+        let mut flags = [0u8; 1];
+        self.reader
+            .read_exact(&mut key)
+            .and_then(|_| self.reader.read_exact(&mut offset))
+            .and_then(|_| self.reader.read_exact(&mut extent_len))
+            .and_then(|_| self.reader.read_exact(&mut flags))
+            .map_err(|e| Error::io(Operation::Read, Some(&self.path), e))?;
+
+        self.head = Some((
+            key,
+            Extent {
+                offset: u64::from_le_bytes(offset),
+                len: u32::from_le_bytes(extent_len),
+                tombstone: flags[0] & 1 != 0,
+                reference: flags[0] & 2 != 0,
+            },
+        ));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::heap::FailingStorage;
+    use crate::{HeapOptions, Index};
+
+    fn open_heap(dir: &tempfile::TempDir) -> Heap {
+        Heap::from(dir.path().join("heap")).unwrap()
+    }
+
+    #[test]
+    fn test_compact_drops_stale_tuples() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut heap = open_heap(&dir);
+
+        heap.put(b"key1", b"red").unwrap();
+        heap.put(b"key2", b"green").unwrap();
+        heap.put(b"key1", b"blue").unwrap();
+        heap.put(b"key3", b"yellow").unwrap();
+        heap.put(b"key2", b"purple").unwrap();
+
+        let report = heap.compact().unwrap();
+
+        assert_eq!(report.records_before, 5);
+        assert_eq!(report.records_dropped, 2);
+        assert_eq!(report.bytes_before, 5 * (4 + 3) + 3 + 5 + 4 + 6 + 6);
+        assert_eq!(report.bytes_after, 3 * (4 + 3) + 4 + 6 + 6);
+        assert_eq!(report.spilled_runs, 0);
+        assert_eq!(heap.file.metadata().unwrap().len(), report.bytes_after);
+
+        assert_eq!(heap.get(b"key1").unwrap(), Some(b"blue".to_vec()));
+        assert_eq!(heap.get(b"key2").unwrap(), Some(b"purple".to_vec()));
+        assert_eq!(heap.get(b"key3").unwrap(), Some(b"yellow".to_vec()));
+        assert!(!shadow_path(&dir.path().join("heap")).exists());
+    }
+
+    #[test]
+    fn test_compact_preserves_insertion_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut heap = open_heap(&dir);
+
+        heap.put(b"key1", b"value1").unwrap();
+        heap.put(b"key2", b"value2").unwrap();
+        heap.put(b"key1", b"value3").unwrap();
+        heap.put(b"key3", b"value4").unwrap();
+
+        heap.compact().unwrap();
+
+        let keys: Vec<_> = heap.iter().map(|t| t.unwrap().key).collect();
+        assert_eq!(
+            keys,
+            vec![b"key3".to_vec(), b"key1".to_vec(), b"key2".to_vec()]
+        );
+    }
+
+    #[test]
+    fn test_compact_drops_deleted_keys() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut heap = open_heap(&dir);
+
+        heap.put(b"key1", b"value1").unwrap();
+        heap.put(b"key2", b"value2").unwrap();
+        heap.delete(b"key1").unwrap();
+
+        let report = heap.compact().unwrap();
+
+        assert_eq!(report.records_before, 3);
+        assert_eq!(report.records_dropped, 2);
+        assert_eq!(report.bytes_after, 4 + 6 + 3);
+        assert_eq!(heap.get(b"key1").unwrap(), None);
+        assert_eq!(heap.get(b"key2").unwrap(), Some(b"value2".to_vec()));
+    }
+
+    #[test]
+    fn test_compact_empty_heap() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut heap = open_heap(&dir);
+
+        let report = heap.compact().unwrap();
+
+        assert_eq!(report.bytes_before, 0);
+        assert_eq!(report.bytes_after, 0);
+        assert_eq!(heap.iter().count(), 0);
+    }
+
+    #[test]
+    fn test_compact_keeps_heap_usable() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut heap = open_heap(&dir);
+
+        heap.put(b"key1", b"value1").unwrap();
+        heap.put(b"key1", b"value2").unwrap();
+        heap.compact().unwrap();
+        heap.put(b"key2", b"value3").unwrap();
+
+        assert_eq!(heap.get(b"key1").unwrap(), Some(b"value2".to_vec()));
+        assert_eq!(heap.get(b"key2").unwrap(), Some(b"value3".to_vec()));
+
+        drop(heap);
+        let mut heap = open_heap(&dir);
+        assert_eq!(heap.get(b"key2").unwrap(), Some(b"value3".to_vec()));
+    }
+
+    #[test]
+    fn test_compact_syncs_directory() {
+        let dir = tempfile::tempdir().unwrap();
+        let faults = FailingStorage::new();
+        let mut heap = HeapOptions::new()
+            .faults(faults.clone())
+            .open(dir.path().join("heap"))
+            .unwrap();
+        heap.put(b"key1", b"value1").unwrap();
+        heap.put(b"key1", b"value2").unwrap();
+        faults.clear();
+
+        heap.compact().unwrap();
+        assert_eq!(faults.synced_dirs(), vec![dir.path().to_path_buf()]);
+
+        // Without directory syncs, compaction works all the same.
+        drop(heap);
+        let mut heap = HeapOptions::new()
+            .sync_directory(false)
+            .faults(faults.clone())
+            .open(dir.path().join("heap"))
+            .unwrap();
+        heap.put(b"key1", b"value3").unwrap();
+        heap.compact().unwrap();
+        assert_eq!(faults.synced_dirs().len(), 1);
+        assert_eq!(heap.get(b"key1").unwrap(), Some(b"value3".to_vec()));
+    }
+
+    #[test]
+    fn test_vacuum_into() {
+        let dir = tempfile::tempdir().unwrap();
+        let faults = FailingStorage::new();
+        let mut heap = HeapOptions::new()
+            .faults(faults.clone())
+            .open(dir.path().join("heap"))
+            .unwrap();
+        for i in 0..50u32 {
+            heap.put(&(i % 10).to_be_bytes(), &i.to_be_bytes()).unwrap();
+        }
+        heap.delete(&3u32.to_be_bytes()).unwrap();
+        let len = heap.file.metadata().unwrap().len();
+
+        fs::create_dir(dir.path().join("other")).unwrap();
+        let dest = dir.path().join("other").join("vacuumed");
+        faults.clear();
+        let report = heap.vacuum_into(&dest).unwrap();
+        assert_eq!(faults.synced_dirs(), vec![dir.path().join("other")]);
+        assert_eq!(report.records_before, 51);
+        assert_eq!(report.records_dropped, 42);
+        assert_eq!(fs::metadata(&dest).unwrap().len(), report.bytes_after);
+
+        let vacuumed = Heap::from(dest.clone()).unwrap();
+        assert!(heap.diff(&vacuumed).unwrap().is_empty());
+
+        // The source is untouched and keeps accepting writes.
+        assert_eq!(heap.file.metadata().unwrap().len(), len);
+        heap.put(b"key", b"value").unwrap();
+        assert_eq!(heap.get(b"key").unwrap(), Some(b"value".to_vec()));
+
+        // The destination must not exist yet.
+        match heap.vacuum_into(&dest) {
+            Err(Error::IO(e)) => assert_eq!(e.kind(), io::ErrorKind::AlreadyExists),
+            other => panic!("expected an existing file, got {:?}", other),
+        }
+        assert_eq!(fs::metadata(&dest).unwrap().len(), report.bytes_after);
+    }
+
+    #[test]
+    fn test_compact_spills_with_low_memory_budget() {
+        let dir = tempfile::tempdir().unwrap();
+        let spill_dir = tempfile::tempdir().unwrap();
+        let mut heap = open_heap(&dir);
+
+        let value = |key: u32, round: u32| vec![(key + round) as u8; 1000];
+        for round in 0..3 {
+            for key in 0..200u32 {
+                heap.put(&key.to_be_bytes(), &value(key, round)).unwrap();
+            }
+        }
+
+        let report = heap
+            .compact_with(CompactOptions {
+                memory_budget: 1024,
+                spill_dir: Some(spill_dir.path().to_path_buf()),
+                ..Default::default()
+            })
+            .unwrap();
+
+        assert!(report.spilled_runs > 1);
+        assert!(report.peak_memory < 600 * 1000 / 4);
+        assert_eq!(report.records_before, 600);
+        assert_eq!(report.records_dropped, 400);
+        for key in 0..200u32 {
+            let got = heap.get(&key.to_be_bytes()).unwrap();
+            assert_eq!(got, Some(value(key, 2)));
+        }
+        assert_eq!(fs::read_dir(spill_dir.path()).unwrap().count(), 0);
+    }
+
+    fn fill_heap(heap: &mut Heap) {
+        for round in 0..3u32 {
+            for key in 0..1000u32 {
+                heap.put(&key.to_be_bytes(), &[round as u8; 1000]).unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn test_compact_reports_progress() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut heap = open_heap(&dir);
+        fill_heap(&mut heap);
+        let bytes_total = heap.stats().unwrap().file_size;
+
+        let reports = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let recorded = reports.clone();
+        let report = heap
+            .compact_with(CompactOptions {
+                progress: Some(Box::new(move |p| recorded.lock().unwrap().push(p))),
+                ..Default::default()
+            })
+            .unwrap();
+
+        let reports = reports.lock().unwrap();
+        // About one report per megabyte read or written, plus those after
+        // each pass.
+        assert!((4..=8).contains(&reports.len()), "{:?}", reports);
+        assert!(reports
+            .windows(2)
+            .all(|w| w[0].bytes_read <= w[1].bytes_read
+                && w[0].bytes_written <= w[1].bytes_written
+                && w[0].records_processed <= w[1].records_processed));
+        let last = reports.last().unwrap();
+        assert_eq!(last.bytes_total, bytes_total);
+        assert_eq!(last.records_processed, 3000);
+        assert_eq!(last.bytes_read, bytes_total + report.bytes_after);
+        assert_eq!(last.bytes_written, report.bytes_after);
+    }
+
+    #[test]
+    fn test_compact_cancel() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut heap = open_heap(&dir);
+        fill_heap(&mut heap);
+        let digest = heap.physical_digest().unwrap();
+
+        // Cancel from the second report, which happens while scanning.
+        let cancel = Arc::new(AtomicBool::new(false));
+        let flag = cancel.clone();
+        let mut reports = 0;
+        let result = heap.compact_with(CompactOptions {
+            progress: Some(Box::new(move |_| {
+                reports += 1;
+                if reports == 2 {
+                    flag.store(true, Ordering::Relaxed);
+                }
+            })),
+            cancel: Some(cancel),
+            ..Default::default()
+        });
+
+        assert!(matches!(result, Err(Error::Cancelled)));
+        assert!(!shadow_path(&dir.path().join("heap")).exists());
+        assert_eq!(heap.physical_digest().unwrap(), digest);
+        assert_eq!(heap.get(&7u32.to_be_bytes()).unwrap(), Some(vec![2; 1000]));
+
+        // The Heap can still be compacted.
+        heap.compact().unwrap();
+        assert_eq!(heap.len().unwrap(), 1000);
+    }
+}
@@ -0,0 +1,340 @@
+//! Export and import of the live tuples of a Heap as CSV.
+//!
+//! Rows hold a key and a value, quoted as described in RFC 4180. Cells that
+//! aren't valid UTF-8 are written as base64 behind a "base64:" prefix, and
+//! so are those that start with the prefix themselves, so that every cell
+//! reads back to the same bytes.
+
+use super::Heap;
+use crate::{Error, Index};
+use std::io::{self, BufRead, Write};
+
+/// Options for [`Heap::export_csv`] and [`Heap::import_csv`].
+#[derive(Debug, Clone)]
+pub struct CsvOptions {
+    /// Whether the first row is the header "key,value".
+    pub header: bool,
+}
+
+impl Default for CsvOptions {
+    fn default() -> Self {
+        Self { header: true }
+    }
+}
+
+const BASE64_PREFIX: &[u8] = b"base64:";
+
+const BASE64_ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+impl Heap {
+    /// Writes every live key and its value to w as a row of CSV, and
+    /// returns the number of rows written, not counting the header.
+    pub fn export_csv<W: Write>(&self, mut w: W, opts: &CsvOptions) -> Result<u64, Error> {
+        if opts.header {
+            w.write_all(b"key,value\r\n").map_err(Error::IO)?;
+        }
+
+        let mut rows = 0;
+        for tuple in self.iter() {
+            let tuple = tuple?;
+            let mut row = encode_cell(&tuple.key);
+            row.push(b',');
+            row.extend_from_slice(&encode_cell(&tuple.value));
+            row.extend_from_slice(b"\r\n");
+            w.write_all(&row).map_err(Error::IO)?;
+            rows += 1;
+        }
+
+        Ok(rows)
+    }
+
+    /// Puts the key and value of every row of CSV read from r, in the format
+    /// written by [`Heap::export_csv`], and returns the number of rows.
+    ///
+    /// Rows are put one by one, so the rows in front of one that fails stay
+    /// in the Heap. Malformed CSV fails with an I/O error of kind
+    /// [`io::ErrorKind::InvalidData`] that names the line of the row.
+    pub fn import_csv<R: BufRead>(&mut self, mut r: R, opts: &CsvOptions) -> Result<u64, Error> {
+        let mut reader = RowReader::new(&mut r);
+        if opts.header {
+            match reader.next_row()? {
+                Some(row) if row == [b"key".to_vec(), b"value".to_vec()] => {}
+                Some(_) => return Err(invalid_data(1, "expected the header key,value")),
+                None => return Ok(0),
+            }
+        }
+
+        let mut rows = 0;
+        while let Some(row) = reader.next_row()? {
+            let line = reader.row_line;
+            let [key, value] = <[Vec<u8>; 2]>::try_from(row).map_err(|row| {
+                invalid_data(line, &format!("expected 2 fields, found {}", row.len()))
+            })?;
+            let key = decode_cell(key).ok_or_else(|| invalid_data(line, "invalid base64 key"))?;
+            let value =
+                decode_cell(value).ok_or_else(|| invalid_data(line, "invalid base64 value"))?;
+            self.put(&key, &value)?;
+            rows += 1;
+        }
+
+        Ok(rows)
+    }
+}
+
+fn invalid_data(line: usize, message: &str) -> Error {
+    Error::IO(io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("line {}: {}", line, message),
+    ))
+}
+
+/// Encodes bytes as a CSV cell, quoting it if needed.
+fn encode_cell(bytes: &[u8]) -> Vec<u8> {
+    if std::str::from_utf8(bytes).is_err() || bytes.starts_with(BASE64_PREFIX) {
+        let mut cell = BASE64_PREFIX.to_vec();
+        cell.extend_from_slice(base64_encode(bytes).as_bytes());
+        return cell;
+    }
+    if !bytes
+        .iter()
+        .any(|b| matches!(b, b',' | b'"' | b'\r' | b'\n'))
+    {
+        return bytes.to_vec();
+    }
+
+    let mut cell = Vec::with_capacity(bytes.len() + 2);
+    cell.push(b'"');
+    for &b in bytes {
+        if b == b'"' {
+            cell.push(b'"');
+        }
+        cell.push(b);
+    }
+    cell.push(b'"');
+    cell
+}
+
+/// Returns the bytes of an unquoted cell, or None if it holds invalid
+/// base64.
+fn decode_cell(cell: Vec<u8>) -> Option<Vec<u8>> {
+    match cell.strip_prefix(BASE64_PREFIX) {
+        Some(encoded) => base64_decode(encoded),
+        None => Some(cell),
+    }
+}
+
+pub(super) fn base64_encode(bytes: &[u8]) -> String {
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, &b)| n | (b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(BASE64_ALPHABET[(n >> (18 - 6 * i)) as usize & 0x3f] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+pub(super) fn base64_decode(encoded: &[u8]) -> Option<Vec<u8>> {
+    if !encoded.len().is_multiple_of(4) {
+        return None;
+    }
+
+    let mut bytes = Vec::with_capacity(encoded.len() / 4 * 3);
+    for (i, chunk) in encoded.chunks(4).enumerate() {
+        let last = i == encoded.len() / 4 - 1;
+        let padding = chunk.iter().rev().take_while(|&&c| c == b'=').count();
+        if padding > 2 || (padding > 0 && !last) {
+            return None;
+        }
+
+        let mut n = 0u32;
+        for &c in &chunk[..4 - padding] {
+            let digit = BASE64_ALPHABET.iter().position(|&a| a == c)?;
+            n = n << 6 | digit as u32;
+        }
+        n <<= 6 * padding;
+        bytes.extend_from_slice(&n.to_be_bytes()[1..4 - padding]);
+    }
+    Some(bytes)
+}
+
+/// Splits CSV into rows of fields.
+struct RowReader<'r, R> {
+    reader: &'r mut R,
+    // The number of lines read so far, and the line the last row started on.
+    line: usize,
+    row_line: usize,
+}
+
+#[derive(PartialEq)]
+enum State {
+    FieldStart,
+    Unquoted,
+    Quoted,
+    // A quote inside a quoted field, which either closes the field or
+    // escapes another quote.
+    QuoteInQuoted,
+}
+
+impl<'r, R: BufRead> RowReader<'r, R> {
+    fn new(reader: &'r mut R) -> Self {
+        Self {
+            reader,
+            line: 0,
+            row_line: 0,
+        }
+    }
+
+    /// Reads the next row, skipping empty lines, or returns None at the end
+    /// of the input.
+    fn next_row(&mut self) -> Result<Option<Vec<Vec<u8>>>, Error> {
+        let mut fields = Vec::new();
+        let mut field = Vec::new();
+        let mut state = State::FieldStart;
+        let mut buf = Vec::new();
+
+        loop {
+            buf.clear();
+            if self.reader.read_until(b'\n', &mut buf).map_err(Error::IO)? == 0 {
+                if state == State::Quoted {
+                    return Err(invalid_data(self.row_line, "unterminated quoted field"));
+                }
+                return Ok(None);
+            }
+            self.line += 1;
+            if state != State::Quoted {
+                self.row_line = self.line;
+            }
+
+            let content = buf.strip_suffix(b"\n").unwrap_or(&buf);
+            let content = content.strip_suffix(b"\r").unwrap_or(content);
+            if content.is_empty() && state == State::FieldStart && fields.is_empty() {
+                continue;
+            }
+
+            for &b in content {
+                state = match (state, b) {
+                    (State::FieldStart, b'"') => State::Quoted,
+                    (State::FieldStart | State::Unquoted | State::QuoteInQuoted, b',') => {
+                        fields.push(std::mem::take(&mut field));
+                        State::FieldStart
+                    }
+                    (State::Unquoted, b'"') => {
+                        return Err(invalid_data(self.line, "quote in unquoted field"));
+                    }
+                    (State::FieldStart | State::Unquoted, b) => {
+                        field.push(b);
+                        State::Unquoted
+                    }
+                    (State::Quoted, b'"') => State::QuoteInQuoted,
+                    (State::Quoted, b) => {
+                        field.push(b);
+                        State::Quoted
+                    }
+                    (State::QuoteInQuoted, b'"') => {
+                        field.push(b'"');
+                        State::Quoted
+                    }
+                    (State::QuoteInQuoted, _) => {
+                        return Err(invalid_data(self.line, "character after closing quote"));
+                    }
+                };
+            }
+
+            if state == State::Quoted {
+                // The line break is part of the quoted field.
+                field.extend_from_slice(&buf[content.len()..]);
+                continue;
+            }
+            fields.push(field);
+            return Ok(Some(fields));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::collections::HashMap;
+    use tempfile::tempfile;
+
+    fn contents(heap: &Heap) -> HashMap<Vec<u8>, Vec<u8>> {
+        heap.iter()
+            .map(|tuple| tuple.map(|t| (t.key, t.value)))
+            .collect::<Result<_, _>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_csv_round_trip() {
+        let mut heap = Heap::new(tempfile().unwrap()).unwrap();
+        heap.put(b"plain", b"value").unwrap();
+        heap.put(b"quoted", b"a, \"b\"\nc\r\n").unwrap();
+        heap.put(b"binary", &[0xff, 0, 1, 2]).unwrap();
+        heap.put(b"base64:key", b"base64:value").unwrap();
+        heap.put(b"empty", b"").unwrap();
+        heap.put(b"deleted", b"value").unwrap();
+        heap.delete(b"deleted").unwrap();
+
+        let mut csv = Vec::new();
+        let rows = heap.export_csv(&mut csv, &CsvOptions::default()).unwrap();
+        assert_eq!(rows, 5);
+        let csv_text = String::from_utf8(csv.clone()).unwrap();
+        assert!(csv_text.starts_with("key,value\r\n"));
+        assert!(csv_text.contains("quoted,\"a, \"\"b\"\"\nc\r\n\"\r\n"));
+        assert!(csv_text.contains("binary,base64:/wABAg==\r\n"));
+
+        let mut copy = Heap::new(tempfile().unwrap()).unwrap();
+        let rows = copy.import_csv(&csv[..], &CsvOptions::default()).unwrap();
+        assert_eq!(rows, 5);
+        assert_eq!(contents(&copy), contents(&heap));
+    }
+
+    #[test]
+    fn test_csv_import_reports_line() {
+        let opts = CsvOptions::default();
+        for (csv, line) in [
+            (&b"key,value\nk,v\n\"k\nk\",v,v\n"[..], 3),
+            (b"key,value\n\nk,\"v\"x\n", 3),
+            (b"key,value\nk,v\n\"k,v\n", 3),
+            (b"key,value\nk,base64:abc\n", 2),
+            (b"k,v\n", 1),
+        ] {
+            let mut heap = Heap::new(tempfile().unwrap()).unwrap();
+            match heap.import_csv(csv, &opts) {
+                Err(Error::IO(e)) => {
+                    assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+                    assert!(
+                        e.to_string().starts_with(&format!("line {}:", line)),
+                        "{}",
+                        e
+                    );
+                }
+                other => panic!("expected invalid data, got {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn test_base64() {
+        for bytes in [&b""[..], b"f", b"fo", b"foo", b"foob", b"fooba", b"foobar"] {
+            assert_eq!(
+                base64_decode(base64_encode(bytes).as_bytes()).unwrap(),
+                bytes
+            );
+        }
+        assert_eq!(base64_encode(b"foobar"), "Zm9vYmFy");
+        assert_eq!(base64_encode(b"fo"), "Zm8=");
+        assert!(base64_decode(b"Zm8").is_none());
+        assert!(base64_decode(b"Zm==Zm8=").is_none());
+        assert!(base64_decode(b"Z!8=").is_none());
+    }
+}
@@ -0,0 +1,287 @@
+//! Deduplication of values written by puts.
+//!
+//! A Heap that deduplicates values remembers where it wrote recent values,
+//! by their hash. A put of a value it remembers writes a tuple with the
+//! [`REFERENCE_FLAG`](super::REFERENCE_FLAG) that refers to the earlier
+//! value instead of storing the value again. Scans resolve references as
+//! they read the tuples, so they are invisible to everything but the size
+//! of the file.
+
+use super::{Heap, REFERENCE_LEN};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, VecDeque};
+use std::hash::{Hash, Hasher};
+
+/// Values that aren't longer than a reference are always stored as they
+/// are.
+const MIN_VALUE_LEN: usize = REFERENCE_LEN + 1;
+
+/// The locations of recently written values by their hash.
+///
+/// The cache holds a bounded number of values and forgets the oldest ones
+/// first. Forgetting a value, or a hash collision, only means that the
+/// value is stored again.
+#[derive(Debug)]
+pub(super) struct ValueCache {
+    capacity: usize,
+    locations: HashMap<u64, (u64, usize)>,
+    order: VecDeque<u64>,
+}
+
+impl ValueCache {
+    pub(super) fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            locations: HashMap::new(),
+            order: VecDeque::new(),
+        }
+    }
+
+    fn insert(&mut self, hash: u64, location: (u64, usize)) {
+        if self.locations.insert(hash, location).is_some() {
+            return;
+        }
+        self.order.push_back(hash);
+        if self.order.len() > self.capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.locations.remove(&oldest);
+            }
+        }
+    }
+
+    fn clear(&mut self) {
+        self.locations.clear();
+        self.order.clear();
+    }
+}
+
+fn hash(value: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// The values that a single write of several tuples stores, by their
+/// offsets, so that later tuples of the write can refer to them before
+/// they are in the file.
+#[derive(Debug, Default)]
+pub(super) struct PendingValues {
+    offsets: HashMap<Vec<u8>, u64>,
+}
+
+/// Encodes the value of a tuple that refers to the value at the offset.
+pub(super) fn encode_reference(offset: u64, len: usize) -> [u8; REFERENCE_LEN] {
+    let mut reference = [0; REFERENCE_LEN];
+    reference[..8].copy_from_slice(&offset.to_be_bytes());
+    reference[8..].copy_from_slice(&(len as u16).to_be_bytes());
+    reference
+}
+
+/// Decodes the offset and length of the value a reference refers to.
+pub(super) fn decode_reference(reference: &[u8]) -> (u64, usize) {
+    let offset = u64::from_be_bytes(reference[..8].try_into().unwrap());
+    let len = u16::from_be_bytes(reference[8..REFERENCE_LEN].try_into().unwrap());
+    (offset, len as usize)
+}
+
+impl Heap {
+    /// Returns a reference to an earlier copy of the value, if the Heap
+    /// deduplicates values and remembers one.
+    ///
+    /// The earlier copy is read back and compared before it is used, so
+    /// that neither hash collisions nor a file that changed since the value
+    /// was written can lead to a wrong reference.
+    pub(super) fn find_duplicate(&self, value: &[u8]) -> Option<[u8; REFERENCE_LEN]> {
+        let values = self.values.as_ref()?;
+        if value.len() < MIN_VALUE_LEN {
+            return None;
+        }
+        let location = values
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .locations
+            .get(&hash(value))
+            .copied();
+        let (offset, len) = location?;
+        if len != value.len() || offset + len as u64 > self.committed_len() {
+            return None;
+        }
+
+        let mut stored = vec![0; len];
+        self.read_at(&mut stored, offset).ok()?;
+        self.counters.read(len);
+        (stored == value).then(|| encode_reference(offset, len))
+    }
+
+    /// Returns a reference to an earlier copy of the value like
+    /// find_duplicate, including the copies that are about to be written
+    /// along with it.
+    pub(super) fn find_pending_duplicate(
+        &self,
+        value: &[u8],
+        pending: &PendingValues,
+    ) -> Option<[u8; REFERENCE_LEN]> {
+        match pending.offsets.get(value) {
+            Some(&offset) => Some(encode_reference(offset, value.len())),
+            None => self.find_duplicate(value),
+        }
+    }
+
+    /// Adds the value that is about to be written at the offset to the
+    /// pending ones, if the Heap deduplicates values.
+    pub(super) fn add_pending_value(&self, pending: &mut PendingValues, offset: u64, value: &[u8]) {
+        if self.values.is_some() && value.len() >= MIN_VALUE_LEN {
+            pending.offsets.entry(value.to_vec()).or_insert(offset);
+        }
+    }
+
+    /// Remembers the pending values once they were written.
+    pub(super) fn remember_pending_values(&self, pending: PendingValues) {
+        for (value, offset) in pending.offsets {
+            self.remember_value(offset, &value);
+        }
+    }
+
+    /// Remembers that the value was written at the offset, if the Heap
+    /// deduplicates values.
+    pub(super) fn remember_value(&self, offset: u64, value: &[u8]) {
+        let Some(values) = &self.values else {
+            return;
+        };
+        if value.len() >= MIN_VALUE_LEN {
+            let mut values = values.lock().unwrap_or_else(|e| e.into_inner());
+            values.insert(hash(value), (offset, value.len()));
+        }
+    }
+
+    /// Forgets the values written so far, once their offsets changed.
+    pub(super) fn forget_values(&self) {
+        if let Some(values) = &self.values {
+            values.lock().unwrap_or_else(|e| e.into_inner()).clear();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{HeapOptions, Index};
+    use std::fs;
+
+    fn open(path: &std::path::Path) -> Heap {
+        HeapOptions::new()
+            .deduplicate_values(16)
+            .open(path.to_path_buf())
+            .unwrap()
+    }
+
+    #[test]
+    fn test_value_cache_forgets_oldest() {
+        let mut cache = ValueCache::new(2);
+        cache.insert(1, (0, 20));
+        cache.insert(2, (20, 20));
+        cache.insert(1, (40, 20));
+        cache.insert(3, (60, 20));
+        assert!(!cache.locations.contains_key(&1));
+        assert_eq!(cache.locations[&2], (20, 20));
+        assert_eq!(cache.locations[&3], (60, 20));
+    }
+
+    #[test]
+    fn test_deduplicate_values() {
+        let dir = tempfile::tempdir().unwrap();
+        let shared = [b's'; 1000];
+
+        let distinct = dir.path().join("distinct");
+        let mut heap = open(&distinct);
+        heap.put(b"key1", &shared).unwrap();
+        heap.put(b"key2", &[b'd'; 1000]).unwrap();
+
+        let path = dir.path().join("heap");
+        let mut heap = open(&path);
+        heap.put(b"key1", &shared).unwrap();
+        heap.put(b"key2", &shared).unwrap();
+        heap.put(b"key3", b"short").unwrap();
+        heap.put(b"key4", b"short").unwrap();
+        let len = fs::metadata(&path).unwrap().len();
+        assert_eq!(len, 1000 + 4 * (4 + 3) + REFERENCE_LEN as u64 + 2 * 5);
+        assert!(len < fs::metadata(&distinct).unwrap().len());
+
+        // put_many refers to earlier values as well as to those it writes
+        // itself.
+        let other = [b'o'; 100];
+        heap.put_many([
+            (&b"key5"[..], &shared[..]),
+            (b"key6", &other),
+            (b"key7", &other),
+        ])
+        .unwrap();
+        let tuple_len = |value_len| 4 + value_len + 3;
+        assert_eq!(
+            fs::metadata(&path).unwrap().len(),
+            len + 2 * tuple_len(REFERENCE_LEN as u64) + tuple_len(100)
+        );
+        assert_eq!(heap.get(b"key5").unwrap(), Some(shared.to_vec()));
+        assert_eq!(heap.get(b"key7").unwrap(), Some(other.to_vec()));
+        heap.put(b"key8", &other).unwrap();
+        assert_eq!(
+            fs::metadata(&path).unwrap().len(),
+            len + 3 * tuple_len(REFERENCE_LEN as u64) + tuple_len(100)
+        );
+
+        assert_eq!(heap.get(b"key2").unwrap(), Some(shared.to_vec()));
+        let mut tuples: Vec<_> = heap.iter().map(|t| t.unwrap()).collect();
+        tuples.sort_by(|a, b| a.key.cmp(&b.key));
+        assert_eq!(tuples[0].value, shared);
+        assert_eq!(tuples[1].value, shared);
+        assert_eq!(tuples[3].value, b"short");
+        assert_eq!(tuples[6].value, other);
+
+        // References survive reopening, also without deduplication.
+        drop(heap);
+        let mut heap = Heap::from(path.clone()).unwrap();
+        assert_eq!(heap.get(b"key2").unwrap(), Some(shared.to_vec()));
+        heap.verify_and_clear().unwrap();
+    }
+
+    #[test]
+    fn test_compact_keeps_referenced_values() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("heap");
+        let shared = [b's'; 1000];
+        let mut heap = open(&path);
+        heap.put(b"key1", &shared).unwrap();
+        heap.put(b"key2", &shared).unwrap();
+        heap.put(b"key3", &shared).unwrap();
+        heap.put(b"key4", &[b'o'; 100]).unwrap();
+        heap.put(b"key5", &[b'o'; 100]).unwrap();
+
+        // The value of key1 only stays on disk for the other keys.
+        heap.delete(b"key1").unwrap();
+        heap.compact().unwrap();
+        assert_eq!(heap.get(b"key1").unwrap(), None);
+        assert_eq!(heap.get(b"key2").unwrap(), Some(shared.to_vec()));
+        assert_eq!(heap.get(b"key3").unwrap(), Some(shared.to_vec()));
+        assert_eq!(heap.get(b"key5").unwrap(), Some(vec![b'o'; 100]));
+        // Both shared values are stored once.
+        let len = fs::metadata(&path).unwrap().len();
+        assert_eq!(len, 1000 + 100 + 4 * (4 + 3) + 2 * REFERENCE_LEN as u64);
+
+        // Compaction forgets the values, so the next put stores it again.
+        heap.put(b"key6", &shared).unwrap();
+        heap.put(b"key7", &shared).unwrap();
+        let len = fs::metadata(&path).unwrap().len();
+        heap.put(b"key8", &shared).unwrap();
+        assert_eq!(
+            fs::metadata(&path).unwrap().len(),
+            len + 4 + 3 + REFERENCE_LEN as u64
+        );
+
+        heap.delete(b"key2").unwrap();
+        heap.compact().unwrap();
+        for key in [&b"key3"[..], b"key6", b"key7", b"key8"] {
+            assert_eq!(heap.get(key).unwrap(), Some(shared.to_vec()));
+        }
+        heap.verify_and_clear().unwrap();
+    }
+}
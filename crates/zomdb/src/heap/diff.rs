@@ -0,0 +1,212 @@
+use super::{Heap, HeapTuple};
+use crate::Error;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+/// Options to tune [`Heap::diff_with`].
+#[derive(Debug, Clone)]
+pub struct DiffOptions {
+    /// Maximum number of keys listed per kind of difference. All of them
+    /// are counted in the totals regardless.
+    pub max_keys: usize,
+}
+
+impl Default for DiffOptions {
+    fn default() -> Self {
+        Self { max_keys: 100 }
+    }
+}
+
+/// The differences between the live keys of two Heaps, see [`Heap::diff`].
+///
+/// Keys are listed in ascending order.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct DiffReport {
+    /// Keys that only have a value in the Heap diff was called on.
+    pub only_in_self: Vec<Vec<u8>>,
+    /// Keys that only have a value in the other Heap.
+    pub only_in_other: Vec<Vec<u8>>,
+    /// Keys that have different values in both Heaps.
+    pub different: Vec<Vec<u8>>,
+
+    /// Number of keys that only have a value in the Heap diff was called on.
+    pub only_in_self_total: u64,
+    /// Number of keys that only have a value in the other Heap.
+    pub only_in_other_total: u64,
+    /// Number of keys that have different values in both Heaps.
+    pub different_total: u64,
+}
+
+impl DiffReport {
+    /// Returns whether the Heaps hold the same keys and values.
+    pub fn is_empty(&self) -> bool {
+        self.only_in_self_total == 0 && self.only_in_other_total == 0 && self.different_total == 0
+    }
+}
+
+impl Heap {
+    /// Compares the live keys and values of this Heap to those of another.
+    ///
+    /// Stale tuples and tombstones don't count, so a Heap equals its
+    /// compacted copy. Both Heaps are scanned once, and their live tuples
+    /// are held in memory to be merged.
+    pub fn diff(&self, other: &Heap) -> Result<DiffReport, Error> {
+        self.diff_with(other, DiffOptions::default())
+    }
+
+    /// Like [`Heap::diff`], with options to tune the report.
+    pub fn diff_with(&self, other: &Heap, opts: DiffOptions) -> Result<DiffReport, Error> {
+        let ours = sorted_tuples(self)?;
+        let theirs = sorted_tuples(other)?;
+
+        let mut report = DiffReport::default();
+        let note = |keys: &mut Vec<Vec<u8>>, total: &mut u64, key: &[u8]| {
+            if keys.len() < opts.max_keys {
+                keys.push(key.to_vec());
+            }
+            *total += 1;
+        };
+
+        let (mut i, mut j) = (0, 0);
+        while i < ours.len() || j < theirs.len() {
+            let order = match (ours.get(i), theirs.get(j)) {
+                (Some(a), Some(b)) => a.key.cmp(&b.key),
+                (Some(_), None) => Ordering::Less,
+                _ => Ordering::Greater,
+            };
+            match order {
+                Ordering::Less => {
+                    let key = &ours[i].key;
+                    note(
+                        &mut report.only_in_self,
+                        &mut report.only_in_self_total,
+                        key,
+                    );
+                    i += 1;
+                }
+                Ordering::Greater => {
+                    let key = &theirs[j].key;
+                    note(
+                        &mut report.only_in_other,
+                        &mut report.only_in_other_total,
+                        key,
+                    );
+                    j += 1;
+                }
+                Ordering::Equal => {
+                    if ours[i].value != theirs[j].value {
+                        let key = &ours[i].key;
+                        note(&mut report.different, &mut report.different_total, key);
+                    }
+                    i += 1;
+                    j += 1;
+                }
+            }
+        }
+
+        Ok(report)
+    }
+
+    /// Returns whether this Heap holds the same live keys and values as
+    /// another.
+    ///
+    /// Unlike [`Heap::diff`], this stops scanning the other Heap at the
+    /// first difference.
+    pub fn content_equal(&self, other: &Heap) -> Result<bool, Error> {
+        let mut ours = HashMap::new();
+        for tuple in self.iter() {
+            let tuple = tuple?;
+            ours.insert(tuple.key, tuple.value);
+        }
+
+        for tuple in other.iter() {
+            let tuple = tuple?;
+            match ours.remove(&tuple.key) {
+                Some(value) if value == tuple.value => {}
+                _ => return Ok(false),
+            }
+        }
+
+        Ok(ours.is_empty())
+    }
+}
+
+/// Returns the live tuples of the Heap ordered by key.
+fn sorted_tuples(heap: &Heap) -> Result<Vec<HeapTuple>, Error> {
+    let mut tuples = heap.iter().collect::<Result<Vec<_>, _>>()?;
+    tuples.sort_unstable_by(|a, b| a.key.cmp(&b.key));
+    Ok(tuples)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Index;
+    use tempfile::tempfile;
+
+    fn heap_with(tuples: &[(&[u8], &[u8])]) -> Heap {
+        let mut heap = Heap::new(tempfile().unwrap()).unwrap();
+        for (key, value) in tuples {
+            heap.put(key, value).unwrap();
+        }
+        heap
+    }
+
+    #[test]
+    fn test_diff_identical() {
+        let a = heap_with(&[(b"key1", b"value1"), (b"key2", b"value2")]);
+        // The same contents, written in a different order and with a stale
+        // version of a key.
+        let b = heap_with(&[
+            (b"key2", b"old"),
+            (b"key1", b"value1"),
+            (b"key2", b"value2"),
+        ]);
+
+        assert!(a.diff(&b).unwrap().is_empty());
+        assert!(a.content_equal(&b).unwrap());
+        assert!(b.content_equal(&a).unwrap());
+    }
+
+    #[test]
+    fn test_diff_divergent_key() {
+        let a = heap_with(&[(b"key1", b"value1"), (b"key2", b"value2")]);
+        let b = heap_with(&[(b"key1", b"value1"), (b"key2", b"other")]);
+
+        let report = a.diff(&b).unwrap();
+        assert_eq!(report.different, vec![b"key2".to_vec()]);
+        assert_eq!(report.different_total, 1);
+        assert!(report.only_in_self.is_empty() && report.only_in_other.is_empty());
+        assert!(!a.content_equal(&b).unwrap());
+    }
+
+    #[test]
+    fn test_diff_deleted_key() {
+        let a = heap_with(&[(b"key1", b"value1"), (b"key2", b"value2")]);
+        let mut b = heap_with(&[(b"key1", b"value1"), (b"key2", b"value2"), (b"key3", b"v")]);
+        b.delete(b"key1").unwrap();
+
+        let report = a.diff(&b).unwrap();
+        assert_eq!(report.only_in_self, vec![b"key1".to_vec()]);
+        assert_eq!(report.only_in_other, vec![b"key3".to_vec()]);
+        assert_eq!(report.different_total, 0);
+        assert!(!a.content_equal(&b).unwrap());
+        assert!(!b.content_equal(&a).unwrap());
+    }
+
+    #[test]
+    fn test_diff_limits_keys() {
+        let mut a = Heap::new(tempfile().unwrap()).unwrap();
+        for i in 0..10 {
+            a.put(format!("key{}", i).as_bytes(), b"value").unwrap();
+        }
+        let b = heap_with(&[]);
+
+        let report = a.diff_with(&b, DiffOptions { max_keys: 3 }).unwrap();
+        assert_eq!(
+            report.only_in_self,
+            vec![b"key0".to_vec(), b"key1".to_vec(), b"key2".to_vec()]
+        );
+        assert_eq!(report.only_in_self_total, 10);
+    }
+}
@@ -0,0 +1,243 @@
+//! SHA-256 digests of the contents of a Heap, to verify copies of it.
+
+use super::Heap;
+use crate::{Error, Operation};
+use std::cmp;
+
+const READ_BUFFER_SIZE: usize = 64 * 1024;
+
+impl Heap {
+    /// Returns the digest to verify a backup of the Heap with.
+    ///
+    /// This is the [`Heap::physical_digest`], which matches that of a copy
+    /// made by [`Heap::backup_to`] at the same time.
+    pub fn checksum_all(&self) -> Result<[u8; 32], Error> {
+        self.physical_digest()
+    }
+
+    /// Returns the SHA-256 of the bytes of the file, up to the end of the
+    /// tuples that are completely written.
+    ///
+    /// Two Heaps have the same physical digest if their files are identical,
+    /// so it changes with every write.
+    pub fn physical_digest(&self) -> Result<[u8; 32], Error> {
+        let result = self.check_file().and_then(|_| self.hash_file());
+        self.track(result)
+    }
+
+    /// Returns the SHA-256 of the live keys and their values.
+    ///
+    /// The pairs are hashed in ascending order of their keys, each as the
+    /// length of the key as four big-endian bytes, the key, and the value
+    /// framed the same way. Two Heaps with the same contents have the same
+    /// logical digest, no matter in which order or how often the keys were
+    /// written, so it stays the same across compaction. The live pairs are
+    /// held in memory to sort them.
+    pub fn logical_digest(&self) -> Result<[u8; 32], Error> {
+        let mut pairs = Vec::new();
+        for tuple in self.iter() {
+            let tuple = tuple?;
+            pairs.push((tuple.key, tuple.value));
+        }
+        pairs.sort_unstable();
+
+        let mut hasher = Sha256::new();
+        for (key, value) in &pairs {
+            hasher.update(&(key.len() as u32).to_be_bytes());
+            hasher.update(key);
+            hasher.update(&(value.len() as u32).to_be_bytes());
+            hasher.update(value);
+        }
+        Ok(hasher.finish())
+    }
+
+    fn hash_file(&self) -> Result<[u8; 32], Error> {
+        let len = self.committed_len();
+        let mut buffer = vec![0u8; cmp::min(READ_BUFFER_SIZE as u64, len) as usize];
+        let mut hasher = Sha256::new();
+        let mut offset = 0;
+        while offset < len {
+            let chunk = &mut buffer[..cmp::min(READ_BUFFER_SIZE as u64, len - offset) as usize];
+            self.read_at(chunk, offset)
+                .map_err(|e| Error::io(Operation::Read, self.path.as_deref(), e))?;
+            self.counters.read(chunk.len());
+            hasher.update(chunk);
+            offset += chunk.len() as u64;
+        }
+        Ok(hasher.finish())
+    }
+}
+
+/// Computes SHA-256 as specified in FIPS 180-4.
+struct Sha256 {
+    state: [u32; 8],
+    block: [u8; 64],
+    block_len: usize,
+    len: u64,
+}
+
+const K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+impl Sha256 {
+    fn new() -> Self {
+        Self {
+            state: [
+                0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab,
+                0x5be0cd19,
+            ],
+            block: [0; 64],
+            block_len: 0,
+            len: 0,
+        }
+    }
+
+    fn update(&mut self, mut data: &[u8]) {
+        self.len += data.len() as u64;
+        while !data.is_empty() {
+            let n = cmp::min(64 - self.block_len, data.len());
+            self.block[self.block_len..self.block_len + n].copy_from_slice(&data[..n]);
+            self.block_len += n;
+            data = &data[n..];
+            if self.block_len == 64 {
+                self.compress();
+                self.block_len = 0;
+            }
+        }
+    }
+
+    fn finish(mut self) -> [u8; 32] {
+        let bits = self.len * 8;
+        self.update(&[0x80]);
+        while self.block_len != 56 {
+            self.update(&[0]);
+        }
+        self.update(&bits.to_be_bytes());
+
+        let mut digest = [0; 32];
+        for (chunk, word) in digest.chunks_exact_mut(4).zip(self.state) {
+            chunk.copy_from_slice(&word.to_be_bytes());
+        }
+        digest
+    }
+
+    fn compress(&mut self) {
+        let mut w = [0u32; 64];
+        for (i, chunk) in self.block.chunks_exact(4).enumerate() {
+            w[i] = u32::from_be_bytes(chunk.try_into().unwrap());
+        }
+        for i in 16..64 {
+            let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+            let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+            w[i] = w[i - 16]
+                .wrapping_add(s0)
+                .wrapping_add(w[i - 7])
+                .wrapping_add(s1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = self.state;
+        for i in 0..64 {
+            let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+            let ch = (e & f) ^ (!e & g);
+            let t1 = h
+                .wrapping_add(s1)
+                .wrapping_add(ch)
+                .wrapping_add(K[i])
+                .wrapping_add(w[i]);
+            let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+            let maj = (a & b) ^ (a & c) ^ (b & c);
+            let t2 = s0.wrapping_add(maj);
+            h = g;
+            g = f;
+            f = e;
+            e = d.wrapping_add(t1);
+            d = c;
+            c = b;
+            b = a;
+            a = t1.wrapping_add(t2);
+        }
+
+        for (state, value) in self.state.iter_mut().zip([a, b, c, d, e, f, g, h]) {
+            *state = state.wrapping_add(value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Index;
+
+    fn sha256(data: &[u8]) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(data);
+        hasher
+            .finish()
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect()
+    }
+
+    #[test]
+    fn test_sha256() {
+        assert_eq!(
+            sha256(b""),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        assert_eq!(
+            sha256(b"abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(
+            sha256(b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"),
+            "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1"
+        );
+        assert_eq!(
+            sha256(&[b'a'; 1_000_000]),
+            "cdc76e5c9914fb9281a1c7e284d73e67f1809a48a497200e046d39ccc7112cd0"
+        );
+    }
+
+    #[test]
+    fn test_digests() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut heap = Heap::from(dir.path().join("heap")).unwrap();
+        heap.put(b"key1", b"value1").unwrap();
+        heap.put(b"key2", b"value2").unwrap();
+        heap.put(b"key1", b"value3").unwrap();
+        heap.put(b"key3", b"value4").unwrap();
+        heap.delete(b"key3").unwrap();
+
+        let physical = heap.physical_digest().unwrap();
+        let logical = heap.logical_digest().unwrap();
+        assert_eq!(heap.checksum_all().unwrap(), physical);
+
+        // A backup is identical to the Heap.
+        let dest = dir.path().join("backup");
+        heap.backup_to(&dest).unwrap();
+        let backup = Heap::from(dest).unwrap();
+        assert_eq!(backup.physical_digest().unwrap(), physical);
+
+        // Compaction only changes the bytes.
+        heap.compact().unwrap();
+        assert_ne!(heap.physical_digest().unwrap(), physical);
+        assert_eq!(heap.logical_digest().unwrap(), logical);
+
+        // Any write changes the bytes, even one that keeps the contents.
+        let compacted = heap.physical_digest().unwrap();
+        heap.put(b"key2", b"value2").unwrap();
+        assert_ne!(heap.physical_digest().unwrap(), compacted);
+        assert_eq!(heap.logical_digest().unwrap(), logical);
+
+        heap.put(b"key2", b"other").unwrap();
+        assert_ne!(heap.logical_digest().unwrap(), logical);
+    }
+}
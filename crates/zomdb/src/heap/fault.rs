@@ -0,0 +1,144 @@
+//! Fault injection for testing the error paths of a Heap.
+
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+use std::{fs, path};
+
+/// Makes the file operations of a Heap fail on demand.
+///
+/// Attach it with [`HeapOptions::faults`](crate::HeapOptions::faults) and
+/// keep a clone around to inject faults while the Heap is in use; clones
+/// share their faults. Readers and iterators of the Heap are affected as
+/// well. Only available with the `testing` feature, which is meant for the
+/// integration tests of code that handles the errors of a Heap.
+#[derive(Debug, Clone, Default)]
+pub struct FailingStorage {
+    state: Arc<Mutex<State>>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    // The number of bytes that may still be written before writes fail.
+    // Writes are unrestricted if None.
+    write_budget: Option<usize>,
+    // The number of writes and reads that still succeed before the next one
+    // fails with the given kind of error.
+    write_failure: Option<(usize, io::ErrorKind)>,
+    read_failure: Option<(usize, io::ErrorKind)>,
+    // Whether reads stop halfway through, as if the file was truncated.
+    short_reads: bool,
+    // The directories synced so far, in order.
+    synced_dirs: Vec<path::PathBuf>,
+}
+
+impl FailingStorage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Lets the next n writes succeed and fails the one after with an error
+    /// of the given kind. Writes succeed again afterwards.
+    pub fn fail_write(&self, n: usize, kind: io::ErrorKind) {
+        self.state().write_failure = Some((n, kind));
+    }
+
+    /// Lets the next n reads succeed and fails the one after with an error
+    /// of the given kind. Reads succeed again afterwards.
+    pub fn fail_read(&self, n: usize, kind: io::ErrorKind) {
+        self.state().read_failure = Some((n, kind));
+    }
+
+    /// Lets writes succeed for the next `bytes` bytes, cutting the write
+    /// that crosses the limit short, and fails all writes afterwards.
+    pub fn fail_writes_after(&self, bytes: usize) {
+        self.state().write_budget = Some(bytes);
+    }
+
+    /// Makes every read return only the first half of the bytes asked for,
+    /// and fail with [`io::ErrorKind::UnexpectedEof`].
+    pub fn short_reads(&self, short_reads: bool) {
+        self.state().short_reads = short_reads;
+    }
+
+    /// Returns the directories that were synced, in order, including
+    /// repeats.
+    pub fn synced_dirs(&self) -> Vec<path::PathBuf> {
+        self.state().synced_dirs.clone()
+    }
+
+    /// Removes all faults and forgets the synced directories.
+    pub fn clear(&self) {
+        *self.state() = State::default();
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub(super) fn write_vectored(
+        &self,
+        mut file: &fs::File,
+        slices: &[io::IoSlice<'_>],
+    ) -> io::Result<usize> {
+        let mut state = self.state();
+        if let Some(kind) = countdown(&mut state.write_failure) {
+            return Err(io::Error::new(kind, "injected write failure"));
+        }
+        let Some(remaining) = state.write_budget.as_mut() else {
+            return file.write_vectored(slices);
+        };
+        if *remaining == 0 {
+            return Err(io::Error::other("injected write failure"));
+        }
+
+        let data: Vec<u8> = slices.iter().flat_map(|s| s.iter().copied()).collect();
+        let n = file.write(&data[..data.len().min(*remaining)])?;
+        *remaining -= n;
+
+        Ok(n)
+    }
+
+    pub(super) fn sync_dir(&self, dir: &path::Path) -> io::Result<()> {
+        self.state().synced_dirs.push(dir.to_path_buf());
+        super::sync_dir(dir)
+    }
+
+    pub(super) fn read_exact_at(
+        &self,
+        file: &fs::File,
+        buf: &mut [u8],
+        offset: u64,
+    ) -> io::Result<()> {
+        let mut state = self.state();
+        if let Some(kind) = countdown(&mut state.read_failure) {
+            return Err(io::Error::new(kind, "injected read failure"));
+        }
+        if !state.short_reads || buf.is_empty() {
+            return super::read_exact_at(file, buf, offset);
+        }
+
+        let half = buf.len() / 2;
+        super::read_exact_at(file, &mut buf[..half], offset)?;
+        Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "injected short read",
+        ))
+    }
+}
+
+/// Counts down the operations that still succeed and returns the kind of
+/// error to fail with once none are left.
+fn countdown(failure: &mut Option<(usize, io::ErrorKind)>) -> Option<io::ErrorKind> {
+    match failure {
+        Some((0, kind)) => {
+            let kind = *kind;
+            *failure = None;
+            Some(kind)
+        }
+        Some((n, _)) => {
+            *n -= 1;
+            None
+        }
+        None => None,
+    }
+}
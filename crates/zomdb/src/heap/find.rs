@@ -0,0 +1,90 @@
+use super::{Heap, Scanner};
+use crate::Error;
+use std::collections::HashSet;
+
+impl Heap {
+    /// Returns the keys whose value equals the given one.
+    ///
+    /// See [`Heap::find_values_where`].
+    pub fn find_by_value(&self, value: &[u8]) -> Result<Vec<Vec<u8>>, Error> {
+        self.find_values_where(|v| v == value)
+    }
+
+    /// Returns the keys whose value matches the predicate.
+    ///
+    /// Values are compared in the buffer the file is read into, so only the
+    /// matching keys are copied. Still, there is no index on values, so this
+    /// reads the whole file and keeps every key in memory. The keys are
+    /// returned in no particular order.
+    pub fn find_values_where(
+        &self,
+        pred: impl FnMut(&[u8]) -> bool,
+    ) -> Result<Vec<Vec<u8>>, Error> {
+        let result = self.check_file().and_then(|_| self.find_keys(pred));
+        self.track(result)
+    }
+
+    fn find_keys(&self, mut pred: impl FnMut(&[u8]) -> bool) -> Result<Vec<Vec<u8>>, Error> {
+        let mut scanner = Scanner::new();
+        scanner.reset(self.committed_len());
+        let mut seen_keys = HashSet::new();
+        let mut keys = Vec::new();
+
+        while let Some(tuple) = scanner.next_tuple(self)? {
+            if seen_keys.contains(tuple.key) {
+                continue;
+            }
+            seen_keys.insert(tuple.key.to_vec());
+
+            if !tuple.tombstone && pred(tuple.value) {
+                keys.push(tuple.key.to_vec());
+            }
+        }
+
+        Ok(keys)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Index;
+    use tempfile::tempfile;
+
+    #[test]
+    fn test_find_by_value() {
+        let mut heap = Heap::new(tempfile().unwrap()).unwrap();
+        heap.put(b"key1", b"blob").unwrap();
+        heap.put(b"key2", b"other").unwrap();
+        heap.put(b"key3", b"blob").unwrap();
+        // The old values of these keys match, their latest ones don't.
+        heap.put(b"key4", b"blob").unwrap();
+        heap.put(b"key4", b"changed").unwrap();
+        heap.put(b"key5", b"blob").unwrap();
+        heap.delete(b"key5").unwrap();
+
+        let mut keys = heap.find_by_value(b"blob").unwrap();
+        keys.sort();
+        assert_eq!(keys, vec![b"key1".to_vec(), b"key3".to_vec()]);
+        assert!(heap.find_by_value(b"missing").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_find_values_where() {
+        let mut heap = Heap::new(tempfile().unwrap()).unwrap();
+        for i in 0..100u32 {
+            heap.put(&i.to_be_bytes(), &vec![b'v'; i as usize]).unwrap();
+        }
+
+        let mut calls = 0;
+        let mut keys = heap
+            .find_values_where(|value| {
+                calls += 1;
+                value.len() >= 98
+            })
+            .unwrap();
+        keys.sort();
+        assert_eq!(keys, vec![98u32.to_be_bytes(), 99u32.to_be_bytes()]);
+        assert_eq!(calls, 100);
+    }
+}
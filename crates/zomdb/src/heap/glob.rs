@@ -0,0 +1,246 @@
+use super::{Heap, HeapTuple, Tuples};
+use crate::{Error, PatternError};
+
+/// A part of a glob pattern.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Token {
+    Literal(u8),
+    /// `?` matches a single byte other than the separator.
+    Any,
+    /// `*` matches any run of bytes other than the separator.
+    Star,
+    /// `**` as the last segment matches any run of bytes.
+    Globstar,
+    /// `**` followed by the separator matches any number of whole segments,
+    /// including none.
+    GlobstarSegments,
+}
+
+/// A compiled glob pattern, see [`Heap::scan_glob`].
+#[derive(Debug, Clone)]
+struct Glob {
+    tokens: Vec<Token>,
+    separator: u8,
+}
+
+impl Glob {
+    fn new(pattern: &[u8], separator: u8) -> Result<Self, PatternError> {
+        let mut tokens = Vec::new();
+        let mut i = 0;
+        while i < pattern.len() {
+            let token = match pattern[i] {
+                b'\\' => match pattern.get(i + 1) {
+                    Some(&b) => {
+                        i += 1;
+                        Token::Literal(b)
+                    }
+                    None => return Err(PatternError::new(i, "pattern ends with an escape")),
+                },
+                b'?' => Token::Any,
+                b'*' if pattern.get(i + 1) != Some(&b'*') => Token::Star,
+                b'*' => {
+                    // A globstar has to be a whole segment.
+                    let starts_segment = i == 0 || pattern[i - 1] == separator;
+                    if !starts_segment {
+                        return Err(PatternError::new(i, "** must be a whole segment"));
+                    }
+                    i += 1;
+                    match pattern.get(i + 1) {
+                        None => Token::Globstar,
+                        Some(&b) if b == separator => {
+                            i += 1;
+                            Token::GlobstarSegments
+                        }
+                        Some(_) => {
+                            return Err(PatternError::new(i + 1, "** must be a whole segment"))
+                        }
+                    }
+                }
+                b => Token::Literal(b),
+            };
+            tokens.push(token);
+            i += 1;
+        }
+
+        Ok(Self { tokens, separator })
+    }
+
+    /// Returns the bytes every matching key starts with.
+    fn literal_prefix(&self) -> Vec<u8> {
+        self.tokens
+            .iter()
+            .map_while(|token| match token {
+                Token::Literal(b) => Some(*b),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn matches(&self, key: &[u8]) -> bool {
+        // matched[i][j] holds whether the tokens from i on match the key
+        // from byte j on. Rows are filled from the end, so that each only
+        // depends on the one below it and on itself further right.
+        let width = key.len() + 1;
+        let mut below = vec![false; width];
+        below[key.len()] = true;
+        let mut row = vec![false; width];
+
+        for token in self.tokens.iter().rev() {
+            for j in (0..width).rev() {
+                let byte = key.get(j).copied();
+                let not_separator = byte.is_some_and(|b| b != self.separator);
+                row[j] = match *token {
+                    Token::Literal(b) => byte == Some(b) && below[j + 1],
+                    Token::Any => not_separator && below[j + 1],
+                    Token::Star => below[j] || (not_separator && row[j + 1]),
+                    Token::Globstar => below[j] || (byte.is_some() && row[j + 1]),
+                    Token::GlobstarSegments => {
+                        // Either no segment is skipped, or the one starting
+                        // at j is and more may follow.
+                        let next_segment = key[j..]
+                            .iter()
+                            .position(|&b| b == self.separator)
+                            .map(|p| j + p + 1);
+                        below[j] || next_segment.is_some_and(|k| row[k])
+                    }
+                };
+            }
+            std::mem::swap(&mut below, &mut row);
+        }
+
+        below[0]
+    }
+}
+
+impl Heap {
+    /// Returns an iterator over the tuples whose keys match the glob
+    /// pattern, using '/' to separate the segments of keys.
+    ///
+    /// See [`Heap::scan_glob_with`].
+    pub fn scan_glob(&self, pattern: &[u8]) -> Result<GlobIter<'_>, Error> {
+        self.scan_glob_with(pattern, b'/')
+    }
+
+    /// Returns an iterator over the tuples whose keys match the glob
+    /// pattern, whose segments are separated by the given byte.
+    ///
+    /// In the pattern, `?` matches a single byte and `*` any run of bytes
+    /// within a segment. `**` matches any number of whole segments and has
+    /// to be a segment of its own. A backslash matches the byte following it
+    /// literally. All other bytes match themselves. Values of keys that
+    /// don't match aren't copied.
+    ///
+    /// Fails with [`Error::Pattern`] if the pattern is invalid.
+    pub fn scan_glob_with(&self, pattern: &[u8], separator: u8) -> Result<GlobIter<'_>, Error> {
+        let glob = Glob::new(pattern, separator).map_err(Error::Pattern)?;
+        Ok(GlobIter {
+            tuples: Tuples::new(self, None).with_prefix(&glob.literal_prefix()),
+            glob,
+        })
+    }
+}
+
+/// Iterator returned by [`Heap::scan_glob`].
+pub struct GlobIter<'a> {
+    tuples: Tuples<&'a Heap>,
+    glob: Glob,
+}
+
+impl<'a> Iterator for GlobIter<'a> {
+    type Item = Result<HeapTuple, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let glob = &self.glob;
+        self.tuples
+            .next_matching(|key| glob.matches(key))
+            .transpose()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Index;
+    use tempfile::tempfile;
+
+    fn matches(pattern: &[u8], key: &[u8]) -> bool {
+        Glob::new(pattern, b'/').unwrap().matches(key)
+    }
+
+    #[test]
+    fn test_glob_matches() {
+        assert!(matches(b"cfg/prod/db/host", b"cfg/prod/db/host"));
+        assert!(!matches(b"cfg/prod/db/host", b"cfg/prod/db/hosts"));
+
+        assert!(matches(b"cfg/*/db/*", b"cfg/prod/db/host"));
+        assert!(matches(b"cfg/*/db/*", b"cfg//db/"));
+        assert!(!matches(b"cfg/*/db/*", b"cfg/prod/eu/db/host"));
+        assert!(matches(b"cfg/p*d*/db/h?st", b"cfg/prod/db/host"));
+        assert!(!matches(b"cfg/p*d*/db/h?st", b"cfg/prod/db/hst"));
+        assert!(!matches(b"a?b", b"a/b"));
+
+        assert!(matches(b"cfg/**/host", b"cfg/host"));
+        assert!(matches(b"cfg/**/host", b"cfg/prod/eu/db/host"));
+        assert!(!matches(b"cfg/**/host", b"cfg/prod/ghost"));
+        assert!(matches(b"**/host", b"host"));
+        assert!(matches(b"**/host", b"a/b/host"));
+        assert!(matches(b"cfg/**", b"cfg/prod/db"));
+        assert!(matches(b"cfg/**", b"cfg/"));
+        assert!(!matches(b"cfg/**", b"cfg"));
+        assert!(matches(b"**", b"anything/at/all"));
+
+        assert!(matches(b"a\\*b", b"a*b"));
+        assert!(!matches(b"a\\*b", b"axb"));
+        assert!(matches(b"\x00?\xff*", b"\x00\x01\xff\xfe"));
+        assert!(matches(b"k*\xff", b"k\x00\x80\xff"));
+
+        let glob = Glob::new(b"a:*:c", b':').unwrap();
+        assert!(glob.matches(b"a:b:c"));
+        assert!(!glob.matches(b"a:b:b:c"));
+    }
+
+    #[test]
+    fn test_glob_errors() {
+        for (pattern, position) in [(&b"a**"[..], 1), (b"a/**b", 4), (b"***", 2), (b"a\\", 1)] {
+            let e = Glob::new(pattern, b'/').unwrap_err();
+            assert_eq!(e.position(), position, "{:?}", pattern);
+        }
+
+        let heap = Heap::new(tempfile().unwrap()).unwrap();
+        assert!(matches!(heap.scan_glob(b"a**"), Err(Error::Pattern(_))));
+    }
+
+    #[test]
+    fn test_scan_glob() {
+        let mut heap = Heap::new(tempfile().unwrap()).unwrap();
+        for key in [
+            &b"cfg/prod/db/host"[..],
+            b"cfg/prod/db/port",
+            b"cfg/dev/db/host",
+            b"cfg/dev/cache/host",
+            b"cfg/\xff/db/host",
+            b"other",
+        ] {
+            heap.put(key, b"value").unwrap();
+        }
+        heap.put(b"cfg/prod/db/host", b"new").unwrap();
+        heap.delete(b"cfg/dev/db/host").unwrap();
+
+        let mut keys: Vec<_> = heap
+            .scan_glob(b"cfg/*/db/host")
+            .unwrap()
+            .map(|tuple| tuple.unwrap())
+            .map(|tuple| (tuple.key, tuple.value))
+            .collect();
+        keys.sort();
+        assert_eq!(
+            keys,
+            vec![
+                (b"cfg/prod/db/host".to_vec(), b"new".to_vec()),
+                (b"cfg/\xff/db/host".to_vec(), b"value".to_vec()),
+            ]
+        );
+        assert_eq!(heap.scan_glob(b"cfg/**/host").unwrap().count(), 3);
+        assert_eq!(heap.scan_glob(b"**").unwrap().count(), 5);
+    }
+}
@@ -0,0 +1,201 @@
+//! Hashes of keys stored in tuples, see
+//! [`HeapOptions::key_hashes`](crate::HeapOptions::key_hashes).
+
+use super::{Heap, HASH_FLAG};
+#[cfg(test)]
+use std::cell::Cell;
+
+/// The number of bytes of a key hash on disk.
+pub(super) const HASH_LEN: usize = 4;
+
+#[cfg(test)]
+type HashFn = fn(&[u8]) -> u32;
+
+#[cfg(test)]
+thread_local! {
+    // Replaces key_hash in the tests of the current thread, to force
+    // collisions.
+    static HASH_OVERRIDE: Cell<Option<HashFn>> = const { Cell::new(None) };
+}
+
+/// Returns the 32-bit FNV-1a hash of the key.
+///
+/// The hash is part of the file format, so it must not change. Tuples
+/// store it in big-endian byte order.
+pub(super) fn key_hash(key: &[u8]) -> u32 {
+    #[cfg(test)]
+    if let Some(hash) = HASH_OVERRIDE.with(Cell::get) {
+        return hash(key);
+    }
+    key.iter().fold(0x811c_9dc5, |hash, &byte| {
+        (hash ^ u32::from(byte)).wrapping_mul(0x0100_0193)
+    })
+}
+
+impl Heap {
+    /// Returns the flags of a tuple of the key along with the hash to store
+    /// in front of its trailer, if the Heap stores key hashes.
+    pub(super) fn hash_key(&self, key: &[u8], flags: u16) -> (u16, Option<[u8; HASH_LEN]>) {
+        if !self.key_hashes {
+            return (flags, None);
+        }
+        (flags | HASH_FLAG, Some(key_hash(key).to_be_bytes()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{DeserializationError, Error, HeapOptions, Index, WriteBatch};
+    use std::fs;
+
+    #[test]
+    fn test_key_hash() {
+        assert_eq!(key_hash(b""), 0x811c9dc5);
+        assert_eq!(key_hash(b"a"), 0xe40c292c);
+        assert_eq!(key_hash(b"foobar"), 0xbf9cf968);
+    }
+
+    #[test]
+    fn test_key_hashes() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("heap");
+        let mut heap = HeapOptions::new()
+            .key_hashes(true)
+            .open(path.clone())
+            .unwrap();
+        heap.put(b"key1", b"value1").unwrap();
+        heap.put_tagged(b"key2", b"value2", 7).unwrap();
+        heap.put_many([(&b"key3"[..], &b"value3"[..])]).unwrap();
+        let mut batch = WriteBatch::new();
+        batch.put(b"key4", b"value4");
+        batch.delete(b"key3");
+        heap.write_batch(&batch).unwrap();
+        heap.delete(b"key1").unwrap();
+        // Every tuple stores 4 more bytes.
+        assert_eq!(
+            heap.stats().unwrap().file_size,
+            4 * (4 + 6 + 3 + 4) + 1 + 2 * (4 + 3 + 4)
+        );
+
+        let check = |heap: &mut Heap| {
+            assert_eq!(heap.get(b"key1").unwrap(), None);
+            assert_eq!(heap.get(b"key2").unwrap(), Some(b"value2".to_vec()));
+            assert_eq!(heap.get(b"key3").unwrap(), None);
+            assert_eq!(heap.get(b"key4").unwrap(), Some(b"value4".to_vec()));
+            let mut tuples: Vec<_> = heap.iter().map(Result::unwrap).collect();
+            tuples.sort_by(|a, b| a.key.cmp(&b.key));
+            assert_eq!(tuples.len(), 2);
+            assert_eq!(tuples[0].tag(), Some(7));
+            assert_eq!(heap.verify().unwrap().first_corrupt_offset, None);
+        };
+        check(&mut heap);
+
+        // Files may mix tuples with and without hashes.
+        drop(heap);
+        let mut heap = Heap::from(path.clone()).unwrap();
+        check(&mut heap);
+        heap.put(b"key4", b"value4").unwrap();
+        check(&mut heap);
+
+        heap.compact().unwrap();
+        check(&mut heap);
+    }
+
+    #[test]
+    fn test_key_hashes_skip_other_keys() {
+        let long_key = |i: usize| {
+            let mut key = vec![b'k'; 250];
+            key.extend_from_slice(format!("{:06}", i).as_bytes());
+            key
+        };
+        let compared = |key_hashes| {
+            let dir = tempfile::tempdir().unwrap();
+            let mut heap = HeapOptions::new()
+                .key_hashes(key_hashes)
+                .open(dir.path().join("heap"))
+                .unwrap();
+            for i in 0..1000 {
+                heap.put(&long_key(i), b"value").unwrap();
+            }
+            heap.reset_counters();
+            assert_eq!(heap.get(&long_key(0)).unwrap(), Some(b"value".to_vec()));
+            let counters = heap.perf_counters();
+            assert_eq!(counters.records_deserialized, 1000);
+            counters.key_bytes_compared
+        };
+
+        // Without hashes, the key of every tuple is compared, as they all
+        // have the same length. With them, only the one that matches.
+        assert_eq!(compared(false), 1000 * 256);
+        assert_eq!(compared(true), 256);
+    }
+
+    #[test]
+    fn test_key_hash_collisions() {
+        HASH_OVERRIDE.with(|hash| hash.set(Some(|key| key.len() as u32)));
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("heap");
+        let mut heap = HeapOptions::new()
+            .key_hashes(true)
+            .deduplicate_values(4)
+            .open(path.clone())
+            .unwrap();
+        let value = [b'v'; 20];
+        heap.put(b"key1", &value).unwrap();
+        heap.put(b"key2", b"value2").unwrap();
+        heap.put(b"key3", &value).unwrap();
+        heap.put(b"long key", b"value4").unwrap();
+        heap.delete(b"key2").unwrap();
+
+        // All keys of 4 bytes share a hash, so their keys are compared.
+        heap.reset_counters();
+        assert_eq!(heap.get(b"key1").unwrap(), Some(value.to_vec()));
+        assert_eq!(heap.perf_counters().key_bytes_compared, 4 * 4);
+        assert_eq!(heap.get(b"key2").unwrap(), None);
+        assert_eq!(heap.get(b"key3").unwrap(), Some(value.to_vec()));
+        assert_eq!(heap.get(b"key4").unwrap(), None);
+        assert_eq!(heap.get(b"long key").unwrap(), Some(b"value4".to_vec()));
+        assert_eq!(heap.verify().unwrap().first_corrupt_offset, None);
+
+        drop(heap);
+        HASH_OVERRIDE.with(|hash| hash.set(None));
+        // Opening the file compares the stored hashes with the real ones.
+        let opened = HeapOptions::new().open(path);
+        assert!(matches!(opened, Err(Error::Data(_))));
+    }
+
+    #[test]
+    fn test_key_hash_mismatch() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("heap");
+        let mut heap = HeapOptions::new()
+            .key_hashes(true)
+            .open(path.clone())
+            .unwrap();
+        for i in 1..=6 {
+            heap.put(format!("key{}", i).as_bytes(), b"value").unwrap();
+        }
+        drop(heap);
+
+        // Flip a bit of the hash of the first tuple, which is far enough
+        // from the end for the file to be opened.
+        let mut data = fs::read(&path).unwrap();
+        data[9] ^= 1;
+        fs::write(&path, data).unwrap();
+
+        let mut heap = Heap::from(path).unwrap();
+        let report = heap.verify().unwrap();
+        assert_eq!(report.valid_records, 5);
+        assert_eq!(report.first_corrupt_offset, Some(16));
+        // Lookups of other keys don't look at the hash.
+        assert_eq!(heap.get(b"key2").unwrap(), Some(b"value".to_vec()));
+        let Err(Error::Data(e)) = heap.compact() else {
+            panic!("expected a data error");
+        };
+        assert!(matches!(
+            e.cause(),
+            DeserializationError::KeyHashMismatch { .. }
+        ));
+    }
+}
@@ -0,0 +1,295 @@
+//! Overwriting values where they are stored, see [`Heap::update_in_place`].
+
+use super::dedup::decode_reference;
+use super::{validate, Heap, HeapTuple, Scanner, REFERENCE_LEN, TRANSFORM_FLAG};
+use crate::{Error, Operation};
+use std::collections::HashSet;
+use std::sync::atomic::Ordering;
+use std::{fs, io};
+
+impl Heap {
+    /// Overwrites the bytes of the latest value of the key with the new
+    /// value, if both are stored with the same number of bytes, and returns
+    /// whether it did.
+    ///
+    /// This saves appending a tuple for every update of fixed-width values
+    /// like counters. Returns false without writing anything if the key has
+    /// no value or the lengths differ, so that the caller can fall back to
+    /// [`Index::put`](crate::Index::put). It also returns false if the
+    /// value is shared with other tuples through deduplication.
+    ///
+    /// Unlike all other writes, this breaks the append-only history of the
+    /// Heap: the old value is gone rather than kept as an older version.
+    /// Because of that:
+    ///
+    /// - [`Heap::log_since`] and [`repl::send_since`](crate::repl::send_since)
+    ///   couldn't see the update, since no tuple is written past the offset
+    ///   a follower continues from. Once the op log was read from the Heap,
+    ///   false is returned instead.
+    /// - An observer couldn't tell the update from a put by the offset it
+    ///   is given, so Heaps with an observer return false as well.
+    /// - A savepoint can't undo the update, so values written before the
+    ///   latest savepoint aren't updated and false is returned.
+    /// - Readers, snapshots and read-only Heaps see the new value right
+    ///   away, and one that reads the value during the write can see a mix
+    ///   of the old and new bytes. A crash in the middle of the write can
+    ///   leave such a mix behind as well.
+    ///
+    /// The file is opened again for the write, so the Heap has to be backed
+    /// by a path. Only failures are reported to the observer.
+    pub fn update_in_place(&mut self, key: &[u8], value: &[u8]) -> Result<bool, Error> {
+        let timer = self.time_op();
+        let result = self.overwrite_value(key, value);
+        self.log_slow_op(timer, "update", key.len());
+        self.observe(&result, |_, _| {});
+        result
+    }
+
+    /// Overwrites the value like update_in_place.
+    fn overwrite_value(&mut self, key: &[u8], value: &[u8]) -> Result<bool, Error> {
+        self.check_writable()?;
+        self.check_poisoned()?;
+        let path = self.path_for("in-place updates")?.to_path_buf();
+        let key = &*self.normalize(key);
+        let (encoded, flags) = self.encode(key, value)?;
+        validate(key, &encoded)?;
+        self.check_value_size(&encoded)?;
+        if self.observer.is_some() || self.logged.load(Ordering::Relaxed) {
+            return Ok(false);
+        }
+
+        let located = self.check_file().and_then(|_| self.locate_value(key));
+        let Some((offset, len, stored_flags)) = self.track(located)? else {
+            return Ok(false);
+        };
+        if len != encoded.len() || stored_flags & TRANSFORM_FLAG != flags {
+            return Ok(false);
+        }
+        if self.has_savepoint_after(offset)? {
+            return Ok(false);
+        }
+        let update = self.prepare_index_update([(key, Some(value))])?;
+
+        // The file of the Heap appends every write, even positioned ones on
+        // Linux, so the value is written through a handle of its own.
+        let file = fs::OpenOptions::new()
+            .write(true)
+            .open(&path)
+            .map_err(|e| Error::io(Operation::Open, Some(&path), e))?;
+        write_all_at(&file, &encoded, offset)
+            .map_err(|e| Error::io(Operation::Write, Some(&path), e))?;
+        if self.sync_on_put {
+            file.sync_data()
+                .map_err(|e| Error::io(Operation::Sync, Some(&path), e))?;
+        }
+
+        self.apply_index_update(update)?;
+        Ok(true)
+    }
+
+    /// Returns the offset, length and flags of the value stored by the latest
+    /// tuple of the key, unless it has none that can be overwritten: if it
+    /// was deleted, or its value is a reference or referred to by one.
+    fn locate_value(&self, key: &[u8]) -> Result<Option<(u64, usize, u16)>, Error> {
+        let mut scanner = Scanner::raw();
+        scanner.reset(self.committed_len());
+
+        // References only point backwards, so those that point to the value
+        // are all found before it.
+        let mut referenced = HashSet::new();
+        let mut reference = [0; REFERENCE_LEN];
+        while let Some(tuple) = scanner.next_tuple(self)? {
+            if tuple.key == key {
+                if tuple.tombstone || tuple.reference || referenced.contains(&tuple.offset) {
+                    return Ok(None);
+                }
+                let (offset, len, disk_len) = (tuple.offset, tuple.stored_len, tuple.disk_len());
+                let mut trailer = [0; 3];
+                self.read_value_bytes(&mut trailer, offset + disk_len as u64 - 3)?;
+                let (_, _, flags) = HeapTuple::decode_trailer(&trailer);
+                return Ok(Some((offset, len, flags)));
+            }
+            if tuple.reference {
+                let offset = tuple.offset;
+                self.read_value_bytes(&mut reference, offset)?;
+                referenced.insert(decode_reference(&reference).0);
+            }
+        }
+        Ok(None)
+    }
+
+    fn read_value_bytes(&self, buf: &mut [u8], offset: u64) -> Result<(), Error> {
+        self.read_at(buf, offset)
+            .map_err(|e| Error::io(Operation::Read, self.path.as_deref(), e))?;
+        self.counters.read(buf.len());
+        Ok(())
+    }
+}
+
+/// Writes all of buf to the file starting at offset.
+#[cfg(unix)]
+fn write_all_at(file: &fs::File, buf: &[u8], offset: u64) -> io::Result<()> {
+    use std::os::unix::fs::FileExt;
+    file.write_all_at(buf, offset)
+}
+
+/// Writes all of buf to the file starting at offset.
+#[cfg(windows)]
+fn write_all_at(file: &fs::File, mut buf: &[u8], mut offset: u64) -> io::Result<()> {
+    use std::os::windows::fs::FileExt;
+    while !buf.is_empty() {
+        match file.seek_write(buf, offset) {
+            Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+            Ok(n) => {
+                buf = &buf[n..];
+                offset += n as u64;
+            }
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{HeapOptions, Index};
+
+    #[test]
+    fn test_update_in_place() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("heap");
+        let mut heap = Heap::from(path.clone()).unwrap();
+        heap.put(b"counter", &1u64.to_be_bytes()).unwrap();
+        heap.put_tagged(b"flag", b"off", 7).unwrap();
+        heap.put(b"other", b"value").unwrap();
+        let len = heap.committed_len();
+
+        assert!(heap
+            .update_in_place(b"counter", &2u64.to_be_bytes())
+            .unwrap());
+        assert!(heap.update_in_place(b"flag", b"on!").unwrap());
+        assert_eq!(heap.committed_len(), len);
+        assert_eq!(fs::metadata(&path).unwrap().len(), len);
+
+        assert_eq!(
+            heap.get(b"counter").unwrap(),
+            Some(2u64.to_be_bytes().to_vec())
+        );
+        let mut tuples: Vec<_> = heap.iter().map(Result::unwrap).collect();
+        tuples.sort_by(|a, b| a.key.cmp(&b.key));
+        assert_eq!(tuples[1].value, b"on!");
+        assert_eq!(tuples[1].tag(), Some(7));
+        assert_eq!(tuples[2].value, b"value");
+
+        drop(heap);
+        let mut heap = Heap::from(path).unwrap();
+        assert_eq!(heap.get(b"flag").unwrap(), Some(b"on!".to_vec()));
+        assert_eq!(heap.verify().unwrap().first_corrupt_offset, None);
+    }
+
+    #[test]
+    fn test_update_in_place_leaves_other_values_untouched() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("heap");
+        let mut heap = Heap::from(path.clone()).unwrap();
+        heap.put(b"key1", b"value1").unwrap();
+        heap.put(b"key2", b"old").unwrap();
+        heap.put(b"key2", b"value2").unwrap();
+        heap.put(b"key3", b"value3").unwrap();
+        heap.delete(b"key3").unwrap();
+        let contents = fs::read(&path).unwrap();
+
+        assert!(!heap.update_in_place(b"key1", b"longer value").unwrap());
+        assert!(!heap.update_in_place(b"key1", b"short").unwrap());
+        // Only the latest value of a key is updated.
+        assert!(!heap.update_in_place(b"key2", b"new").unwrap());
+        assert!(!heap.update_in_place(b"key3", b"value4").unwrap());
+        assert!(!heap.update_in_place(b"key4", b"value4").unwrap());
+        assert_eq!(fs::read(&path).unwrap(), contents);
+        assert_eq!(heap.get(b"key2").unwrap(), Some(b"value2".to_vec()));
+    }
+
+    #[test]
+    fn test_update_in_place_skips_shared_values() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("heap");
+        let mut heap = HeapOptions::new()
+            .deduplicate_values(4)
+            .open(path.clone())
+            .unwrap();
+        let value = [b'v'; 100];
+        heap.put(b"key1", &value).unwrap();
+        heap.put(b"key2", &value).unwrap();
+        let contents = fs::read(&path).unwrap();
+
+        // The value of key1 is also the value of key2, which refers to it.
+        assert!(!heap.update_in_place(b"key1", &[b'w'; 100]).unwrap());
+        assert!(!heap.update_in_place(b"key2", &[b'w'; 100]).unwrap());
+        assert_eq!(fs::read(&path).unwrap(), contents);
+
+        heap.put(b"key3", &[b'x'; 100]).unwrap();
+        assert!(heap.update_in_place(b"key3", &value).unwrap());
+        assert_eq!(heap.get(b"key3").unwrap(), Some(value.to_vec()));
+        assert_eq!(heap.get(b"key2").unwrap(), Some(value.to_vec()));
+    }
+
+    #[test]
+    fn test_update_in_place_keeps_savepoints_intact() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("heap");
+        let mut heap = Heap::from(path.clone()).unwrap();
+        heap.put(b"key1", b"value1").unwrap();
+        heap.savepoint("before").unwrap();
+        heap.put(b"key2", b"value2").unwrap();
+        let contents = fs::read(&path).unwrap();
+
+        // The value of key1 is older than the savepoint, which couldn't
+        // restore it.
+        assert!(!heap.update_in_place(b"key1", b"VALUE1").unwrap());
+        assert_eq!(fs::read(&path).unwrap(), contents);
+        assert!(heap.update_in_place(b"key2", b"VALUE2").unwrap());
+
+        heap.rollback_to("before").unwrap();
+        assert_eq!(heap.get(b"key1").unwrap(), Some(b"value1".to_vec()));
+        assert_eq!(heap.get(b"key2").unwrap(), None);
+    }
+
+    #[test]
+    fn test_update_in_place_after_log_since() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("heap");
+        let mut heap = Heap::from(path.clone()).unwrap();
+        heap.put(b"key", b"value").unwrap();
+        assert!(heap.update_in_place(b"key", b"VALUE").unwrap());
+
+        // A follower reading the op log would miss the update.
+        assert_eq!(heap.log_since(0).count(), 1);
+        let contents = fs::read(&path).unwrap();
+        assert!(!heap.update_in_place(b"key", b"value").unwrap());
+        assert_eq!(fs::read(&path).unwrap(), contents);
+    }
+
+    #[test]
+    fn test_update_in_place_requires_path() {
+        let mut heap = Heap::new(tempfile::tempfile().unwrap()).unwrap();
+        heap.put(b"key", b"value").unwrap();
+        assert!(matches!(
+            heap.update_in_place(b"key", b"VALUE"),
+            Err(Error::IO(e)) if e.kind() == io::ErrorKind::Unsupported
+        ));
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("heap");
+        Heap::from(path.clone())
+            .unwrap()
+            .put(b"key", b"value")
+            .unwrap();
+        let mut heap = Heap::open_read_only(path).unwrap();
+        assert!(matches!(
+            heap.update_in_place(b"key", b"VALUE"),
+            Err(Error::IO(e)) if e.kind() == io::ErrorKind::PermissionDenied
+        ));
+    }
+}
@@ -0,0 +1,146 @@
+//! Waiting for the lock on a Heap file while another writer holds it.
+
+use super::lock_exclusive;
+use crate::{Error, Operation};
+use std::time::{Duration, Instant};
+use std::{cmp, fs, path, thread};
+
+/// How long opening a Heap waits for the lock on its file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum LockWait {
+    /// Fail with Error::Locked right away.
+    Never,
+    For(Duration),
+    Forever,
+}
+
+/// The first pause between two attempts to take the lock, which doubles
+/// with every attempt up to MAX_BACKOFF.
+const MIN_BACKOFF: Duration = Duration::from_millis(1);
+const MAX_BACKOFF: Duration = Duration::from_millis(100);
+
+/// Takes an exclusive advisory lock on the file, waiting for another writer
+/// to release it as configured.
+pub(super) fn lock_waiting(
+    file: &fs::File,
+    path: &path::Path,
+    wait: LockWait,
+) -> Result<(), Error> {
+    let timeout = match wait {
+        LockWait::Never => return lock_exclusive(file, path),
+        LockWait::For(timeout) => timeout,
+        LockWait::Forever => {
+            return match lock_exclusive(file, path) {
+                Err(Error::Locked) => {
+                    log::debug!("waiting for another writer to release the heap");
+                    file.lock()
+                        .map_err(|e| Error::io(Operation::Lock, Some(path), e))
+                }
+                result => result,
+            };
+        }
+    };
+
+    let start = Instant::now();
+    let mut backoff = MIN_BACKOFF;
+    loop {
+        match lock_exclusive(file, path) {
+            Err(Error::Locked) => {}
+            result => return result,
+        }
+        let waited = start.elapsed();
+        if waited >= timeout {
+            return Err(Error::LockTimeout {
+                waited,
+                holder: lock_holder(file),
+            });
+        }
+        thread::sleep(cmp::min(backoff, timeout - waited));
+        backoff = cmp::min(backoff * 2, MAX_BACKOFF);
+    }
+}
+
+/// Returns the id of the process that holds the lock on the file, as listed
+/// in /proc/locks.
+#[cfg(target_os = "linux")]
+fn lock_holder(file: &fs::File) -> Option<u32> {
+    use std::os::unix::fs::MetadataExt;
+
+    let metadata = file.metadata().ok()?;
+    // The device is listed by its major and minor number, split like glibc
+    // does it.
+    let dev = metadata.dev();
+    let major = ((dev >> 8) & 0xfff) | ((dev >> 32) & !0xfff);
+    let minor = (dev & 0xff) | ((dev >> 12) & !0xff);
+    let id = format!("{:02x}:{:02x}:{}", major, minor, metadata.ino());
+
+    // Lines look like "1: FLOCK  ADVISORY  WRITE 4242 fe:00:1234 0 EOF".
+    // Processes waiting for a lock are listed with an arrow after the
+    // number, and are skipped as they have one field more.
+    let locks = fs::read_to_string("/proc/locks").ok()?;
+    locks.lines().find_map(|line| {
+        let fields: Vec<_> = line.split_whitespace().collect();
+        match fields[..] {
+            [_, "FLOCK", _, "WRITE", pid, file, ..] if file == id => pid.parse().ok(),
+            _ => None,
+        }
+    })
+}
+
+#[cfg(not(target_os = "linux"))]
+fn lock_holder(_file: &fs::File) -> Option<u32> {
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{Error, Heap, HeapOptions};
+
+    use std::time::Duration;
+    use std::{process, thread};
+
+    #[test]
+    fn test_lock_wait_until_released() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("heap");
+        let heap = Heap::from(path.clone()).unwrap();
+
+        let holder = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            drop(heap);
+        });
+        HeapOptions::new()
+            .lock_wait(Duration::from_secs(10))
+            .open(path.clone())
+            .unwrap();
+        holder.join().unwrap();
+
+        let heap = Heap::from(path.clone()).unwrap();
+        let holder = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            drop(heap);
+        });
+        HeapOptions::new().lock_wait_forever().open(path).unwrap();
+        holder.join().unwrap();
+    }
+
+    #[test]
+    fn test_lock_wait_times_out() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("heap");
+        let _heap = Heap::from(path.clone()).unwrap();
+
+        let result = HeapOptions::new()
+            .lock_wait(Duration::from_millis(20))
+            .open(path);
+        match result {
+            Err(Error::LockTimeout { waited, holder }) => {
+                assert!(waited >= Duration::from_millis(20));
+                if cfg!(target_os = "linux") {
+                    assert_eq!(holder, Some(process::id()));
+                }
+            }
+            other => panic!("expected a lock timeout, got {:?}", other.err()),
+        }
+    }
+}
@@ -0,0 +1,195 @@
+//! Callbacks on the operations of a Heap, see [`HeapObserver`].
+
+use super::{CompactionReport, Heap};
+use crate::Error;
+use std::{fmt, panic};
+
+/// Receives events for the operations of a Heap, for example to account
+/// for quotas or to keep an audit trail.
+///
+/// Install an observer with
+/// [`HeapOptions::observer`](crate::HeapOptions::observer). Every method
+/// does nothing by default. They are called synchronously after the
+/// operation completed, on the thread that performed it, so they should
+/// return quickly. An observer only gets shared access to itself, never to
+/// the Heap, so it can't modify the Heap while it is being notified.
+///
+/// Only the operations of the [`Index`](crate::Index) trait and compaction
+/// are observed; batches, bulk writes and scans are not. A panic in an
+/// observer is caught and logged, and doesn't fail the operation.
+pub trait HeapObserver: Send + Sync {
+    /// Called after the value of the key was written in a tuple at the
+    /// offset.
+    fn on_put(&self, _key: &[u8], _value_len: usize, _offset: u64) {}
+
+    /// Called after a lookup of the key, with whether it has a value and
+    /// the number of bytes read to find out.
+    fn on_get(&self, _key: &[u8], _found: bool, _bytes_scanned: u64) {}
+
+    /// Called after a tombstone for the key was written at the offset.
+    /// Deletes of keys without a value write nothing and aren't reported.
+    fn on_delete(&self, _key: &[u8], _offset: u64) {}
+
+    /// Called after the Heap was compacted.
+    fn on_compaction(&self, _report: &CompactionReport) {}
+
+    /// Called instead of the other methods when an operation fails.
+    fn on_error(&self, _error: &Error) {}
+}
+
+impl fmt::Debug for dyn HeapObserver {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("HeapObserver")
+    }
+}
+
+impl Heap {
+    /// Passes the outcome of an operation to the observer, if there is one:
+    /// successes to event, and failures to HeapObserver::on_error.
+    pub(super) fn observe<T>(
+        &self,
+        result: &Result<T, Error>,
+        event: impl FnOnce(&dyn HeapObserver, &T),
+    ) {
+        let Some(observer) = &self.observer else {
+            return;
+        };
+        let observer = &**observer;
+        let notified = panic::catch_unwind(panic::AssertUnwindSafe(|| match result {
+            Ok(value) => event(observer, value),
+            Err(e) => observer.on_error(e),
+        }));
+        if let Err(payload) = notified {
+            let message = payload
+                .downcast_ref::<&str>()
+                .copied()
+                .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+                .unwrap_or("unknown cause");
+            log::error!("observer of {} panicked: {}", self.log_name(), message);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{HeapOptions, Index, InputError};
+    use std::fs;
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Default)]
+    struct Recorder {
+        events: Mutex<Vec<String>>,
+    }
+
+    impl Recorder {
+        fn record(&self, event: String) {
+            self.events.lock().unwrap().push(event);
+        }
+    }
+
+    impl HeapObserver for Arc<Recorder> {
+        fn on_put(&self, key: &[u8], value_len: usize, offset: u64) {
+            let key = String::from_utf8_lossy(key);
+            self.record(format!("put {} {} at {}", key, value_len, offset));
+        }
+
+        fn on_get(&self, key: &[u8], found: bool, bytes_scanned: u64) {
+            let key = String::from_utf8_lossy(key);
+            self.record(format!("get {} {} {}", key, found, bytes_scanned));
+        }
+
+        fn on_delete(&self, key: &[u8], offset: u64) {
+            let key = String::from_utf8_lossy(key);
+            self.record(format!("delete {} at {}", key, offset));
+        }
+
+        fn on_compaction(&self, report: &CompactionReport) {
+            self.record(format!(
+                "compaction {} -> {}",
+                report.bytes_before, report.bytes_after
+            ));
+        }
+
+        fn on_error(&self, error: &Error) {
+            self.record(format!("error {}", error));
+        }
+    }
+
+    #[test]
+    fn test_observer_events() {
+        let dir = tempfile::tempdir().unwrap();
+        let recorder = Arc::new(Recorder::default());
+        let mut heap = HeapOptions::new()
+            .observer(Box::new(recorder.clone()))
+            .open(dir.path().join("heap"))
+            .unwrap();
+
+        heap.put(b"key1", b"value1").unwrap();
+        heap.put(b"key2", b"value2").unwrap();
+        heap.get(b"key1").unwrap();
+        heap.delete(b"key2").unwrap();
+        heap.delete(b"key3").unwrap();
+        heap.get(b"key2").unwrap();
+        heap.put(b"", b"value3").unwrap_err();
+        heap.compact().unwrap();
+
+        let error = Error::Input(InputError::EmptyKey);
+        assert_eq!(
+            *recorder.events.lock().unwrap(),
+            [
+                "put key1 6 at 0".to_string(),
+                "put key2 6 at 13".to_string(),
+                "get key1 true 26".to_string(),
+                "delete key2 at 26".to_string(),
+                "get key2 false 33".to_string(),
+                format!("error {}", error),
+                "compaction 33 -> 13".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_observer_update_in_place() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("heap");
+        let recorder = Arc::new(Recorder::default());
+        let mut heap = HeapOptions::new()
+            .observer(Box::new(recorder.clone()))
+            .open(path.clone())
+            .unwrap();
+
+        // Heaps with an observer aren't updated in place, but failures are
+        // reported.
+        heap.put(b"key1", b"value1").unwrap();
+        let contents = fs::read(&path).unwrap();
+        assert!(!heap.update_in_place(b"key1", b"VALUE1").unwrap());
+        assert_eq!(fs::read(&path).unwrap(), contents);
+        heap.update_in_place(b"", b"value").unwrap_err();
+
+        let error = Error::Input(InputError::EmptyKey);
+        assert_eq!(
+            *recorder.events.lock().unwrap(),
+            ["put key1 6 at 0".to_string(), format!("error {}", error)]
+        );
+    }
+
+    #[test]
+    fn test_observer_panics_are_caught() {
+        struct Panicking;
+
+        impl HeapObserver for Panicking {
+            fn on_put(&self, _key: &[u8], _value_len: usize, _offset: u64) {
+                panic!("observer failed");
+            }
+        }
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut heap = HeapOptions::new()
+            .observer(Box::new(Panicking))
+            .open(dir.path().join("heap"))
+            .unwrap();
+        heap.put(b"key1", b"value1").unwrap();
+        assert_eq!(heap.get(b"key1").unwrap(), Some(b"value1".to_vec()));
+    }
+}
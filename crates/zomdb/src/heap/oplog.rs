@@ -0,0 +1,321 @@
+//! The tuples of a Heap as a log of operations, to replay them into another
+//! Heap.
+
+use super::Heap;
+use crate::{DeserializationError, Error, Index, MAX_KEY_SIZE, MAX_VALUE_SIZE};
+use std::sync::atomic::Ordering;
+
+/// An operation on a Heap, as recorded by one of its tuples.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Op {
+    Put {
+        key: Vec<u8>,
+        value: Vec<u8>,
+        /// The tag of the tuple, see [`Heap::put_tagged`].
+        tag: Option<u8>,
+    },
+    Delete {
+        key: Vec<u8>,
+    },
+}
+
+const PUT_TAG: u8 = 0;
+const DELETE_TAG: u8 = 1;
+const TAGGED_PUT_TAG: u8 = 2;
+
+impl Op {
+    /// Encodes the operation into a frame of bytes.
+    ///
+    /// A frame starts with a tag byte, 0 for puts, 1 for deletes and 2 for
+    /// puts of tagged tuples, followed by the length of the key as two
+    /// big-endian bytes and the key. Puts continue with the value, framed
+    /// the same way, and tagged puts end with the tag of the tuple. Frames
+    /// can be concatenated, since each of them records its own length.
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut data = Vec::new();
+        match self {
+            Op::Put { key, value, tag } => {
+                data.push(if tag.is_some() {
+                    TAGGED_PUT_TAG
+                } else {
+                    PUT_TAG
+                });
+                push_field(&mut data, key);
+                push_field(&mut data, value);
+                data.extend(tag);
+            }
+            Op::Delete { key } => {
+                data.push(DELETE_TAG);
+                push_field(&mut data, key);
+            }
+        }
+        data
+    }
+
+    /// Decodes the frame at the start of data, returning the operation and
+    /// the length of the frame.
+    pub fn deserialize(data: &[u8]) -> Result<(Self, usize), DeserializationError> {
+        let (&kind, rest) = data
+            .split_first()
+            .ok_or(DeserializationError::DataTooShort {
+                needed: 1,
+                available: 0,
+            })?;
+        let (key, rest) = take_field(rest)?;
+        if key.len() > MAX_KEY_SIZE {
+            return Err(DeserializationError::KeySizeTooBig {
+                decoded: key.len(),
+                max: MAX_KEY_SIZE,
+            });
+        }
+
+        let (op, rest) = match kind {
+            PUT_TAG | TAGGED_PUT_TAG => {
+                let (value, mut rest) = take_field(rest)?;
+                if value.len() > MAX_VALUE_SIZE {
+                    return Err(DeserializationError::ValueSizeTooBig {
+                        decoded: value.len(),
+                        max: MAX_VALUE_SIZE,
+                    });
+                }
+                let mut tag = None;
+                if kind == TAGGED_PUT_TAG {
+                    let (&byte, after) =
+                        rest.split_first()
+                            .ok_or(DeserializationError::DataTooShort {
+                                needed: 1,
+                                available: 0,
+                            })?;
+                    tag = Some(byte);
+                    rest = after;
+                }
+                let op = Op::Put {
+                    key: key.to_vec(),
+                    value: value.to_vec(),
+                    tag,
+                };
+                (op, rest)
+            }
+            DELETE_TAG => (Op::Delete { key: key.to_vec() }, rest),
+            _ => return Err(DeserializationError::InvalidFlags),
+        };
+
+        Ok((op, data.len() - rest.len()))
+    }
+}
+
+fn push_field(data: &mut Vec<u8>, field: &[u8]) {
+    data.extend_from_slice(&(field.len() as u16).to_be_bytes());
+    data.extend_from_slice(field);
+}
+
+/// Splits a length-prefixed field off the front of data.
+fn take_field(data: &[u8]) -> Result<(&[u8], &[u8]), DeserializationError> {
+    let too_short = |needed| DeserializationError::DataTooShort {
+        needed,
+        available: data.len(),
+    };
+    let (len, rest) = data.split_first_chunk::<2>().ok_or(too_short(2))?;
+    let len = u16::from_be_bytes(*len) as usize;
+    if rest.len() < len {
+        return Err(too_short(2 + len));
+    }
+    Ok(rest.split_at(len))
+}
+
+impl Heap {
+    /// Returns the operations recorded by the tuples starting at or after
+    /// the offset, in the order they were written, along with their
+    /// offsets.
+    ///
+    /// Pass the offset of the last operation seen plus one to continue
+    /// where an earlier call left off. The operations are read into memory
+    /// up front. If the file can't be read, the iterator yields only the
+    /// error, so that no operation is skipped.
+    pub fn log_since(&self, offset: u64) -> impl Iterator<Item = Result<(u64, Op), Error>> {
+        self.logged.store(true, Ordering::Relaxed);
+        let mut ops = Vec::new();
+        for record in self.records() {
+            let record = match record {
+                Ok(record) => record,
+                Err(e) => return vec![Err(e)].into_iter(),
+            };
+            if record.offset < offset {
+                break;
+            }
+
+            let op = if record.tombstone {
+                Op::Delete { key: record.key }
+            } else {
+                Op::Put {
+                    key: record.key,
+                    value: record.value,
+                    tag: record.tag,
+                }
+            };
+            ops.push(Ok((record.offset, op)));
+        }
+
+        ops.reverse();
+        ops.into_iter()
+    }
+
+    /// Applies the operations in order, and returns their number.
+    ///
+    /// Operations before one that fails stay applied. Deleting a key that
+    /// has no value writes nothing, like [`Index::delete`].
+    pub fn apply(&mut self, ops: impl IntoIterator<Item = Op>) -> Result<u64, Error> {
+        let mut applied = 0;
+        for op in ops {
+            match op {
+                Op::Put {
+                    key,
+                    value,
+                    tag: None,
+                } => self.put(&key, &value)?,
+                Op::Put {
+                    key,
+                    value,
+                    tag: Some(tag),
+                } => self.put_tagged(&key, &value, tag)?,
+                Op::Delete { key } => {
+                    self.delete(&key)?;
+                }
+            }
+            applied += 1;
+        }
+        Ok(applied)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tempfile::tempfile;
+
+    #[test]
+    fn test_op_round_trip() {
+        let ops = [
+            Op::Put {
+                key: b"key".to_vec(),
+                value: b"value".to_vec(),
+                tag: None,
+            },
+            Op::Put {
+                key: vec![0xff; MAX_KEY_SIZE],
+                value: vec![],
+                tag: None,
+            },
+            Op::Delete { key: vec![0] },
+            Op::Put {
+                key: b"key".to_vec(),
+                value: b"value".to_vec(),
+                tag: Some(7),
+            },
+        ];
+
+        let mut data = Vec::new();
+        for op in &ops {
+            data.extend_from_slice(&op.serialize());
+        }
+        assert_eq!(&data[..13], b"\x00\x00\x03key\x00\x05value");
+
+        let mut rest = &data[..];
+        for op in &ops {
+            let (decoded, len) = Op::deserialize(rest).unwrap();
+            assert_eq!(&decoded, op);
+            rest = &rest[len..];
+        }
+        assert!(rest.is_empty());
+    }
+
+    #[test]
+    fn test_op_deserialize_errors() {
+        for (data, expected) in [
+            (
+                &b""[..],
+                DeserializationError::DataTooShort {
+                    needed: 1,
+                    available: 0,
+                },
+            ),
+            (
+                b"\x00\x00\x03ke",
+                DeserializationError::DataTooShort {
+                    needed: 5,
+                    available: 4,
+                },
+            ),
+            (
+                b"\x00\x00\x01k\x00",
+                DeserializationError::DataTooShort {
+                    needed: 2,
+                    available: 1,
+                },
+            ),
+            (b"\x03\x00\x01k", DeserializationError::InvalidFlags),
+            (
+                b"\x02\x00\x01k\x00\x00",
+                DeserializationError::DataTooShort {
+                    needed: 1,
+                    available: 0,
+                },
+            ),
+            (
+                b"\x01\x01\x01",
+                DeserializationError::DataTooShort {
+                    needed: 259,
+                    available: 2,
+                },
+            ),
+        ] {
+            let e = Op::deserialize(data).unwrap_err();
+            assert_eq!(e.to_string(), expected.to_string());
+        }
+
+        let mut data = vec![DELETE_TAG];
+        push_field(&mut data, &[b'k'; MAX_KEY_SIZE + 1]);
+        assert!(matches!(
+            Op::deserialize(&data),
+            Err(DeserializationError::KeySizeTooBig { .. })
+        ));
+    }
+
+    #[test]
+    fn test_replay_log_into_follower() {
+        let mut primary = Heap::new(tempfile().unwrap()).unwrap();
+        let mut follower = Heap::new(tempfile().unwrap()).unwrap();
+        let replay = |primary: &Heap, follower: &mut Heap, since: u64| {
+            let log = primary.log_since(since).collect::<Result<Vec<_>, _>>();
+            let log = log.unwrap();
+            let last = log.last().map(|(offset, _)| *offset + 1);
+            let applied = follower.apply(log.into_iter().map(|(_, op)| op));
+            (applied.unwrap(), last.unwrap_or(since))
+        };
+
+        for i in 0..20 {
+            primary
+                .put(format!("key{}", i % 7).as_bytes(), &[i as u8; 3])
+                .unwrap();
+        }
+        primary.delete(b"key3").unwrap();
+        let (applied, since) = replay(&primary, &mut follower, 0);
+        assert_eq!(applied, 21);
+        assert!(primary.diff(&follower).unwrap().is_empty());
+
+        primary.put(b"key3", b"again").unwrap();
+        primary.delete(b"key4").unwrap();
+        let (applied, since) = replay(&primary, &mut follower, since);
+        assert_eq!(applied, 2);
+        assert!(primary.diff(&follower).unwrap().is_empty());
+
+        assert_eq!(replay(&primary, &mut follower, since).0, 0);
+
+        primary.put_tagged(b"key5", b"tagged", 9).unwrap();
+        let (applied, _) = replay(&primary, &mut follower, since);
+        assert_eq!(applied, 1);
+        let tagged: Vec<_> = follower.iter().with_tag(9).map(Result::unwrap).collect();
+        assert_eq!(tagged.len(), 1);
+        assert_eq!(tagged[0].key, b"key5");
+    }
+}
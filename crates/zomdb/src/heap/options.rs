@@ -0,0 +1,701 @@
+use super::dedup::ValueCache;
+use super::lock::{lock_waiting, LockWait};
+use super::{Heap, HeapObserver, KeyNormalizer, Scanner, ValueTransform};
+use crate::{DataError, DeserializationError, Error, Operation, MAX_VALUE_SIZE};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use std::{cmp, fs, path};
+
+/// Options to configure how a Heap is opened.
+///
+/// The default options open a Heap for writing and create its file if it
+/// doesn't exist yet, just like [`Heap::from`].
+#[derive(Debug, Clone)]
+pub struct HeapOptions {
+    read_only: bool,
+    create: bool,
+    create_new: bool,
+    lock_wait: LockWait,
+    sync_on_put: bool,
+    sync_dir: bool,
+    max_value_size: usize,
+    normalize_key: Option<KeyNormalizer>,
+    deduplicate_values: usize,
+    value_transform: Option<Arc<dyn ValueTransform>>,
+    key_hashes: bool,
+    observer: Option<Arc<dyn HeapObserver>>,
+    slow_op_threshold: Option<Duration>,
+    preallocate: u64,
+    #[cfg(any(test, feature = "testing"))]
+    faults: super::FailingStorage,
+}
+
+impl Default for HeapOptions {
+    fn default() -> Self {
+        Self {
+            read_only: false,
+            create: true,
+            create_new: false,
+            lock_wait: LockWait::Never,
+            sync_on_put: false,
+            sync_dir: true,
+            max_value_size: MAX_VALUE_SIZE,
+            normalize_key: None,
+            deduplicate_values: 0,
+            value_transform: None,
+            key_hashes: false,
+            observer: None,
+            slow_op_threshold: None,
+            preallocate: 0,
+            #[cfg(any(test, feature = "testing"))]
+            faults: Default::default(),
+        }
+    }
+}
+
+impl HeapOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Opens the Heap for reading only.
+    ///
+    /// See [`Heap::open_read_only`]. The file is never created, so the
+    /// create options are ignored.
+    pub fn read_only(&mut self, read_only: bool) -> &mut Self {
+        self.read_only = read_only;
+        self
+    }
+
+    /// Creates the file if it doesn't exist yet.
+    pub fn create(&mut self, create: bool) -> &mut Self {
+        self.create = create;
+        self
+    }
+
+    /// Creates the file and fails if it exists already.
+    pub fn create_new(&mut self, create_new: bool) -> &mut Self {
+        self.create_new = create_new;
+        self
+    }
+
+    /// Waits up to the timeout for another writer to release the lock on the
+    /// file, instead of failing with [`Error::Locked`] right away.
+    ///
+    /// The lock is retried with a backoff of up to 100 milliseconds. Once
+    /// the timeout passed, opening fails with [`Error::LockTimeout`].
+    pub fn lock_wait(&mut self, timeout: Duration) -> &mut Self {
+        self.lock_wait = LockWait::For(timeout);
+        self
+    }
+
+    /// Blocks until another writer releases the lock on the file, instead of
+    /// failing with [`Error::Locked`].
+    pub fn lock_wait_forever(&mut self) -> &mut Self {
+        self.lock_wait = LockWait::Forever;
+        self
+    }
+
+    /// Flushes every write to disk before it returns.
+    ///
+    /// This makes writes durable one by one at the cost of an fsync each.
+    /// See [`Heap::sync`].
+    pub fn sync_on_put(&mut self, sync_on_put: bool) -> &mut Self {
+        self.sync_on_put = sync_on_put;
+        self
+    }
+
+    /// Syncs the directory of the Heap after its file is created, and after
+    /// compaction replaces it, as well as the directory that
+    /// [`Heap::vacuum_into`] writes to. Enabled by default.
+    ///
+    /// Without it, a crash shortly after may lose the new file, or revert
+    /// to the file from before compaction, on file systems that persist
+    /// directory entries separately, like ext4. Does nothing on Windows.
+    pub fn sync_directory(&mut self, sync_directory: bool) -> &mut Self {
+        self.sync_dir = sync_directory;
+        self
+    }
+
+    /// Rejects values larger than the given number of bytes.
+    ///
+    /// The limit can only be lowered below the maximum the on-disk format
+    /// supports; larger limits are capped.
+    pub fn max_value_size(&mut self, max_value_size: usize) -> &mut Self {
+        self.max_value_size = max_value_size.min(MAX_VALUE_SIZE);
+        self
+    }
+
+    /// Maps every key to a normalized form before it is stored or looked
+    /// up, for example to match keys case-insensitively.
+    ///
+    /// Writes, lookups, deletes and prefix scans all go through the
+    /// normalizer, so that they agree on which keys are the same. Only the
+    /// normalized keys are stored; the original bytes are lost, and
+    /// iterators return the normalized keys. The normalized keys have to
+    /// satisfy the size limits, not the original ones. Glob patterns aren't
+    /// normalized.
+    ///
+    /// The normalizer has to be idempotent, since stored keys may pass
+    /// through it again, and prefixes have to normalize to prefixes of the
+    /// normalized keys. The same normalizer has to be used every time the
+    /// Heap is opened.
+    pub fn key_normalizer(&mut self, normalize: KeyNormalizer) -> &mut Self {
+        self.normalize_key = Some(normalize);
+        self
+    }
+
+    /// Makes puts store a value that was written recently as a reference to
+    /// the earlier copy, remembering up to the given number of values.
+    ///
+    /// Lookups and iterators resolve references transparently, at the cost
+    /// of an extra read per reference, and compaction keeps the referenced
+    /// values. Only [`Index::put`](crate::Index::put) and
+    /// [`Heap::put_many`] deduplicate, and only values longer than a
+    /// reference, which takes 10 bytes. The remembered values are forgotten
+    /// when the Heap is reopened or compacted. Files with references can't
+    /// be read by versions of this crate that predate them. Disabled with
+    /// 0, the default.
+    pub fn deduplicate_values(&mut self, values: usize) -> &mut Self {
+        self.deduplicate_values = values;
+        self
+    }
+
+    /// Stores every value in the form the transform encodes it to, and
+    /// decodes the values as they are read.
+    ///
+    /// The size limits apply to the encoded values. Compaction copies them
+    /// without decoding them. Tuples with encoded values are marked as such,
+    /// so that reading them from a Heap opened without a transform fails
+    /// with [`DeserializationError::MissingTransform`] instead of returning
+    /// the encoded bytes. The same transform has to be used every time the
+    /// Heap is opened.
+    ///
+    /// [`DeserializationError::MissingTransform`]: crate::DeserializationError::MissingTransform
+    pub fn value_transform(&mut self, transform: Box<dyn ValueTransform>) -> &mut Self {
+        self.value_transform = Some(Arc::from(transform));
+        self
+    }
+
+    /// Stores a hash of the key in every tuple written, so that lookups skip
+    /// the tuples of other keys without comparing their keys or resolving
+    /// their values.
+    ///
+    /// The hash is the 32-bit FNV-1a hash of the key, stored in 4 more bytes
+    /// per tuple. Files can mix tuples with and without hashes, so this can
+    /// be changed between opens, but files with hashes can't be read by
+    /// versions of this crate that predate them. [`Heap::verify`], opening
+    /// and compaction check the stored hashes against the keys. Disabled by
+    /// default.
+    pub fn key_hashes(&mut self, key_hashes: bool) -> &mut Self {
+        self.key_hashes = key_hashes;
+        self
+    }
+
+    /// Notifies the observer of the operations of the Heap.
+    ///
+    /// See [`HeapObserver`] for which operations are observed. Readers
+    /// created from the Heap aren't.
+    pub fn observer(&mut self, observer: Box<dyn HeapObserver>) -> &mut Self {
+        self.observer = Some(Arc::from(observer));
+        self
+    }
+
+    /// Logs a warning for every get, put, delete and step of an iterator
+    /// that takes longer than the threshold.
+    ///
+    /// The warning names the operation, the length of the key, the bytes
+    /// read and the time it took. Operations aren't timed at all without a
+    /// threshold. Readers created from the Heap use the same threshold.
+    pub fn slow_op_threshold(&mut self, threshold: Duration) -> &mut Self {
+        self.slow_op_threshold = Some(threshold);
+        self
+    }
+
+    /// Reserves disk space for the given number of bytes past the end of
+    /// the file when the Heap is opened for writing.
+    ///
+    /// See [`Heap::reserve`].
+    pub fn preallocate(&mut self, bytes: u64) -> &mut Self {
+        self.preallocate = bytes;
+        self
+    }
+
+    /// Injects the faults of the FailingStorage into the file operations of
+    /// the Heap.
+    #[cfg(any(test, feature = "testing"))]
+    pub fn faults(&mut self, faults: super::FailingStorage) -> &mut Self {
+        self.faults = faults;
+        self
+    }
+
+    /// Opens the Heap at the path with these options.
+    pub fn open(&self, path: path::PathBuf) -> Result<Heap, Error> {
+        let mut created = false;
+        let file = if self.read_only {
+            fs::File::open(&path).map_err(|e| Error::io(Operation::Open, Some(&path), e))?
+        } else {
+            created = (self.create || self.create_new) && !path.exists();
+            let file = fs::OpenOptions::new()
+                .read(true)
+                .append(true)
+                .create(self.create)
+                .create_new(self.create_new)
+                .open(&path)
+                .map_err(|e| Error::io(Operation::Open, Some(&path), e))?;
+            lock_waiting(&file, &path, self.lock_wait)?;
+            file
+        };
+
+        let mut heap = Heap {
+            path: Some(path),
+            read_only: self.read_only,
+            sync_on_put: self.sync_on_put,
+            sync_dir: self.sync_dir,
+            max_value_size: self.max_value_size,
+            normalize_key: self.normalize_key,
+            values: (self.deduplicate_values > 0)
+                .then(|| Mutex::new(ValueCache::new(self.deduplicate_values))),
+            transform: self.value_transform.clone(),
+            key_hashes: self.key_hashes,
+            observer: self.observer.clone(),
+            slow_op_threshold: self.slow_op_threshold,
+            #[cfg(any(test, feature = "testing"))]
+            faults: self.faults.clone(),
+            ..Heap::new(file)?
+        };
+        if let (true, Some(path)) = (created, &heap.path) {
+            heap.sync_parent(path)?;
+        }
+        log::debug!(
+            "opened {} with {} bytes{}",
+            heap.log_name(),
+            heap.committed_len(),
+            if heap.read_only { " for reading" } else { "" }
+        );
+        heap.check_format()?;
+        if log::log_enabled!(log::Level::Warn) {
+            heap.check_tail();
+        }
+        if !heap.read_only {
+            heap.reserve(self.preallocate)?;
+        }
+
+        Ok(heap)
+    }
+}
+
+/// Number of tuples that have to be readable in front of a position of a
+/// file whose last tuple isn't, for it to count as a Heap with a torn last
+/// write rather than some other file.
+const TORN_WRITE_RECORDS: usize = 4;
+
+/// Number of bytes at the start of a file that isn't a Heap to describe it
+/// with.
+const FOREIGN_START_SIZE: usize = 16;
+
+impl Heap {
+    /// Fails with [`DeserializationError::NotAZomdbFile`] if the file is
+    /// neither empty nor ends with a tuple or a torn write after one.
+    ///
+    /// Files don't have a header to tell Heaps from other files by, so the
+    /// last few tuples are parsed instead. A crash in the middle of a write
+    /// leaves less than a tuple behind the last complete one, so a file
+    /// whose last tuple can't be parsed still counts as a Heap if a few
+    /// tuples, or all tuples up to the start of the file, can be read from
+    /// a position within that distance of its end.
+    fn check_format(&self) -> Result<(), Error> {
+        let len = self.committed_len();
+        if len == 0 || self.ends_with_tuples(len)? {
+            return Ok(());
+        }
+        let start = len.saturating_sub(Heap::MAX_TUPLE_SIZE as u64);
+        for end in (start..len).rev() {
+            if self.ends_with_tuples(end)? {
+                return Ok(());
+            }
+        }
+
+        let mut head = vec![0; cmp::min(len, FOREIGN_START_SIZE as u64) as usize];
+        self.read_at(&mut head, 0)
+            .map_err(|e| Error::io(Operation::Read, self.path.as_deref(), e))?;
+        let cause = DeserializationError::NotAZomdbFile { head };
+        Err(Error::Data(DataError::new(
+            cause,
+            len,
+            self.path.as_deref(),
+            None,
+            Vec::new(),
+        )))
+    }
+
+    /// Returns whether TORN_WRITE_RECORDS tuples, or all tuples up to the
+    /// start of the file, can be read backwards from the end offset.
+    fn ends_with_tuples(&self, end: u64) -> Result<bool, Error> {
+        if end == 0 {
+            return Ok(false);
+        }
+        let mut scanner = Scanner::raw();
+        scanner.reset(end);
+        for _ in 0..TORN_WRITE_RECORDS {
+            match scanner.next_tuple(self) {
+                Ok(Some(_)) => {}
+                Ok(None) => return Ok(true),
+                Err(Error::Data(_)) => return Ok(false),
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(true)
+    }
+
+    /// Warns if the file doesn't end with a complete tuple, like after a
+    /// crash in the middle of a write.
+    ///
+    /// Reads fail once they reach such a tuple, until it is removed with
+    /// Heap::verify_and_clear, so warn about it early. Other errors are left
+    /// to the reads.
+    fn check_tail(&self) {
+        let mut scanner = Scanner::raw();
+        scanner.reset(self.committed_len());
+        if let Err(Error::Data(e)) = scanner.next_tuple(self) {
+            log::warn!(
+                "{} doesn't end with a complete tuple and needs recovery: {}",
+                self.log_name(),
+                e
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::heap::{FailingStorage, HeapTuple, WriteBatch};
+    use crate::{Index, InputError, MAX_KEY_SIZE};
+    use std::sync::Mutex;
+
+    /// Collects the messages logged while tests run.
+    struct TestLogger;
+
+    static LOGGED: Mutex<Vec<(log::Level, String)>> = Mutex::new(Vec::new());
+
+    impl log::Log for TestLogger {
+        fn enabled(&self, _: &log::Metadata) -> bool {
+            true
+        }
+
+        fn log(&self, record: &log::Record) {
+            let message = (record.level(), record.args().to_string());
+            LOGGED.lock().unwrap().push(message);
+        }
+
+        fn flush(&self) {}
+    }
+
+    #[test]
+    fn test_options_open_warns_about_torn_tail() {
+        let _ = log::set_logger(&TestLogger);
+        log::set_max_level(log::LevelFilter::Debug);
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("heap");
+        // The second write was cut short after the first bytes of its value.
+        let mut data = HeapTuple::from(b"key1", b"value1").serialize();
+        data.extend_from_slice(b"val");
+        fs::write(&path, data).unwrap();
+
+        HeapOptions::new().open(path.clone()).unwrap();
+        let logged = LOGGED.lock().unwrap();
+        let name = format!("heap {}", path.display());
+        assert!(logged
+            .iter()
+            .any(|(level, message)| *level == log::Level::Debug
+                && message.starts_with(&format!("opened {} with 16 bytes", name))));
+        assert!(logged
+            .iter()
+            .any(|(level, message)| *level == log::Level::Warn
+                && message.starts_with(&format!("{} doesn't end with a complete tuple", name))
+                && message.contains("offset 16")));
+    }
+
+    #[test]
+    fn test_options_slow_op_threshold() {
+        let _ = log::set_logger(&TestLogger);
+        log::set_max_level(log::LevelFilter::Debug);
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("slow");
+        let mut heap = HeapOptions::new()
+            .slow_op_threshold(Duration::from_nanos(1))
+            .open(path.clone())
+            .unwrap();
+        let mut batch = WriteBatch::new();
+        for i in 0..10_000 {
+            batch.put(format!("key{}", i).as_bytes(), b"value");
+        }
+        heap.write_batch(&batch).unwrap();
+        let len = heap.committed_len();
+
+        // A missing key is looked up in the whole file.
+        assert_eq!(heap.get(b"missing").unwrap(), None);
+        heap.iter().next().unwrap().unwrap();
+
+        let logged = LOGGED.lock().unwrap();
+        let warnings: Vec<_> = logged
+            .iter()
+            .filter(|(level, message)| {
+                *level == log::Level::Warn && message.contains(&path.display().to_string())
+            })
+            .map(|(_, message)| message)
+            .collect();
+        let get = warnings
+            .iter()
+            .find(|message| message.starts_with("slow get on heap "))
+            .unwrap();
+        assert!(get.contains("for a key of 7 bytes"), "{}", get);
+        assert!(get.ends_with(&format!("scanned {} bytes", len)), "{}", get);
+        let next = warnings
+            .iter()
+            .find(|message| message.starts_with("slow next on heap "))
+            .unwrap();
+        assert!(next.contains("for a key of 7 bytes"), "{}", next);
+    }
+
+    #[test]
+    fn test_options_slow_update_in_place() {
+        let _ = log::set_logger(&TestLogger);
+        log::set_max_level(log::LevelFilter::Debug);
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("slow_update");
+        let mut heap = HeapOptions::new()
+            .slow_op_threshold(Duration::from_nanos(1))
+            .open(path.clone())
+            .unwrap();
+        let mut batch = WriteBatch::new();
+        for i in 0..1000 {
+            batch.put(format!("key{:04}", i).as_bytes(), b"value");
+        }
+        heap.write_batch(&batch).unwrap();
+        assert!(heap.update_in_place(b"key0000", b"VALUE").unwrap());
+
+        let logged = LOGGED.lock().unwrap();
+        let update = logged
+            .iter()
+            .filter(|(level, message)| {
+                *level == log::Level::Warn && message.contains(&path.display().to_string())
+            })
+            .map(|(_, message)| message)
+            .find(|message| message.starts_with("slow update on heap "))
+            .unwrap();
+        assert!(update.contains("for a key of 7 bytes"), "{}", update);
+    }
+
+    #[test]
+    fn test_options_open_rejects_foreign_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("server.log");
+        let log = "2026-10-15T12:00:00Z INFO listening on 0.0.0.0:8080\n".repeat(100);
+        fs::write(&path, &log).unwrap();
+
+        for read_only in [false, true] {
+            let opened = HeapOptions::new().read_only(read_only).open(path.clone());
+            let Err(Error::Data(e)) = opened else {
+                panic!("expected a data error");
+            };
+            match e.cause() {
+                DeserializationError::NotAZomdbFile { head } => {
+                    assert_eq!(head, &log.as_bytes()[..16])
+                }
+                other => panic!("expected a foreign file, got {:?}", other),
+            }
+            let message = e.to_string();
+            assert!(message.contains(&path.display().to_string()), "{}", message);
+            assert!(message.contains("32 30 32 36"), "{}", message);
+        }
+        // The file is left alone.
+        assert_eq!(fs::read(&path).unwrap(), log.as_bytes());
+
+        // Empty files are Heaps without tuples.
+        let path = dir.path().join("empty");
+        fs::write(&path, b"").unwrap();
+        let heap = HeapOptions::new().open(path).unwrap();
+        assert_eq!(heap.len().unwrap(), 0);
+
+        // Files written by earlier versions have no header either.
+        let path = dir.path().join("heap");
+        let mut data = HeapTuple::from(b"key1", b"value1").serialize();
+        data.extend(HeapTuple::from(b"key2", b"value2").serialize());
+        fs::write(&path, data).unwrap();
+        let mut heap = HeapOptions::new().open(path).unwrap();
+        assert_eq!(heap.get(b"key2").unwrap(), Some(b"value2".to_vec()));
+    }
+
+    #[test]
+    fn test_options_create() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("heap");
+
+        let opened = HeapOptions::new().create(false).open(path.clone());
+        assert!(matches!(opened, Err(Error::IO(e)) if e.kind() == std::io::ErrorKind::NotFound));
+
+        let mut heap = HeapOptions::new()
+            .create_new(true)
+            .open(path.clone())
+            .unwrap();
+        heap.put(b"key", b"value").unwrap();
+        drop(heap);
+
+        let opened = HeapOptions::new().create_new(true).open(path.clone());
+        assert!(
+            matches!(opened, Err(Error::IO(e)) if e.kind() == std::io::ErrorKind::AlreadyExists)
+        );
+
+        let mut heap = HeapOptions::new().create(false).open(path).unwrap();
+        assert_eq!(heap.get(b"key").unwrap(), Some(b"value".to_vec()));
+    }
+
+    #[test]
+    fn test_options_read_only() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("heap");
+        let mut writer = Heap::from(path.clone()).unwrap();
+        writer.put(b"key", b"value").unwrap();
+
+        let mut heap = HeapOptions::new().read_only(true).open(path).unwrap();
+        assert_eq!(heap.get(b"key").unwrap(), Some(b"value".to_vec()));
+        assert!(matches!(heap.put(b"key", b"other"), Err(Error::IO(_))));
+    }
+
+    #[test]
+    fn test_options_sync_directory() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("heap");
+        let faults = FailingStorage::new();
+
+        let open = |sync_directory| {
+            HeapOptions::new()
+                .sync_directory(sync_directory)
+                .faults(faults.clone())
+                .open(path.clone())
+                .unwrap()
+        };
+        drop(open(true));
+        assert_eq!(faults.synced_dirs(), vec![dir.path().to_path_buf()]);
+
+        // Opening an existing file doesn't change the directory.
+        drop(open(true));
+        assert_eq!(faults.synced_dirs().len(), 1);
+
+        fs::remove_file(&path).unwrap();
+        drop(open(false));
+        assert!(path.exists());
+        assert_eq!(faults.synced_dirs().len(), 1);
+    }
+
+    #[test]
+    fn test_options_key_normalizer() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("heap");
+        let open = || {
+            HeapOptions::new()
+                .key_normalizer(<[u8]>::to_ascii_lowercase)
+                .open(path.clone())
+                .unwrap()
+        };
+        let mut heap = open();
+
+        heap.put(b"Foo", b"value1").unwrap();
+        assert_eq!(heap.get(b"foo").unwrap(), Some(b"value1".to_vec()));
+        heap.put(b"FOO", b"value2").unwrap();
+        assert_eq!(heap.get(b"fOo").unwrap(), Some(b"value2".to_vec()));
+        assert!(heap.contains(b"FoO").unwrap());
+        heap.put_many([(&b"Bar1"[..], &b"value3"[..]), (b"BAR2", b"value4")])
+            .unwrap();
+        let mut batch = WriteBatch::new();
+        batch.put(b"BAZ", b"value5");
+        batch.delete(b"bAr2");
+        heap.write_batch(&batch).unwrap();
+
+        // Only the normalized keys are stored.
+        let mut keys: Vec<_> = heap.iter().map(|t| t.unwrap().key).collect();
+        keys.sort();
+        assert_eq!(
+            keys,
+            vec![b"bar1".to_vec(), b"baz".to_vec(), b"foo".to_vec()]
+        );
+        let scanned: Vec<_> = heap.scan_prefix(b"BA").map(|t| t.unwrap().key).collect();
+        assert_eq!(scanned.len(), 2);
+        assert_eq!(heap.count_prefix(b"Ba").unwrap(), 2);
+
+        assert!(heap.delete(b"FOO").unwrap());
+        assert!(!heap.delete(b"foo").unwrap());
+        assert_eq!(heap.get(b"Foo").unwrap(), None);
+
+        // Readers and reopened Heaps normalize the same way.
+        assert_eq!(
+            heap.reader().unwrap().get(b"BAZ").unwrap(),
+            Some(b"value5".to_vec())
+        );
+        drop(heap);
+        let mut heap = open();
+        assert_eq!(heap.get(b"Bar1").unwrap(), Some(b"value3".to_vec()));
+    }
+
+    #[test]
+    fn test_options_key_normalizer_size_limits() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut heap = HeapOptions::new()
+            .key_normalizer(|key| key.repeat(2))
+            .open(dir.path().join("heap"))
+            .unwrap();
+
+        // The limits apply to the normalized keys.
+        let key = [b'k'; MAX_KEY_SIZE / 2 + 1];
+        assert!(matches!(
+            heap.put(&key, b"value"),
+            Err(Error::Input(InputError::KeySize(len))) if len == MAX_KEY_SIZE + 2
+        ));
+        assert!(heap.put_many([(&key[..], &b"value"[..])]).is_err());
+        heap.put(&key[1..], b"value").unwrap();
+        assert_eq!(heap.get(&key[1..]).unwrap(), Some(b"value".to_vec()));
+        assert_eq!(heap.len().unwrap(), 1);
+    }
+
+    #[test]
+    fn test_options_sync_on_put() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut heap = HeapOptions::new()
+            .sync_on_put(true)
+            .open(dir.path().join("heap"))
+            .unwrap();
+
+        heap.put(b"key", b"value").unwrap();
+        heap.put_many([(&b"key1"[..], &b"value1"[..]), (b"key2", b"value2")])
+            .unwrap();
+        heap.delete(b"key").unwrap();
+
+        assert_eq!(heap.perf_counters().fsyncs, 3);
+    }
+
+    #[test]
+    fn test_options_max_value_size() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut heap = HeapOptions::new()
+            .max_value_size(4)
+            .open(dir.path().join("heap"))
+            .unwrap();
+
+        heap.put(b"key", b"1234").unwrap();
+        assert!(matches!(
+            heap.put(b"key", b"12345"),
+            Err(Error::Input(InputError::ValueSize(5)))
+        ));
+        assert!(matches!(
+            heap.put_many([(&b"key1"[..], &b"1"[..]), (b"key2", b"12345")]),
+            Err(Error::Input(InputError::ValueSize(5)))
+        ));
+        assert_eq!(heap.get(b"key").unwrap(), Some(b"1234".to_vec()));
+        assert_eq!(heap.get(b"key1").unwrap(), None);
+    }
+}
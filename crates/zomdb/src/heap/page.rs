@@ -0,0 +1,375 @@
+//! Paginated scans of the keys of a Heap, see [`Heap::scan_prefix_page`]
+//! and [`Heap::keys_page`].
+//!
+//! Pages are ordered by key and continue after the last key of the previous
+//! page, so that a token only needs to hold that key rather than the keys
+//! seen so far or an offset, which would be invalidated by compaction.
+
+use super::csv::{base64_decode, base64_encode};
+use super::{Heap, HeapTuple, Scanner, Tuples};
+use crate::{Error, MAX_KEY_SIZE};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::{fmt, io, str};
+
+/// Where a paginated scan continues, returned with every page but the last
+/// by [`Heap::scan_prefix_page`] and [`Heap::keys_page`].
+///
+/// The token holds the last key of the page. It converts to bytes, and to
+/// a string of URL-safe base64 through Display and FromStr, so that it can
+/// be handed out and read back later. Tokens are validated when they are
+/// read back and when they are used, but aren't signed: a client can craft
+/// a token that continues at any key under the prefix.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PageToken {
+    last_key: Vec<u8>,
+}
+
+impl PageToken {
+    /// The version of the encoding, stored in the first byte.
+    const VERSION: u8 = 1;
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(1 + self.last_key.len());
+        bytes.push(Self::VERSION);
+        bytes.extend_from_slice(&self.last_key);
+        bytes
+    }
+
+    /// Reads back a token from the bytes returned by
+    /// [`PageToken::to_bytes`].
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        match bytes {
+            [Self::VERSION, key @ ..] if !key.is_empty() && key.len() <= MAX_KEY_SIZE => Ok(Self {
+                last_key: key.to_vec(),
+            }),
+            _ => Err(invalid_token("malformed page token")),
+        }
+    }
+}
+
+impl fmt::Display for PageToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let encoded = base64_encode(&self.to_bytes())
+            .replace('+', "-")
+            .replace('/', "_");
+        f.write_str(encoded.trim_end_matches('='))
+    }
+}
+
+impl str::FromStr for PageToken {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        let mut encoded = s.replace('-', "+").replace('_', "/");
+        while !encoded.len().is_multiple_of(4) {
+            encoded.push('=');
+        }
+        let bytes = base64_decode(encoded.as_bytes())
+            .ok_or_else(|| invalid_token("page token isn't valid base64"))?;
+        Self::from_bytes(&bytes)
+    }
+}
+
+fn invalid_token(message: &str) -> Error {
+    Error::IO(io::Error::new(io::ErrorKind::InvalidInput, message))
+}
+
+fn check_limit(limit: usize) -> Result<(), Error> {
+    if limit == 0 {
+        return Err(Error::IO(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "page limit must not be zero",
+        )));
+    }
+    Ok(())
+}
+
+impl Heap {
+    /// Returns up to limit live tuples whose keys start with the prefix,
+    /// along with a token to pass to the next call for the following page,
+    /// or None if this was the last page.
+    ///
+    /// Pages are ordered by key, lexicographically byte by byte, and every
+    /// page continues after the last key of the previous one. Paging through
+    /// all pages thus yields every key that is live throughout exactly once,
+    /// even if the Heap is written or compacted in between. A key written
+    /// between two pages is only yielded if it sorts after the last key
+    /// returned so far, and then with its latest value.
+    ///
+    /// Every page scans the whole Heap. A limit of zero, and a token that
+    /// doesn't belong to the prefix, are rejected with an IO error of kind
+    /// InvalidInput.
+    pub fn scan_prefix_page(
+        &self,
+        prefix: &[u8],
+        limit: usize,
+        token: Option<PageToken>,
+    ) -> Result<(Vec<HeapTuple>, Option<PageToken>), Error> {
+        check_limit(limit)?;
+        let prefix = self.normalize(prefix);
+        let after = token.map(|token| token.last_key);
+        if let Some(after) = &after {
+            if !after.starts_with(&prefix) {
+                return Err(invalid_token("page token belongs to another prefix"));
+            }
+        }
+
+        // The smallest keys after the token, one more than fit on the page
+        // to tell whether there is another page.
+        let mut page = BTreeMap::new();
+        let mut tuples = Tuples::new(self, None).with_prefix(&prefix);
+        while let Some(tuple) =
+            tuples.next_matching(|key| after.as_deref().is_none_or(|after| key > after))?
+        {
+            page.insert(tuple.key, tuple.value);
+            if page.len() > limit + 1 {
+                page.pop_last();
+            }
+        }
+
+        let more = page.len() > limit;
+        if more {
+            page.pop_last();
+        }
+        let next = match page.last_key_value() {
+            Some((key, _)) if more => Some(PageToken {
+                last_key: key.clone(),
+            }),
+            _ => None,
+        };
+        let tuples = page
+            .into_iter()
+            .map(|(key, value)| HeapTuple::from(&key, &value))
+            .collect();
+        Ok((tuples, next))
+    }
+
+    /// Returns up to limit live keys of the Heap, along with a token to pass
+    /// to the next call for the following page, or None if this was the last
+    /// page.
+    ///
+    /// Like [`Heap::scan_prefix_page`] over all keys, with the same
+    /// guarantees and tokens, but values are neither copied nor decoded.
+    pub fn keys_page(
+        &self,
+        limit: usize,
+        token: Option<PageToken>,
+    ) -> Result<(Vec<Vec<u8>>, Option<PageToken>), Error> {
+        check_limit(limit)?;
+        let after = token.map(|token| token.last_key);
+        let (page, more) = self.track(self.smallest_keys_after(after.as_deref(), limit))?;
+
+        let next = match page.last() {
+            Some(key) if more => Some(PageToken {
+                last_key: key.clone(),
+            }),
+            _ => None,
+        };
+        Ok((page, next))
+    }
+
+    /// Returns the up to limit smallest live keys after the given one in
+    /// order, and whether there are more.
+    fn smallest_keys_after(
+        &self,
+        after: Option<&[u8]>,
+        limit: usize,
+    ) -> Result<(Vec<Vec<u8>>, bool), Error> {
+        self.check_file()?;
+        let mut scanner = Scanner::raw();
+        scanner.reset(self.committed_len());
+
+        let mut page: BTreeSet<Vec<u8>> = BTreeSet::new();
+        let mut seen = HashSet::new();
+        while let Some(tuple) = scanner.next_tuple(self)? {
+            if after.is_some_and(|after| tuple.key <= after) {
+                continue;
+            }
+            // Once the page is full, larger keys can't make it anymore, and
+            // neither can their older tuples.
+            if page.len() > limit && page.last().is_some_and(|last| tuple.key > last.as_slice()) {
+                continue;
+            }
+            if !seen.insert(tuple.key.to_vec()) {
+                // A more recent tuple of the key was seen already.
+                continue;
+            }
+            if !tuple.tombstone {
+                page.insert(tuple.key.to_vec());
+                if page.len() > limit + 1 {
+                    page.pop_last();
+                }
+            }
+        }
+
+        let more = page.len() > limit;
+        if more {
+            page.pop_last();
+        }
+        Ok((page.into_iter().collect(), more))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Index;
+    use std::collections::BTreeSet;
+    use tempfile::tempfile;
+
+    fn page_through(heap: &Heap, prefix: &[u8], limit: usize) -> Vec<Vec<u8>> {
+        let mut keys = Vec::new();
+        let mut token = None;
+        loop {
+            let (page, next) = heap.scan_prefix_page(prefix, limit, token).unwrap();
+            assert!(page.len() <= limit);
+            keys.extend(page.into_iter().map(|tuple| tuple.key));
+            // Tokens survive a round trip through a string.
+            token = match next {
+                Some(next) => Some(next.to_string().parse().unwrap()),
+                None => return keys,
+            };
+        }
+    }
+
+    #[test]
+    fn test_scan_prefix_page() {
+        let mut heap = Heap::new(tempfile().unwrap()).unwrap();
+        let mut expected = BTreeSet::new();
+        for i in 0..300 {
+            let key = format!("user:{:03}", i).into_bytes();
+            heap.put(&key, b"value").unwrap();
+            expected.insert(key);
+            heap.put(format!("other:{}", i).as_bytes(), b"value")
+                .unwrap();
+        }
+        for i in (0..300).step_by(10) {
+            let key = format!("user:{:03}", i).into_bytes();
+            heap.delete(&key).unwrap();
+            expected.remove(&key);
+        }
+        heap.put(b"user:005", b"updated").unwrap();
+
+        let keys = page_through(&heap, b"user:", 7);
+        assert_eq!(keys.len(), expected.len());
+        assert_eq!(keys.into_iter().collect::<BTreeSet<_>>(), expected);
+
+        // A limit that divides the number of keys doesn't end with an empty
+        // page.
+        let (page, next) = heap.scan_prefix_page(b"user:", 270, None).unwrap();
+        assert_eq!(page.len(), 270);
+        assert_eq!(next, None);
+    }
+
+    #[test]
+    fn test_scan_prefix_page_with_writes_in_between() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut heap = Heap::from(dir.path().join("heap")).unwrap();
+        for i in 0..100 {
+            heap.put(format!("key{:03}", i).as_bytes(), b"value")
+                .unwrap();
+        }
+
+        let (first, token) = heap.scan_prefix_page(b"key", 7, None).unwrap();
+        assert_eq!(first.last().unwrap().key, b"key006");
+        heap.put(b"key000a", b"before").unwrap();
+        heap.put(b"key050a", b"after").unwrap();
+        heap.put(b"key010", b"updated").unwrap();
+        heap.compact().unwrap();
+
+        let mut keys: Vec<_> = first.into_iter().map(|tuple| tuple.key).collect();
+        let mut token = token;
+        let mut updated = None;
+        while let Some(next) = token {
+            let (page, next) = heap.scan_prefix_page(b"key", 7, Some(next)).unwrap();
+            for tuple in page {
+                if tuple.key == b"key010" {
+                    updated = Some(tuple.value.clone());
+                }
+                keys.push(tuple.key);
+            }
+            token = next;
+        }
+        assert_eq!(keys.len(), 101);
+        assert!(!keys.contains(&b"key000a".to_vec()));
+        assert!(keys.contains(&b"key050a".to_vec()));
+        assert_eq!(updated, Some(b"updated".to_vec()));
+    }
+
+    #[test]
+    fn test_scan_prefix_page_rejects_invalid_tokens() {
+        let mut heap = Heap::new(tempfile().unwrap()).unwrap();
+        for i in 0..10 {
+            heap.put(format!("key{}", i).as_bytes(), b"value").unwrap();
+        }
+        let (_, token) = heap.scan_prefix_page(b"key", 3, None).unwrap();
+        let token = token.unwrap();
+
+        fn rejected<T>(result: Result<T, Error>) -> bool {
+            matches!(result, Err(Error::IO(e)) if e.kind() == io::ErrorKind::InvalidInput)
+        }
+        assert!(rejected(heap.scan_prefix_page(b"other", 3, Some(token))));
+        assert!(rejected(heap.scan_prefix_page(b"key", 0, None)));
+        for forged in ["", "A", "AQ", "Ag", "!!!!"] {
+            assert!(rejected(forged.parse::<PageToken>()), "{}", forged);
+        }
+        assert!(rejected(PageToken::from_bytes(&[1; MAX_KEY_SIZE + 2])));
+        assert!(rejected(PageToken::from_bytes(&[1])));
+
+        // Any valid token under the prefix continues after its key.
+        let forged = PageToken::from_bytes(b"\x01key5").unwrap();
+        assert_eq!(forged.to_string().parse::<PageToken>().unwrap(), forged);
+        let (page, next) = heap.scan_prefix_page(b"key", 3, Some(forged)).unwrap();
+        let keys: Vec<_> = page.into_iter().map(|tuple| tuple.key).collect();
+        assert_eq!(keys, [b"key6", b"key7", b"key8"]);
+        assert_eq!(heap.scan_prefix_page(b"key", 3, next).unwrap().0.len(), 1);
+    }
+
+    #[test]
+    fn test_keys_page_with_overwrites() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut heap = Heap::from(dir.path().join("heap")).unwrap();
+        let mut expected = BTreeSet::new();
+        for round in 0..20 {
+            for i in 0..50 {
+                let key = format!("key{:02}", i).into_bytes();
+                if (i + round) % 7 == 0 {
+                    heap.delete(&key).unwrap();
+                    expected.remove(&key);
+                } else {
+                    heap.put(&key, format!("value{}", round).as_bytes())
+                        .unwrap();
+                    expected.insert(key);
+                }
+            }
+        }
+
+        let mut keys = Vec::new();
+        let mut token = None;
+        loop {
+            let (page, next) = heap.keys_page(3, token).unwrap();
+            assert!(page.len() <= 3);
+            keys.extend(page);
+            match next {
+                Some(next) => token = Some(next),
+                None => break,
+            }
+        }
+        assert!(keys.windows(2).all(|pair| pair[0] < pair[1]));
+        assert_eq!(keys.into_iter().collect::<BTreeSet<_>>(), expected);
+
+        // Tokens are shared with prefix pages, which return the same keys.
+        let (keys, token) = heap.keys_page(10, None).unwrap();
+        let (tuples, _) = heap.scan_prefix_page(b"", 10, token.clone()).unwrap();
+        let (next, _) = heap.keys_page(10, token).unwrap();
+        assert!(keys.last() < tuples.first().map(|tuple| &tuple.key));
+        assert_eq!(
+            tuples
+                .into_iter()
+                .map(|tuple| tuple.key)
+                .collect::<Vec<_>>(),
+            next
+        );
+        assert!(matches!(heap.keys_page(0, None), Err(Error::IO(_))));
+    }
+}
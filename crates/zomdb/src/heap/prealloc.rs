@@ -0,0 +1,125 @@
+//! Reserving disk space for the tuples a Heap is about to write.
+//!
+//! On Linux, the space is allocated with `fallocate` and the
+//! `FALLOC_FL_KEEP_SIZE` flag, which reserves blocks past the end of the
+//! file without changing its length. The length stays the end of the last
+//! tuple, so scans, lookups and recovery of a torn tail never see the
+//! reserved space, and appends keep going to the end of the tuples.
+//!
+//! Other platforms can only reserve space by extending the file, which
+//! would put a region of zeros between the tuples and the next append.
+//! Reserving space does nothing there.
+
+use super::Heap;
+use crate::Error;
+
+impl Heap {
+    /// Reserves disk space for the given number of bytes past the end of
+    /// the Heap, so that the writes filling it can't run out of space and
+    /// the file stays contiguous on disk.
+    ///
+    /// The length of the file doesn't change. Space that is reserved already
+    /// is kept, and file systems that can't reserve space are skipped with
+    /// a debug log. On platforms other than Linux this does nothing.
+    pub fn reserve(&mut self, additional: u64) -> Result<(), Error> {
+        self.check_writable()?;
+        if additional == 0 {
+            return Ok(());
+        }
+        self.allocate(self.committed_len(), additional)
+    }
+
+    #[cfg(target_os = "linux")]
+    fn allocate(&self, offset: u64, len: u64) -> Result<(), Error> {
+        use crate::Operation;
+        use std::io;
+        use std::os::unix::io::AsRawFd;
+
+        let (Ok(offset), Ok(len)) = (offset.try_into(), len.try_into()) else {
+            return Err(Error::IO(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("can't reserve {} bytes at offset {}", len, offset),
+            )));
+        };
+        loop {
+            // SAFETY: The descriptor belongs to the file, which outlives
+            // the call.
+            let ret = unsafe {
+                libc::fallocate(
+                    self.file.as_raw_fd(),
+                    libc::FALLOC_FL_KEEP_SIZE,
+                    offset,
+                    len,
+                )
+            };
+            if ret == 0 {
+                return Ok(());
+            }
+
+            let e = io::Error::last_os_error();
+            match e.raw_os_error() {
+                Some(libc::EINTR) => {}
+                Some(libc::EOPNOTSUPP) => {
+                    log::debug!("{} can't reserve space: {}", self.log_name(), e);
+                    return Ok(());
+                }
+                _ => return Err(Error::io(Operation::Allocate, self.path.as_deref(), e)),
+            }
+        }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn allocate(&self, _offset: u64, _len: u64) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{HeapOptions, Index};
+    use std::fs;
+
+    #[test]
+    fn test_preallocate() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("heap");
+        let mut heap = HeapOptions::new()
+            .preallocate(1 << 20)
+            .open(path.clone())
+            .unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().len(), 0);
+
+        heap.put(b"key1", b"value1").unwrap();
+        heap.put(b"key2", b"value2").unwrap();
+        heap.reserve(1 << 20).unwrap();
+        heap.put(b"key1", b"value3").unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().len(), 3 * 13);
+
+        #[cfg(target_os = "linux")]
+        {
+            use std::os::unix::fs::MetadataExt;
+            assert!(fs::metadata(&path).unwrap().blocks() * 512 >= 1 << 20);
+        }
+
+        // Reopening the Heap finds the tuples and nothing after them.
+        drop(heap);
+        let mut heap = Heap::from(path.clone()).unwrap();
+        assert_eq!(heap.committed_len(), 3 * 13);
+        let keys: Vec<_> = heap.iter().map(|t| t.unwrap().key).collect();
+        assert_eq!(keys, vec![b"key1".to_vec(), b"key2".to_vec()]);
+        assert_eq!(heap.get(b"key1").unwrap(), Some(b"value3".to_vec()));
+        heap.verify_and_clear().unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().len(), 3 * 13);
+    }
+
+    #[test]
+    fn test_reserve_read_only() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("heap");
+        Heap::from(path.clone()).unwrap();
+
+        let mut heap = Heap::open_read_only(path).unwrap();
+        assert!(heap.reserve(1024).is_err());
+    }
+}
@@ -0,0 +1,297 @@
+use super::{Heap, HeapTuple, Iter, Tuples};
+use crate::perf::{Counters, PerfCounters};
+use crate::{Error, Operation};
+use std::path;
+use std::sync::atomic::AtomicBool;
+
+impl Heap {
+    /// Creates a read-only handle to this Heap.
+    ///
+    /// The handle uses its own duplicate of the file descriptor and can be
+    /// moved to another thread. It sees tuples as soon as this Heap has
+    /// completely written them, but never a partially written one.
+    pub fn reader(&self) -> Result<HeapReader, Error> {
+        let file = self
+            .file
+            .try_clone()
+            .map_err(|e| Error::io(Operation::Open, self.path.as_deref(), e))?;
+
+        Ok(HeapReader {
+            heap: Heap {
+                file,
+                // The reader sticks to the file it was created from, even
+                // if the Heap replaces it during compaction.
+                path: None,
+                counters: Counters::default(),
+                committed: self.committed.clone(),
+                read_only: true,
+                sync_on_put: false,
+                sync_dir: false,
+                max_value_size: self.max_value_size,
+                normalize_key: self.normalize_key,
+                poison: Default::default(),
+                indexes: Vec::new(),
+                values: None,
+                transform: self.transform.clone(),
+                key_hashes: self.key_hashes,
+                observer: None,
+                logged: AtomicBool::new(false),
+                slow_op_threshold: self.slow_op_threshold,
+                #[cfg(any(test, feature = "testing"))]
+                faults: self.faults.clone(),
+            },
+        })
+    }
+
+    /// Returns a view of the Heap as of now.
+    ///
+    /// Tuples written after the snapshot was taken are not visible through
+    /// it.
+    pub fn snapshot(&self) -> Snapshot<'_> {
+        Snapshot {
+            heap: self,
+            end: self.committed_len(),
+        }
+    }
+}
+
+/// A read-only handle to a Heap.
+///
+/// Use [`Heap::reader`] to create an instance of this struct.
+pub struct HeapReader {
+    // The inner Heap is never written to.
+    heap: Heap,
+}
+
+impl HeapReader {
+    pub fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, Error> {
+        self.heap.find(key, self.heap.committed_len())
+    }
+
+    /// Creates another handle to the same Heap.
+    pub fn try_clone(&self) -> Result<HeapReader, Error> {
+        self.heap.reader()
+    }
+
+    /// Copies the Heap to a new file at dest.
+    ///
+    /// See [`Heap::backup_to`]. Unlike a backup through the Heap itself,
+    /// this doesn't need access to the Heap while copying.
+    pub fn backup_to(&self, dest: &path::Path) -> Result<u64, Error> {
+        self.heap.backup_to(dest)
+    }
+
+    /// Returns an Iter that starts iterating from the last inserted tuple.
+    pub fn iter(&self) -> Iter<'_> {
+        self.heap.iter()
+    }
+
+    /// Returns a view of the Heap as of now.
+    ///
+    /// See [`Heap::snapshot`].
+    pub fn snapshot(&self) -> Snapshot<'_> {
+        self.heap.snapshot()
+    }
+
+    /// Returns a snapshot of the I/O counters accumulated by this reader.
+    pub fn perf_counters(&self) -> PerfCounters {
+        self.heap.perf_counters()
+    }
+
+    /// Turns the reader into an iterator over the tuples whose keys start
+    /// with the prefix.
+    ///
+    /// See [`Heap::scan_prefix`]. Like iterators created with `into_iter`,
+    /// it owns the reader and can outlive the Heap it was created from.
+    pub fn into_scan_prefix(self, prefix: &[u8]) -> ReaderIter {
+        ReaderIter {
+            tuples: {
+                let prefix = self.heap.normalize(prefix).into_owned();
+                Tuples::new(Box::new(self.heap), None).with_prefix(&prefix)
+            },
+        }
+    }
+}
+
+impl IntoIterator for HeapReader {
+    type Item = Result<HeapTuple, Error>;
+    type IntoIter = ReaderIter;
+
+    /// Turns the reader into an iterator that starts iterating from the
+    /// last inserted tuple.
+    ///
+    /// The iterator owns the reader, so it doesn't borrow from the Heap it
+    /// was created from and can outlive it.
+    fn into_iter(self) -> ReaderIter {
+        ReaderIter {
+            tuples: Tuples::new(Box::new(self.heap), None),
+        }
+    }
+}
+
+/// An iterator that owns a HeapReader.
+///
+/// Use [`HeapReader::into_iter`] or [`HeapReader::into_scan_prefix`] to
+/// create an instance of this struct.
+pub struct ReaderIter {
+    tuples: Tuples<Box<Heap>>,
+}
+
+impl ReaderIter {
+    /// Restarts the iteration from the last inserted tuple.
+    ///
+    /// The iterator then behaves like a new one created from the same
+    /// reader: it sees the tuples written until the next call to `next`,
+    /// and yields every key again.
+    pub fn reset(&mut self) {
+        self.tuples.rewind();
+    }
+}
+
+impl Iterator for ReaderIter {
+    type Item = Result<HeapTuple, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.tuples.next_tuple().transpose()
+    }
+}
+
+/// A point-in-time view of a Heap.
+///
+/// Use [`Heap::snapshot`] or [`HeapReader::snapshot`] to create an instance
+/// of this struct.
+pub struct Snapshot<'a> {
+    heap: &'a Heap,
+    end: u64,
+}
+
+impl<'a> Snapshot<'a> {
+    pub fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, Error> {
+        self.heap.find(key, self.end)
+    }
+
+    /// Returns an Iter over the tuples visible in this snapshot.
+    pub fn iter(&self) -> Iter<'a> {
+        Iter {
+            tuples: Tuples::new(self.heap, Some(self.end)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Index;
+    use std::sync::mpsc;
+    use std::thread;
+    use tempfile::tempfile;
+
+    #[test]
+    fn test_reader_get_from_other_thread_while_appending() {
+        let mut heap = Heap::new(tempfile().unwrap()).unwrap();
+        heap.put(b"key0", b"value0").unwrap();
+
+        let reader = heap.reader().unwrap();
+        let (written_tx, written_rx) = mpsc::channel::<u32>();
+
+        let handle = thread::spawn(move || {
+            assert_eq!(reader.get(b"key0").unwrap(), Some(b"value0".to_vec()));
+            for i in written_rx {
+                let key = format!("key{}", i);
+                let value = format!("value{}", i);
+                assert_eq!(
+                    reader.get(key.as_bytes()).unwrap(),
+                    Some(value.into_bytes())
+                );
+            }
+            reader.iter().count()
+        });
+
+        for i in 1..100 {
+            let key = format!("key{}", i);
+            let value = format!("value{}", i);
+            heap.put(key.as_bytes(), value.as_bytes()).unwrap();
+            written_tx.send(i).unwrap();
+        }
+        drop(written_tx);
+
+        assert_eq!(handle.join().unwrap(), 100);
+    }
+
+    #[test]
+    fn test_reader_survives_compaction() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut heap = Heap::from(dir.path().join("heap")).unwrap();
+        heap.put(b"key", b"value1").unwrap();
+        heap.put(b"key", b"value2").unwrap();
+
+        let reader = heap.reader().unwrap();
+        heap.compact().unwrap();
+        heap.put(b"key", b"value3").unwrap();
+
+        // The reader keeps seeing the file it was created from.
+        assert_eq!(reader.get(b"key").unwrap(), Some(b"value2".to_vec()));
+        assert_eq!(heap.get(b"key").unwrap(), Some(b"value3".to_vec()));
+    }
+
+    #[test]
+    fn test_reader_iter_outlives_heap() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut heap = Heap::from(dir.path().join("heap")).unwrap();
+        heap.put(b"key1", b"value1").unwrap();
+        heap.put(b"other", b"value2").unwrap();
+
+        let mut iter = heap.reader().unwrap().into_iter();
+        let prefixed = heap.reader().unwrap().into_scan_prefix(b"key");
+        heap.put(b"key2", b"value3").unwrap();
+        drop(heap);
+
+        // Appends made before the first call to next are visible.
+        assert_eq!(
+            iter.next().unwrap().unwrap(),
+            HeapTuple::from(b"key2", b"value3")
+        );
+        assert_eq!(iter.count(), 2);
+        assert_eq!(
+            prefixed.map(|t| t.unwrap().key).collect::<Vec<_>>(),
+            vec![b"key2".to_vec(), b"key1".to_vec()]
+        );
+    }
+
+    #[test]
+    fn test_reader_iter_reset() {
+        let mut heap = Heap::new(tempfile().unwrap()).unwrap();
+        heap.put(b"key1", b"value1").unwrap();
+
+        let mut iter = heap.reader().unwrap().into_scan_prefix(b"key");
+        assert_eq!(iter.by_ref().count(), 1);
+        heap.put(b"key2", b"value2").unwrap();
+        heap.put(b"other", b"value3").unwrap();
+        assert!(iter.next().is_none());
+
+        iter.reset();
+        assert_eq!(
+            iter.map(|t| t.unwrap().key).collect::<Vec<_>>(),
+            vec![b"key2".to_vec(), b"key1".to_vec()]
+        );
+    }
+
+    #[test]
+    fn test_snapshot_ignores_later_appends() {
+        let mut heap = Heap::new(tempfile().unwrap()).unwrap();
+        heap.put(b"key1", b"value1").unwrap();
+
+        let reader = heap.reader().unwrap();
+        let snapshot = reader.snapshot();
+        heap.put(b"key1", b"value2").unwrap();
+        heap.put(b"key2", b"value3").unwrap();
+
+        assert_eq!(snapshot.get(b"key1").unwrap(), Some(b"value1".to_vec()));
+        assert_eq!(snapshot.get(b"key2").unwrap(), None);
+        assert_eq!(
+            snapshot.iter().map(Result::unwrap).collect::<Vec<_>>(),
+            vec![HeapTuple::from(b"key1", b"value1")]
+        );
+        assert_eq!(reader.get(b"key2").unwrap(), Some(b"value3".to_vec()));
+    }
+}
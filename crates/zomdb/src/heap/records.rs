@@ -0,0 +1,120 @@
+use super::{Heap, Scanner};
+use crate::Error;
+
+/// A tuple as it is stored on disk, including stale versions of keys and
+/// tombstones.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Record {
+    /// Offset of the first byte of the tuple in the file.
+    pub offset: u64,
+    pub key: Vec<u8>,
+    /// Empty for tombstones.
+    pub value: Vec<u8>,
+    /// Whether the tuple marks its key as deleted.
+    pub tombstone: bool,
+    /// The tag written by [`Heap::put_tagged`], if any.
+    pub tag: Option<u8>,
+}
+
+impl Heap {
+    /// Returns an iterator over every tuple stored in the Heap, starting
+    /// from the last one.
+    ///
+    /// Unlike [`Heap::iter`], this yields every version of a key along with
+    /// tombstones, which is useful to inspect a file. The iterator ends
+    /// after the first error.
+    pub fn records(&self) -> Records<'_> {
+        let mut scanner = Scanner::new();
+        scanner.reset(self.committed_len());
+        Records {
+            heap: self,
+            scanner,
+            failed: false,
+        }
+    }
+}
+
+/// Iterator returned by [`Heap::records`].
+pub struct Records<'a> {
+    heap: &'a Heap,
+    scanner: Scanner,
+    failed: bool,
+}
+
+impl<'a> Iterator for Records<'a> {
+    type Item = Result<Record, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+
+        let result = self.heap.check_file().and_then(|_| {
+            Ok(self.scanner.next_tuple(self.heap)?.map(|tuple| Record {
+                offset: tuple.offset,
+                key: tuple.key.to_vec(),
+                value: tuple.value.to_vec(),
+                tombstone: tuple.tombstone,
+                tag: tuple.tag,
+            }))
+        });
+        self.failed = result.is_err();
+        self.heap.track(result).transpose()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Index;
+    use tempfile::tempfile;
+
+    #[test]
+    fn test_records() {
+        let mut heap = Heap::new(tempfile().unwrap()).unwrap();
+        heap.put(b"key1", b"value1").unwrap();
+        heap.put(b"key1", b"value2").unwrap();
+        heap.delete(b"key1").unwrap();
+
+        let records: Vec<_> = heap.records().map(Result::unwrap).collect();
+        assert_eq!(
+            records,
+            vec![
+                Record {
+                    offset: 26,
+                    key: b"key1".to_vec(),
+                    value: vec![],
+                    tombstone: true,
+                    tag: None,
+                },
+                Record {
+                    offset: 13,
+                    key: b"key1".to_vec(),
+                    value: b"value2".to_vec(),
+                    tombstone: false,
+                    tag: None,
+                },
+                Record {
+                    offset: 0,
+                    key: b"key1".to_vec(),
+                    value: b"value1".to_vec(),
+                    tombstone: false,
+                    tag: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_records_end_after_error() {
+        let mut heap = Heap::new(tempfile().unwrap()).unwrap();
+        heap.put(b"key1", b"value1").unwrap();
+        heap.file.set_len(12).unwrap();
+        heap.committed
+            .store(12, std::sync::atomic::Ordering::Release);
+
+        let mut records = heap.records();
+        assert!(matches!(records.next(), Some(Err(Error::Data(_)))));
+        assert!(records.next().is_none());
+    }
+}
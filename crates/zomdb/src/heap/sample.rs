@@ -0,0 +1,125 @@
+use super::{Heap, HeapTuple};
+use crate::Error;
+
+impl Heap {
+    /// Returns up to n live tuples of the Heap, chosen uniformly at random.
+    ///
+    /// The same seed picks the same tuples as long as the Heap isn't
+    /// written to. If the Heap has no more than n live keys, all of their
+    /// tuples are returned. This scans the whole file, but holds no more
+    /// than n tuples in memory.
+    pub fn sample(&self, n: usize, seed: u64) -> Result<Vec<HeapTuple>, Error> {
+        let mut rng = SplitMix64(seed);
+        let mut reservoir = Vec::with_capacity(n.min(1024));
+
+        // Algorithm R: the i-th tuple replaces a random one among the
+        // sampled ones with probability n/i.
+        for (i, tuple) in self.iter().enumerate() {
+            let tuple = tuple?;
+            if reservoir.len() < n {
+                reservoir.push(tuple);
+                continue;
+            }
+            let j = rng.below(i as u64 + 1) as usize;
+            if j < n {
+                reservoir[j] = tuple;
+            }
+        }
+
+        Ok(reservoir)
+    }
+}
+
+/// A small, seedable pseudo-random number generator, good enough to pick
+/// samples.
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Returns a number in 0..bound.
+    fn below(&mut self, bound: u64) -> u64 {
+        ((self.next() as u128 * bound as u128) >> 64) as u64
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Index;
+    use std::collections::HashSet;
+    use tempfile::tempfile;
+
+    fn heap_with_keys(n: usize) -> Heap {
+        let mut heap = Heap::new(tempfile().unwrap()).unwrap();
+        for i in 0..n {
+            heap.put(format!("key{}", i).as_bytes(), b"old").unwrap();
+        }
+        for i in 0..n {
+            heap.put(format!("key{}", i).as_bytes(), b"new").unwrap();
+        }
+        heap
+    }
+
+    #[test]
+    fn test_sample() {
+        let mut heap = heap_with_keys(100);
+        for i in 0..10 {
+            heap.delete(format!("key{}", i).as_bytes()).unwrap();
+        }
+
+        let sample = heap.sample(10, 42).unwrap();
+        assert_eq!(sample.len(), 10);
+        let keys: HashSet<_> = sample.iter().map(|tuple| tuple.key.clone()).collect();
+        assert_eq!(keys.len(), 10);
+        for tuple in &sample {
+            assert_eq!(tuple.value, b"new");
+            assert!(heap.get(&tuple.key).unwrap().is_some());
+        }
+
+        assert_eq!(heap.sample(10, 42).unwrap(), sample);
+        assert_ne!(heap.sample(10, 43).unwrap(), sample);
+    }
+
+    #[test]
+    fn test_sample_more_than_stored() {
+        let heap = heap_with_keys(5);
+
+        let sample = heap.sample(10, 42).unwrap();
+        let mut keys: Vec<_> = sample.iter().map(|tuple| tuple.key.clone()).collect();
+        keys.sort();
+        let expected: Vec<_> = (0..5).map(|i| format!("key{}", i).into_bytes()).collect();
+        assert_eq!(keys, expected);
+        assert!(sample.iter().all(|tuple| tuple.value == b"new"));
+
+        assert!(heap.sample(0, 42).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_sample_is_uniform() {
+        let heap = heap_with_keys(10);
+
+        // Every key should be picked in about a fifth of the samples.
+        let mut counts = [0; 10];
+        for seed in 0..1000 {
+            for tuple in heap.sample(2, seed).unwrap() {
+                let i: usize = std::str::from_utf8(&tuple.key[3..])
+                    .unwrap()
+                    .parse()
+                    .unwrap();
+                counts[i] += 1;
+            }
+        }
+        assert!(
+            counts.iter().all(|&c| (150..250).contains(&c)),
+            "{:?}",
+            counts
+        );
+    }
+}
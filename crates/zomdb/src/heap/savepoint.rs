@@ -0,0 +1,401 @@
+//! Named offsets of a Heap to roll back to.
+//!
+//! Savepoints are kept in a file next to the Heap, named like it with the
+//! suffix ".savepoints". Its first line holds the number of times the Heap
+//! was compacted while the file existed, and each following line a
+//! savepoint: its offset, that number at the time it was created, and its
+//! name.
+
+use super::Heap;
+use crate::{Error, Operation, SavepointError};
+use std::io::Write;
+use std::sync::atomic::Ordering;
+use std::{fs, io, path};
+
+/// A named offset of a Heap, see [`Heap::savepoint`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Savepoint {
+    pub name: String,
+    /// Length of the file when the savepoint was created.
+    pub offset: u64,
+    /// Number of compactions of the Heap before the savepoint was created.
+    /// The savepoint can only be rolled back to if there were none since.
+    pub generation: u64,
+}
+
+/// The contents of a savepoints file.
+#[derive(Debug, Default)]
+struct Savepoints {
+    generation: u64,
+    savepoints: Vec<Savepoint>,
+}
+
+impl Heap {
+    /// Records the current end of the Heap under the name, replacing an
+    /// earlier savepoint of the same name.
+    ///
+    /// Names must not be empty or contain line breaks. Savepoints require a
+    /// Heap backed by a path, and survive reopening it. The Heap is synced
+    /// first, so that the savepoint never refers to writes a crash can lose.
+    pub fn savepoint(&mut self, name: &str) -> Result<(), Error> {
+        self.check_writable()?;
+        self.check_poisoned()?;
+        if name.is_empty() || name.contains(['\n', '\r']) {
+            return Err(Error::IO(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid savepoint name: {:?}", name),
+            )));
+        }
+        let path = savepoints_path(self.path_for("savepoints")?);
+        self.sync()?;
+
+        let mut file = Savepoints::read(&path)?;
+        let savepoint = Savepoint {
+            name: name.to_string(),
+            offset: self.committed_len(),
+            generation: file.generation,
+        };
+        file.savepoints.retain(|s| s.name != name);
+        file.savepoints.push(savepoint);
+        self.write_savepoints(&file, &path)
+    }
+
+    /// Truncates the Heap to the savepoint with the name, dropping every
+    /// write since.
+    ///
+    /// Savepoints created after this one are removed. Fails with
+    /// [`SavepointError::Compacted`] without touching the file if the Heap
+    /// was compacted since the savepoint was created, since compaction moves
+    /// the tuples its offset refers to, and with
+    /// [`SavepointError::BeyondEnd`] if the file is shorter than the offset.
+    /// Readers of the Heap see the truncation, and the indexes registered
+    /// with [`Heap::add_index`] are rebuilt to match it.
+    pub fn rollback_to(&mut self, name: &str) -> Result<(), Error> {
+        self.check_writable()?;
+        self.check_poisoned()?;
+        self.track(self.check_file())?;
+        let path = savepoints_path(self.path_for("savepoints")?);
+
+        let mut file = Savepoints::read(&path)?;
+        let savepoint = file
+            .savepoints
+            .iter()
+            .find(|s| s.name == name)
+            .cloned()
+            .ok_or_else(|| Error::Savepoint(SavepointError::NotFound(name.to_string())))?;
+        if savepoint.generation != file.generation {
+            return Err(Error::Savepoint(SavepointError::Compacted(
+                name.to_string(),
+            )));
+        }
+        if savepoint.offset > self.committed_len() {
+            return Err(Error::Savepoint(SavepointError::BeyondEnd(
+                name.to_string(),
+            )));
+        }
+
+        // Readers must not read past the new end while the file shrinks.
+        self.committed.store(savepoint.offset, Ordering::Release);
+        self.forget_values();
+        self.file
+            .set_len(savepoint.offset)
+            .map_err(|e| Error::io(Operation::Truncate, self.path.as_deref(), e))?;
+        self.sync()?;
+        log::info!(
+            "rolled back {} to savepoint {:?} at {} bytes",
+            self.log_name(),
+            name,
+            savepoint.offset
+        );
+
+        file.savepoints.retain(|s| s.offset <= savepoint.offset);
+        self.write_savepoints(&file, &path)?;
+
+        let names: Vec<_> = self.indexes.iter().map(|i| i.name().to_string()).collect();
+        for name in names {
+            self.rebuild_index(&name)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the savepoints of the Heap in the order they were created.
+    pub fn savepoints(&self) -> Result<Vec<Savepoint>, Error> {
+        let path = savepoints_path(self.path_for("savepoints")?);
+        Ok(Savepoints::read(&path)?.savepoints)
+    }
+
+    /// Returns whether the Heap has a savepoint that can still be rolled
+    /// back to and was created after the offset.
+    ///
+    /// A corrupt savepoints file might hold one, so it counts as such.
+    pub(super) fn has_savepoint_after(&self, offset: u64) -> Result<bool, Error> {
+        let path = savepoints_path(self.path_for("savepoints")?);
+        let Some(file) = Savepoints::read_valid(&path)? else {
+            return Ok(true);
+        };
+        Ok(file
+            .savepoints
+            .iter()
+            .any(|s| s.generation == file.generation && s.offset > offset))
+    }
+
+    /// Marks the savepoints of the Heap as unusable, because compaction is
+    /// about to move the tuples they refer to.
+    ///
+    /// A corrupt savepoints file is replaced by one without savepoints, as
+    /// they would be unusable all the same.
+    pub(super) fn invalidate_savepoints(&self, path: &path::Path) -> Result<(), Error> {
+        let path = savepoints_path(path);
+        if !path.exists() {
+            return Ok(());
+        }
+        let mut file = Savepoints::read_valid(&path)?.unwrap_or_default();
+        file.generation += 1;
+        self.write_savepoints(&file, &path)
+    }
+
+    /// Replaces the savepoints file at path, and syncs its directory as
+    /// configured.
+    fn write_savepoints(&self, file: &Savepoints, path: &path::Path) -> Result<(), Error> {
+        file.write(path)?;
+        self.sync_parent(path)
+    }
+
+    /// Returns the path of the Heap, or an error naming the feature that
+    /// requires it.
+    pub(super) fn path_for(&self, feature: &str) -> Result<&path::Path, Error> {
+        self.path.as_deref().ok_or_else(|| {
+            Error::IO(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("{} require a heap backed by a path", feature),
+            ))
+        })
+    }
+}
+
+fn savepoints_path(path: &path::Path) -> path::PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".savepoints");
+    path.with_file_name(name)
+}
+
+impl Savepoints {
+    /// Reads the file at path, which counts as empty if it doesn't exist.
+    fn read(path: &path::Path) -> Result<Self, Error> {
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(Error::io(Operation::Read, Some(path), e)),
+        };
+        let invalid = |line: usize| {
+            Error::IO(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}: invalid line {}", path.display(), line),
+            ))
+        };
+
+        let mut lines = contents.lines();
+        let generation = lines
+            .next()
+            .and_then(|line| line.parse().ok())
+            .ok_or_else(|| invalid(1))?;
+        let mut savepoints = Vec::new();
+        for (i, line) in lines.enumerate() {
+            let mut fields = line.splitn(3, ' ');
+            let mut next = || fields.next().ok_or_else(|| invalid(i + 2));
+            let offset = next()?.parse().map_err(|_| invalid(i + 2))?;
+            let generation = next()?.parse().map_err(|_| invalid(i + 2))?;
+            let name = next()?.to_string();
+            savepoints.push(Savepoint {
+                name,
+                offset,
+                generation,
+            });
+        }
+
+        Ok(Self {
+            generation,
+            savepoints,
+        })
+    }
+
+    /// Reads the file at path like read, but returns None if its contents
+    /// are corrupt.
+    fn read_valid(path: &path::Path) -> Result<Option<Self>, Error> {
+        match Self::read(path) {
+            Err(Error::IO(e)) if e.kind() == io::ErrorKind::InvalidData => {
+                log::warn!("ignoring corrupt savepoints: {}", e);
+                Ok(None)
+            }
+            result => result.map(Some),
+        }
+    }
+
+    /// Replaces the file at path atomically, syncing the new contents
+    /// before they replace the old ones.
+    fn write(&self, path: &path::Path) -> Result<(), Error> {
+        let mut contents = format!("{}\n", self.generation);
+        for s in &self.savepoints {
+            contents.push_str(&format!("{} {} {}\n", s.offset, s.generation, s.name));
+        }
+
+        let mut tmp_name = path.file_name().unwrap_or_default().to_os_string();
+        tmp_name.push(".tmp");
+        let tmp_path = path.with_file_name(tmp_name);
+        let mut tmp = fs::File::create(&tmp_path)
+            .map_err(|e| Error::io(Operation::Open, Some(&tmp_path), e))?;
+        tmp.write_all(contents.as_bytes())
+            .map_err(|e| Error::io(Operation::Write, Some(&tmp_path), e))?;
+        tmp.sync_all()
+            .map_err(|e| Error::io(Operation::Sync, Some(&tmp_path), e))?;
+        fs::rename(&tmp_path, path).map_err(|e| Error::io(Operation::Rename, Some(&tmp_path), e))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::heap::FailingStorage;
+    use crate::{HeapOptions, Index, SecondaryIndex};
+
+    #[test]
+    fn test_savepoints() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("heap");
+        let mut heap = Heap::from(path.clone()).unwrap();
+        assert!(heap.savepoints().unwrap().is_empty());
+
+        heap.put(b"key1", b"value1").unwrap();
+        heap.savepoint("first").unwrap();
+        heap.put(b"key1", b"value2").unwrap();
+        heap.put(b"key2", b"value3").unwrap();
+        heap.savepoint("second").unwrap();
+        heap.delete(b"key2").unwrap();
+
+        heap.rollback_to("second").unwrap();
+        assert_eq!(heap.get(b"key2").unwrap(), Some(b"value3".to_vec()));
+
+        // Savepoints survive reopening the Heap.
+        drop(heap);
+        let mut heap = Heap::from(path.clone()).unwrap();
+        let names: Vec<_> = heap
+            .savepoints()
+            .unwrap()
+            .into_iter()
+            .map(|s| s.name)
+            .collect();
+        assert_eq!(names, ["first", "second"]);
+
+        heap.rollback_to("first").unwrap();
+        assert_eq!(heap.get(b"key1").unwrap(), Some(b"value1".to_vec()));
+        assert_eq!(heap.get(b"key2").unwrap(), None);
+        assert_eq!(fs::metadata(&path).unwrap().len(), 13);
+        let savepoints = heap.savepoints().unwrap();
+        assert_eq!(
+            savepoints,
+            [Savepoint {
+                name: "first".to_string(),
+                offset: 13,
+                generation: 0,
+            }]
+        );
+
+        // The Heap keeps working after a rollback.
+        heap.put(b"key3", b"value4").unwrap();
+        assert_eq!(heap.len().unwrap(), 2);
+
+        assert!(matches!(
+            heap.rollback_to("second"),
+            Err(Error::Savepoint(SavepointError::NotFound(_)))
+        ));
+        assert!(heap.savepoint("").is_err());
+        assert!(heap.savepoint("two\nlines").is_err());
+    }
+
+    #[test]
+    fn test_rollback_after_compaction_fails() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("heap");
+        let mut heap = Heap::from(path.clone()).unwrap();
+        heap.put(b"key1", b"value1").unwrap();
+        heap.put(b"key1", b"value2").unwrap();
+        heap.savepoint("before").unwrap();
+        heap.put(b"key2", b"value3").unwrap();
+
+        heap.compact().unwrap();
+        let len = fs::metadata(&path).unwrap().len();
+        assert!(matches!(
+            heap.rollback_to("before"),
+            Err(Error::Savepoint(SavepointError::Compacted(_)))
+        ));
+        assert_eq!(fs::metadata(&path).unwrap().len(), len);
+        assert_eq!(heap.get(b"key2").unwrap(), Some(b"value3".to_vec()));
+
+        // Savepoints created after the compaction work again.
+        heap.savepoint("after").unwrap();
+        heap.put(b"key3", b"value4").unwrap();
+        heap.rollback_to("after").unwrap();
+        assert_eq!(heap.get(b"key3").unwrap(), None);
+    }
+
+    #[test]
+    fn test_rollback_rebuilds_indexes() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut heap = Heap::from(dir.path().join("heap")).unwrap();
+        let index = Heap::new(tempfile::tempfile().unwrap()).unwrap();
+        heap.add_index(SecondaryIndex::new("city", index, |value| {
+            let end = value.iter().position(|&b| b == b':')?;
+            Some(value[..end].to_vec())
+        }));
+        heap.put(b"alice", b"berlin:1").unwrap();
+        heap.savepoint("before").unwrap();
+        heap.put(b"alice", b"paris:2").unwrap();
+        heap.put(b"bob", b"paris:3").unwrap();
+
+        heap.rollback_to("before").unwrap();
+        let alice = vec![b"alice".to_vec()];
+        assert_eq!(heap.lookup_secondary("city", b"berlin").unwrap(), alice);
+        assert!(heap.lookup_secondary("city", b"paris").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_corrupt_savepoints() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("heap");
+        let mut heap = Heap::from(path.clone()).unwrap();
+        heap.put(b"key1", b"value1").unwrap();
+        heap.put(b"key1", b"value2").unwrap();
+        fs::write(dir.path().join("heap.savepoints"), b"garbage").unwrap();
+        assert!(heap.savepoints().is_err());
+
+        // The file might name a savepoint the update would break.
+        assert!(!heap.update_in_place(b"key1", b"VALUE2").unwrap());
+        // Compaction drops the savepoints, which were unusable already.
+        heap.compact().unwrap();
+        assert!(heap.savepoints().unwrap().is_empty());
+        heap.savepoint("after").unwrap();
+        assert_eq!(heap.savepoints().unwrap()[0].generation, 1);
+    }
+
+    #[test]
+    fn test_rollback_beyond_end() {
+        let dir = tempfile::tempdir().unwrap();
+        let faults = FailingStorage::new();
+        let mut heap = HeapOptions::new()
+            .faults(faults.clone())
+            .open(dir.path().join("heap"))
+            .unwrap();
+        heap.put(b"key1", b"value1").unwrap();
+        faults.clear();
+        heap.savepoint("first").unwrap();
+        // Replacing the savepoints file syncs its directory.
+        assert_eq!(faults.synced_dirs(), vec![dir.path().to_path_buf()]);
+
+        fs::write(dir.path().join("heap.savepoints"), b"0\n1000 0 far\n").unwrap();
+        assert!(matches!(
+            heap.rollback_to("far"),
+            Err(Error::Savepoint(SavepointError::BeyondEnd(_)))
+        ));
+        assert_eq!(heap.get(b"key1").unwrap(), Some(b"value1".to_vec()));
+    }
+}
@@ -0,0 +1,307 @@
+//! Secondary indexes, which map fields derived from values back to the keys
+//! holding them.
+//!
+//! An index keeps an entry per key whose value has the field, in a Heap of
+//! its own. Entry keys hold the field and the primary key as segments of a
+//! [`KeyBuilder`], so that the entries of a field share a prefix.
+
+use super::{Heap, WriteBatch};
+use crate::keys::{KeyBuilder, KeyError, KeyParser};
+use crate::Error;
+use std::collections::{HashMap, HashSet};
+use std::io;
+
+type Extractor = dyn Fn(&[u8]) -> Option<Vec<u8>> + Send + Sync;
+
+/// An index over a field of the values of a Heap, see
+/// [`Heap::add_index`].
+pub struct SecondaryIndex {
+    name: String,
+    heap: Heap,
+    extract: Box<Extractor>,
+}
+
+impl SecondaryIndex {
+    /// Creates an index that stores its entries in the given Heap.
+    ///
+    /// The extractor returns the indexed field of a value, or None if the
+    /// value isn't indexed. An entry has to fit into a key along with the
+    /// primary key, so the two may take at most about 250 bytes together.
+    pub fn new(
+        name: &str,
+        heap: Heap,
+        extract: impl Fn(&[u8]) -> Option<Vec<u8>> + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            name: name.to_string(),
+            heap,
+            extract: Box::new(extract),
+        }
+    }
+
+    /// Returns the name the index is looked up by.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the Heap that stores the entries of the index.
+    pub fn heap(&self) -> &Heap {
+        &self.heap
+    }
+}
+
+/// Returns the key of the index entry of a field and a primary key.
+fn entry_key(field: &[u8], key: &[u8]) -> Vec<u8> {
+    KeyBuilder::new().bytes(field).bytes(key).build()
+}
+
+/// Index entries to write once the tuples they belong to are written, per
+/// index.
+pub(super) type IndexUpdate = Vec<(usize, WriteBatch)>;
+
+impl Heap {
+    /// Registers an index, which is updated by every following write to the
+    /// Heap.
+    ///
+    /// The index isn't filled with the keys that are stored already; call
+    /// [`Heap::rebuild_index`] for that. An index with the same name is
+    /// replaced.
+    ///
+    /// Entries are written after the tuples they belong to. If that fails,
+    /// or the process crashes in between, the index misses the latest
+    /// writes until it is rebuilt.
+    pub fn add_index(&mut self, index: SecondaryIndex) {
+        match self.indexes.iter().position(|i| i.name == index.name) {
+            Some(i) => self.indexes[i] = index,
+            None => self.indexes.push(index),
+        }
+    }
+
+    /// Returns the keys whose values have the given field in the index with
+    /// the given name, in ascending order.
+    pub fn lookup_secondary(&self, name: &str, field: &[u8]) -> Result<Vec<Vec<u8>>, Error> {
+        let index = self.index(name)?;
+        let prefix = KeyBuilder::new().bytes(field).build();
+
+        let mut keys = Vec::new();
+        for tuple in index.heap.scan_prefix(&prefix) {
+            let key = primary_key(&tuple?.key)
+                .map_err(|e| Error::IO(io::Error::new(io::ErrorKind::InvalidData, e)))?;
+            keys.push(key);
+        }
+        keys.sort();
+        Ok(keys)
+    }
+
+    /// Makes the index with the given name match the current values of the
+    /// Heap, and returns the number of entries that had to be written.
+    pub fn rebuild_index(&mut self, name: &str) -> Result<u64, Error> {
+        let index = self.index(name)?;
+
+        let mut wanted = HashSet::new();
+        for tuple in self.iter() {
+            let tuple = tuple?;
+            if let Some(field) = (index.extract)(&tuple.value) {
+                wanted.insert(entry_key(&field, &tuple.key));
+            }
+        }
+
+        let mut batch = WriteBatch::new();
+        for tuple in index.heap.iter() {
+            let entry = tuple?.key;
+            if !wanted.remove(&entry) {
+                batch.delete(&entry);
+            }
+        }
+        for entry in &wanted {
+            batch.put(entry, &[]);
+        }
+
+        if !batch.is_empty() {
+            index.heap.append_batch(&batch)?;
+        }
+        Ok(batch.len() as u64)
+    }
+
+    fn index(&self, name: &str) -> Result<&SecondaryIndex, Error> {
+        self.indexes.iter().find(|i| i.name == name).ok_or_else(|| {
+            Error::IO(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("unknown index: {:?}", name),
+            ))
+        })
+    }
+
+    /// Determines the index entries to remove and add for writes of the
+    /// given values, None meaning a delete.
+    ///
+    /// This has to be called before the writes, to find the entries of the
+    /// values they replace.
+    pub(super) fn prepare_index_update<'t>(
+        &self,
+        writes: impl IntoIterator<Item = (&'t [u8], Option<&'t [u8]>)>,
+    ) -> Result<IndexUpdate, Error> {
+        if self.indexes.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        // Later writes of a key override earlier ones.
+        let writes: HashMap<_, _> = writes.into_iter().collect();
+
+        let mut batches: Vec<_> = (0..self.indexes.len()).map(|_| WriteBatch::new()).collect();
+        for (key, value) in writes {
+            let old_value = self.get_with(key, <[u8]>::to_vec)?;
+            for (index, batch) in self.indexes.iter().zip(&mut batches) {
+                let old = old_value.as_deref().and_then(&index.extract);
+                let new = value.and_then(&index.extract);
+                if old == new {
+                    continue;
+                }
+                if let Some(old) = old {
+                    batch.delete(&entry_key(&old, key));
+                }
+                if let Some(new) = new {
+                    batch.put(&entry_key(&new, key), &[]);
+                }
+            }
+        }
+
+        Ok(batches
+            .into_iter()
+            .enumerate()
+            .filter(|(_, batch)| !batch.is_empty())
+            .collect())
+    }
+
+    /// Writes the index entries determined by prepare_index_update.
+    pub(super) fn apply_index_update(&self, update: IndexUpdate) -> Result<(), Error> {
+        for (i, batch) in update {
+            self.indexes[i].heap.append_batch(&batch)?;
+        }
+        Ok(())
+    }
+}
+
+/// Returns the primary key of an index entry.
+fn primary_key(entry: &[u8]) -> Result<Vec<u8>, KeyError> {
+    let mut parser = KeyParser::new(entry);
+    parser.bytes()?;
+    let key = parser.bytes()?;
+    parser.finish()?;
+    Ok(key)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Index, SyncHeap};
+    use tempfile::tempfile;
+
+    /// Indexes values of the form "city:rest" by their city.
+    fn city_index() -> SecondaryIndex {
+        SecondaryIndex::new("city", Heap::new(tempfile().unwrap()).unwrap(), |value| {
+            let end = value.iter().position(|&b| b == b':')?;
+            Some(value[..end].to_vec())
+        })
+    }
+
+    fn lookup(heap: &Heap, city: &[u8]) -> Vec<Vec<u8>> {
+        heap.lookup_secondary("city", city).unwrap()
+    }
+
+    #[test]
+    fn test_secondary_index() {
+        let mut heap = Heap::new(tempfile().unwrap()).unwrap();
+        heap.add_index(city_index());
+
+        heap.put(b"alice", b"berlin:1").unwrap();
+        heap.put(b"bob", b"paris:2").unwrap();
+        heap.put(b"carol", b"berlin:3").unwrap();
+        heap.put(b"dave", b"no city").unwrap();
+        assert_eq!(
+            lookup(&heap, b"berlin"),
+            vec![b"alice".to_vec(), b"carol".to_vec()]
+        );
+        assert_eq!(lookup(&heap, b"paris"), vec![b"bob".to_vec()]);
+        assert!(lookup(&heap, b"berl").is_empty());
+
+        // Changing the field moves the key to the new entry.
+        heap.put(b"alice", b"paris:4").unwrap();
+        assert_eq!(lookup(&heap, b"berlin"), vec![b"carol".to_vec()]);
+        assert_eq!(
+            lookup(&heap, b"paris"),
+            vec![b"alice".to_vec(), b"bob".to_vec()]
+        );
+
+        heap.delete(b"bob").unwrap();
+        heap.put(b"dave", b"rome:5").unwrap();
+        heap.put_many([(&b"carol"[..], &b"no city"[..])]).unwrap();
+        let mut batch = WriteBatch::new();
+        batch.put(b"erin", b"berlin:6");
+        batch.put(b"erin", b"rome:7");
+        batch.delete(b"dave");
+        heap.write_batch(&batch).unwrap();
+
+        assert!(lookup(&heap, b"berlin").is_empty());
+        assert_eq!(lookup(&heap, b"paris"), vec![b"alice".to_vec()]);
+        assert_eq!(lookup(&heap, b"rome"), vec![b"erin".to_vec()]);
+
+        assert!(heap.lookup_secondary("other", b"paris").is_err());
+    }
+
+    #[test]
+    fn test_secondary_index_through_sync_heap() {
+        let mut heap = Heap::new(tempfile().unwrap()).unwrap();
+        heap.add_index(city_index());
+
+        let heap = SyncHeap::new(heap);
+        heap.put(b"alice", b"berlin:1").unwrap();
+        heap.put(b"alice", b"paris:2").unwrap();
+        heap.put(b"bob", b"paris:3").unwrap();
+        heap.delete(b"bob").unwrap();
+
+        let heap = heap.into_inner();
+        assert!(lookup(&heap, b"berlin").is_empty());
+        assert_eq!(lookup(&heap, b"paris"), vec![b"alice".to_vec()]);
+    }
+
+    #[test]
+    fn test_rebuild_index() {
+        let mut heap = Heap::new(tempfile().unwrap()).unwrap();
+        heap.put(b"alice", b"berlin:1").unwrap();
+        heap.put(b"bob", b"paris:2").unwrap();
+
+        // The index starts out empty, and is filled by the rebuild.
+        heap.add_index(city_index());
+        assert!(lookup(&heap, b"berlin").is_empty());
+        assert_eq!(heap.rebuild_index("city").unwrap(), 2);
+        assert_eq!(lookup(&heap, b"berlin"), vec![b"alice".to_vec()]);
+
+        heap.put(b"carol", b"berlin:3").unwrap();
+        heap.put(b"alice", b"rome:4").unwrap();
+        heap.delete(b"bob").unwrap();
+        let entries = |heap: &Heap| {
+            let mut entries: Vec<_> = heap.indexes[0]
+                .heap()
+                .iter()
+                .map(|tuple| tuple.unwrap().key)
+                .collect();
+            entries.sort();
+            entries
+        };
+        let maintained = entries(&heap);
+
+        // A rebuild of an index that was kept up to date changes nothing.
+        assert_eq!(heap.rebuild_index("city").unwrap(), 0);
+        assert_eq!(entries(&heap), maintained);
+
+        // Writes the index missed are picked up.
+        let index = heap.indexes.pop().unwrap();
+        heap.put(b"carol", b"paris:5").unwrap();
+        heap.add_index(index);
+        assert_eq!(lookup(&heap, b"berlin"), vec![b"carol".to_vec()]);
+        assert_eq!(heap.rebuild_index("city").unwrap(), 2);
+        assert!(lookup(&heap, b"berlin").is_empty());
+        assert_eq!(lookup(&heap, b"paris"), vec![b"carol".to_vec()]);
+    }
+}
@@ -0,0 +1,47 @@
+//! Logging of slow operations, see
+//! [`HeapOptions::slow_op_threshold`](crate::HeapOptions::slow_op_threshold).
+
+use super::Heap;
+use std::time::Instant;
+
+/// The start of an operation that is timed.
+pub(super) struct OpTimer {
+    start: Instant,
+    bytes_read: u64,
+}
+
+impl Heap {
+    /// Starts timing an operation, if the Heap logs slow operations.
+    pub(super) fn time_op(&self) -> Option<OpTimer> {
+        self.slow_op_threshold?;
+        Some(OpTimer {
+            start: Instant::now(),
+            bytes_read: self.counters.snapshot().bytes_read,
+        })
+    }
+
+    /// Logs a warning if the operation on a key of the length took longer
+    /// than the threshold since the timer was started.
+    pub(super) fn log_slow_op(&self, timer: Option<OpTimer>, operation: &str, key_len: usize) {
+        let (Some(timer), Some(threshold)) = (timer, self.slow_op_threshold) else {
+            return;
+        };
+        let elapsed = timer.start.elapsed();
+        if elapsed <= threshold {
+            return;
+        }
+        let scanned = self
+            .counters
+            .snapshot()
+            .bytes_read
+            .saturating_sub(timer.bytes_read);
+        log::warn!(
+            "slow {} on {}: took {:?} for a key of {} bytes, scanned {} bytes",
+            operation,
+            self.log_name(),
+            elapsed,
+            key_len,
+            scanned
+        );
+    }
+}
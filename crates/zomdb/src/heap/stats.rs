@@ -0,0 +1,223 @@
+use super::{Heap, Scanner};
+use crate::Error;
+use std::collections::HashSet;
+
+/// Statistics about the tuples stored in a Heap.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct HeapStats {
+    /// Size of the Heap file in bytes.
+    pub file_size: u64,
+    /// Number of tuples stored, including stale ones and tombstones.
+    pub total_records: u64,
+    /// Number of keys that have a value.
+    pub live_keys: u64,
+    /// Number of tuples that compaction would drop.
+    pub stale_records: u64,
+    /// Number of bytes that compaction would free.
+    pub dead_bytes: u64,
+    /// Sizes of the keys that have a value.
+    pub key_sizes: SizeHistogram,
+    /// Sizes of the values of those keys.
+    pub value_sizes: SizeHistogram,
+}
+
+/// Number of buckets of a [`SizeHistogram`].
+pub const SIZE_BUCKETS: usize = 12;
+
+/// Counts sizes in buckets that double in width.
+///
+/// Bucket 0 counts the size 0, and bucket i counts the sizes from 2^(i-1) up
+/// to 2^i - 1, so the last bucket counts the sizes from 1024 to 2047. This
+/// covers the largest keys and values, and the boundaries stay the same
+/// regardless of the limits a Heap is opened with.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct SizeHistogram {
+    /// Number of sizes counted in each bucket.
+    pub buckets: [u64; SIZE_BUCKETS],
+    /// The largest size counted.
+    pub max: u64,
+}
+
+impl SizeHistogram {
+    fn add(&mut self, size: usize) {
+        let bucket = (usize::BITS - size.leading_zeros()) as usize;
+        self.buckets[bucket.min(SIZE_BUCKETS - 1)] += 1;
+        self.max = self.max.max(size as u64);
+    }
+
+    /// Returns the number of sizes counted.
+    pub fn count(&self) -> u64 {
+        self.buckets.iter().sum()
+    }
+
+    /// Returns an upper bound for the median size.
+    pub fn p50(&self) -> u64 {
+        self.percentile(50)
+    }
+
+    /// Returns an upper bound for the size that 95% of sizes don't exceed.
+    pub fn p95(&self) -> u64 {
+        self.percentile(95)
+    }
+
+    /// Returns the largest size of the bucket that holds the given
+    /// percentile, or the largest size counted if that is smaller. Returns 0
+    /// if no sizes were counted.
+    fn percentile(&self, percent: u64) -> u64 {
+        // The rank of the size, counting from 1.
+        let rank = (self.count() * percent).div_ceil(100).max(1);
+        let mut counted = 0;
+        for (i, count) in self.buckets.iter().enumerate() {
+            counted += count;
+            if counted >= rank {
+                let upper = (1u64 << i) - 1;
+                return upper.min(self.max);
+            }
+        }
+        0
+    }
+}
+
+impl Heap {
+    /// Collects statistics about the tuples stored in the Heap.
+    ///
+    /// This scans the whole file and keeps every key in memory.
+    pub fn stats(&self) -> Result<HeapStats, Error> {
+        let result = self.check_file().and_then(|_| self.collect_stats());
+        self.track(result)
+    }
+
+    fn collect_stats(&self) -> Result<HeapStats, Error> {
+        let file_size = self.committed_len();
+        let mut scanner = Scanner::new();
+        scanner.reset(file_size);
+
+        let mut seen_keys = HashSet::new();
+        let mut stats = HeapStats {
+            file_size,
+            ..Default::default()
+        };
+        let mut live_bytes = 0;
+
+        while let Some(tuple) = scanner.next_tuple(self)? {
+            stats.total_records += 1;
+            if seen_keys.contains(tuple.key) {
+                continue;
+            }
+            seen_keys.insert(tuple.key.to_vec());
+
+            if !tuple.tombstone {
+                stats.live_keys += 1;
+                live_bytes += tuple.disk_len() as u64;
+                stats.key_sizes.add(tuple.key.len());
+                stats.value_sizes.add(tuple.value.len());
+            }
+        }
+
+        stats.stale_records = stats.total_records - stats.live_keys;
+        stats.dead_bytes = file_size - live_bytes;
+
+        Ok(stats)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Index;
+    use tempfile::tempfile;
+
+    #[test]
+    fn test_heap_stats() {
+        let mut heap = Heap::new(tempfile().unwrap()).unwrap();
+        assert_eq!(heap.stats().unwrap(), HeapStats::default());
+
+        heap.put(b"key1", b"value1").unwrap();
+        heap.put(b"key1", b"value2").unwrap();
+        heap.put(b"key2", b"value3").unwrap();
+        heap.put(b"key3", b"value4").unwrap();
+        heap.delete(b"key3").unwrap();
+
+        let tuple_len = 4 + 6 + 3;
+        let mut key_sizes = SizeHistogram {
+            max: 4,
+            ..Default::default()
+        };
+        key_sizes.buckets[3] = 2;
+        let mut value_sizes = SizeHistogram {
+            max: 6,
+            ..Default::default()
+        };
+        value_sizes.buckets[3] = 2;
+        assert_eq!(
+            heap.stats().unwrap(),
+            HeapStats {
+                file_size: 4 * tuple_len + (4 + 3),
+                total_records: 5,
+                live_keys: 2,
+                stale_records: 3,
+                dead_bytes: 2 * tuple_len + (4 + 3),
+                key_sizes,
+                value_sizes,
+            }
+        );
+    }
+
+    #[test]
+    fn test_heap_stats_histograms() {
+        let mut heap = Heap::new(tempfile().unwrap()).unwrap();
+        // 100 values: 90 of 10 bytes, 6 of 100, 3 of 0 and the largest one.
+        for i in 0..100usize {
+            let size = match i {
+                0..=89 => 10,
+                90..=95 => 100,
+                96..=98 => 0,
+                _ => 1024,
+            };
+            heap.put(format!("{:03}", i).as_bytes(), &vec![b'v'; size])
+                .unwrap();
+        }
+        // Overwritten values and deleted keys don't count.
+        heap.put(b"000", &[b'v'; 10]).unwrap();
+        heap.put(b"gone", &[b'v'; 500]).unwrap();
+        heap.delete(b"gone").unwrap();
+
+        let stats = heap.stats().unwrap();
+        let mut buckets = [0; SIZE_BUCKETS];
+        buckets[0] = 3;
+        buckets[4] = 90;
+        buckets[7] = 6;
+        buckets[11] = 1;
+        assert_eq!(stats.value_sizes.buckets, buckets);
+        assert_eq!(stats.value_sizes.count(), 100);
+        assert_eq!(stats.value_sizes.max, 1024);
+        assert_eq!(stats.value_sizes.p50(), 15);
+        assert_eq!(stats.value_sizes.p95(), 127);
+
+        let mut buckets = [0; SIZE_BUCKETS];
+        buckets[2] = 100;
+        assert_eq!(stats.key_sizes.buckets, buckets);
+        assert_eq!(stats.key_sizes.p50(), 3);
+        assert_eq!(stats.key_sizes.p95(), 3);
+        assert_eq!(stats.key_sizes.max, 3);
+
+        let empty = SizeHistogram::default();
+        assert_eq!((empty.p50(), empty.p95(), empty.max), (0, 0, 0));
+    }
+
+    #[test]
+    fn test_heap_stats_match_compaction() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut heap = Heap::from(dir.path().join("heap")).unwrap();
+        for i in 0..100u32 {
+            heap.put(&(i % 7).to_be_bytes(), &i.to_be_bytes()).unwrap();
+        }
+
+        let stats = heap.stats().unwrap();
+        let report = heap.compact().unwrap();
+
+        assert_eq!(stats.stale_records, report.records_dropped);
+        assert_eq!(stats.file_size - stats.dead_bytes, report.bytes_after);
+        assert_eq!(heap.stats().unwrap().dead_bytes, 0);
+    }
+}
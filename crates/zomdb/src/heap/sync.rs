@@ -0,0 +1,239 @@
+use super::{CompactOptions, CompactionReport, Heap, HeapTuple, Tuples};
+use crate::Error;
+use std::path;
+use std::sync::{Mutex, RwLock, RwLockReadGuard};
+
+/// A Heap that can be shared across threads.
+///
+/// Reads use positioned I/O and proceed concurrently with each other and
+/// with appends. Appends are serialized by an internal mutex. After an append
+/// completes, the Heap publishes the new end of the file atomically, and
+/// readers only ever scan up to the last published end. This way readers
+/// never observe a partially written tuple.
+///
+/// Operations that replace the underlying file, like compaction, wait for
+/// all readers and writers to finish.
+pub struct SyncHeap {
+    heap: RwLock<Heap>,
+    append: Mutex<()>,
+}
+
+impl SyncHeap {
+    /// Wraps a Heap to share it across threads.
+    pub fn new(heap: Heap) -> Self {
+        Self {
+            heap: RwLock::new(heap),
+            append: Mutex::new(()),
+        }
+    }
+
+    /// Creates a new SyncHeap from the provided path.
+    ///
+    /// See [`Heap::from`].
+    pub fn from(path: path::PathBuf) -> Result<Self, Error> {
+        Heap::from(path).map(Self::new)
+    }
+
+    /// Unwraps the inner Heap.
+    pub fn into_inner(self) -> Heap {
+        self.heap.into_inner().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn put(&self, key: &[u8], value: &[u8]) -> Result<(), Error> {
+        let heap = self.read_heap();
+        let _append = self.append.lock().unwrap_or_else(|e| e.into_inner());
+
+        heap.append(key, value, None).map(|_| ())
+    }
+
+    /// Writes multiple key-value pairs at once.
+    ///
+    /// See [`Heap::put_many`].
+    pub fn put_many<'t, I>(&self, tuples: I) -> Result<(), Error>
+    where
+        I: IntoIterator<Item = (&'t [u8], &'t [u8])>,
+    {
+        let heap = self.read_heap();
+        let _append = self.append.lock().unwrap_or_else(|e| e.into_inner());
+
+        heap.append_many(tuples).map(|_| ())
+    }
+
+    pub fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, Error> {
+        let heap = self.read_heap();
+        heap.find(key, heap.committed_len())
+    }
+
+    /// Removes the key, returning whether it had a value.
+    pub fn delete(&self, key: &[u8]) -> Result<bool, Error> {
+        let heap = self.read_heap();
+        // Holding the append lock makes sure no value is written for the key
+        // between the lookup and the tombstone.
+        let _append = self.append.lock().unwrap_or_else(|e| e.into_inner());
+
+        if heap.find(key, heap.committed_len())?.is_none() {
+            return Ok(false);
+        }
+
+        heap.append_tombstone(key).map(|_| true)
+    }
+
+    /// Returns an iterator over the tuples written before this call.
+    ///
+    /// Tuples appended while iterating are not visited. The iterator holds a
+    /// shared lock on the Heap, so compaction waits until it is dropped.
+    pub fn iter(&self) -> SyncIter<'_> {
+        let heap = self.read_heap();
+        let end = heap.committed_len();
+
+        SyncIter {
+            tuples: Tuples::new(heap, Some(end)),
+        }
+    }
+
+    /// Compacts the Heap once all ongoing operations have finished.
+    ///
+    /// See [`Heap::compact`].
+    pub fn compact(&self) -> Result<CompactionReport, Error> {
+        self.compact_with(CompactOptions::default())
+    }
+
+    /// Compacts the Heap with the provided options.
+    ///
+    /// See [`Heap::compact_with`].
+    pub fn compact_with(&self, opts: CompactOptions) -> Result<CompactionReport, Error> {
+        let mut heap = self.heap.write().unwrap_or_else(|e| e.into_inner());
+        heap.compact_with(opts)
+    }
+
+    /// Verifies the Heap and makes it accept writes again once all ongoing
+    /// operations have finished.
+    ///
+    /// See [`Heap::verify_and_clear`].
+    pub fn verify_and_clear(&self) -> Result<(), Error> {
+        let mut heap = self.heap.write().unwrap_or_else(|e| e.into_inner());
+        heap.verify_and_clear()
+    }
+
+    fn read_heap(&self) -> RwLockReadGuard<'_, Heap> {
+        // The Heap holds no invariants that a panicking thread could break,
+        // so it is safe to keep using it after the lock was poisoned.
+        self.heap.read().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Iterates the tuples of a SyncHeap.
+///
+/// Use [`SyncHeap::iter`] to create an instance of this struct.
+pub struct SyncIter<'a> {
+    tuples: Tuples<RwLockReadGuard<'a, Heap>>,
+}
+
+impl<'a> Iterator for SyncIter<'a> {
+    type Item = Result<HeapTuple, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.tuples.next_tuple().transpose()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::thread;
+    use tempfile::tempfile;
+
+    fn value_for(key: u32, round: u32) -> Vec<u8> {
+        // Vary the length and contents so that a torn read would be caught
+        // by comparing against the expected value.
+        let len = (key * 7 + round * 13) as usize % 900 + 4;
+        let mut value = vec![(key + round) as u8; len];
+        value[..4].copy_from_slice(&round.to_be_bytes());
+        value
+    }
+
+    #[test]
+    fn test_sync_heap_put_get_delete() {
+        let heap = SyncHeap::new(Heap::new(tempfile().unwrap()).unwrap());
+
+        heap.put(b"key1", b"value1").unwrap();
+        heap.put(b"key2", b"value2").unwrap();
+
+        assert_eq!(heap.get(b"key1").unwrap(), Some(b"value1".to_vec()));
+        assert!(heap.delete(b"key1").unwrap());
+        assert!(!heap.delete(b"key1").unwrap());
+        assert_eq!(heap.get(b"key1").unwrap(), None);
+
+        let keys: Vec<_> = heap.iter().map(|t| t.unwrap().key).collect();
+        assert_eq!(keys, vec![b"key2".to_vec()]);
+    }
+
+    #[test]
+    fn test_sync_heap_iter_ignores_later_appends() {
+        let heap = SyncHeap::new(Heap::new(tempfile().unwrap()).unwrap());
+        heap.put(b"key1", b"value1").unwrap();
+
+        let mut iter = heap.iter();
+        heap.put(b"key2", b"value2").unwrap();
+
+        assert_eq!(
+            iter.next().unwrap().unwrap(),
+            HeapTuple::from(b"key1", b"value1")
+        );
+        assert!(iter.next().is_none());
+    }
+
+    #[test]
+    fn test_sync_heap_concurrent_reads_and_writes() {
+        const KEYS: u32 = 50;
+        const ROUNDS: u32 = 20;
+
+        let heap = SyncHeap::new(Heap::new(tempfile().unwrap()).unwrap());
+        let done = AtomicBool::new(false);
+
+        thread::scope(|s| {
+            for writer in 0..2 {
+                let heap = &heap;
+                s.spawn(move || {
+                    for round in 0..ROUNDS {
+                        for key in (writer..KEYS).step_by(2) {
+                            heap.put(&key.to_be_bytes(), &value_for(key, round))
+                                .unwrap();
+                        }
+                    }
+                });
+            }
+
+            for _ in 0..4 {
+                let (heap, done) = (&heap, &done);
+                s.spawn(move || {
+                    while !done.load(Ordering::Relaxed) {
+                        for key in 0..KEYS {
+                            if let Some(value) = heap.get(&key.to_be_bytes()).unwrap() {
+                                let round = u32::from_be_bytes(value[..4].try_into().unwrap());
+                                assert_eq!(value, value_for(key, round));
+                            }
+                        }
+                        for tuple in heap.iter() {
+                            let tuple = tuple.unwrap();
+                            let key = u32::from_be_bytes(tuple.key[..].try_into().unwrap());
+                            let round = u32::from_be_bytes(tuple.value[..4].try_into().unwrap());
+                            assert_eq!(tuple.value, value_for(key, round));
+                        }
+                    }
+                });
+            }
+
+            s.spawn(|| {
+                thread::sleep(std::time::Duration::from_millis(200));
+                done.store(true, Ordering::Relaxed);
+            });
+        });
+
+        for key in 0..KEYS {
+            let got = heap.get(&key.to_be_bytes()).unwrap();
+            assert_eq!(got, Some(value_for(key, ROUNDS - 1)));
+        }
+    }
+}
@@ -0,0 +1,179 @@
+//! User-provided encodings of the values of a Heap.
+//!
+//! A Heap opened with [`HeapOptions::value_transform`] stores the encoded
+//! form of every value it writes, in a tuple with the
+//! [`TRANSFORM_FLAG`](super::TRANSFORM_FLAG), and decodes the values of such
+//! tuples as it reads them. Compaction copies the encoded bytes as they are.
+//!
+//! [`HeapOptions::value_transform`]: crate::HeapOptions::value_transform
+
+use super::{Heap, TRANSFORM_FLAG};
+use crate::Error;
+use std::borrow::Cow;
+use std::fmt;
+
+/// An encoding of values, like a compression or encryption, that a Heap
+/// applies to the values it stores.
+///
+/// Both functions get the key of the tuple along with its value, for
+/// example to derive a nonce from it. Decoding has to return the value that
+/// was encoded.
+pub trait ValueTransform: Send + Sync {
+    /// Returns the form of the value to store.
+    fn encode(&self, key: &[u8], value: &[u8]) -> Result<Vec<u8>, Error>;
+
+    /// Returns the value that was encoded to the stored bytes.
+    fn decode(&self, key: &[u8], stored: &[u8]) -> Result<Vec<u8>, Error>;
+}
+
+impl fmt::Debug for dyn ValueTransform {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ValueTransform")
+    }
+}
+
+impl Heap {
+    /// Returns the form of the value to store, along with the flags to mark
+    /// its tuple with.
+    pub(super) fn encode<'v>(
+        &self,
+        key: &[u8],
+        value: &'v [u8],
+    ) -> Result<(Cow<'v, [u8]>, u16), Error> {
+        match &self.transform {
+            Some(transform) => Ok((Cow::Owned(transform.encode(key, value)?), TRANSFORM_FLAG)),
+            None => Ok((Cow::Borrowed(value), 0)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{DeserializationError, HeapOptions, Index};
+    use std::{fs, path};
+
+    /// XORs every byte of the value with the first byte of the key, and
+    /// appends a byte so that the size limits see the encoded value.
+    struct Xor;
+
+    impl ValueTransform for Xor {
+        fn encode(&self, key: &[u8], value: &[u8]) -> Result<Vec<u8>, Error> {
+            let mut stored: Vec<u8> = value.iter().map(|b| b ^ key[0]).collect();
+            stored.push(key[0]);
+            Ok(stored)
+        }
+
+        fn decode(&self, key: &[u8], stored: &[u8]) -> Result<Vec<u8>, Error> {
+            let (value, check) = stored.split_at(stored.len() - 1);
+            assert_eq!(check, [key[0]]);
+            Ok(value.iter().map(|b| b ^ key[0]).collect())
+        }
+    }
+
+    fn open(path: &path::Path) -> Heap {
+        HeapOptions::new()
+            .value_transform(Box::new(Xor))
+            .open(path.to_path_buf())
+            .unwrap()
+    }
+
+    #[test]
+    fn test_value_transform() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("heap");
+        let mut heap = open(&path);
+        heap.put(b"key1", b"value1").unwrap();
+        heap.put(b"key2", b"value2").unwrap();
+        heap.put(b"key1", b"value3").unwrap();
+        heap.delete(b"key2").unwrap();
+        let mut batch = crate::WriteBatch::new();
+        batch.put(b"key3", b"value4");
+        heap.write_batch(&batch).unwrap();
+
+        // Only the encoded values are stored.
+        let contents = fs::read(&path).unwrap();
+        assert!(!contents.windows(5).any(|w| w == b"value"));
+        assert_eq!(heap.get(b"key1").unwrap(), Some(b"value3".to_vec()));
+        assert_eq!(heap.get(b"key2").unwrap(), None);
+        let mut tuples: Vec<_> = heap.iter().map(|t| t.unwrap()).collect();
+        tuples.sort_by(|a, b| a.key.cmp(&b.key));
+        let values: Vec<_> = tuples.iter().map(|t| &t.value[..]).collect();
+        assert_eq!(values, [b"value3", b"value4"]);
+
+        // Compaction copies the encoded values without encoding them again.
+        heap.compact().unwrap();
+        assert_eq!(heap.get(b"key1").unwrap(), Some(b"value3".to_vec()));
+        assert_eq!(heap.get(b"key3").unwrap(), Some(b"value4".to_vec()));
+
+        // Readers decode with the transform of their Heap.
+        let reader = heap.reader().unwrap();
+        assert_eq!(reader.get(b"key3").unwrap(), Some(b"value4".to_vec()));
+
+        drop(reader);
+        drop(heap);
+        let mut heap = open(&path);
+        assert_eq!(heap.get(b"key1").unwrap(), Some(b"value3".to_vec()));
+    }
+
+    #[test]
+    fn test_value_transform_with_references() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut heap = HeapOptions::new()
+            .value_transform(Box::new(Xor))
+            .deduplicate_values(4)
+            .open(dir.path().join("heap"))
+            .unwrap();
+        // The keys encode the value the same way, so the second put refers
+        // to the encoded value of the first.
+        let value = [b'v'; 100];
+        heap.put(b"key1", &value).unwrap();
+        heap.put(b"key2", &value).unwrap();
+        assert_eq!(heap.get(b"key2").unwrap(), Some(value.to_vec()));
+
+        // Compaction stores the value again in the reference's place, still
+        // encoded.
+        heap.delete(b"key1").unwrap();
+        heap.compact().unwrap();
+        assert_eq!(heap.get(b"key2").unwrap(), Some(value.to_vec()));
+        assert_eq!(heap.stats().unwrap().file_size, 100 + 1 + 4 + 3);
+    }
+
+    #[test]
+    fn test_value_transform_size_limits() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut heap = HeapOptions::new()
+            .value_transform(Box::new(Xor))
+            .max_value_size(10)
+            .open(dir.path().join("heap"))
+            .unwrap();
+        heap.put(b"key", &[1; 9]).unwrap();
+        assert!(matches!(
+            heap.put(b"key", &[1; 10]),
+            Err(Error::Input(crate::InputError::ValueSize(11)))
+        ));
+    }
+
+    #[test]
+    fn test_missing_value_transform() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("heap");
+        let mut heap = open(&path);
+        heap.put(b"key1", b"value1").unwrap();
+        drop(heap);
+
+        let mut heap = Heap::from(path.clone()).unwrap();
+        match heap.get(b"key1") {
+            Err(Error::Data(e)) => {
+                assert!(matches!(e.cause(), DeserializationError::MissingTransform))
+            }
+            other => panic!("expected a data error, got {:?}", other),
+        }
+        // The tuples are intact, so nothing is truncated.
+        assert_eq!(heap.verify().unwrap().first_corrupt_offset, None);
+        drop(heap);
+
+        let mut heap = open(&path);
+        assert_eq!(heap.get(b"key1").unwrap(), Some(b"value1".to_vec()));
+    }
+}
@@ -0,0 +1,101 @@
+//! Accessors for keys and values that are integers.
+//!
+//! Integer keys are stored big-endian, so that they order like the numbers
+//! byte by byte. Integer values are stored little-endian.
+
+use super::Heap;
+use crate::{Error, Index};
+
+impl Heap {
+    /// Stores an integer value under an integer key.
+    pub fn put_u64(&mut self, key: u64, value: u64) -> Result<(), Error> {
+        self.put(&key.to_be_bytes(), &value.to_le_bytes())
+    }
+
+    /// Returns the integer value of an integer key.
+    ///
+    /// Fails with [`Error::ValueLength`] if the stored value doesn't have 8
+    /// bytes.
+    pub fn get_u64(&mut self, key: u64) -> Result<Option<u64>, Error> {
+        self.get_u64_value(&key.to_be_bytes())
+    }
+
+    /// Stores an integer value under a key.
+    pub fn put_u64_value(&mut self, key: &[u8], value: u64) -> Result<(), Error> {
+        self.put(key, &value.to_le_bytes())
+    }
+
+    /// Returns the integer value of a key.
+    ///
+    /// See [`Heap::get_u64`].
+    pub fn get_u64_value(&mut self, key: &[u8]) -> Result<Option<u64>, Error> {
+        self.get_with(key, decode_u64)?.transpose()
+    }
+}
+
+fn decode_u64(value: &[u8]) -> Result<u64, Error> {
+    let bytes = <[u8; 8]>::try_from(value).map_err(|_| Error::ValueLength {
+        expected: 8,
+        actual: value.len(),
+    })?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tempfile::tempfile;
+
+    #[test]
+    fn test_u64_round_trip() {
+        let mut heap = Heap::new(tempfile().unwrap()).unwrap();
+        for n in [0, 1, u64::MAX] {
+            heap.put_u64(n, u64::MAX - n).unwrap();
+            assert_eq!(heap.get_u64(n).unwrap(), Some(u64::MAX - n));
+
+            heap.put_u64_value(b"key", n).unwrap();
+            assert_eq!(heap.get_u64_value(b"key").unwrap(), Some(n));
+        }
+        assert_eq!(heap.get_u64(2).unwrap(), None);
+        assert_eq!(heap.get_u64_value(b"missing").unwrap(), None);
+
+        // The bytes are those of the documented encodings.
+        assert_eq!(
+            heap.get(&1u64.to_be_bytes()).unwrap(),
+            Some((u64::MAX - 1).to_le_bytes().to_vec())
+        );
+        let mut keys: Vec<_> = heap
+            .iter()
+            .map(|tuple| tuple.unwrap().key)
+            .filter(|key| key.len() == 8)
+            .collect();
+        keys.sort();
+        let expected: Vec<_> = [0, 1, u64::MAX]
+            .iter()
+            .map(|n: &u64| n.to_be_bytes().to_vec())
+            .collect();
+        assert_eq!(keys, expected);
+    }
+
+    #[test]
+    fn test_u64_rejects_value_of_other_length() {
+        let mut heap = Heap::new(tempfile().unwrap()).unwrap();
+        heap.put(b"key", b"abc").unwrap();
+        heap.put(&7u64.to_be_bytes(), &[0; 9]).unwrap();
+
+        assert!(matches!(
+            heap.get_u64_value(b"key"),
+            Err(Error::ValueLength {
+                expected: 8,
+                actual: 3
+            })
+        ));
+        assert!(matches!(
+            heap.get_u64(7),
+            Err(Error::ValueLength {
+                expected: 8,
+                actual: 9
+            })
+        ));
+    }
+}
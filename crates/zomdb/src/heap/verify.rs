@@ -0,0 +1,100 @@
+use super::{Heap, Scanner};
+use crate::Error;
+
+/// The findings of [`Heap::verify`].
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct VerifyReport {
+    /// Number of tuples that could be read, counting from the end of the
+    /// file.
+    pub valid_records: u64,
+    /// Offset at which the scan ran into bytes that don't form a tuple, if
+    /// it did. The scan reads from the end of the file towards its start, so
+    /// this is the end of the corrupted region, and the tuples in front of
+    /// it can't be reached.
+    pub first_corrupt_offset: Option<u64>,
+    /// Whether the file doesn't end with a complete tuple, like after a
+    /// write that was cut short, or is shorter than what the Heap wrote.
+    pub truncated_tail: bool,
+}
+
+impl Heap {
+    /// Scans the whole file and reports whether all of its tuples can be
+    /// read.
+    ///
+    /// Corrupted bytes are reported instead of failing the call, which only
+    /// fails if the file can't be read. Unlike other reads, this covers
+    /// bytes written after the Heap was opened by someone else, and it
+    /// doesn't poison the Heap.
+    pub fn verify(&self) -> Result<VerifyReport, Error> {
+        let len = self.stat()?.len();
+        let mut scanner = Scanner::raw();
+        scanner.reset(len);
+
+        let mut report = VerifyReport {
+            truncated_tail: len < self.committed_len(),
+            ..Default::default()
+        };
+        loop {
+            let offset = scanner.cursor;
+            match scanner.next_tuple(self) {
+                Ok(Some(_)) => report.valid_records += 1,
+                Ok(None) => break,
+                Err(Error::Data(_)) => {
+                    report.first_corrupt_offset = Some(offset);
+                    report.truncated_tail |= offset == len;
+                    break;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Index;
+    use std::io::{self, Seek, Write};
+    use tempfile::tempfile;
+
+    #[test]
+    fn test_verify() {
+        let mut heap = Heap::new(tempfile().unwrap()).unwrap();
+        assert_eq!(heap.verify().unwrap(), VerifyReport::default());
+
+        heap.put(b"key1", b"value1").unwrap();
+        heap.put(b"key2", b"value2").unwrap();
+        heap.delete(b"key1").unwrap();
+        let report = heap.verify().unwrap();
+        assert_eq!(report.valid_records, 3);
+        assert_eq!(report.first_corrupt_offset, None);
+        assert!(!report.truncated_tail);
+    }
+
+    #[test]
+    fn test_verify_corruption() {
+        let mut heap = Heap::new(tempfile().unwrap()).unwrap();
+        heap.put(b"key1", b"value1").unwrap();
+        heap.put(b"key2", b"value2").unwrap();
+        let len = heap.file.metadata().unwrap().len();
+
+        // Bytes that don't form a tuple at the end of the file.
+        (&heap.file).write_all(b"value").unwrap();
+        let report = heap.verify().unwrap();
+        assert_eq!(report.valid_records, 0);
+        assert_eq!(report.first_corrupt_offset, Some(len + 5));
+        assert!(report.truncated_tail);
+
+        // A key size in the trailer of the first tuple that reaches past the
+        // start of the file.
+        heap.file.set_len(len).unwrap();
+        (&heap.file).seek(io::SeekFrom::Start(12)).unwrap();
+        (&heap.file).write_all(&[0x40]).unwrap();
+        let report = heap.verify().unwrap();
+        assert_eq!(report.valid_records, 1);
+        assert_eq!(report.first_corrupt_offset, Some(len / 2));
+        assert!(!report.truncated_tail);
+    }
+}
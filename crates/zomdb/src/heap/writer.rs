@@ -0,0 +1,267 @@
+use super::{validate, Heap, HeapReader};
+use crate::{Error, Index};
+use std::sync::mpsc;
+use std::{io, thread};
+
+impl Heap {
+    /// Moves the Heap onto a background thread that performs all writes.
+    ///
+    /// Writes are sent to the thread through a bounded queue, so that any
+    /// number of threads can write without locking. Once the queue is full,
+    /// writers block until the thread catches up. Writes sent from the same
+    /// thread are applied in the order they were sent.
+    ///
+    /// The returned ReaderFactory creates read handles that see every write
+    /// once it was acknowledged.
+    pub fn into_writer_handle(self) -> Result<(WriterHandle, ReaderFactory), Error> {
+        let reader = self.reader()?;
+        let (commands, queue) = mpsc::sync_channel(WriterHandle::QUEUE_CAPACITY);
+
+        // Spawning the thread isn't an operation on the Heap file, so the
+        // error carries no IoContext.
+        thread::Builder::new()
+            .name("zomdb-writer".to_string())
+            .spawn(move || run(self, queue))
+            .map_err(Error::IO)?;
+
+        Ok((WriterHandle { commands }, ReaderFactory { reader }))
+    }
+}
+
+enum Command {
+    Put {
+        key: Vec<u8>,
+        value: Vec<u8>,
+        ack: mpsc::SyncSender<Result<(), Error>>,
+    },
+    Delete {
+        key: Vec<u8>,
+        ack: mpsc::SyncSender<Result<bool, Error>>,
+    },
+    Shutdown {
+        ack: mpsc::SyncSender<Result<Heap, Error>>,
+    },
+}
+
+/// Applies commands to the Heap until the last WriterHandle is dropped or
+/// one of them requests a shutdown.
+fn run(mut heap: Heap, queue: mpsc::Receiver<Command>) {
+    while let Ok(command) = queue.recv() {
+        // Acks are allowed to be dropped by senders that don't wait for
+        // them, so failing to send one is fine.
+        match command {
+            Command::Put { key, value, ack } => {
+                let _ = ack.send(heap.put(&key, &value));
+            }
+            Command::Delete { key, ack } => {
+                let _ = ack.send(heap.delete(&key));
+            }
+            Command::Shutdown { ack } => {
+                // Commands sent before the shutdown have been applied
+                // already. Closing the queue before acknowledging makes
+                // sure that later sends fail right away.
+                let result = heap.sync().map(|_| heap);
+                drop(queue);
+                let _ = ack.send(result);
+                return;
+            }
+        }
+    }
+
+    let _ = heap.sync();
+}
+
+/// Sends writes to a Heap owned by a background thread.
+///
+/// Use [`Heap::into_writer_handle`] to create an instance of this struct.
+/// The handle can be cloned to write from multiple threads. The background
+/// thread stops once all handles are dropped or [`WriterHandle::shutdown`]
+/// is called.
+#[derive(Clone)]
+pub struct WriterHandle {
+    commands: mpsc::SyncSender<Command>,
+}
+
+impl WriterHandle {
+    /// The number of writes that can be queued before writers block.
+    const QUEUE_CAPACITY: usize = 1024;
+
+    /// Queues a key-value pair to be written.
+    ///
+    /// Invalid input is rejected right away. Otherwise the returned Ack
+    /// can be used to wait for the write to complete, or be dropped if the
+    /// caller doesn't care.
+    pub fn put(&self, key: &[u8], value: &[u8]) -> Result<Ack<()>, Error> {
+        validate(key, value)?;
+
+        let (ack, reply) = mpsc::sync_channel(1);
+        self.send(Command::Put {
+            key: key.to_vec(),
+            value: value.to_vec(),
+            ack,
+        })?;
+
+        Ok(Ack { reply })
+    }
+
+    /// Queues the removal of a key.
+    ///
+    /// The Ack resolves to whether the key had a value.
+    pub fn delete(&self, key: &[u8]) -> Result<Ack<bool>, Error> {
+        let (ack, reply) = mpsc::sync_channel(1);
+        self.send(Command::Delete {
+            key: key.to_vec(),
+            ack,
+        })?;
+
+        Ok(Ack { reply })
+    }
+
+    /// Stops the background thread and returns the Heap.
+    ///
+    /// All writes queued before this call are applied and synced to disk
+    /// first. Writes sent through other handles afterwards fail.
+    pub fn shutdown(self) -> Result<Heap, Error> {
+        let (ack, reply) = mpsc::sync_channel(1);
+        self.send(Command::Shutdown { ack })?;
+
+        Ack { reply }.wait()
+    }
+
+    fn send(&self, command: Command) -> Result<(), Error> {
+        self.commands.send(command).map_err(|_| stopped())
+    }
+}
+
+/// A pending write of a WriterHandle.
+#[must_use = "an Ack does nothing unless waited on"]
+pub struct Ack<T> {
+    reply: mpsc::Receiver<Result<T, Error>>,
+}
+
+impl<T> Ack<T> {
+    /// Blocks until the write has been applied and returns its result.
+    pub fn wait(self) -> Result<T, Error> {
+        self.reply.recv().map_err(|_| stopped())?
+    }
+}
+
+/// Creates read handles for a Heap owned by a WriterHandle.
+///
+/// Use [`Heap::into_writer_handle`] to create an instance of this struct.
+pub struct ReaderFactory {
+    reader: HeapReader,
+}
+
+impl ReaderFactory {
+    /// Creates a read handle that sees all acknowledged writes.
+    ///
+    /// Use [`HeapReader::snapshot`] for a stable view while the writer
+    /// keeps appending.
+    pub fn reader(&self) -> Result<HeapReader, Error> {
+        self.reader.try_clone()
+    }
+}
+
+/// The error returned for writes sent after the background thread stopped.
+fn stopped() -> Error {
+    Error::IO(io::Error::new(
+        io::ErrorKind::BrokenPipe,
+        "the heap writer has been shut down",
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::heap::Scanner;
+    use tempfile::tempfile;
+
+    #[test]
+    fn test_writer_handle_preserves_order_per_producer() {
+        const PRODUCERS: u32 = 4;
+        const PUTS: u32 = 200;
+
+        let heap = Heap::new(tempfile().unwrap()).unwrap();
+        let (writer, readers) = heap.into_writer_handle().unwrap();
+
+        thread::scope(|s| {
+            for producer in 0..PRODUCERS {
+                let writer = writer.clone();
+                s.spawn(move || {
+                    let acks: Vec<_> = (0..PUTS)
+                        .map(|n| {
+                            let key = format!("{}-{}", producer, n);
+                            writer.put(key.as_bytes(), &n.to_be_bytes()).unwrap()
+                        })
+                        .collect();
+                    for ack in acks {
+                        ack.wait().unwrap();
+                    }
+                });
+            }
+        });
+
+        let reader = readers.reader().unwrap();
+        assert_eq!(
+            reader.get(b"0-0").unwrap(),
+            Some(0u32.to_be_bytes().to_vec())
+        );
+
+        let mut heap = writer.shutdown().unwrap();
+        for producer in 0..PRODUCERS {
+            for n in 0..PUTS {
+                let key = format!("{}-{}", producer, n);
+                assert_eq!(
+                    heap.get(key.as_bytes()).unwrap(),
+                    Some(n.to_be_bytes().to_vec())
+                );
+            }
+        }
+
+        // The scanner yields tuples from last to first, so every producer's
+        // sequence numbers have to be strictly decreasing.
+        let mut last_seen = vec![u32::MAX; PRODUCERS as usize];
+        let mut scanner = Scanner::new();
+        scanner.reset(heap.committed_len());
+        while let Some(tuple) = scanner.next_tuple(&heap).unwrap() {
+            let key = std::str::from_utf8(tuple.key).unwrap();
+            let producer: usize = key.split('-').next().unwrap().parse().unwrap();
+            let n = u32::from_be_bytes(tuple.value.try_into().unwrap());
+            assert!(n < last_seen[producer]);
+            last_seen[producer] = n;
+        }
+        assert_eq!(last_seen, vec![0; PRODUCERS as usize]);
+    }
+
+    #[test]
+    fn test_writer_handle_shutdown_drains_queue() {
+        let heap = Heap::new(tempfile().unwrap()).unwrap();
+        let (writer, _readers) = heap.into_writer_handle().unwrap();
+        let other = writer.clone();
+
+        for n in 0u32..100 {
+            // Acks are dropped without waiting for them.
+            let _ = writer.put(b"key", &n.to_be_bytes()).unwrap();
+        }
+        let deleted = writer.delete(b"missing").unwrap();
+
+        let mut heap = writer.shutdown().unwrap();
+        assert!(!deleted.wait().unwrap());
+        assert_eq!(
+            heap.get(b"key").unwrap(),
+            Some(99u32.to_be_bytes().to_vec())
+        );
+        assert_eq!(heap.perf_counters().fsyncs, 1);
+
+        assert!(matches!(other.put(b"key", b"value"), Err(Error::IO(_))));
+    }
+
+    #[test]
+    fn test_writer_handle_rejects_invalid_input() {
+        let heap = Heap::new(tempfile().unwrap()).unwrap();
+        let (writer, _readers) = heap.into_writer_handle().unwrap();
+
+        assert!(matches!(writer.put(b"", b"value"), Err(Error::Input(_))));
+    }
+}
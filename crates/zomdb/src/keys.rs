@@ -0,0 +1,360 @@
+//! Encoding of composite keys whose byte order matches the order of their
+//! parts.
+//!
+//! A [`KeyBuilder`] appends segments to a key, and a [`KeyParser`] reads
+//! them back in the same order. Comparing two keys byte by byte, as Heaps
+//! and range scans do, gives the same result as comparing their segments one
+//! after another.
+//!
+//! Unsigned integers are written big-endian. Signed integers are written
+//! the same way with their sign bit flipped, so that negative numbers order
+//! first. Byte and string segments escape each 0x00 byte as 0x00 0xff and
+//! end with 0x00 0x01, so that no segment can run into the next one and a
+//! segment orders before all segments it is a prefix of.
+use std::{error, fmt};
+
+const ESCAPE: u8 = 0x00;
+const ESCAPED_NUL: u8 = 0xff;
+const TERMINATOR: u8 = 0x01;
+
+/// Builds a key out of segments, see the [module docs](self).
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct KeyBuilder {
+    key: Vec<u8>,
+}
+
+/// Writes an integer type as a fixed number of big-endian bytes.
+macro_rules! write_int {
+    ($name:ident, $ty:ty, $doc:literal) => {
+        #[doc = $doc]
+        pub fn $name(&mut self, n: $ty) -> &mut Self {
+            self.key.extend_from_slice(&n.to_be_bytes());
+            self
+        }
+    };
+    ($name:ident, $ty:ty, $uty:ty, $doc:literal) => {
+        #[doc = $doc]
+        pub fn $name(&mut self, n: $ty) -> &mut Self {
+            let flipped = (n as $uty) ^ (1 << (<$uty>::BITS - 1));
+            self.key.extend_from_slice(&flipped.to_be_bytes());
+            self
+        }
+    };
+}
+
+impl KeyBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    write_int!(u8, u8, "Appends a byte.");
+    write_int!(u16, u16, "Appends an unsigned 16-bit integer.");
+    write_int!(u32, u32, "Appends an unsigned 32-bit integer.");
+    write_int!(u64, u64, "Appends an unsigned 64-bit integer.");
+    write_int!(i8, i8, u8, "Appends a signed byte.");
+    write_int!(i16, i16, u16, "Appends a signed 16-bit integer.");
+    write_int!(i32, i32, u32, "Appends a signed 32-bit integer.");
+    write_int!(i64, i64, u64, "Appends a signed 64-bit integer.");
+
+    /// Appends a segment of bytes of any length.
+    pub fn bytes(&mut self, bytes: &[u8]) -> &mut Self {
+        for &b in bytes {
+            self.key.push(b);
+            if b == ESCAPE {
+                self.key.push(ESCAPED_NUL);
+            }
+        }
+        self.key.extend_from_slice(&[ESCAPE, TERMINATOR]);
+        self
+    }
+
+    /// Appends a string segment, which orders like its UTF-8 bytes.
+    pub fn str(&mut self, s: &str) -> &mut Self {
+        self.bytes(s.as_bytes())
+    }
+
+    /// Returns the key built so far.
+    pub fn build(&self) -> Vec<u8> {
+        self.key.clone()
+    }
+}
+
+/// Reads the segments of a key built by a [`KeyBuilder`].
+///
+/// The segments have to be read with the same types they were appended
+/// with, since keys don't record them.
+#[derive(Debug, Clone)]
+pub struct KeyParser<'k> {
+    key: &'k [u8],
+    offset: usize,
+}
+
+/// Reads an integer type from a fixed number of big-endian bytes.
+macro_rules! read_int {
+    ($name:ident, $ty:ty, $doc:literal) => {
+        #[doc = $doc]
+        pub fn $name(&mut self) -> Result<$ty, KeyError> {
+            let bytes = self.take(std::mem::size_of::<$ty>())?;
+            Ok(<$ty>::from_be_bytes(bytes.try_into().unwrap()))
+        }
+    };
+    ($name:ident, $ty:ty, $uty:ty, $doc:literal) => {
+        #[doc = $doc]
+        pub fn $name(&mut self) -> Result<$ty, KeyError> {
+            let bytes = self.take(std::mem::size_of::<$ty>())?;
+            let flipped = <$uty>::from_be_bytes(bytes.try_into().unwrap());
+            Ok((flipped ^ (1 << (<$uty>::BITS - 1))) as $ty)
+        }
+    };
+}
+
+impl<'k> KeyParser<'k> {
+    pub fn new(key: &'k [u8]) -> Self {
+        Self { key, offset: 0 }
+    }
+
+    read_int!(u8, u8, "Reads a byte.");
+    read_int!(u16, u16, "Reads an unsigned 16-bit integer.");
+    read_int!(u32, u32, "Reads an unsigned 32-bit integer.");
+    read_int!(u64, u64, "Reads an unsigned 64-bit integer.");
+    read_int!(i8, i8, u8, "Reads a signed byte.");
+    read_int!(i16, i16, u16, "Reads a signed 16-bit integer.");
+    read_int!(i32, i32, u32, "Reads a signed 32-bit integer.");
+    read_int!(i64, i64, u64, "Reads a signed 64-bit integer.");
+
+    /// Reads a segment of bytes.
+    pub fn bytes(&mut self) -> Result<Vec<u8>, KeyError> {
+        let mut bytes = Vec::new();
+        let mut rest = self.key[self.offset..].iter();
+        while let Some(&b) = rest.next() {
+            if b != ESCAPE {
+                bytes.push(b);
+                continue;
+            }
+            let escape = self.key.len() - rest.len() - 1;
+            match rest.next() {
+                Some(&ESCAPED_NUL) => bytes.push(ESCAPE),
+                Some(&TERMINATOR) => {
+                    self.offset = self.key.len() - rest.len();
+                    return Ok(bytes);
+                }
+                _ => return Err(KeyError::InvalidEscape { offset: escape }),
+            }
+        }
+        Err(KeyError::UnterminatedSegment {
+            offset: self.offset,
+        })
+    }
+
+    /// Reads a string segment.
+    pub fn str(&mut self) -> Result<String, KeyError> {
+        let offset = self.offset;
+        String::from_utf8(self.bytes()?).map_err(|_| KeyError::Utf8 { offset })
+    }
+
+    /// Returns whether all segments have been read.
+    pub fn is_empty(&self) -> bool {
+        self.offset == self.key.len()
+    }
+
+    /// Checks that all segments have been read.
+    pub fn finish(&self) -> Result<(), KeyError> {
+        match self.key.len() - self.offset {
+            0 => Ok(()),
+            remaining => Err(KeyError::TrailingBytes { remaining }),
+        }
+    }
+
+    fn take(&mut self, n: usize) -> Result<&'k [u8], KeyError> {
+        let available = self.key.len() - self.offset;
+        if available < n {
+            return Err(KeyError::TooShort {
+                needed: n,
+                available,
+            });
+        }
+        let bytes = &self.key[self.offset..self.offset + n];
+        self.offset += n;
+        Ok(bytes)
+    }
+}
+
+/// Describes why a key can't be read as the requested segments.
+///
+/// Offsets count from the start of the key.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KeyError {
+    /// Fewer bytes are left than the integer needs.
+    TooShort { needed: usize, available: usize },
+
+    /// The bytes segment starting at the offset doesn't end.
+    UnterminatedSegment { offset: usize },
+
+    /// The 0x00 byte at the offset is followed by neither 0xff nor 0x01.
+    InvalidEscape { offset: usize },
+
+    /// The string segment starting at the offset isn't valid UTF-8.
+    Utf8 { offset: usize },
+
+    /// Bytes are left after the last segment.
+    TrailingBytes { remaining: usize },
+}
+
+impl error::Error for KeyError {}
+
+impl fmt::Display for KeyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KeyError::TooShort { needed, available } => write!(
+                f,
+                "key too short: {} bytes needed, {} available",
+                needed, available
+            ),
+            KeyError::UnterminatedSegment { offset } => {
+                write!(f, "segment at byte {} doesn't end", offset)
+            }
+            KeyError::InvalidEscape { offset } => {
+                write!(f, "invalid escape at byte {}", offset)
+            }
+            KeyError::Utf8 { offset } => {
+                write!(f, "segment at byte {} isn't valid UTF-8", offset)
+            }
+            KeyError::TrailingBytes { remaining } => {
+                write!(f, "{} bytes left after the last segment", remaining)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+    struct Parts {
+        tenant: u64,
+        offset: i32,
+        name: Vec<u8>,
+        tag: String,
+        small: i8,
+    }
+
+    impl Parts {
+        fn random(rng: &mut fastrand::Rng) -> Self {
+            // Few distinct values, so that tuples often share leading parts.
+            let name_len = rng.usize(0..4);
+            Self {
+                tenant: [0, 1, u64::MAX][rng.usize(0..3)],
+                offset: [i32::MIN, -1, 0, 1, i32::MAX][rng.usize(0..5)],
+                name: (0..name_len)
+                    .map(|_| [0x00, 0x01, 0xff, b'a'][rng.usize(0..4)])
+                    .collect(),
+                tag: ["", "a", "\0", "é"][rng.usize(0..4)].to_string(),
+                small: rng.i8(..),
+            }
+        }
+
+        fn key(&self) -> Vec<u8> {
+            KeyBuilder::new()
+                .u64(self.tenant)
+                .i32(self.offset)
+                .bytes(&self.name)
+                .str(&self.tag)
+                .i8(self.small)
+                .build()
+        }
+
+        fn parse(key: &[u8]) -> Result<Self, KeyError> {
+            let mut parser = KeyParser::new(key);
+            let parts = Self {
+                tenant: parser.u64()?,
+                offset: parser.i32()?,
+                name: parser.bytes()?,
+                tag: parser.str()?,
+                small: parser.i8()?,
+            };
+            parser.finish()?;
+            Ok(parts)
+        }
+    }
+
+    #[test]
+    fn test_keys_round_trip() {
+        let mut rng = fastrand::Rng::with_seed(7);
+        for _ in 0..1000 {
+            let parts = Parts::random(&mut rng);
+            assert_eq!(Parts::parse(&parts.key()).unwrap(), parts);
+        }
+
+        let key = KeyBuilder::new()
+            .u8(u8::MAX)
+            .u16(0x1234)
+            .u32(u32::MAX)
+            .i16(i16::MIN)
+            .i64(-2)
+            .build();
+        let mut parser = KeyParser::new(&key);
+        assert_eq!(parser.u8().unwrap(), u8::MAX);
+        assert_eq!(parser.u16().unwrap(), 0x1234);
+        assert_eq!(parser.u32().unwrap(), u32::MAX);
+        assert_eq!(parser.i16().unwrap(), i16::MIN);
+        assert_eq!(parser.i64().unwrap(), -2);
+        assert!(parser.is_empty());
+    }
+
+    #[test]
+    fn test_keys_preserve_order() {
+        let mut rng = fastrand::Rng::with_seed(7);
+        let parts: Vec<_> = (0..200).map(|_| Parts::random(&mut rng)).collect();
+        for a in &parts {
+            for b in &parts {
+                assert_eq!(a.cmp(b), a.key().cmp(&b.key()), "{:?} {:?}", a, b);
+            }
+        }
+    }
+
+    #[test]
+    fn test_keys_segments_cant_be_forged() {
+        // Without escaping, both would be "a" followed by "\0\x01b".
+        let a = KeyBuilder::new().bytes(b"a\0\x01b").bytes(b"").build();
+        let b = KeyBuilder::new().bytes(b"a").bytes(b"b").build();
+        assert_ne!(a, b);
+
+        let mut parser = KeyParser::new(&a);
+        assert_eq!(parser.bytes().unwrap(), b"a\0\x01b");
+        assert_eq!(parser.bytes().unwrap(), b"");
+        assert!(parser.is_empty());
+    }
+
+    #[test]
+    fn test_keys_parse_errors() {
+        let mut parser = KeyParser::new(&[0, 1, 2]);
+        assert_eq!(
+            parser.u32(),
+            Err(KeyError::TooShort {
+                needed: 4,
+                available: 3
+            })
+        );
+        assert_eq!(
+            KeyParser::new(b"ab").bytes(),
+            Err(KeyError::UnterminatedSegment { offset: 0 })
+        );
+        assert_eq!(
+            KeyParser::new(b"a\0\x02").bytes(),
+            Err(KeyError::InvalidEscape { offset: 1 })
+        );
+        assert_eq!(
+            KeyParser::new(b"\xff\0\x01").str(),
+            Err(KeyError::Utf8 { offset: 0 })
+        );
+
+        let key = KeyBuilder::new().u16(1).u16(2).build();
+        let mut parser = KeyParser::new(&key);
+        parser.u16().unwrap();
+        assert_eq!(
+            parser.finish(),
+            Err(KeyError::TrailingBytes { remaining: 2 })
+        );
+    }
+}
@@ -1,12 +1,36 @@
+//! An append-only key-value store, whose Heaps keep tuples in a single file
+//! each.
+//!
+//! The library doesn't panic on the input it is given or the bytes it reads
+//! from disk, so it can be embedded in processes that must not abort.
+//! Invalid keys and values are rejected with [`Error::Input`], and corrupted
+//! files surface as [`Error::Data`].
 use std::{
     error, fmt,
     io::{self},
-    str,
+    path,
+    sync::Arc,
+    time::Duration,
 };
 
+mod database;
+pub mod debug;
 mod heap;
+pub mod keys;
+mod perf;
+pub mod repl;
 
-pub use heap::{Heap, HeapTuple, Iter};
+pub use database::Database;
+#[cfg(feature = "testing")]
+pub use heap::FailingStorage;
+pub use heap::{
+    Ack, CompactOptions, CompactionProgress, CompactionReport, CsvOptions, DiffOptions, DiffReport,
+    GlobIter, Heap, HeapObserver, HeapOptions, HeapReader, HeapStats, HeapTuple, IntoIter, Iter,
+    Op, PageToken, ReaderFactory, ReaderIter, Record, Records, Savepoint, SecondaryIndex,
+    SizeHistogram, Snapshot, SyncHeap, SyncIter, ValueTransform, VerifyReport, WriteBatch,
+    WriterHandle, SIZE_BUCKETS,
+};
+pub use perf::PerfCounters;
 
 /// The maximum byte size of keys.
 const MAX_KEY_SIZE: usize = 256;
@@ -17,33 +41,330 @@ const MAX_VALUE_SIZE: usize = 1024;
 pub trait Index {
     fn put(&mut self, key: &[u8], value: &[u8]) -> Result<(), Error>;
     fn get(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>, Error>;
+
+    /// Removes the key, returning whether it had a value.
+    fn delete(&mut self, key: &[u8]) -> Result<bool, Error>;
 }
 
 #[derive(Debug)]
+#[non_exhaustive]
 pub enum Error {
     Input(InputError),
+
+    /// Indicates that an operation on a file failed.
+    ///
+    /// Errors of operations on the Heap file carry an [`IoContext`] that
+    /// names the operation and the file, see [`Error::io_context`].
     IO(io::Error),
 
+    /// Indicates that another writer holds the lock on the heap file.
+    Locked,
+
+    /// Indicates that another writer still held the lock on the heap file
+    /// after waiting for it, see
+    /// [`HeapOptions::lock_wait`](crate::HeapOptions::lock_wait).
+    LockTimeout {
+        waited: Duration,
+        /// The id of the process that holds the lock, if the platform tells.
+        holder: Option<u32>,
+    },
+
     /// Indicates that the data on disk was corrupted.
-    Data(DeserializationError),
+    Data(DataError),
+
+    /// Indicates that the heap file was changed by someone else while it
+    /// was open.
+    ExternallyModified(ExternalModification),
+
+    /// Indicates that the heap refuses writes after an earlier error left
+    /// it in an unknown state. See [`Heap::verify_and_clear`].
+    Poisoned {
+        cause: Arc<Error>,
+    },
+
+    /// Indicates that a pattern to match keys against is invalid.
+    Pattern(PatternError),
+
+    /// Indicates that a stored value can't be read as the requested type,
+    /// because it doesn't have the type's length.
+    ValueLength {
+        expected: usize,
+        actual: usize,
+    },
+
+    /// Indicates that a Heap can't be rolled back to a savepoint.
+    Savepoint(SavepointError),
+
+    /// Indicates that an operation was cancelled through its options before
+    /// it completed, like a compaction.
+    Cancelled,
 }
 
-impl error::Error for Error {}
+impl Error {
+    /// Wraps an error of an operation on a file with the operation and the
+    /// path of the file, if known. The error keeps its kind.
+    pub(crate) fn io(operation: Operation, path: Option<&path::Path>, source: io::Error) -> Self {
+        let kind = source.kind();
+        Error::IO(io::Error::new(
+            kind,
+            IoContext {
+                operation,
+                path: path.map(path::Path::to_path_buf),
+                source,
+            },
+        ))
+    }
+
+    /// Returns the stable numeric code of the kind of error.
+    ///
+    /// The values are listed in [`codes`] and never change, so they can be
+    /// stored or passed across language boundaries.
+    pub fn code(&self) -> u16 {
+        match self {
+            Error::Input(InputError::EmptyKey) => codes::EMPTY_KEY,
+            Error::Input(InputError::KeySize(_)) => codes::KEY_SIZE,
+            Error::Input(InputError::ValueSize(_)) => codes::VALUE_SIZE,
+            Error::IO(_) => codes::IO,
+            Error::Locked | Error::LockTimeout { .. } => codes::LOCKED,
+            Error::Data(_) => codes::DATA,
+            Error::ExternallyModified(_) => codes::EXTERNALLY_MODIFIED,
+            Error::Poisoned { .. } => codes::POISONED,
+            Error::Pattern(_) => codes::PATTERN,
+            Error::ValueLength { .. } => codes::VALUE_LENGTH,
+            Error::Savepoint(_) => codes::SAVEPOINT,
+            Error::Cancelled => codes::CANCELLED,
+        }
+    }
+
+    /// Returns the operation and file an IO error occurred on, if known.
+    pub fn io_context(&self) -> Option<&IoContext> {
+        match self {
+            Error::IO(e) => e.get_ref()?.downcast_ref(),
+            _ => None,
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::Input(e) => Some(e),
+            Error::IO(e) => Some(e),
+            Error::Locked => None,
+            Error::LockTimeout { .. } => None,
+            Error::Data(e) => Some(e),
+            Error::ExternallyModified(e) => Some(e),
+            Error::Poisoned { cause } => Some(cause.as_ref()),
+            Error::Pattern(e) => Some(e),
+            Error::ValueLength { .. } => None,
+            Error::Savepoint(e) => Some(e),
+            Error::Cancelled => None,
+        }
+    }
+}
 
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Error::Input(e) => write!(f, "Input error: {}", e),
             Error::IO(e) => write!(f, "IO error: {}", e),
+            Error::Locked => write!(f, "Heap is locked by another writer"),
+            Error::LockTimeout { waited, holder } => {
+                write!(f, "Heap is still locked by another writer")?;
+                if let Some(pid) = holder {
+                    write!(f, " (process {})", pid)?;
+                }
+                write!(f, " after waiting for {:?}", waited)
+            }
             Error::Data(e) => write!(f, "Data error: {}", e),
+            Error::ExternallyModified(e) => write!(f, "Heap file modified externally: {}", e),
+            Error::Poisoned { cause } => write!(f, "Heap poisoned by an earlier error: {}", cause),
+            Error::Pattern(e) => write!(f, "Invalid pattern: {}", e),
+            Error::ValueLength { expected, actual } => write!(
+                f,
+                "Value has {} bytes, but the requested type needs {}",
+                actual, expected
+            ),
+            Error::Savepoint(e) => write!(f, "Savepoint error: {}", e),
+            Error::Cancelled => write!(f, "Operation was cancelled"),
+        }
+    }
+}
+
+/// The stable numeric codes returned by [`Error::code`].
+///
+/// Codes from 10 describe failures of the storage, from 30 invalid input,
+/// and from 50 problems with the data on disk. Gaps are left for codes of
+/// the FFI layer, which uses the same values.
+pub mod codes {
+    /// [`Error::IO`](crate::Error::IO).
+    pub const IO: u16 = 10;
+    /// [`Error::Locked`](crate::Error::Locked) and
+    /// [`Error::LockTimeout`](crate::Error::LockTimeout).
+    pub const LOCKED: u16 = 11;
+    /// [`Error::Poisoned`](crate::Error::Poisoned).
+    pub const POISONED: u16 = 12;
+    /// [`Error::Cancelled`](crate::Error::Cancelled).
+    pub const CANCELLED: u16 = 16;
+    /// [`InputError::KeySize`](crate::InputError::KeySize).
+    pub const KEY_SIZE: u16 = 31;
+    /// [`InputError::ValueSize`](crate::InputError::ValueSize).
+    pub const VALUE_SIZE: u16 = 32;
+    /// [`InputError::EmptyKey`](crate::InputError::EmptyKey).
+    pub const EMPTY_KEY: u16 = 37;
+    /// [`Error::Pattern`](crate::Error::Pattern).
+    pub const PATTERN: u16 = 38;
+    /// [`Error::Savepoint`](crate::Error::Savepoint).
+    pub const SAVEPOINT: u16 = 39;
+    /// [`Error::Data`](crate::Error::Data).
+    pub const DATA: u16 = 50;
+    /// [`Error::ExternallyModified`](crate::Error::ExternallyModified).
+    pub const EXTERNALLY_MODIFIED: u16 = 51;
+    /// [`Error::ValueLength`](crate::Error::ValueLength).
+    pub const VALUE_LENGTH: u16 = 52;
+}
+
+/// Describes why a pattern to match keys against is invalid.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PatternError {
+    position: usize,
+    message: &'static str,
+}
+
+impl PatternError {
+    pub(crate) fn new(position: usize, message: &'static str) -> Self {
+        Self { position, message }
+    }
+
+    /// Returns the offset of the invalid byte in the pattern.
+    pub fn position(&self) -> usize {
+        self.position
+    }
+}
+
+impl error::Error for PatternError {}
+
+impl fmt::Display for PatternError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} at byte {}", self.message, self.position)
+    }
+}
+
+/// Describes why a Heap can't be rolled back to a savepoint.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SavepointError {
+    /// No savepoint has the name.
+    NotFound(String),
+    /// The Heap was compacted since the savepoint with the name was created.
+    Compacted(String),
+    /// The file of the Heap is shorter than the offset of the savepoint with
+    /// the name, for example because it was truncated by another process.
+    BeyondEnd(String),
+}
+
+impl error::Error for SavepointError {}
+
+impl fmt::Display for SavepointError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SavepointError::NotFound(name) => write!(f, "no savepoint named {:?}", name),
+            SavepointError::Compacted(name) => {
+                write!(f, "heap was compacted since savepoint {:?}", name)
+            }
+            SavepointError::BeyondEnd(name) => {
+                write!(f, "heap ends before savepoint {:?}", name)
+            }
+        }
+    }
+}
+
+/// An operation on a file that can fail with [`Error::IO`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Operation {
+    Open,
+    Read,
+    Write,
+    Seek,
+    Sync,
+    Truncate,
+    Allocate,
+    Rename,
+    /// Reading the metadata of a file, like its length.
+    Stat,
+    /// Taking or releasing the lock on a file.
+    Lock,
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Operation::Open => write!(f, "open"),
+            Operation::Read => write!(f, "read"),
+            Operation::Write => write!(f, "write"),
+            Operation::Seek => write!(f, "seek"),
+            Operation::Sync => write!(f, "sync"),
+            Operation::Truncate => write!(f, "truncate"),
+            Operation::Allocate => write!(f, "allocate"),
+            Operation::Rename => write!(f, "rename"),
+            Operation::Stat => write!(f, "stat"),
+            Operation::Lock => write!(f, "lock"),
+        }
+    }
+}
+
+/// The operation and file that an IO error occurred on.
+///
+/// It is stored as the inner error of the io::Error held by [`Error::IO`].
+#[derive(Debug)]
+pub struct IoContext {
+    operation: Operation,
+    path: Option<path::PathBuf>,
+    source: io::Error,
+}
+
+impl IoContext {
+    pub fn operation(&self) -> Operation {
+        self.operation
+    }
+
+    /// Returns the path of the file, unless the Heap wasn't opened from one.
+    pub fn path(&self) -> Option<&path::Path> {
+        self.path.as_deref()
+    }
+}
+
+impl error::Error for IoContext {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        Some(&self.source)
+    }
+}
+
+impl fmt::Display for IoContext {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.path {
+            Some(path) => write!(
+                f,
+                "{} of {} failed: {}",
+                self.operation,
+                path.display(),
+                self.source
+            ),
+            None => write!(f, "{} failed: {}", self.operation, self.source),
         }
     }
 }
 
 #[derive(Debug)]
+#[non_exhaustive]
 pub enum InputError {
-    Utf8(str::Utf8Error),
+    /// The key is empty. Keys need at least one byte.
+    EmptyKey,
+
+    /// The key is longer than the maximum.
     KeySize(usize),
+
+    /// The value is longer than the maximum.
     ValueSize(usize),
 }
 
@@ -52,22 +373,163 @@ impl error::Error for InputError {}
 impl fmt::Display for InputError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            InputError::Utf8(e) => write!(f, "UTF-8 error: {}", e),
+            InputError::EmptyKey => write!(f, "Key is empty"),
             InputError::KeySize(size) => {
-                write!(f, "Key size not in [1,{}]: {}", MAX_KEY_SIZE, size)
+                write!(f, "Key size too big: {} > {}", size, MAX_KEY_SIZE)
             }
             InputError::ValueSize(size) => {
-                write!(f, "Value size not in [1,{}]: {}", MAX_VALUE_SIZE, size)
+                write!(f, "Value size too big: {} > {}", size, MAX_VALUE_SIZE)
             }
         }
     }
 }
 
-#[derive(Debug)]
+/// Describes a tuple on disk that could not be deserialized.
+#[derive(Debug, Clone)]
+pub struct DataError {
+    cause: DeserializationError,
+    offset: u64,
+    path: Option<path::PathBuf>,
+    // The sizes decoded from the trailer, unless it was incomplete.
+    sizes: Option<(usize, usize)>,
+    excerpt: Vec<u8>,
+}
+
+impl DataError {
+    /// The number of bytes in front of the offset kept in the excerpt.
+    pub(crate) const EXCERPT_SIZE: usize = 32;
+
+    pub(crate) fn new(
+        cause: DeserializationError,
+        offset: u64,
+        path: Option<&path::Path>,
+        sizes: Option<(usize, usize)>,
+        excerpt: Vec<u8>,
+    ) -> Self {
+        Self {
+            cause,
+            offset,
+            path: path.map(path::Path::to_path_buf),
+            sizes,
+            excerpt,
+        }
+    }
+
+    /// Returns why the tuple could not be deserialized.
+    pub fn cause(&self) -> &DeserializationError {
+        &self.cause
+    }
+
+    /// Returns the offset in the file at which the tuple ends.
+    ///
+    /// Tuples are read from their end, so this is where deserialization
+    /// started.
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// Returns the path of the file, unless the Heap wasn't opened from one.
+    pub fn path(&self) -> Option<&path::Path> {
+        self.path.as_deref()
+    }
+
+    /// Returns the key size decoded from the trailer of the tuple, unless
+    /// the file ended before the trailer was complete.
+    pub fn key_size(&self) -> Option<usize> {
+        self.sizes.map(|(key_size, _)| key_size)
+    }
+
+    /// Returns the value size decoded from the trailer of the tuple, unless
+    /// the file ended before the trailer was complete.
+    pub fn value_size(&self) -> Option<usize> {
+        self.sizes.map(|(_, value_size)| value_size)
+    }
+
+    /// Returns the bytes of the file in front of the offset, up to 32 of
+    /// them. They end with the trailer of the tuple.
+    ///
+    /// The excerpt is empty if the bytes could not be read.
+    pub fn excerpt(&self) -> &[u8] {
+        &self.excerpt
+    }
+}
+
+impl error::Error for DataError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        Some(&self.cause)
+    }
+}
+
+impl fmt::Display for DataError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} in tuple ending at offset {}",
+            self.cause, self.offset
+        )?;
+        if let Some(path) = &self.path {
+            write!(f, " of {}", path.display())?;
+        }
+        if let Some((key_size, value_size)) = self.sizes {
+            write!(f, ", key size {}, value size {}", key_size, value_size)?;
+        }
+        if !self.excerpt.is_empty() {
+            let start = self.offset - self.excerpt.len() as u64;
+            write!(f, ", bytes from offset {}:", start)?;
+            for byte in &self.excerpt {
+                write!(f, " {:02x}", byte)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+#[non_exhaustive]
 pub enum DeserializationError {
-    KeySizeTooBig,
-    ValueSizeTooBig,
-    DataTooShort,
+    /// The trailer holds a key size above the maximum.
+    KeySizeTooBig {
+        decoded: usize,
+        max: usize,
+    },
+
+    /// The trailer holds a value size above the maximum.
+    ValueSizeTooBig {
+        decoded: usize,
+        max: usize,
+    },
+
+    /// Fewer bytes are available than the trailer says the tuple needs.
+    DataTooShort {
+        needed: usize,
+        available: usize,
+    },
+
+    InvalidFlags,
+
+    /// The tuple refers to a value that doesn't end before the tuple
+    /// starts.
+    InvalidReference {
+        target: u64,
+        len: usize,
+    },
+
+    /// The value of the tuple is encoded, but the Heap was opened without a
+    /// value transform to decode it.
+    MissingTransform,
+
+    /// The hash stored in the tuple doesn't match the hash of its key, see
+    /// [`HeapOptions::key_hashes`].
+    KeyHashMismatch {
+        stored: u32,
+        computed: u32,
+    },
+
+    /// The file doesn't end with a tuple, nor with a torn write after one,
+    /// so it is likely some other kind of file. Holds its first bytes.
+    NotAZomdbFile {
+        head: Vec<u8>,
+    },
 }
 
 impl error::Error for DeserializationError {}
@@ -75,13 +537,154 @@ impl error::Error for DeserializationError {}
 impl fmt::Display for DeserializationError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            DeserializationError::KeySizeTooBig => write!(f, "Key size too big"),
-            DeserializationError::ValueSizeTooBig => {
-                write!(f, "Value size too big")
+            DeserializationError::KeySizeTooBig { decoded, max } => {
+                write!(f, "Key size too big: {} > {}", decoded, max)
+            }
+            DeserializationError::ValueSizeTooBig { decoded, max } => {
+                write!(f, "Value size too big: {} > {}", decoded, max)
+            }
+            DeserializationError::DataTooShort { needed, available } => {
+                write!(
+                    f,
+                    "data buffer too short: {} bytes needed, {} available",
+                    needed, available
+                )
+            }
+            DeserializationError::InvalidFlags => write!(f, "Invalid tuple flags"),
+            DeserializationError::InvalidReference { target, len } => {
+                write!(f, "Invalid reference to {} bytes at offset {}", len, target)
+            }
+            DeserializationError::MissingTransform => {
+                write!(f, "Encoded value without a value transform to decode it")
+            }
+            DeserializationError::KeyHashMismatch { stored, computed } => {
+                write!(
+                    f,
+                    "Key hash mismatch: stored {:08x}, the key hashes to {:08x}",
+                    stored, computed
+                )
             }
-            DeserializationError::DataTooShort => {
-                write!(f, "data buffer too short")
+            DeserializationError::NotAZomdbFile { head } => {
+                write!(f, "Not a zomdb heap, the file starts with")?;
+                for byte in head {
+                    write!(f, " {:02x}", byte)?;
+                }
+                Ok(())
             }
         }
     }
 }
+
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub enum ExternalModification {
+    /// The file is shorter than the tuples that were written to it.
+    Truncated { committed: u64, len: u64 },
+
+    /// The path points to a different file than the one that was opened.
+    Replaced,
+
+    /// The file was removed from its path.
+    Removed,
+}
+
+impl error::Error for ExternalModification {}
+
+impl fmt::Display for ExternalModification {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExternalModification::Truncated { committed, len } => {
+                write!(
+                    f,
+                    "truncated to {} bytes, expected at least {}",
+                    len, committed
+                )
+            }
+            ExternalModification::Replaced => write!(f, "replaced by another file"),
+            ExternalModification::Removed => write!(f, "removed"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::fs;
+
+    fn assert_input_error<T>(result: Result<T, Error>) {
+        match result {
+            Err(Error::Input(_)) => {}
+            Err(e) => panic!("expected input error, got {:?}", e),
+            Ok(_) => panic!("expected input error"),
+        }
+    }
+
+    #[test]
+    fn test_hostile_input_is_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let key = [b'k'; MAX_KEY_SIZE + 1];
+        let value = [b'v'; MAX_VALUE_SIZE + 1];
+        let mut heap = Heap::from(dir.path().join("heap")).unwrap();
+
+        assert_input_error(Heap::validate(b"", b"value"));
+        assert_input_error(heap.put(b"", b"value"));
+        assert_input_error(heap.put(&key, b"value"));
+        assert_input_error(heap.put(b"key", &value));
+        // Keys that can't be stored have no value to delete.
+        assert!(!heap.delete(b"").unwrap());
+        assert!(!heap.delete(&key).unwrap());
+        assert_input_error(heap.put_many([(&b"key"[..], &b"value"[..]), (&key[..], &[][..])]));
+        let mut batch = WriteBatch::new();
+        batch.put(b"key", &value);
+        assert_input_error(heap.write_batch(&batch));
+        assert_eq!(heap.get(b"").unwrap(), None);
+        assert_eq!(heap.get(&key).unwrap(), None);
+        assert!(!heap.contains(&key).unwrap());
+        assert_eq!(heap.scan_prefix(&key).count(), 0);
+        assert_eq!(heap.len().unwrap(), 0);
+
+        let (writer, _readers) = heap.into_writer_handle().unwrap();
+        assert_input_error(writer.put(b"", b"value"));
+        assert_input_error(writer.put(b"key", &value));
+        let heap = SyncHeap::new(writer.shutdown().unwrap());
+        assert_input_error(heap.put(b"", b"value"));
+        assert!(!heap.delete(&key).unwrap());
+
+        let db = Database::open(dir.path().to_path_buf()).unwrap();
+        for name in ["", "../heap", "heap\0"] {
+            assert!(matches!(db.heap(name), Err(Error::IO(_))));
+        }
+    }
+
+    #[test]
+    fn test_corrupt_file_is_reported() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("heap");
+        // A tuple followed by bytes that don't form one, which is still
+        // accepted as a Heap with a torn last write.
+        Heap::from(path.clone())
+            .unwrap()
+            .put(b"key1", b"value1")
+            .unwrap();
+        let mut data = fs::read(&path).unwrap();
+        data.extend_from_slice(&[0xff; 100]);
+        fs::write(&path, data).unwrap();
+        let mut heap = Heap::from(path).unwrap();
+
+        assert!(matches!(heap.get(b"key"), Err(Error::Data(_))));
+        assert!(matches!(heap.contains(b"key"), Err(Error::Data(_))));
+        assert!(matches!(heap.iter().next(), Some(Err(Error::Data(_)))));
+        assert!(matches!(heap.len(), Err(Error::Data(_))));
+        assert_eq!(heap.verify().unwrap().first_corrupt_offset, Some(113));
+        assert!(heap.stats().is_err());
+        assert!(heap.compact().is_err());
+        assert!(matches!(
+            heap.put(b"key", b"value"),
+            Err(Error::Poisoned { .. })
+        ));
+
+        let reader = heap.reader().unwrap();
+        assert!(matches!(reader.get(b"key"), Err(Error::Data(_))));
+        assert!(matches!(reader.iter().next(), Some(Err(Error::Data(_)))));
+    }
+}
@@ -0,0 +1,70 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// A snapshot of the work a Heap performed against its backing file.
+///
+/// The counters accumulate from the time the Heap was created or last reset
+/// and can be used to tell how much of the file an operation had to touch.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct PerfCounters {
+    /// Number of bytes read from the file.
+    pub bytes_read: u64,
+    /// Number of positioned read calls issued against the file.
+    pub reads: u64,
+    /// Number of tuples deserialized while scanning.
+    pub records_deserialized: u64,
+    /// Number of times the file was synced to disk.
+    pub fsyncs: u64,
+    /// Number of key bytes compared with the key of a lookup.
+    pub key_bytes_compared: u64,
+}
+
+/// Live counters updated on the hot paths.
+///
+/// Relaxed atomics are used so that counters can be incremented through
+/// shared references at negligible cost.
+#[derive(Debug, Default)]
+pub(crate) struct Counters {
+    bytes_read: AtomicU64,
+    reads: AtomicU64,
+    records_deserialized: AtomicU64,
+    fsyncs: AtomicU64,
+    key_bytes_compared: AtomicU64,
+}
+
+impl Counters {
+    pub(crate) fn read(&self, bytes: usize) {
+        self.reads.fetch_add(1, Ordering::Relaxed);
+        self.bytes_read.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_deserialized(&self) {
+        self.records_deserialized.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn fsync(&self) {
+        self.fsyncs.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn compare_key(&self, bytes: usize) {
+        self.key_bytes_compared
+            .fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn snapshot(&self) -> PerfCounters {
+        PerfCounters {
+            bytes_read: self.bytes_read.load(Ordering::Relaxed),
+            reads: self.reads.load(Ordering::Relaxed),
+            records_deserialized: self.records_deserialized.load(Ordering::Relaxed),
+            fsyncs: self.fsyncs.load(Ordering::Relaxed),
+            key_bytes_compared: self.key_bytes_compared.load(Ordering::Relaxed),
+        }
+    }
+
+    pub(crate) fn reset(&self) {
+        self.bytes_read.store(0, Ordering::Relaxed);
+        self.reads.store(0, Ordering::Relaxed);
+        self.records_deserialized.store(0, Ordering::Relaxed);
+        self.fsyncs.store(0, Ordering::Relaxed);
+        self.key_bytes_compared.store(0, Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,253 @@
+//! Replication of the operations of a Heap over any byte stream.
+//!
+//! [`send_since`] writes the operations of a Heap as a batch of frames, and
+//! [`recv_apply`] reads a batch and applies it to another Heap. A batch
+//! ends with a frame that holds the offset to continue from, so that
+//! batches can follow each other on a stream that stays open, like a TCP
+//! connection.
+//!
+//! A frame starts with the length of its payload and the CRC-32 of the
+//! payload, both as four big-endian bytes. The payload of an operation is
+//! its offset in the Heap as eight big-endian bytes followed by the
+//! operation, see [`Op::serialize`]. The payload of the frame that ends a
+//! batch is only the offset to continue from.
+
+use crate::{Error, Heap, Op, MAX_KEY_SIZE, MAX_VALUE_SIZE};
+use std::io::{self, Read, Write};
+
+/// The largest payload of a frame, that of a tagged put of the largest key
+/// and value.
+const MAX_PAYLOAD: usize = 8 + 1 + 2 + MAX_KEY_SIZE + 2 + MAX_VALUE_SIZE + 1;
+
+/// Length of the payload of the frame that ends a batch.
+const END_PAYLOAD: usize = 8;
+
+/// Writes the operations of the Heap starting at or after the offset to w,
+/// and returns the offset to continue from.
+///
+/// See [`Heap::log_since`]. Nothing but the frame that ends the batch is
+/// written if there are no new operations.
+pub fn send_since<W: Write>(heap: &Heap, offset: u64, mut w: W) -> Result<u64, Error> {
+    let mut next = offset;
+    for op in heap.log_since(offset) {
+        let (op_offset, op) = op?;
+        let mut payload = op_offset.to_be_bytes().to_vec();
+        payload.extend_from_slice(&op.serialize());
+        write_frame(&mut w, &payload)?;
+        next = op_offset + 1;
+    }
+
+    write_frame(&mut w, &next.to_be_bytes())?;
+    w.flush().map_err(Error::IO)?;
+    Ok(next)
+}
+
+/// Reads a batch of operations written by [`send_since`] from r, applies
+/// them to the Heap, and returns the offset to continue from.
+///
+/// Operations are applied as they are read, so those in front of a frame
+/// that fails to read stay applied. Frames that are truncated or don't
+/// match their checksum fail with an I/O error of kind
+/// [`io::ErrorKind::InvalidData`] that names the index of the frame,
+/// counting from 0.
+pub fn recv_apply<R: Read>(heap: &mut Heap, mut r: R) -> Result<u64, Error> {
+    let mut index = 0;
+    loop {
+        let payload = read_frame(&mut r).map_err(|e| frame_error(index, e))?;
+        let (offset, op) = payload.split_at(8);
+        let offset = u64::from_be_bytes(offset.try_into().unwrap());
+        if op.is_empty() {
+            return Ok(offset);
+        }
+
+        let (op, len) = Op::deserialize(op).map_err(|e| frame_error(index, e))?;
+        if 8 + len != payload.len() {
+            return Err(frame_error(index, "trailing bytes after the operation"));
+        }
+        heap.apply([op])?;
+        index += 1;
+    }
+}
+
+fn frame_error<E: ToString>(index: usize, e: E) -> Error {
+    Error::IO(io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("frame {}: {}", index, e.to_string()),
+    ))
+}
+
+fn write_frame<W: Write>(w: &mut W, payload: &[u8]) -> Result<(), Error> {
+    let mut frame = Vec::with_capacity(8 + payload.len());
+    frame.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    frame.extend_from_slice(&crc32(payload).to_be_bytes());
+    frame.extend_from_slice(payload);
+    w.write_all(&frame).map_err(Error::IO)
+}
+
+/// Reads a frame and returns its payload, which is at least long enough to
+/// hold an offset.
+fn read_frame<R: Read>(r: &mut R) -> io::Result<Vec<u8>> {
+    let mut header = [0; 8];
+    r.read_exact(&mut header)?;
+    let len = u32::from_be_bytes(header[..4].try_into().unwrap()) as usize;
+    let checksum = u32::from_be_bytes(header[4..].try_into().unwrap());
+    if !(END_PAYLOAD..=MAX_PAYLOAD).contains(&len) {
+        return Err(io::Error::other(format!("invalid length {}", len)));
+    }
+
+    let mut payload = vec![0; len];
+    r.read_exact(&mut payload)?;
+    if crc32(&payload) != checksum {
+        return Err(io::Error::other("checksum mismatch"));
+    }
+    Ok(payload)
+}
+
+/// Computes the CRC-32 used by zlib and Ethernet.
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &b in data {
+        crc ^= b as u32;
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xedb8_8320 & (crc & 1).wrapping_neg());
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Index;
+    use std::thread;
+
+    fn assert_invalid_frame(result: Result<u64, Error>, index: usize) {
+        match result {
+            Err(Error::IO(e)) => {
+                assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+                let prefix = format!("frame {}:", index);
+                assert!(e.to_string().starts_with(&prefix), "{}", e);
+            }
+            other => panic!("expected invalid data, got {:?}", other),
+        }
+    }
+
+    fn open_heap(dir: &tempfile::TempDir, name: &str) -> Heap {
+        Heap::from(dir.path().join(name)).unwrap()
+    }
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+    }
+
+    #[test]
+    fn test_replicate_through_buffer() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut primary = open_heap(&dir, "primary");
+        let mut follower = open_heap(&dir, "follower");
+        primary.put(b"key1", b"value1").unwrap();
+        primary.put(b"key2", b"value2").unwrap();
+        primary.put(b"key1", b"value3").unwrap();
+
+        let mut stream = Vec::new();
+        let sent = send_since(&primary, 0, &mut stream).unwrap();
+        primary.delete(b"key2").unwrap();
+        primary.put(b"key3", b"value4").unwrap();
+        assert_eq!(send_since(&primary, sent, &mut stream).unwrap(), 47);
+        // A batch without new operations.
+        assert_eq!(send_since(&primary, 47, &mut stream).unwrap(), 47);
+
+        let mut r = &stream[..];
+        assert_eq!(recv_apply(&mut follower, &mut r).unwrap(), sent);
+        assert_eq!(follower.get(b"key1").unwrap(), Some(b"value3".to_vec()));
+        assert_eq!(recv_apply(&mut follower, &mut r).unwrap(), 47);
+        assert_eq!(recv_apply(&mut follower, &mut r).unwrap(), 47);
+        assert!(r.is_empty());
+        assert!(primary.diff(&follower).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_replicate_tags() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut primary = open_heap(&dir, "primary");
+        let mut follower = open_heap(&dir, "follower");
+        primary.put_tagged(b"key1", b"value1", 1).unwrap();
+        primary.put(b"key2", b"value2").unwrap();
+        primary.put_tagged(b"key3", b"value3", 1).unwrap();
+        primary.put_tagged(b"key2", b"value4", 2).unwrap();
+
+        let mut stream = Vec::new();
+        send_since(&primary, 0, &mut stream).unwrap();
+        recv_apply(&mut follower, &stream[..]).unwrap();
+
+        let tags = |heap: &Heap| {
+            let mut tags: Vec<_> = heap
+                .iter()
+                .map(|tuple| {
+                    let tuple = tuple.unwrap();
+                    (tuple.key.clone(), tuple.tag())
+                })
+                .collect();
+            tags.sort();
+            tags
+        };
+        assert_eq!(tags(&follower), tags(&primary));
+        assert_eq!(follower.iter().with_tag(1).count(), 2);
+        assert!(primary.diff(&follower).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_replicate_through_pipe() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut primary = open_heap(&dir, "primary");
+        let mut follower = open_heap(&dir, "follower");
+        for i in 0..100u32 {
+            primary
+                .put(&(i % 13).to_be_bytes(), &i.to_be_bytes())
+                .unwrap();
+        }
+
+        let (reader, writer) = io::pipe().unwrap();
+        let sender = thread::spawn(move || {
+            send_since(&primary, 0, writer).unwrap();
+            primary
+        });
+        recv_apply(&mut follower, reader).unwrap();
+
+        let primary = sender.join().unwrap();
+        assert!(primary.diff(&follower).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_recv_detects_corrupt_frames() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut primary = open_heap(&dir, "primary");
+        primary.put(b"key1", b"value1").unwrap();
+        primary.put(b"key2", b"value2").unwrap();
+        let mut stream = Vec::new();
+        send_since(&primary, 0, &mut stream).unwrap();
+        let frame_len = 8 + 8 + 1 + 2 + 4 + 2 + 6;
+
+        // A flipped bit in the value of the second frame.
+        let mut corrupt = stream.clone();
+        corrupt[2 * frame_len - 1] ^= 1;
+        let mut follower = open_heap(&dir, "follower");
+        assert_invalid_frame(recv_apply(&mut follower, &corrupt[..]), 1);
+        // The frame before it was applied.
+        assert_eq!(follower.get(b"key1").unwrap(), Some(b"value1".to_vec()));
+        assert_eq!(follower.get(b"key2").unwrap(), None);
+
+        // A stream that ends before the batch does.
+        let mut follower = open_heap(&dir, "truncated");
+        let truncated = &stream[..stream.len() - 1];
+        assert_invalid_frame(recv_apply(&mut follower, truncated), 2);
+
+        // A length that no frame has.
+        let mut corrupt = stream.clone();
+        corrupt[0] = 0xff;
+        let mut follower = open_heap(&dir, "corrupt_length");
+        assert_invalid_frame(recv_apply(&mut follower, &corrupt[..]), 0);
+    }
+}
@@ -1,94 +1,1653 @@
+/*
+ * Errors are reported as a ZomdbErrorCode and never through errno. Functions
+ * returning a ZomdbErrorCode return ZOMDB_ERROR_CODE_OK on success or the
+ * code of the error. Functions returning a pointer return null on failure,
+ * and functions returning nothing report failure only through
+ * zomdb_last_error. The code and message of the last error on the calling
+ * thread are available from zomdb_last_error and zomdb_last_error_message.
+ * Functions with an _e suffix additionally hand out the error as a
+ * ZomdbError object.
+ *
+ * Heap handles may be shared across threads; calls using them may block
+ * while another thread uses the same heap. All other handles, like
+ * iterators and tuples, must only be used by one thread at a time.
+ */
+
 #include <stdarg.h>
 #include <stdbool.h>
 #include <stdint.h>
 #include <stdlib.h>
 
+#if defined(__unix__) || defined(__APPLE__)
+#define ZOMDB_UNIX
+#endif
+
+/**
+ * Value of a HeapOpenOptions flag that leaves the option at its default.
+ */
+#define HEAP_OPTION_DEFAULT 0
+
+/**
+ * Value of a HeapOpenOptions flag that enables the option.
+ */
+#define HEAP_OPTION_ENABLED 1
+
+/**
+ * Value of a HeapOpenOptions flag that disables the option.
+ */
+#define HEAP_OPTION_DISABLED 2
+
+/**
+ * The version of the C ABI exposed by this library.
+ *
+ * It is incremented whenever a change could break callers built against an
+ * earlier version, like changed struct layouts, error codes or function
+ * semantics. Compare it with zomdb_abi_version to check that the loaded
+ * library matches the header.
+ */
+#define ZOMDB_ABI_VERSION 4
+
 /**
- * Error code for keys that could not be found.
+ * Same as ZomdbErrorCode::NotFound.
  */
 #define ERR_NOT_FOUND 1
 
 /**
- * Error code for I/O errors.
+ * Same as ZomdbErrorCode::Io.
  */
 #define ERR_IO 10
 
 /**
- * Error code for invalid UTF-8.
- * Type of an input error.
+ * Same as ZomdbErrorCode::Locked.
+ */
+#define ERR_LOCKED 11
+
+/**
+ * Same as ZomdbErrorCode::Poisoned.
+ */
+#define ERR_POISONED 12
+
+/**
+ * Same as ZomdbErrorCode::Busy.
+ */
+#define ERR_BUSY 13
+
+/**
+ * Same as ZomdbErrorCode::ReadOnly.
+ */
+#define ERR_READ_ONLY 14
+
+/**
+ * Same as ZomdbErrorCode::OutOfMemory.
+ */
+#define ERR_OUT_OF_MEMORY 15
+
+/**
+ * Same as ZomdbErrorCode::Cancelled.
+ */
+#define ERR_CANCELLED 16
+
+/**
+ * Same as ZomdbErrorCode::Utf8.
  */
 #define ERR_UTF8 30
 
 /**
- * Error code for invalid key size.
- * Type of an input error.
+ * Same as ZomdbErrorCode::KeySize.
  */
 #define ERR_KEY_SIZE 31
 
 /**
- * Error code for invalid value size.
- * Type of an input error.
+ * Same as ZomdbErrorCode::ValueSize.
  */
 #define ERR_VALUE_SIZE 32
 
 /**
- * Error code for data errors.
- * Indicates that data on disk is corrupted.
+ * Same as ZomdbErrorCode::NulByte.
+ */
+#define ERR_NUL_BYTE 33
+
+/**
+ * Same as ZomdbErrorCode::NullArgument.
+ */
+#define ERR_NULL_ARGUMENT 34
+
+/**
+ * Same as ZomdbErrorCode::InvalidHandle.
+ */
+#define ERR_INVALID_HANDLE 35
+
+/**
+ * Same as ZomdbErrorCode::BufferTooSmall.
+ */
+#define ERR_BUFFER_TOO_SMALL 36
+
+/**
+ * Same as ZomdbErrorCode::EmptyKey.
+ */
+#define ERR_EMPTY_KEY 37
+
+/**
+ * Same as ZomdbErrorCode::Pattern.
+ */
+#define ERR_PATTERN 38
+
+/**
+ * Same as ZomdbErrorCode::Savepoint.
+ */
+#define ERR_SAVEPOINT 39
+
+/**
+ * Same as ZomdbErrorCode::Data.
  */
 #define ERR_DATA 50
 
+/**
+ * Same as ZomdbErrorCode::ExternallyModified.
+ */
+#define ERR_EXTERNALLY_MODIFIED 51
+
+/**
+ * Same as ZomdbErrorCode::ValueLength.
+ */
+#define ERR_VALUE_LENGTH 52
+
+/**
+ * Same as ZomdbErrorCode::Panic.
+ */
+#define ERR_PANIC 60
+
+/**
+ * Log level of messages about failed operations.
+ */
+#define ZOMDB_LOG_ERROR 1
+
+/**
+ * Log level of messages about unexpected conditions that were handled.
+ */
+#define ZOMDB_LOG_WARN 2
+
+/**
+ * Log level of informational messages.
+ */
+#define ZOMDB_LOG_INFO 3
+
+/**
+ * The code of an error reported by this library.
+ *
+ * Functions that return a status return ZomdbErrorCode::Ok on success, and
+ * the code of the error otherwise. The values never change, and each has an
+ * ERR_* constant of the same value for callers that store codes as ints.
+ * Errors of the core library keep the value of their zomdb::Error::code.
+ */
+typedef enum ZomdbErrorCode {
+  /**
+   * No error occurred.
+   */
+  ZOMDB_ERROR_CODE_OK = 0,
+  /**
+   * Key could not be found.
+   */
+  ZOMDB_ERROR_CODE_NOT_FOUND = 1,
+  /**
+   * I/O error.
+   */
+  ZOMDB_ERROR_CODE_IO = 10,
+  /**
+   * Heap is already opened by another writer.
+   */
+  ZOMDB_ERROR_CODE_LOCKED = 11,
+  /**
+   * Heap refuses writes after an earlier error.
+   */
+  ZOMDB_ERROR_CODE_POISONED = 12,
+  /**
+   * Operation can't run while iterators are alive.
+   */
+  ZOMDB_ERROR_CODE_BUSY = 13,
+  /**
+   * Write to a heap that was opened for reading only.
+   */
+  ZOMDB_ERROR_CODE_READ_ONLY = 14,
+  /**
+   * Allocator registered with zomdb_set_allocator returned null.
+   */
+  ZOMDB_ERROR_CODE_OUT_OF_MEMORY = 15,
+  /**
+   * Operation was cancelled before it completed.
+   */
+  ZOMDB_ERROR_CODE_CANCELLED = 16,
+  /**
+   * Invalid UTF-8. Type of an input error.
+   */
+  ZOMDB_ERROR_CODE_UTF8 = 30,
+  /**
+   * Key is longer than the maximum. Type of an input error.
+   */
+  ZOMDB_ERROR_CODE_KEY_SIZE = 31,
+  /**
+   * Invalid value size. Type of an input error.
+   */
+  ZOMDB_ERROR_CODE_VALUE_SIZE = 32,
+  /**
+   * Value contains a null byte but was requested as a null-terminated
+   * string. Use the functions taking byte buffers instead.
+   */
+  ZOMDB_ERROR_CODE_NUL_BYTE = 33,
+  /**
+   * Null pointer passed where a valid one is required. Type of an input
+   * error.
+   */
+  ZOMDB_ERROR_CODE_NULL_ARGUMENT = 34,
+  /**
+   * Handle was not created by this library, was created as another type,
+   * or was destroyed already. Only detected in debug builds or with the
+   * handle-checks feature.
+   */
+  ZOMDB_ERROR_CODE_INVALID_HANDLE = 35,
+  /**
+   * Buffer is too small to hold the result.
+   */
+  ZOMDB_ERROR_CODE_BUFFER_TOO_SMALL = 36,
+  /**
+   * Key is empty. Type of an input error.
+   */
+  ZOMDB_ERROR_CODE_EMPTY_KEY = 37,
+  /**
+   * Pattern to match keys against is invalid. Type of an input error.
+   */
+  ZOMDB_ERROR_CODE_PATTERN = 38,
+  /**
+   * Savepoint is unknown, the heap was compacted since it was created, or
+   * the heap ends before it.
+   */
+  ZOMDB_ERROR_CODE_SAVEPOINT = 39,
+  /**
+   * Data on disk is corrupted.
+   */
+  ZOMDB_ERROR_CODE_DATA = 50,
+  /**
+   * Heap file was truncated or replaced while open.
+   */
+  ZOMDB_ERROR_CODE_EXTERNALLY_MODIFIED = 51,
+  /**
+   * Stored value doesn't have the length of the requested type.
+   */
+  ZOMDB_ERROR_CODE_VALUE_LENGTH = 52,
+  /**
+   * Unexpected failure inside the library.
+   */
+  ZOMDB_ERROR_CODE_PANIC = 60,
+} ZomdbErrorCode;
+
+/**
+ * Database is a directory of named heaps.
+ *
+ * Use zomdb_db_open to create an instance of this struct, and release it
+ * with zomdb_db_close. It may be shared across threads like a Heap.
+ */
+typedef struct Database Database;
+
 /**
  * Heap is a primitive on-disk key-value structure.
  *
  * A Heap can be used to set and get key-value pairs, and to iterate over them.
+ *
+ * A Heap may be shared across threads. Calls using it from several threads
+ * at once are serialized, so they may block until the others return. It
+ * must not be destroyed while other threads still use it.
  */
 typedef struct Heap Heap;
 
 /**
  * Can be used to iterate a Heap structure.
  *
- * Use heap_iter to create an instance of this struct from a Heap.
+ * Use zomdb_heap_iter to create an instance of this struct from a Heap.
+ * Unlike the Heap, an iterator and its tuples must only be used by one
+ * thread at a time.
  */
 typedef struct HeapIter HeapIter;
 
+typedef struct Option_HeapForEachCallback Option_HeapForEachCallback;
+
+/**
+ * WriteBatch collects puts and deletes to apply to a heap at once.
+ *
+ * Use zomdb_batch_create to create an instance of this struct. It is
+ * consumed by zomdb_heap_apply_batch, or released with zomdb_batch_destroy
+ * if it is never applied.
+ */
+typedef struct WriteBatch WriteBatch;
+
+/**
+ * ZomdbError describes an error that occurred in a call to the library.
+ *
+ * It must be released with zomdb_error_free.
+ */
+typedef struct ZomdbError ZomdbError;
+
+/**
+ * HeapOpenOptions configures how zomdb_heap_create_with_options opens a heap.
+ *
+ * Every field that is zero keeps its default, so a zero-initialized struct
+ * opens a heap just like zomdb_heap_create does. New fields are only ever
+ * added with that in mind. Flags are set to HEAP_OPTION_ENABLED or
+ * HEAP_OPTION_DISABLED; any other non-zero value enables them.
+ */
+typedef struct HeapOpenOptions {
+  /**
+   * Open the heap for reading only. Writes fail with ERR_READ_ONLY.
+   * Disabled by default.
+   */
+  uint8_t read_only;
+  /**
+   * Create the file if it doesn't exist. Enabled by default.
+   */
+  uint8_t create;
+  /**
+   * Create the file and fail if it exists already. Disabled by default.
+   */
+  uint8_t create_new;
+  /**
+   * Flush every write to disk before it returns. Disabled by default.
+   */
+  uint8_t sync_on_put;
+  /**
+   * Reject values larger than this many bytes. Zero means the largest
+   * size the heap supports.
+   */
+  uint32_t max_value_size;
+} HeapOpenOptions;
+
+/**
+ * HeapCompactionReport describes the outcome of zomdb_heap_compact.
+ */
+typedef struct HeapCompactionReport {
+  /**
+   * Size of the heap file before compaction.
+   */
+  uint64_t bytes_before;
+  /**
+   * Size of the heap file after compaction.
+   */
+  uint64_t bytes_after;
+  /**
+   * Number of stale or deleted tuples that were dropped.
+   */
+  uint64_t records_dropped;
+} HeapCompactionReport;
+
+/**
+ * HeapStats holds statistics about the tuples stored in a heap.
+ */
+typedef struct HeapStats {
+  /**
+   * Size of the heap file in bytes.
+   */
+  uint64_t file_size;
+  /**
+   * Number of tuples stored, including stale ones and tombstones.
+   */
+  uint64_t total_records;
+  /**
+   * Number of keys that have a value.
+   */
+  uint64_t live_keys;
+  /**
+   * Number of tuples that compaction would drop.
+   */
+  uint64_t stale_records;
+  /**
+   * Number of bytes that compaction would free.
+   */
+  uint64_t dead_bytes;
+} HeapStats;
+
+/**
+ * HeapVerifyReport holds the findings of zomdb_heap_verify.
+ */
+typedef struct HeapVerifyReport {
+  /**
+   * Number of tuples that could be read, counting from the end of the
+   * file.
+   */
+  uint64_t valid_records;
+  /**
+   * Offset at which the scan from the end of the file ran into corrupted
+   * bytes, or UINT64_MAX if the heap is intact. Tuples in front of it
+   * can't be read.
+   */
+  uint64_t first_corrupt_offset;
+  /**
+   * 1 if the file doesn't end with a complete tuple, like after a write
+   * that was cut short, 0 otherwise.
+   */
+  uint8_t truncated_tail;
+} HeapVerifyReport;
+
+/**
+ * HeapTuple2 is a key-value pair of arbitrary bytes from a Heap.
+ *
+ * The key and value are owned by the tuple and released along with it by
+ * zomdb_heap_tuple2_destroy. They are not null-terminated.
+ */
+typedef struct HeapTuple2 {
+  uint8_t *key_ptr;
+  uintptr_t key_len;
+  uint8_t *value_ptr;
+  uintptr_t value_len;
+} HeapTuple2;
+
 /**
  * HeapTuple is a key-value pair from a Heap.
+ *
+ * Prefer zomdb_heap_tuple_key and zomdb_heap_tuple_value over accessing the
+ * fields directly.
  */
 typedef struct HeapTuple {
   const char *key;
   const char *value;
 } HeapTuple;
 
-struct Heap *create_heap(const char *file_name_cstr);
+/**
+ * Called to allocate a buffer of at least size bytes, which is never zero.
+ *
+ * Returns null if the memory could not be allocated.
+ */
+typedef void *(*ZomdbAllocFn)(uintptr_t size, void *user_data);
 
 /**
- * Get a value from the heap.
+ * Called to release a buffer returned by the ZomdbAllocFn, with the same
+ * size it was allocated with.
+ */
+typedef void (*ZomdbDeallocFn)(void *ptr, uintptr_t size, void *user_data);
+
+/**
+ * Callback invoked by zomdb_db_list for the name of every heap.
+ *
+ * The name pointer is only valid for the duration of the call. Returning a
+ * non-zero value stops the iteration.
+ */
+typedef int (*DatabaseListCallback)(const uint8_t *name, uintptr_t name_len, void *user_data);
+
+/**
+ * Called with every message the library logs.
+ *
+ * The level is one of the ZOMDB_LOG_* constants. The message is only valid
+ * for the duration of the call.
+ */
+typedef void (*ZomdbLogCallback)(int level, const char *msg, void *user_data);
+
+/**
+ * Open or create the heap backed by the given file.
+ *
+ * Returns null if the heap could not be opened, in which case
+ * zomdb_last_error returns the code of the error. File names that aren't
+ * valid UTF-8 fail with ERR_UTF8; open them with
+ * zomdb_heap_create_from_bytes or zomdb_heap_create_w instead.
+ *
+ * # Safety
  *
- * Returns a pointer to the value if found, or null if not found. If not
- * found, the global errno will be set to ERR_NOT_FOUND.
+ * The file name must be a valid null-terminated string.
+ */
+struct Heap *zomdb_heap_create(const char *file_name_cstr);
+
+/**
+ * Return the options zomdb_heap_create opens a heap with.
+ */
+struct HeapOpenOptions zomdb_heap_open_options_default(void);
+
+/**
+ * Open or create the heap backed by the file at the given path.
+ *
+ * The path consists of path_len bytes and doesn't need to be
+ * null-terminated. On Windows, it must be valid UTF-8. The options may be
+ * null, in which case the defaults are used.
+ *
+ * Returns 0 on success, in which case the heap is written to out_heap, or
+ * the code of the error that occurred. The heap must be released with
+ * zomdb_heap_destroy.
+ *
+ * # Safety
+ *
+ * The path pointer must point to path_len readable bytes. The options
+ * pointer must be null or point to an initialized HeapOpenOptions, and
+ * out_heap must be valid for writes.
+ */
+enum ZomdbErrorCode zomdb_heap_open(const uint8_t *path_ptr,
+                                    uintptr_t path_len,
+                                    const struct HeapOpenOptions *opts,
+                                    struct Heap **out_heap);
+
+/**
+ * Open or create the heap backed by the file at the given path.
+ *
+ * Like zomdb_heap_open, but returns null if the heap could not be opened, in
+ * which case zomdb_last_error returns the code of the error.
+ *
+ * # Safety
+ *
+ * The path pointer must point to path_len readable bytes. The options
+ * pointer must be null or point to an initialized HeapOpenOptions.
+ */
+struct Heap *zomdb_heap_create_with_options(const uint8_t *path_ptr,
+                                            uintptr_t path_len,
+                                            const struct HeapOpenOptions *opts);
+
+#if defined(ZOMDB_UNIX)
+/**
+ * Open or create the heap backed by the file at the given path of
+ * arbitrary bytes, like zomdb_heap_create does.
+ *
+ * The path consists of path_len bytes and doesn't need to be
+ * null-terminated or valid UTF-8. Only available on Unix.
+ *
+ * Returns null if the heap could not be opened, in which case
+ * zomdb_last_error returns the code of the error.
+ *
+ * # Safety
+ *
+ * The path pointer must point to path_len readable bytes.
+ */
+struct Heap *zomdb_heap_create_from_bytes(const uint8_t *path, uintptr_t path_len);
+#endif
+
+/**
+ * Open the existing heap backed by the file at the given path for reading
+ * only.
+ *
+ * The path consists of path_len bytes like for zomdb_heap_open. The file is
+ * never created, so missing files fail with ERR_IO. Reading and iterating
+ * works as usual, while every write fails with ERR_READ_ONLY. Any number
+ * of read-only heaps can be opened alongside the single writer, and they
+ * see the tuples that were complete when they were opened.
+ *
+ * Returns null if the heap could not be opened, in which case
+ * zomdb_last_error returns the code of the error.
+ *
+ * # Safety
+ *
+ * The path pointer must point to path_len readable bytes.
+ */
+struct Heap *zomdb_heap_open_read_only(const uint8_t *path_ptr, uintptr_t path_len);
+
+#if defined(_WIN32)
+/**
+ * Open or create the heap backed by the file at the given wide path, like
+ * zomdb_heap_create does.
+ *
+ * The path consists of path_len UTF-16 code units and doesn't need to be
+ * null-terminated or well-formed. Only available on Windows.
+ *
+ * Returns null if the heap could not be opened, in which case
+ * zomdb_last_error returns the code of the error.
+ *
+ * # Safety
+ *
+ * The path pointer must point to path_len readable code units.
+ */
+struct Heap *zomdb_heap_create_w(const uint16_t *path, uintptr_t path_len);
+#endif
+
+/**
+ * Get a value from the heap.
  *
- * If an error occurs, the global errno will be set to the appropriate error.
+ * Returns a pointer to the value if found, or null otherwise, in which case
+ * zomdb_last_error returns ERR_NOT_FOUND or the code of the error that
+ * occurred. The returned value must be released with zomdb_free_value.
  *
  * The accepted key is a null-terminated string. Any calling code must
  * therefore guarantee that no null bytes are present in the key.
+ * Values containing a null byte can't be returned and fail with
+ * ERR_NUL_BYTE. Use zomdb_heap_get2 to read them.
+ *
+ * # Safety
+ *
+ * The heap pointer must have been returned by zomdb_heap_create and not yet
+ * been destroyed.
  */
-const char *heap_get(struct Heap *ptr, const char *key_cstr);
+const char *zomdb_heap_get(struct Heap *ptr, const char *key_cstr);
 
 /**
  * Set a key and value in the heap.
  *
- * If an error occurs, the global errno will be set to the appropriate error.
+ * Returns 0 if the tuple was written, or the code of the error that
+ * occurred. Afterwards, zomdb_last_error returns the same code.
  *
  * The accepted key and value are null-terminated strings. Any calling code
  * must therefore guarantee that no null bytes are present in the key or
  * value.
+ *
+ * Once this function returns, the tuple is visible to other readers of the
+ * file, but it may not have reached the disk yet. Call zomdb_heap_sync to
+ * make it durable.
+ *
+ * # Safety
+ *
+ * The heap pointer must have been returned by zomdb_heap_create and not yet
+ * been destroyed.
  */
-void heap_set(struct Heap *ptr, const char *key_cstr, const char *value_cstr);
+enum ZomdbErrorCode zomdb_heap_set(struct Heap *ptr, const char *key_cstr, const char *value_cstr);
 
-void destroy_heap(struct Heap *ptr);
+/**
+ * Get a value from the heap by a key of arbitrary bytes.
+ *
+ * Returns 0 if the key was found, in which case the value and its length
+ * are written to the out parameters. The value must be released with
+ * zomdb_free_bytes. Returns ERR_NOT_FOUND if the key was not found, or
+ * the code of any other error.
+ *
+ * # Safety
+ *
+ * The heap pointer must have been returned by zomdb_heap_create and not yet
+ * been destroyed. The key pointer must point to key_len readable bytes, and
+ * the out parameters must be valid for writes.
+ */
+enum ZomdbErrorCode zomdb_heap_get2(struct Heap *ptr,
+                                    const uint8_t *key_ptr,
+                                    uintptr_t key_len,
+                                    uint8_t **out_value_ptr,
+                                    uintptr_t *out_value_len);
 
-struct HeapIter *heap_iter(struct Heap *ptr);
+/**
+ * Get a value from the heap by a key of arbitrary bytes into a buffer
+ * provided by the caller.
+ *
+ * Returns 0 if the key was found and its value fit into the buffer. The
+ * length of the value is written to out_len whenever the key was found,
+ * also if the buffer was too small, in which case ERR_BUFFER_TOO_SMALL is
+ * returned and the buffer is left untouched. Passing a buffer of length
+ * zero queries the length of the value. Returns ERR_NOT_FOUND if the key
+ * was not found, or the code of any other error.
+ *
+ * # Safety
+ *
+ * The heap pointer must have been returned by zomdb_heap_create and not yet
+ * been destroyed. The key pointer must point to key_len readable bytes, the
+ * buffer must point to buf_len writable bytes, and out_len must be valid
+ * for writes.
+ */
+enum ZomdbErrorCode zomdb_heap_get_into(struct Heap *ptr,
+                                        const uint8_t *key_ptr,
+                                        uintptr_t key_len,
+                                        uint8_t *buf,
+                                        uintptr_t buf_len,
+                                        uintptr_t *out_len);
 
-const struct HeapTuple *heap_iter_next(struct HeapIter *ptr);
+/**
+ * Set a key and value of arbitrary bytes in the heap.
+ *
+ * Returns 0 on success or the code of the error that occurred.
+ *
+ * # Safety
+ *
+ * The heap pointer must have been returned by zomdb_heap_create and not yet
+ * been destroyed. The key and value pointers must point to key_len and
+ * value_len readable bytes respectively.
+ */
+enum ZomdbErrorCode zomdb_heap_set2(struct Heap *ptr,
+                                    const uint8_t *key_ptr,
+                                    uintptr_t key_len,
+                                    const uint8_t *value_ptr,
+                                    uintptr_t value_len);
 
-void heap_iter_destroy(struct HeapIter *ptr);
+/**
+ * Set multiple keys and values of arbitrary bytes in the heap at once.
+ *
+ * The i-th entry consists of the key keys[i] of length key_lens[i] and the
+ * value values[i] of length value_lens[i]. All entries are validated before
+ * anything is written, so an invalid entry leaves the heap untouched.
+ *
+ * Returns the number of entries written, or the negated code of the error
+ * that occurred. The last error message names the index of an invalid
+ * entry.
+ *
+ * # Safety
+ *
+ * The heap pointer must have been returned by zomdb_heap_create and not yet
+ * been destroyed. All four arrays must hold count elements, and every key
+ * and value pointer must point to as many readable bytes as its length says.
+ */
+intptr_t zomdb_heap_put_many(struct Heap *ptr,
+                             const uint8_t *const *keys,
+                             const uintptr_t *key_lens,
+                             const uint8_t *const *values,
+                             const uintptr_t *value_lens,
+                             uintptr_t count);
+
+/**
+ * Delete a key of arbitrary bytes from the heap.
+ *
+ * Returns 0 if the key was deleted, ERR_NOT_FOUND if it had no value, or
+ * the code of any other error.
+ *
+ * # Safety
+ *
+ * The heap pointer must have been returned by zomdb_heap_create and not yet
+ * been destroyed. The key pointer must point to key_len readable bytes.
+ */
+enum ZomdbErrorCode zomdb_heap_delete(struct Heap *ptr, const uint8_t *key_ptr, uintptr_t key_len);
+
+/**
+ * Check whether a key of arbitrary bytes has a value in the heap.
+ *
+ * Returns 1 if it has, 0 if it hasn't, or the negated code of the error
+ * that occurred. The value is not copied.
+ *
+ * # Safety
+ *
+ * The heap pointer must have been returned by zomdb_heap_create and not yet
+ * been destroyed. The key pointer must point to key_len readable bytes.
+ */
+int zomdb_heap_contains(struct Heap *ptr, const uint8_t *key_ptr, uintptr_t key_len);
+
+/**
+ * Count the keys that have a value in the heap.
+ *
+ * Returns 0 and writes the count to out_count on success, or returns the
+ * code of the error that occurred. This scans the whole heap without copying
+ * values.
+ *
+ * # Safety
+ *
+ * The heap pointer must have been returned by zomdb_heap_create and not yet
+ * been destroyed. The out parameter must be valid for writes.
+ */
+enum ZomdbErrorCode zomdb_heap_count(struct Heap *ptr, uint64_t *out_count);
+
+/**
+ * Flush all tuples written to the heap to disk.
+ *
+ * Returns 0 on success or the code of the error that occurred. Calling it
+ * without any writes since the last call is allowed.
+ *
+ * # Safety
+ *
+ * The heap pointer must have been returned by zomdb_heap_create and not yet
+ * been destroyed.
+ */
+enum ZomdbErrorCode zomdb_heap_sync(struct Heap *ptr);
+
+/**
+ * Compact the heap such that only the latest value of each key remains.
+ *
+ * Returns 0 and writes the report to out_report on success, or returns the
+ * code of the error that occurred. Returns ERR_BUSY without compacting while
+ * an iterator created from this heap hasn't been destroyed yet.
+ *
+ * # Safety
+ *
+ * The heap pointer must have been returned by zomdb_heap_create and not yet
+ * been destroyed. The out parameter must be valid for writes.
+ */
+enum ZomdbErrorCode zomdb_heap_compact(struct Heap *ptr, struct HeapCompactionReport *out_report);
+
+/**
+ * Collect statistics about the tuples stored in the heap.
+ *
+ * Returns 0 and writes the statistics to out on success, or returns the
+ * code of the error that occurred. This scans the whole heap.
+ *
+ * # Safety
+ *
+ * The heap pointer must have been returned by zomdb_heap_create and not yet
+ * been destroyed. The out parameter must be valid for writes.
+ */
+enum ZomdbErrorCode zomdb_heap_stats(struct Heap *ptr, struct HeapStats *out);
+
+/**
+ * Collect statistics about the tuples stored in the heap as a JSON object.
+ *
+ * The object holds the fields of HeapStats under their names: file_size,
+ * total_records, live_keys, stale_records and dead_bytes, all of them
+ * integers. These keys stay stable, and statistics added later only appear
+ * here under new keys, so consumers should ignore keys they don't know.
+ *
+ * Returns a null-terminated string that must be released with
+ * zomdb_free_value, or null if the statistics could not be collected, in
+ * which case zomdb_last_error returns the code of the error. This scans the
+ * whole heap.
+ *
+ * # Safety
+ *
+ * The heap pointer must have been returned by zomdb_heap_create and not yet
+ * been destroyed.
+ */
+char *zomdb_heap_stats_json(struct Heap *ptr);
+
+/**
+ * Check that all tuples stored in the heap can be read.
+ *
+ * Returns 0 and writes the findings to out if the check ran, even if it
+ * found corrupted bytes. Returns the code of the error that occurred if the
+ * heap could not be read. This scans the whole heap.
+ *
+ * # Safety
+ *
+ * The heap pointer must have been returned by zomdb_heap_create and not yet
+ * been destroyed. The out parameter must be valid for writes.
+ */
+enum ZomdbErrorCode zomdb_heap_verify(struct Heap *ptr, struct HeapVerifyReport *out);
+
+/**
+ * Copy the heap to a new file at the given path.
+ *
+ * The path consists of dest_len bytes like for zomdb_heap_open and must not
+ * exist yet. The copy holds the tuples that were complete when the backup
+ * started and can be opened as a heap. The heap can be used from other
+ * threads while it is copied, but their writes aren't included.
+ *
+ * Returns the number of bytes copied, or the negated code of the error that
+ * occurred. A partial copy is removed on failure.
+ *
+ * # Safety
+ *
+ * The heap pointer must have been returned by zomdb_heap_create and not yet
+ * been destroyed. The path pointer must point to dest_len readable bytes.
+ */
+int64_t zomdb_heap_backup(struct Heap *ptr, const uint8_t *dest_ptr, uintptr_t dest_len);
+
+/**
+ * Release a value returned by zomdb_heap_get, or a string returned by
+ * zomdb_heap_stats_json.
+ *
+ * Passing null is allowed and does nothing.
+ *
+ * # Safety
+ *
+ * The pointer must have been returned by zomdb_heap_get or
+ * zomdb_heap_stats_json and must not have been released before.
+ */
+void zomdb_free_value(char *ptr);
+
+/**
+ * Release a buffer of bytes returned by the heap.
+ *
+ * Passing null is allowed and does nothing.
+ *
+ * # Safety
+ *
+ * The pointer and length must have been returned together by a function
+ * of this library, like zomdb_heap_get2, and the buffer must not have been
+ * released before.
+ */
+void zomdb_free_bytes(uint8_t *ptr, uintptr_t len);
+
+/**
+ * Close the heap and release its resources.
+ *
+ * # Safety
+ *
+ * The heap pointer must have been returned by zomdb_heap_create and not yet
+ * been destroyed. It must not be used after this call.
+ */
+void zomdb_heap_destroy(struct Heap *ptr);
+
+/**
+ * Sync the heap to disk, then close it and release its resources.
+ *
+ * Returns 0 if the heap was synced, or the code of the error that
+ * occurred. The heap is released either way, unlike with zomdb_heap_sync,
+ * so callers learn whether their writes are durable without having to keep
+ * the heap open.
+ *
+ * # Safety
+ *
+ * The heap pointer must have been returned by zomdb_heap_create and not yet
+ * been destroyed. It must not be used after this call.
+ */
+enum ZomdbErrorCode zomdb_heap_close(struct Heap *ptr);
+
+/**
+ * Create an iterator over the heap.
+ *
+ * The iterator reads the heap through its own file handle, so it stays
+ * valid after the heap was destroyed: heap and iterators can be destroyed
+ * in any order. It sees the tuples written before the first call to
+ * zomdb_heap_iter_next.
+ *
+ * Returns null if the iterator could not be created, in which case
+ * zomdb_last_error returns the code of the error.
+ *
+ * # Safety
+ *
+ * The heap pointer must have been returned by zomdb_heap_create and not yet
+ * been destroyed.
+ */
+struct HeapIter *zomdb_heap_iter(struct Heap *ptr);
+
+/**
+ * Create an iterator over the tuples of the heap whose keys start with the
+ * given prefix of arbitrary bytes.
+ *
+ * The iterator is used and destroyed like one created by zomdb_heap_iter. An
+ * empty prefix matches all keys.
+ *
+ * # Safety
+ *
+ * The heap pointer must have been returned by zomdb_heap_create and not yet
+ * been destroyed. The prefix pointer must point to prefix_len readable
+ * bytes.
+ */
+struct HeapIter *zomdb_heap_iter_prefix(struct Heap *ptr,
+                                        const uint8_t *prefix_ptr,
+                                        uintptr_t prefix_len);
+
+/**
+ * Call the callback for every tuple of the heap, starting from the last
+ * inserted one.
+ *
+ * Unlike iterators, this doesn't allocate memory that the caller has to
+ * release. user_data is passed through to the callback unchanged.
+ *
+ * Returns 0 once all tuples were visited or the callback stopped the
+ * iteration, or the code of the error that occurred.
+ *
+ * # Safety
+ *
+ * The heap pointer must have been returned by zomdb_heap_create and not yet
+ * been destroyed. The heap stays locked while the callback runs, so the
+ * callback must not use the heap.
+ */
+enum ZomdbErrorCode zomdb_heap_for_each(struct Heap *ptr,
+                                        struct Option_HeapForEachCallback callback,
+                                        void *user_data);
+
+/**
+ * Advance the iterator and write the next tuple to out_tuple.
+ *
+ * Returns 0 on success or the code of the error that occurred. Once the
+ * iterator is exhausted, 0 is returned and null is written to out_tuple.
+ * Keys and values are returned with their lengths, so they may contain
+ * null bytes. Every returned tuple must be released with
+ * zomdb_heap_tuple2_destroy.
+ *
+ * # Safety
+ *
+ * The iterator pointer must have been returned by zomdb_heap_iter and not
+ * yet been destroyed. The out parameter must be valid for writes.
+ */
+enum ZomdbErrorCode zomdb_heap_iter_next2(struct HeapIter *ptr, struct HeapTuple2 **out_tuple);
+
+/**
+ * Advance the iterator and return the next tuple.
+ *
+ * Returns null once the iterator is exhausted or if an error occurred.
+ * Afterwards, zomdb_last_error returns 0 in the first case and the code of
+ * the error in the second. Every returned tuple must be released with
+ * zomdb_heap_tuple_destroy.
+ *
+ * Tuples whose key or value contain a null byte fail with ERR_NUL_BYTE.
+ * The iterator can still be advanced past them. Use zomdb_heap_iter_next2
+ * to read them.
+ *
+ * # Safety
+ *
+ * The iterator pointer must have been returned by zomdb_heap_iter and not
+ * yet been destroyed.
+ */
+const struct HeapTuple *zomdb_heap_iter_next(struct HeapIter *ptr);
+
+/**
+ * Return the key of the tuple.
+ *
+ * The key is owned by the tuple and released along with it.
+ *
+ * # Safety
+ *
+ * The tuple pointer must have been returned by zomdb_heap_iter_next and not
+ * yet been destroyed.
+ */
+const char *zomdb_heap_tuple_key(const struct HeapTuple *ptr);
+
+/**
+ * Return the value of the tuple.
+ *
+ * The value is owned by the tuple and released along with it.
+ *
+ * # Safety
+ *
+ * The tuple pointer must have been returned by zomdb_heap_iter_next and not
+ * yet been destroyed.
+ */
+const char *zomdb_heap_tuple_value(const struct HeapTuple *ptr);
+
+/**
+ * Release the tuple along with its key and value.
+ *
+ * Passing null is allowed and does nothing.
+ *
+ * # Safety
+ *
+ * The tuple pointer must have been returned by zomdb_heap_iter_next and not
+ * yet been destroyed. It must not be used after this call.
+ */
+void zomdb_heap_tuple_destroy(struct HeapTuple *ptr);
+
+/**
+ * Release the tuple along with its key and value.
+ *
+ * Passing null is allowed and does nothing.
+ *
+ * # Safety
+ *
+ * The tuple pointer must have been returned by zomdb_heap_iter_next2 and
+ * not yet been destroyed. It must not be used after this call.
+ */
+void zomdb_heap_tuple2_destroy(struct HeapTuple2 *ptr);
+
+/**
+ * Rewind the iterator, so that it can be drained again.
+ *
+ * Afterwards, the iterator behaves exactly like a new one created from the
+ * same heap: it sees the tuples written before the next call to
+ * zomdb_heap_iter_next, and yields every key again. This works even after
+ * the heap was destroyed. Tuples returned before stay valid.
+ *
+ * Returns 0 on success or the code of the error that occurred.
+ *
+ * # Safety
+ *
+ * The iterator pointer must have been returned by zomdb_heap_iter and not
+ * yet been destroyed.
+ */
+enum ZomdbErrorCode zomdb_heap_iter_reset(struct HeapIter *ptr);
+
+/**
+ * Release the iterator.
+ *
+ * # Safety
+ *
+ * The iterator pointer must have been returned by zomdb_heap_iter and not
+ * yet been destroyed. It must not be used after this call.
+ */
+void zomdb_heap_iter_destroy(struct HeapIter *ptr);
+
+/**
+ * Return the version of this library as a "MAJOR.MINOR.PATCH" string.
+ *
+ * The string is static and must not be released.
+ */
+const char *zomdb_version(void);
+
+/**
+ * Return the version of the C ABI implemented by this library.
+ *
+ * See ZOMDB_ABI_VERSION.
+ */
+uint32_t zomdb_abi_version(void);
+
+/**
+ * Return a description of the error code.
+ *
+ * Returns "unknown error" for codes that are not defined by this library,
+ * and never null. The string is static and must not be released.
+ */
+const char *zomdb_strerror(int code);
+
+/**
+ * Return the code of the last error that occurred on the calling thread,
+ * or 0 if none occurred yet.
+ *
+ * Functions that report failure only by returning null or nothing reset it
+ * to 0 when they succeed.
+ */
+enum ZomdbErrorCode zomdb_last_error(void);
+
+/**
+ * Return a message describing the last error that occurred on the calling
+ * thread, or null if none occurred yet.
+ *
+ * The message is owned by the library and stays valid until the next call
+ * into the library from the same thread. It must not be released.
+ */
+const char *zomdb_last_error_message(void);
+
+/**
+ * Register the functions that allocate and release the buffers handed out
+ * to the caller.
+ *
+ * Once registered, values returned by zomdb_heap_get and zomdb_heap_get2,
+ * and the keys and values of tuples, are allocated with alloc, and
+ * zomdb_free_value, zomdb_free_bytes and the tuple release functions
+ * release them with dealloc. Passing null for both restores Rust's
+ * allocator, which is the default.
+ *
+ * Returns 0 on success, ERR_NULL_ARGUMENT if only one of the functions is
+ * null, or ERR_BUSY while buffers handed out before are still alive. To be
+ * safe, register the allocator before any other call into the library.
+ * Allocations that fail with null make the call fail with
+ * ERR_OUT_OF_MEMORY.
+ *
+ * # Safety
+ *
+ * The functions may be called from any thread that calls into the library,
+ * also concurrently, so they and the user data must be safe to use from
+ * all of them. They must not call into the library themselves. The user
+ * data must stay valid until another allocator is registered.
+ */
+enum ZomdbErrorCode zomdb_set_allocator(ZomdbAllocFn alloc,
+                                        ZomdbDeallocFn dealloc,
+                                        void *user_data);
+
+/**
+ * Create an empty batch.
+ *
+ * Never returns null. The batch must be passed to zomdb_heap_apply_batch
+ * or released with zomdb_batch_destroy.
+ */
+struct WriteBatch *zomdb_batch_create(void);
+
+/**
+ * Add setting a key to a value, both of arbitrary bytes, to the batch.
+ *
+ * Returns 0 on success or the code of the error that occurred. Key and
+ * value are copied, and only validated when the batch is applied.
+ *
+ * # Safety
+ *
+ * The batch pointer must have been returned by zomdb_batch_create and not
+ * yet been applied or destroyed. The key and value pointers must point to
+ * key_len and value_len readable bytes.
+ */
+enum ZomdbErrorCode zomdb_batch_put(struct WriteBatch *ptr,
+                                    const uint8_t *key_ptr,
+                                    uintptr_t key_len,
+                                    const uint8_t *value_ptr,
+                                    uintptr_t value_len);
+
+/**
+ * Add deleting a key of arbitrary bytes to the batch.
+ *
+ * Returns 0 on success or the code of the error that occurred. The key is
+ * copied, and only validated when the batch is applied.
+ *
+ * # Safety
+ *
+ * The batch pointer must have been returned by zomdb_batch_create and not
+ * yet been applied or destroyed. The key pointer must point to key_len
+ * readable bytes.
+ */
+enum ZomdbErrorCode zomdb_batch_delete(struct WriteBatch *ptr,
+                                       const uint8_t *key_ptr,
+                                       uintptr_t key_len);
+
+/**
+ * Write all operations of the batch to the heap at once, and release the
+ * batch.
+ *
+ * All operations are validated before anything is written, so an invalid
+ * one leaves the heap untouched. Later operations on a key override earlier
+ * ones.
+ *
+ * Returns 0 on success or the code of the error that occurred. The last
+ * error message names the index of an invalid operation. The batch is
+ * released either way and must not be used afterwards.
+ *
+ * # Safety
+ *
+ * The heap pointer must have been returned by zomdb_heap_create and not yet
+ * been destroyed. The batch pointer must have been returned by
+ * zomdb_batch_create and not yet been applied or destroyed.
+ */
+enum ZomdbErrorCode zomdb_heap_apply_batch(struct Heap *ptr, struct WriteBatch *batch);
+
+/**
+ * Release a batch that was never applied.
+ *
+ * # Safety
+ *
+ * The batch pointer must have been returned by zomdb_batch_create and not
+ * yet been applied or destroyed. It must not be used after this call.
+ */
+void zomdb_batch_destroy(struct WriteBatch *ptr);
+
+#if defined(ZOMDB_COMPAT_SYMBOLS)
+/**
+ * Deprecated alias of zomdb_heap_create.
+ *
+ * # Safety
+ *
+ * See zomdb_heap_create.
+ */
+struct Heap *create_heap(const char *file_name_cstr);
+#endif
+
+#if defined(ZOMDB_COMPAT_SYMBOLS)
+/**
+ * Deprecated alias of zomdb_heap_open_options_default.
+ */
+struct HeapOpenOptions heap_open_options_default(void);
+#endif
+
+#if defined(ZOMDB_COMPAT_SYMBOLS)
+/**
+ * Deprecated alias of zomdb_heap_create_with_options.
+ *
+ * # Safety
+ *
+ * See zomdb_heap_create_with_options.
+ */
+struct Heap *create_heap_with_options(const uint8_t *path_ptr,
+                                      uintptr_t path_len,
+                                      const struct HeapOpenOptions *opts);
+#endif
+
+#if defined(ZOMDB_COMPAT_SYMBOLS)
+/**
+ * Deprecated alias of zomdb_heap_get.
+ *
+ * # Safety
+ *
+ * See zomdb_heap_get.
+ */
+const char *heap_get(struct Heap *ptr, const char *key_cstr);
+#endif
+
+#if defined(ZOMDB_COMPAT_SYMBOLS)
+/**
+ * Deprecated alias of zomdb_heap_set.
+ *
+ * # Safety
+ *
+ * See zomdb_heap_set.
+ */
+enum ZomdbErrorCode heap_set(struct Heap *ptr, const char *key_cstr, const char *value_cstr);
+#endif
+
+#if defined(ZOMDB_COMPAT_SYMBOLS)
+/**
+ * Deprecated alias of zomdb_heap_get2.
+ *
+ * # Safety
+ *
+ * See zomdb_heap_get2.
+ */
+enum ZomdbErrorCode heap_get2(struct Heap *ptr,
+                              const uint8_t *key_ptr,
+                              uintptr_t key_len,
+                              uint8_t **out_value_ptr,
+                              uintptr_t *out_value_len);
+#endif
+
+#if defined(ZOMDB_COMPAT_SYMBOLS)
+/**
+ * Deprecated alias of zomdb_heap_set2.
+ *
+ * # Safety
+ *
+ * See zomdb_heap_set2.
+ */
+enum ZomdbErrorCode heap_set2(struct Heap *ptr,
+                              const uint8_t *key_ptr,
+                              uintptr_t key_len,
+                              const uint8_t *value_ptr,
+                              uintptr_t value_len);
+#endif
+
+#if defined(ZOMDB_COMPAT_SYMBOLS)
+/**
+ * Deprecated alias of zomdb_heap_put_many.
+ *
+ * # Safety
+ *
+ * See zomdb_heap_put_many.
+ */
+intptr_t heap_put_many(struct Heap *ptr,
+                       const uint8_t *const *keys,
+                       const uintptr_t *key_lens,
+                       const uint8_t *const *values,
+                       const uintptr_t *value_lens,
+                       uintptr_t count);
+#endif
+
+#if defined(ZOMDB_COMPAT_SYMBOLS)
+/**
+ * Deprecated alias of zomdb_heap_delete.
+ *
+ * # Safety
+ *
+ * See zomdb_heap_delete.
+ */
+enum ZomdbErrorCode heap_delete(struct Heap *ptr, const uint8_t *key_ptr, uintptr_t key_len);
+#endif
+
+#if defined(ZOMDB_COMPAT_SYMBOLS)
+/**
+ * Deprecated alias of zomdb_heap_contains.
+ *
+ * # Safety
+ *
+ * See zomdb_heap_contains.
+ */
+int heap_contains(struct Heap *ptr, const uint8_t *key_ptr, uintptr_t key_len);
+#endif
+
+#if defined(ZOMDB_COMPAT_SYMBOLS)
+/**
+ * Deprecated alias of zomdb_heap_count.
+ *
+ * # Safety
+ *
+ * See zomdb_heap_count.
+ */
+enum ZomdbErrorCode heap_count(struct Heap *ptr, uint64_t *out_count);
+#endif
+
+#if defined(ZOMDB_COMPAT_SYMBOLS)
+/**
+ * Deprecated alias of zomdb_heap_sync.
+ *
+ * # Safety
+ *
+ * See zomdb_heap_sync.
+ */
+enum ZomdbErrorCode heap_sync(struct Heap *ptr);
+#endif
+
+#if defined(ZOMDB_COMPAT_SYMBOLS)
+/**
+ * Deprecated alias of zomdb_heap_compact.
+ *
+ * # Safety
+ *
+ * See zomdb_heap_compact.
+ */
+enum ZomdbErrorCode heap_compact(struct Heap *ptr, struct HeapCompactionReport *out_report);
+#endif
+
+#if defined(ZOMDB_COMPAT_SYMBOLS)
+/**
+ * Deprecated alias of zomdb_heap_stats.
+ *
+ * # Safety
+ *
+ * See zomdb_heap_stats.
+ */
+enum ZomdbErrorCode heap_stats(struct Heap *ptr, struct HeapStats *out);
+#endif
+
+#if defined(ZOMDB_COMPAT_SYMBOLS)
+/**
+ * Deprecated alias of zomdb_heap_destroy.
+ *
+ * # Safety
+ *
+ * See zomdb_heap_destroy.
+ */
+void destroy_heap(struct Heap *ptr);
+#endif
+
+#if defined(ZOMDB_COMPAT_SYMBOLS)
+/**
+ * Deprecated alias of zomdb_heap_iter.
+ *
+ * # Safety
+ *
+ * See zomdb_heap_iter.
+ */
+struct HeapIter *heap_iter(struct Heap *ptr);
+#endif
+
+#if defined(ZOMDB_COMPAT_SYMBOLS)
+/**
+ * Deprecated alias of zomdb_heap_iter_prefix.
+ *
+ * # Safety
+ *
+ * See zomdb_heap_iter_prefix.
+ */
+struct HeapIter *heap_iter_prefix(struct Heap *ptr,
+                                  const uint8_t *prefix_ptr,
+                                  uintptr_t prefix_len);
+#endif
+
+#if defined(ZOMDB_COMPAT_SYMBOLS)
+/**
+ * Deprecated alias of zomdb_heap_for_each.
+ *
+ * # Safety
+ *
+ * See zomdb_heap_for_each.
+ */
+enum ZomdbErrorCode heap_for_each(struct Heap *ptr,
+                                  struct Option_HeapForEachCallback callback,
+                                  void *user_data);
+#endif
+
+#if defined(ZOMDB_COMPAT_SYMBOLS)
+/**
+ * Deprecated alias of zomdb_heap_iter_next.
+ *
+ * # Safety
+ *
+ * See zomdb_heap_iter_next.
+ */
+const struct HeapTuple *heap_iter_next(struct HeapIter *ptr);
+#endif
+
+#if defined(ZOMDB_COMPAT_SYMBOLS)
+/**
+ * Deprecated alias of zomdb_heap_tuple_key.
+ *
+ * # Safety
+ *
+ * See zomdb_heap_tuple_key.
+ */
+const char *heap_tuple_key(const struct HeapTuple *ptr);
+#endif
+
+#if defined(ZOMDB_COMPAT_SYMBOLS)
+/**
+ * Deprecated alias of zomdb_heap_tuple_value.
+ *
+ * # Safety
+ *
+ * See zomdb_heap_tuple_value.
+ */
+const char *heap_tuple_value(const struct HeapTuple *ptr);
+#endif
+
+#if defined(ZOMDB_COMPAT_SYMBOLS)
+/**
+ * Deprecated alias of zomdb_heap_tuple_destroy.
+ *
+ * # Safety
+ *
+ * See zomdb_heap_tuple_destroy.
+ */
+void heap_tuple_destroy(struct HeapTuple *ptr);
+#endif
+
+#if defined(ZOMDB_COMPAT_SYMBOLS)
+/**
+ * Deprecated alias of zomdb_heap_iter_destroy.
+ *
+ * # Safety
+ *
+ * See zomdb_heap_iter_destroy.
+ */
+void heap_iter_destroy(struct HeapIter *ptr);
+#endif
+
+/**
+ * Open the database in the directory at the given path, creating the
+ * directory if it doesn't exist yet.
+ *
+ * The path consists of path_len bytes and doesn't need to be
+ * null-terminated. On Windows, it must be valid UTF-8.
+ *
+ * Returns null if the database could not be opened, in which case
+ * zomdb_last_error returns the code of the error. The database must be
+ * released with zomdb_db_close.
+ *
+ * # Safety
+ *
+ * The path pointer must point to path_len readable bytes.
+ */
+struct Database *zomdb_db_open(const uint8_t *path_ptr, uintptr_t path_len);
+
+/**
+ * Open the heap with the given name in the database, creating it if it
+ * doesn't exist yet.
+ *
+ * The name consists of name_len bytes of UTF-8 and doesn't need to be
+ * null-terminated. It must not be empty, start with a dot, contain path
+ * separators or end in ".compact", ".savepoints" or ".savepoints.tmp";
+ * such names fail with ERR_IO.
+ *
+ * Returns a new handle for the heap, or null if it could not be opened, in
+ * which case zomdb_last_error returns the code of the error. The handle
+ * works with all heap functions and is owned by the caller, who must
+ * release it with zomdb_heap_destroy. Every call returns a separate handle,
+ * but all handles for the same name share one heap. They stay valid after
+ * the database was closed.
+ *
+ * # Safety
+ *
+ * The database pointer must have been returned by zomdb_db_open and not
+ * yet been closed. The name pointer must point to name_len readable bytes.
+ */
+struct Heap *zomdb_db_heap(struct Database *ptr, const uint8_t *name_ptr, uintptr_t name_len);
+
+/**
+ * Call the callback with the name of every heap in the database, in sorted
+ * order. user_data is passed through to the callback unchanged.
+ *
+ * Returns 0 once all names were visited or the callback stopped the
+ * iteration, or the code of the error that occurred.
+ *
+ * # Safety
+ *
+ * The database pointer must have been returned by zomdb_db_open and not
+ * yet been closed.
+ */
+enum ZomdbErrorCode zomdb_db_list(struct Database *ptr,
+                                  DatabaseListCallback callback,
+                                  void *user_data);
+
+/**
+ * Close the database and release its resources.
+ *
+ * Heap handles returned by zomdb_db_heap stay valid and keep their heap
+ * open until they are destroyed.
+ *
+ * # Safety
+ *
+ * The database pointer must have been returned by zomdb_db_open and not
+ * yet been closed. It must not be used after this call.
+ */
+void zomdb_db_close(struct Database *ptr);
+
+/**
+ * Return the code of the error.
+ *
+ * # Safety
+ *
+ * The error pointer must have been returned by this library and not yet
+ * been released.
+ */
+enum ZomdbErrorCode zomdb_error_code(const struct ZomdbError *ptr);
+
+/**
+ * Return a message describing the error, including the errors that caused
+ * it.
+ *
+ * The message is owned by the error and released along with it.
+ *
+ * # Safety
+ *
+ * The error pointer must have been returned by this library and not yet
+ * been released.
+ */
+const char *zomdb_error_message(const struct ZomdbError *ptr);
+
+/**
+ * Release the error.
+ *
+ * Passing null is allowed and does nothing.
+ *
+ * # Safety
+ *
+ * The error pointer must have been returned by this library and not yet
+ * been released. It must not be used after this call.
+ */
+void zomdb_error_free(struct ZomdbError *ptr);
+
+/**
+ * Like zomdb_heap_open, but also reports failure through err_out.
+ *
+ * # Safety
+ *
+ * See zomdb_heap_open. The error pointer must be null or valid for writes.
+ */
+enum ZomdbErrorCode zomdb_heap_open_e(const uint8_t *path_ptr,
+                                      uintptr_t path_len,
+                                      const struct HeapOpenOptions *opts,
+                                      struct Heap **out_heap,
+                                      struct ZomdbError **err_out);
+
+/**
+ * Like zomdb_heap_get2, but also reports failure through err_out.
+ *
+ * # Safety
+ *
+ * See zomdb_heap_get2. The error pointer must be null or valid for writes.
+ */
+enum ZomdbErrorCode zomdb_heap_get2_e(struct Heap *ptr,
+                                      const uint8_t *key_ptr,
+                                      uintptr_t key_len,
+                                      uint8_t **out_value_ptr,
+                                      uintptr_t *out_value_len,
+                                      struct ZomdbError **err_out);
+
+/**
+ * Like zomdb_heap_set2, but also reports failure through err_out.
+ *
+ * # Safety
+ *
+ * See zomdb_heap_set2. The error pointer must be null or valid for writes.
+ */
+enum ZomdbErrorCode zomdb_heap_set2_e(struct Heap *ptr,
+                                      const uint8_t *key_ptr,
+                                      uintptr_t key_len,
+                                      const uint8_t *value_ptr,
+                                      uintptr_t value_len,
+                                      struct ZomdbError **err_out);
+
+/**
+ * Like zomdb_heap_delete, but also reports failure through err_out.
+ *
+ * # Safety
+ *
+ * See zomdb_heap_delete. The error pointer must be null or valid for
+ * writes.
+ */
+enum ZomdbErrorCode zomdb_heap_delete_e(struct Heap *ptr,
+                                        const uint8_t *key_ptr,
+                                        uintptr_t key_len,
+                                        struct ZomdbError **err_out);
+
+/**
+ * Like zomdb_heap_sync, but also reports failure through err_out.
+ *
+ * # Safety
+ *
+ * See zomdb_heap_sync. The error pointer must be null or valid for writes.
+ */
+enum ZomdbErrorCode zomdb_heap_sync_e(struct Heap *ptr, struct ZomdbError **err_out);
+
+/**
+ * Register the callback that receives the library's log messages.
+ *
+ * Passing null removes the current callback, after which messages are
+ * dropped. This is the default. The callback replaces any earlier one.
+ *
+ * # Safety
+ *
+ * The callback may be called from any thread that calls into the library,
+ * also concurrently, so it and the user data must be safe to use from all
+ * of them. The user data must stay valid until another callback is
+ * registered.
+ */
+void zomdb_set_log_callback(ZomdbLogCallback cb, void *user_data);
@@ -13,7 +13,7 @@ import (
 	"errors"
 	"fmt"
 	"iter"
-	"syscall"
+	"runtime"
 	"unsafe"
 )
 
@@ -34,8 +34,9 @@ func New(fileName string) (*Heap, error) {
 	cs := C.CString(fileName)
 	defer C.free(unsafe.Pointer(cs))
 
-	heap, errno := C.create_heap(cs)
-	if err := goErr(errno); err != nil {
+	var heap *C.struct_Heap
+	code := C.zomdb_heap_open((*C.uint8_t)(unsafe.Pointer(cs)), C.uintptr_t(len(fileName)), nil, &heap)
+	if err := goErr(code); err != nil {
 		return nil, err
 	}
 
@@ -43,7 +44,7 @@ func New(fileName string) (*Heap, error) {
 }
 
 func (h *Heap) Close() {
-	C.destroy_heap(h.heap)
+	C.zomdb_heap_destroy(h.heap)
 }
 
 func (h *Heap) Get(key []byte) ([]byte, error) {
@@ -54,8 +55,11 @@ func (h *Heap) Get(key []byte) ([]byte, error) {
 	ck := C.CString(string(key))
 	defer C.free(unsafe.Pointer(ck))
 
-	cv, errno := C.heap_get(h.heap, ck)
-	if err := goErr(errno); err != nil {
+	var cv *C.char
+	if err := withLastErr(func() bool {
+		cv = C.zomdb_heap_get(h.heap, ck)
+		return cv != nil
+	}); err != nil {
 		return nil, err
 	}
 
@@ -75,12 +79,7 @@ func (h *Heap) Set(key, value []byte) error {
 	defer C.free(unsafe.Pointer(ck))
 	defer C.free(unsafe.Pointer(cv))
 
-	_, errno := C.heap_set(h.heap, ck, cv)
-	if err := goErr(errno); err != nil {
-		return err
-	}
-
-	return nil
+	return goErr(C.zomdb_heap_set(h.heap, ck, cv))
 }
 
 // All returns an iterator over all values of the heap.
@@ -88,12 +87,18 @@ func (h *Heap) Set(key, value []byte) error {
 // Yielded values are ordered in reverse insertion order.
 func (h *Heap) All() iter.Seq2[[]byte, []byte] {
 	return func(yield func(k, v []byte) bool) {
-		iter := C.heap_iter(h.heap)
-		defer C.heap_iter_destroy(iter)
+		var iter *C.struct_HeapIter
+		if err := withLastErr(func() bool {
+			iter = C.zomdb_heap_iter(h.heap)
+			return iter != nil
+		}); err != nil {
+			panic(err)
+		}
+		defer C.zomdb_heap_iter_destroy(iter)
 
 		for {
-			tuple, errno := C.heap_iter_next(iter)
-			if err := goErr(errno); err != nil {
+			var tuple *C.struct_HeapTuple2
+			if err := goErr(C.zomdb_heap_iter_next2(iter, &tuple)); err != nil {
 				panic(err)
 			}
 
@@ -102,8 +107,9 @@ func (h *Heap) All() iter.Seq2[[]byte, []byte] {
 				return
 			}
 
-			goKey := []byte(C.GoString(tuple.key))
-			goValue := []byte(C.GoString(tuple.value))
+			goKey := C.GoBytes(unsafe.Pointer(tuple.key_ptr), C.int(tuple.key_len))
+			goValue := C.GoBytes(unsafe.Pointer(tuple.value_ptr), C.int(tuple.value_len))
+			C.zomdb_heap_tuple2_destroy(tuple)
 
 			if !yield(goKey, goValue) {
 				return
@@ -112,37 +118,59 @@ func (h *Heap) All() iter.Seq2[[]byte, []byte] {
 	}
 }
 
-func goErr(err error) error {
-	if err == nil {
-		return nil
-	}
+// withLastErr calls f, which reports whether the C function it calls
+// succeeded, and returns the last error of the library if it did not.
+//
+// The last error is kept per OS thread, so the goroutine must stay on the
+// thread that made the call until the error has been read.
+func withLastErr(f func() bool) error {
+	runtime.LockOSThread()
+	defer runtime.UnlockOSThread()
 
-	errno, ok := err.(syscall.Errno)
-	if !ok {
-		panic(fmt.Sprintf("goErr called with non-cgo err: %v", err))
+	if f() {
+		return nil
 	}
+	return goErr(C.zomdb_last_error())
+}
 
-	if errno == 0 { // no error
+func goErr(code C.ZomdbErrorCode) error {
+	if code == 0 { // no error
 		return nil
 	}
 
-	if int(errno) >= len(errnos) {
-		return fmt.Errorf("unexpected errno: %d", errno)
+	if code < 0 || int(code) >= len(codes) {
+		return fmt.Errorf("unexpected error code: %d", code)
 	}
 
-	if err := errnos[errno]; err != nil {
+	if err := codes[code]; err != nil {
 		return err
 	}
 
-	// We expect the errnos array to be exhaustive.
-	return fmt.Errorf("unexpected errno: %d", errno)
+	// We expect the codes array to be exhaustive.
+	return fmt.Errorf("unexpected error code: %d", code)
 }
 
-var errnos = [...]error{
+var codes = [...]error{
 	1:  errors.New("zomdb: not found"),
 	10: errors.New("zomdb: io error"),
+	11: errors.New("zomdb: heap is locked"),
+	12: errors.New("zomdb: heap is poisoned"),
+	13: errors.New("zomdb: heap is busy"),
+	14: errors.New("zomdb: heap is read-only"),
+	15: errors.New("zomdb: out of memory"),
+	16: errors.New("zomdb: operation cancelled"),
 	30: errors.New("zomdb: not utf8-encoded"),
 	31: errors.New("zomdb: invalid key size"),
 	32: errors.New("zomdb: invalid value size"),
+	33: errors.New("zomdb: value contains a null byte"),
+	34: errors.New("zomdb: null argument"),
+	35: errors.New("zomdb: invalid handle"),
+	36: errors.New("zomdb: buffer too small"),
+	37: errors.New("zomdb: empty key"),
+	38: errors.New("zomdb: invalid pattern"),
+	39: errors.New("zomdb: invalid savepoint"),
 	50: errors.New("zomdb: corrupt data"),
+	51: errors.New("zomdb: heap file modified externally"),
+	52: errors.New("zomdb: value has an unexpected length"),
+	60: errors.New("zomdb: internal error"),
 }